@@ -0,0 +1,90 @@
+//! Compares forwarding a `StreamBody` via the fast path, which hands the body over as is, to the generic path, which
+//! forwards the chunks of a foreign body by copying them.
+//!
+//! Run with `cargo bench --bench forward`.
+
+use bytes::{Buf, Bytes};
+use http::{HeaderMap, HeaderValue};
+use http_body::Body;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use stream_body::{StreamBody, StreamData};
+
+const CHUNK_SIZE: usize = 16 * 1024;
+const CHUNK_COUNT: usize = 4096;
+const ROUNDS: usize = 10;
+
+/// A foreign body whose chunk type isn't known to the crate, so its chunks are copied.
+struct Foreign(StreamBody);
+
+struct ForeignChunk(StreamData);
+
+impl Buf for ForeignChunk {
+    fn remaining(&self) -> usize {
+        self.0.remaining()
+    }
+
+    fn bytes(&self) -> &[u8] {
+        self.0.bytes()
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        self.0.advance(cnt)
+    }
+}
+
+impl Body for Foreign {
+    type Data = ForeignChunk;
+    type Error = std::io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.0)
+            .poll_data(cx)
+            .map(|data| data.map(|data| data.map(ForeignChunk)))
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.0).poll_trailers(cx)
+    }
+}
+
+fn upstream() -> StreamBody {
+    let chunk = Bytes::from(vec![7u8; CHUNK_SIZE]);
+    StreamBody::concat((0..CHUNK_COUNT).map(|_| StreamBody::from(chunk.clone())).collect())
+}
+
+async fn drain(mut body: StreamBody) -> usize {
+    let mut len = 0;
+    while let Some(data) = body.data().await {
+        len += data.unwrap().remaining();
+    }
+    len
+}
+
+async fn bench<F: Fn() -> StreamBody>(name: &str, forward: F) {
+    let mut total = Duration::default();
+    for _ in 0..ROUNDS {
+        let body = forward();
+        let started = Instant::now();
+        assert_eq!(drain(body).await, CHUNK_SIZE * CHUNK_COUNT);
+        total += started.elapsed();
+    }
+
+    let bytes = (CHUNK_SIZE * CHUNK_COUNT * ROUNDS) as f64;
+    println!(
+        "{:<8} {:>8.2} ms per body, {:>8.2} GiB/s",
+        name,
+        total.as_secs_f64() * 1000.0 / ROUNDS as f64,
+        bytes / total.as_secs_f64() / (1 << 30) as f64
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    bench("fast", || StreamBody::forward(upstream())).await;
+    bench("generic", || StreamBody::forward(Foreign(upstream()))).await;
+}
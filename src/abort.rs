@@ -0,0 +1,87 @@
+use crate::body::StreamBody;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Waker};
+use tokio::io;
+
+/// A handle to fail a body from the outside, e.g. from the task feeding the writer half of a channel body, created
+/// by [StreamBody::abort_handle](./struct.StreamBody.html#method.abort_handle).
+///
+/// The handle is cheap to clone and all the clones abort the same body.
+#[derive(Clone)]
+pub struct AbortHandle {
+    shared: Arc<Mutex<AbortState>>,
+}
+
+#[derive(Default)]
+pub(crate) struct AbortState {
+    error: Option<io::Error>,
+    aborted: bool,
+    waker: Option<Waker>,
+}
+
+impl AbortHandle {
+    /// Aborts the body: its next `poll_data` yields `err` and the body ends, so the server resets the stream
+    /// instead of the client seeing a truncated but successful response.
+    ///
+    /// Only the first abort has an effect, as does an abort after the body already ended.
+    pub fn abort(&self, err: io::Error) {
+        let mut state = self.shared.lock().unwrap_or_else(|err| err.into_inner());
+        if state.aborted {
+            return;
+        }
+
+        state.aborted = true;
+        state.error = Some(err);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Returns whether the body was aborted.
+    pub fn is_aborted(&self) -> bool {
+        self.shared.lock().unwrap_or_else(|err| err.into_inner()).aborted
+    }
+}
+
+impl StreamBody {
+    /// Returns a handle to abort the body with an error, which is useful with [channel](#method.channel) bodies when
+    /// the upstream source fails halfway.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use stream_body::StreamBody;
+    /// use tokio::fs::File;
+    /// use tokio::io::AsyncWriteExt;
+    ///
+    /// # async fn run() {
+    /// let (mut writer, mut body) = StreamBody::channel();
+    /// let abort = body.abort_handle();
+    ///
+    /// tokio::spawn(async move {
+    ///     let mut f = File::open("large-file").await.unwrap();
+    ///     if let Err(err) = tokio::io::copy(&mut f, &mut writer).await {
+    ///         abort.abort(err);
+    ///     }
+    /// });
+    /// # }
+    /// ```
+    pub fn abort_handle(&mut self) -> AbortHandle {
+        let shared = self.abort.get_or_insert_with(Default::default);
+        AbortHandle {
+            shared: Arc::clone(shared),
+        }
+    }
+}
+
+/// Takes the error of an aborted body, or registers the task to be woken up once the body is aborted.
+pub(crate) fn take_abort_error(shared: &Mutex<AbortState>, cx: &mut Context) -> Option<io::Error> {
+    let mut state = shared.lock().unwrap_or_else(|err| err.into_inner());
+    match state.error.take() {
+        Some(err) => Some(err),
+        None => {
+            state.waker = Some(cx.waker().clone());
+            None
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use crate::body::{size_hint_with_held, StreamBody};
+use crate::data::StreamData;
+use crate::throughput::ThroughputEstimator;
+use bytes::{Buf, BytesMut};
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io;
+
+// How long a chunk should take the client to consume, the chunk size follows from its estimated speed.
+const TARGET_CHUNK_TIME: Duration = Duration::from_millis(50);
+
+const INITIAL_CHUNK_SIZE: usize = 16 * 1024;
+
+impl StreamBody {
+    /// Adapts the size of the emitted chunks to the speed of the client, between `min` and `max` bytes.
+    ///
+    /// The speed is estimated from how long the client takes to consume every chunk, so slow clients get small
+    /// chunks, which reduces the memory each connection ties up in buffers, while fast clients get large ones, which
+    /// reduces the per-chunk overhead. Larger source chunks are split without copying, while smaller ones are
+    /// coalesced as long as the source has data ready, so live streams aren't delayed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use stream_body::StreamBody;
+    ///
+    /// # async fn run() -> std::io::Result<()> {
+    /// let file = tokio::fs::File::open("large-file").await?;
+    /// let body = StreamBody::from_reader(file).adaptive_chunk_size(4 * 1024, 256 * 1024);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn adaptive_chunk_size(self, min: usize, max: usize) -> StreamBody {
+        let min = min.max(1);
+        let max = max.max(min);
+
+        self.wrap_with(|inner| AdaptiveChunks {
+            inner,
+            min,
+            max,
+            chunk_size: INITIAL_CHUNK_SIZE.max(min).min(max),
+            pending: None,
+            buf: BytesMut::new(),
+            reached_eof: false,
+            estimator: ThroughputEstimator::new(),
+            emitted: None,
+        })
+    }
+}
+
+struct AdaptiveChunks {
+    inner: StreamBody,
+    min: usize,
+    max: usize,
+    chunk_size: usize,
+    // The rest of a source chunk larger than the chunk size.
+    pending: Option<StreamData>,
+    // The coalesced source chunks smaller than the chunk size.
+    buf: BytesMut,
+    reached_eof: bool,
+    estimator: ThroughputEstimator,
+    // When the last chunk was emitted and its length, the next poll tells how long the client took to consume it.
+    emitted: Option<(Instant, usize)>,
+}
+
+impl AdaptiveChunks {
+    fn update_chunk_size(&mut self) {
+        if let Some((at, len)) = self.emitted.take() {
+            self.estimator.record(len, at.elapsed());
+        }
+
+        if let Some(rate) = self.estimator.bytes_per_sec() {
+            let size = rate * TARGET_CHUNK_TIME.as_secs_f64();
+            self.chunk_size = (size as usize).max(self.min).min(self.max);
+        }
+    }
+
+    fn emit(&mut self, data: StreamData) -> Poll<Option<Result<StreamData, io::Error>>> {
+        self.emitted = Some((Instant::now(), data.remaining()));
+        Poll::Ready(Some(Ok(data)))
+    }
+
+    fn take_buf(&mut self) -> Option<StreamData> {
+        if self.buf.is_empty() {
+            return None;
+        }
+        Some(StreamData::from_bytes(self.buf.split().freeze()))
+    }
+}
+
+impl Body for AdaptiveChunks {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let me = &mut *self;
+        me.update_chunk_size();
+
+        loop {
+            if let Some(mut data) = me.pending.take() {
+                if data.remaining() > me.chunk_size {
+                    let part = data.split_to(me.chunk_size);
+                    me.pending = Some(data);
+                    return me.emit(part);
+                }
+                return me.emit(data);
+            }
+
+            if me.reached_eof {
+                return Poll::Ready(me.take_buf().map(Ok));
+            }
+
+            match Pin::new(&mut me.inner).poll_data(cx) {
+                Poll::Ready(Some(Ok(data))) => {
+                    let len = data.remaining();
+
+                    if !me.buf.is_empty() {
+                        if me.buf.len() + len > me.chunk_size {
+                            me.pending = Some(data);
+                            if let Some(coalesced) = me.take_buf() {
+                                return me.emit(coalesced);
+                            }
+                            continue;
+                        }
+                    } else if len >= me.chunk_size / 2 {
+                        // Large enough chunks are passed through or split, without a copy.
+                        me.pending = Some(data);
+                        continue;
+                    }
+
+                    // Dropping the copied chunk lets the source continue.
+                    me.buf.extend_from_slice(data.bytes());
+                    drop(data);
+
+                    if me.buf.len() >= me.chunk_size {
+                        if let Some(coalesced) = me.take_buf() {
+                            return me.emit(coalesced);
+                        }
+                    }
+                }
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                Poll::Ready(None) => me.reached_eof = true,
+                Poll::Pending => {
+                    // The coalesced data doesn't wait for more.
+                    return match me.take_buf() {
+                        Some(coalesced) => me.emit(coalesced),
+                        None => Poll::Pending,
+                    };
+                }
+            }
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.pending.is_none() && self.buf.is_empty() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let held = self.buf.len() as u64 + self.pending.as_ref().map(|data| data.remaining() as u64).unwrap_or(0);
+        size_hint_with_held(self.inner.size_hint(), held)
+    }
+}
@@ -1,17 +1,30 @@
+use crate::abort::{self, AbortState};
+use crate::buffer::ReusableBuf;
+use crate::closed::ClosedGuard;
+#[cfg(feature = "http-body-1")]
+use crate::compat::FramesInner;
 use crate::data::StreamData;
+use crate::flow::CapacitySignal;
+use crate::pool::BodyPool;
+use crate::priority::Priority;
+use crate::source::{Feed, SourceBody};
 use crate::state::State;
-use async_pipe::{self, PipeReader, PipeWriter};
+use crate::tuning::Tuner;
+use crate::watchdog::AbandonAction;
+use async_pipe::{self, PipeWriter};
 use bytes::Bytes;
 use http::{HeaderMap, HeaderValue};
 use http_body::{Body, SizeHint};
-use pin_project_lite::pin_project;
 use std::borrow::Cow;
-use std::marker::Unpin;
-use std::mem::MaybeUninit;
+use std::ops::{Bound, RangeBounds};
 use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
-use tokio::io::{self, AsyncRead};
+use std::time::Instant;
+use tokio::io;
+#[cfg(feature = "tokio-rt")]
+use tokio::io::AsyncRead;
 
 const DEFAULT_BUF_SIZE: usize = 8 * 1024;
 
@@ -20,43 +33,28 @@ const DEFAULT_BUF_SIZE: usize = 8 * 1024;
 /// It is similar to [Body](https://docs.rs/hyper/0.13.4/hyper/body/struct.Body.html).
 pub struct StreamBody {
     inner: Inner,
+    pub(crate) priority: Priority,
+    pub(crate) created_at: Instant,
+    // A short description of where the data comes from, kept across adapters for debugging.
+    pub(crate) source: &'static str,
+    pub(crate) terminated: bool,
+    pub(crate) abort: Option<Arc<Mutex<AbortState>>>,
+    pub(crate) close_guard: Option<ClosedGuard>,
+    // The advisory response headers declared for the body, kept across adapters.
+    pub(crate) headers: HeaderMap<HeaderValue>,
 }
 
 enum Inner {
-    Once(OnceInner),
-    Channel(ChannelInner),
-}
-
-struct OnceInner {
-    data: Option<Bytes>,
-    reached_eof: bool,
-    state: Arc<Mutex<State>>,
-}
-
-pin_project! {
-    struct ChannelInner {
-        #[pin]
-        reader: PipeReader,
-        buf: Box<[u8]>,
-        len: usize,
-        reached_eof: bool,
-        state: Arc<Mutex<State>>,
-    }
+    Source(SourceBody),
+    Wrapped(Pin<Box<dyn Body<Data = StreamData, Error = io::Error> + Send + 'static>>),
+    #[cfg(feature = "http-body-1")]
+    Frames(FramesInner),
 }
 
 impl StreamBody {
     /// Creates an empty body.
     pub fn empty() -> StreamBody {
-        StreamBody {
-            inner: Inner::Once(OnceInner {
-                data: None,
-                reached_eof: true,
-                state: Arc::new(Mutex::new(State {
-                    is_current_stream_data_consumed: true,
-                    waker: None,
-                })),
-            }),
-        }
+        StreamBody::once(None, "empty")
     }
 
     /// Creates a body stream with an associated writer half.
@@ -70,164 +68,390 @@ impl StreamBody {
     ///
     /// Useful when wanting to stream chunks from another thread.
     pub fn channel_with_capacity(capacity: usize) -> (PipeWriter, StreamBody) {
-        let (w, r) = async_pipe::pipe();
-
-        let mut buffer = Vec::with_capacity(capacity);
-        unsafe {
-            buffer.set_len(capacity);
+        StreamBody::channel_from_parts(ReusableBuf::new(capacity), Arc::new(Mutex::new(State::new())), None)
+    }
 
-            let b = &mut *(&mut buffer[..] as *mut [u8] as *mut [MaybeUninit<u8>]);
-            r.prepare_uninitialized_buffer(b);
+    /// Creates a body stream with an associated writer half reading into a ring of `count` buffers of `capacity` bytes.
+    ///
+    /// With a single buffer the writer has to wait until the previous chunk is written to the socket before the next
+    /// one is read. With a ring, the body keeps reading into the next buffer while the previous chunks are still in
+    /// flight, so the producer and the consumer overlap when the server queues several chunks, e.g. when the socket
+    /// is slow to flush.
+    pub fn channel_with_buffers(count: usize, capacity: usize) -> (PipeWriter, StreamBody) {
+        let (w, mut body) = StreamBody::channel_with_capacity(capacity);
+
+        if let Inner::Source(ref mut inner) = body.inner {
+            for _ in 1..count {
+                let state = Arc::new(Mutex::new(State::new()));
+                inner.spare.push((ReusableBuf::new(capacity), state));
+            }
         }
 
-        let body = StreamBody {
-            inner: Inner::Channel(ChannelInner {
-                reader: r,
-                buf: buffer.into_boxed_slice(),
-                len: 0,
-                reached_eof: false,
-                state: Arc::new(Mutex::new(State {
-                    is_current_stream_data_consumed: true,
-                    waker: None,
-                })),
-            }),
-        };
+        (w, body)
+    }
 
+    /// Creates a body stream with an associated writer half which is expected to deliver exactly `len` bytes.
+    ///
+    /// The length is only used to keep the [size_hint](#method.size_hint) accurate while the body streams, it isn't
+    /// enforced on the writer.
+    pub fn channel_with_len(len: u64) -> (PipeWriter, StreamBody) {
+        let (w, mut body) = StreamBody::channel();
+        body.set_known_len(len);
         (w, body)
     }
 
+    /// Creates a channel body from an existing buffer and state, which are handed back to the pool, if any, once the
+    /// body is dropped.
+    pub(crate) fn channel_from_parts(
+        buf: ReusableBuf,
+        state: Arc<Mutex<State>>,
+        pool: Option<BodyPool>,
+    ) -> (PipeWriter, StreamBody) {
+        let (w, r) = async_pipe::pipe();
+
+        let feed = Feed::Pipe {
+            reader: r,
+            eof_on_empty_write: false,
+        };
+        let mut inner = SourceBody::new(feed, buf, state);
+        inner.pool = pool;
+
+        (w, StreamBody::from_source_body(inner, "channel"))
+    }
+
     /// A helper method to convert an [AsyncRead](https://docs.rs/tokio/0.2.16/tokio/io/trait.AsyncRead.html) to a `StreamBody`. If there is any error
     /// thrown during the reading/writing, it will be logged via [log::error!](https://docs.rs/log/0.4.10/log/macro.error.html).
-    pub fn from_reader<R: AsyncRead + Unpin + Send + 'static>(mut r: R) -> StreamBody {
-        let (mut w, body) = StreamBody::channel();
-
-        tokio::spawn(async move {
-            if let Err(err) = io::copy(&mut r, &mut w).await {
-                log::error!(
-                    "{}: StreamBody: Something went wrong while piping the provided reader to the body: {}",
-                    env!("CARGO_PKG_NAME"),
-                    err
-                )
+    #[cfg(feature = "tokio-rt")]
+    pub fn from_reader<R: AsyncRead + Unpin + Send + 'static>(r: R) -> StreamBody {
+        let (w, mut body) = StreamBody::channel();
+        body.source = "reader";
+
+        tokio::spawn(pipe_reader(r, w));
+
+        body
+    }
+
+    /// Same as [from_reader](#method.from_reader), but for readers whose total length is known upfront, e.g. files.
+    ///
+    /// The [size_hint](#method.size_hint) then reports the exact number of bytes left as the body streams, so hyper can
+    /// send a `Content-Length` header. See [set_exact_size](#method.set_exact_size) for other bodies.
+    #[cfg(feature = "tokio-rt")]
+    #[doc(alias = "from_reader_sized")]
+    pub fn from_reader_with_len<R: AsyncRead + Unpin + Send + 'static>(r: R, len: u64) -> StreamBody {
+        let (w, mut body) = StreamBody::channel_with_len(len);
+        body.source = "reader";
+
+        tokio::spawn(pipe_reader(r, w));
+
+        body
+    }
+
+    /// Creates a body from owned bytes, starting at `offset`, e.g. to replay a cached payload from where a previous
+    /// transfer stopped. The bytes aren't copied, and an offset past the end gives an empty body.
+    pub fn from_bytes_at<B: Into<Bytes>>(bytes: B, offset: usize) -> StreamBody {
+        let bytes = bytes.into();
+        StreamBody::from(bytes.slice(offset.min(bytes.len())..))
+    }
+
+    /// Returns a body over the given range of an in-memory body, i.e. one created from bytes which didn't start
+    /// streaming yet, without copying the sliced region. The range is clamped to the length of the body.
+    ///
+    /// It returns `None` for bodies whose data isn't in memory, e.g. channel bodies.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use stream_body::{ByteRange, StreamBody};
+    ///
+    /// let cached = StreamBody::from("Hello world");
+    ///
+    /// let range = ByteRange { start: 6, end: 10 };
+    /// let body = cached.slice(range.start as usize..=range.end as usize).unwrap();
+    /// ```
+    pub fn slice<R: RangeBounds<usize>>(&self, range: R) -> Option<StreamBody> {
+        let bytes = match self.inner {
+            Inner::Source(ref inner) => inner.in_memory()?,
+            _ => return None,
+        };
+
+        let len = bytes.len();
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.saturating_add(1),
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => len,
+        };
+        let end = end.min(len);
+        let start = start.min(end);
+
+        Some(StreamBody::from(bytes.slice(start..end)))
+    }
+
+    /// Returns whether the body reads from the writer half of a channel.
+    pub(crate) fn is_channel(&self) -> bool {
+        matches!(self.inner, Inner::Source(ref inner) if inner.is_pipe())
+    }
+
+    /// Records the total length of a channel body, it's a no-op for other bodies which already know their length.
+    pub(crate) fn set_known_len(&mut self, len: u64) {
+        if let Inner::Source(ref mut inner) = self.inner {
+            if inner.is_pipe() {
+                inner.remaining = Some(len);
             }
-        });
+        }
+    }
 
+    /// Makes an empty write on the writer half end the body, which was the behavior before zero-length writes were
+    /// treated as plain flushes. It's a no-op for bodies not created via a channel.
+    ///
+    /// By default only dropping or shutting down the [PipeWriter](https://docs.rs/async-pipe/0.1.3/async_pipe/struct.PipeWriter.html)
+    /// ends a channel body, so producers can write zero-length chunks without terminating it.
+    pub fn eof_on_empty_write(mut self, enabled: bool) -> StreamBody {
+        if let Inner::Source(SourceBody {
+            feed: Feed::Pipe {
+                ref mut eof_on_empty_write,
+                ..
+            },
+            ..
+        }) = self.inner
+        {
+            *eof_on_empty_write = enabled;
+        }
+        self
+    }
+
+    /// Sets the action taken when a chunk is dropped before being fully consumed, it's a no-op for adapter bodies.
+    pub(crate) fn set_abandon_action(&mut self, action: AbandonAction) {
+        if let Inner::Source(ref mut inner) = self.inner {
+            inner.abandon_action = Some(action);
+        }
+    }
+
+    /// Bounds the size of the reads of a channel body by a capacity signal, returning whether the body is a channel.
+    pub(crate) fn set_read_limit(&mut self, signal: CapacitySignal) -> bool {
+        match self.inner {
+            Inner::Source(ref mut inner) if inner.is_pipe() => {
+                inner.read_limit = Some(signal);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Attaches an auto-tuner to a channel body, it's a no-op for the other bodies.
+    pub(crate) fn set_tuner(&mut self, tuner: Tuner) {
+        if let Inner::Source(ref mut inner) = self.inner {
+            if !inner.is_pipe() {
+                return;
+            }
+            tuner.attach(inner.buf.capacity(), inner.spare.len() + 1);
+            inner.tuner = Some(tuner);
+        }
+    }
+
+    /// Sets the flag shared by the buffers of the body to coalesce wakeups, it's a no-op for adapter bodies.
+    pub(crate) fn set_needs_wake(&mut self, needs_wake: Option<Arc<AtomicBool>>) {
+        let set = |state: &Mutex<State>| {
+            if let Ok(mut state) = state.lock() {
+                state.needs_wake = needs_wake.clone();
+            }
+        };
+
+        if let Inner::Source(ref inner) = self.inner {
+            set(&inner.state);
+            inner.spare.iter().for_each(|(_, state)| set(state));
+        }
+    }
+
+    /// Returns the advisory response headers declared for the body, e.g. via
+    /// [StreamBodyBuilder::header](./struct.StreamBodyBuilder.html#method.header), which
+    /// [into_response](#method.into_response) sets on the response.
+    pub fn headers(&self) -> &HeaderMap<HeaderValue> {
+        &self.headers
+    }
+
+    /// Returns whether the body is terminated, i.e. `poll_data` already returned `None` or an error.
+    ///
+    /// A terminated body is fused: polling it again keeps returning `None` without touching the source, so adapters
+    /// can safely poll it defensively.
+    pub fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+
+    /// Wraps an adapter body so that it can be exposed as a `StreamBody`.
+    pub(crate) fn wrap<B>(body: B) -> StreamBody
+    where
+        B: Body<Data = StreamData, Error = io::Error> + Send + 'static,
+    {
+        StreamBody::new(Inner::Wrapped(Box::pin(body)), "adapter")
+    }
+
+    /// Wraps an `http-body` 1.x body, whose frames are forwarded as is by the `http-body` 1.x implementation.
+    #[cfg(feature = "http-body-1")]
+    pub(crate) fn wrap_frames(inner: FramesInner) -> StreamBody {
+        StreamBody::new(Inner::Frames(inner), "body")
+    }
+
+    /// Creates a body driving its feed through a `SourceBody`, see [Source](./trait.Source.html).
+    pub(crate) fn from_source_body(inner: SourceBody, source: &'static str) -> StreamBody {
+        StreamBody::new(Inner::Source(inner), source)
+    }
+
+    /// Creates a body from in-memory data, `None` giving an empty body.
+    fn once(data: Option<Bytes>, source: &'static str) -> StreamBody {
+        let len = data.as_ref().map_or(0, |data| data.len() as u64);
+        let inner = SourceBody::new(
+            Feed::Bytes(data),
+            ReusableBuf::default(),
+            Arc::new(Mutex::new(State::new())),
+        );
+        StreamBody::from_source_body(inner.with_exact_len(len), source)
+    }
+
+    fn new(inner: Inner, source: &'static str) -> StreamBody {
+        StreamBody {
+            inner,
+            priority: Priority::default(),
+            created_at: Instant::now(),
+            source,
+            terminated: false,
+            abort: None,
+            close_guard: None,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// Returns the wrapped `http-body` 1.x body, unless an adapter was applied since.
+    #[cfg(feature = "http-body-1")]
+    pub(crate) fn frames_mut(&mut self) -> Option<&mut FramesInner> {
+        match self.inner {
+            Inner::Frames(ref mut inner) => Some(inner),
+            _ => None,
+        }
+    }
+
+    /// Wraps an adapter around this body, keeping the tags of the body like its priority, creation time and source.
+    pub(crate) fn wrap_with<B, F>(mut self, f: F) -> StreamBody
+    where
+        F: FnOnce(StreamBody) -> B,
+        B: Body<Data = StreamData, Error = io::Error> + Send + 'static,
+    {
+        let priority = self.priority;
+        let created_at = self.created_at;
+        let source = self.source;
+        let headers = std::mem::take(&mut self.headers);
+        let mut body = StreamBody::wrap(f(self));
+        body.priority = priority;
+        body.created_at = created_at;
+        body.source = source;
+        body.headers = headers;
         body
     }
 }
 
+/// Adds the bytes held by an adapter, e.g. a pending chunk, to the size hint of the body it wraps.
+pub(crate) fn size_hint_with_held(inner_hint: SizeHint, held: u64) -> SizeHint {
+    let mut hint = SizeHint::new();
+    hint.set_lower(inner_hint.lower() + held);
+    if let Some(upper) = inner_hint.upper() {
+        hint.set_upper(upper + held);
+    }
+    hint
+}
+
+impl StreamBody {
+    /// Polls the source of the body, the fused behavior is taken care of by `poll_data`.
+    fn poll_inner_data(&mut self, cx: &mut Context) -> Poll<Option<Result<StreamData, io::Error>>> {
+        match self.inner {
+            Inner::Source(ref mut inner) => Pin::new(inner).poll_data(cx),
+            Inner::Wrapped(ref mut body) => body.as_mut().poll_data(cx),
+            #[cfg(feature = "http-body-1")]
+            Inner::Frames(ref mut inner) => inner.poll_data(cx),
+        }
+    }
+}
+
+/// Copies the reader into the writer half of a channel body, logging any error.
+#[cfg(feature = "tokio-rt")]
+pub(crate) async fn pipe_reader<R: AsyncRead + Unpin>(mut r: R, mut w: PipeWriter) {
+    if let Err(err) = io::copy(&mut r, &mut w).await {
+        log::error!(
+            "{}: StreamBody: Something went wrong while piping the provided reader to the body: {}",
+            env!("CARGO_PKG_NAME"),
+            err
+        )
+    }
+}
+
 impl Body for StreamBody {
     type Data = StreamData;
     type Error = io::Error;
 
     fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
-        match self.inner {
-            Inner::Once(ref mut inner) => {
-                let mut state;
-                match inner.state.lock() {
-                    Ok(s) => state = s,
-                    Err(err) => {
-                        return Poll::Ready(Some(Err(io::Error::new(
-                            io::ErrorKind::Other,
-                            format!(
-                                "{}: StreamBody [Once Data]: Failed to lock the stream state on poll data: {}",
-                                env!("CARGO_PKG_NAME"),
-                                err
-                            ),
-                        ))));
-                    }
-                }
-
-                if !state.is_current_stream_data_consumed {
-                    state.waker = Some(cx.waker().clone());
-                    return Poll::Pending;
-                }
-
-                if inner.reached_eof {
-                    return Poll::Ready(None);
-                }
-
-                if let Some(ref bytes) = inner.data {
-                    state.is_current_stream_data_consumed = false;
-                    inner.reached_eof = true;
-
-                    let data = StreamData::new(&bytes[..], Arc::clone(&inner.state));
-
-                    return Poll::Ready(Some(Ok(data)));
-                }
-
-                return Poll::Ready(None);
-            }
-            Inner::Channel(ref mut inner) => {
-                let mut inner_me = Pin::new(inner).project();
-
-                let mut state;
-                match inner_me.state.lock() {
-                    Ok(s) => state = s,
-                    Err(err) => {
-                        return Poll::Ready(Some(Err(io::Error::new(
-                            io::ErrorKind::Other,
-                            format!(
-                                "{}: StreamBody [Channel Data]: Failed to lock the stream state on poll data: {}",
-                                env!("CARGO_PKG_NAME"),
-                                err
-                            ),
-                        ))));
-                    }
-                }
-
-                if !state.is_current_stream_data_consumed {
-                    state.waker = Some(cx.waker().clone());
-                    return Poll::Pending;
-                }
-
-                if *inner_me.reached_eof {
-                    return Poll::Ready(None);
-                }
-
-                let buf: &mut Box<[u8]> = &mut inner_me.buf;
-                let poll_status = inner_me.reader.poll_read(cx, &mut buf[..]);
-
-                match poll_status {
-                    Poll::Pending => Poll::Pending,
-                    Poll::Ready(result) => match result {
-                        Ok(read_count) if read_count > 0 => {
-                            state.is_current_stream_data_consumed = false;
-
-                            let data = StreamData::new(&buf[..read_count], Arc::clone(&inner_me.state));
-                            Poll::Ready(Some(Ok(data)))
-                        }
-                        Ok(_) => {
-                            *inner_me.reached_eof = true;
-                            Poll::Ready(None)
-                        }
-                        Err(err) => Poll::Ready(Some(Err(err))),
-                    },
-                }
+        if self.terminated {
+            return Poll::Ready(None);
+        }
+
+        if let Some(ref shared) = self.abort {
+            if let Some(err) = abort::take_abort_error(shared, cx) {
+                self.terminated = true;
+                return Poll::Ready(Some(Err(err)));
             }
         }
+
+        let poll_status = self.poll_inner_data(cx);
+        if let Poll::Ready(None) | Poll::Ready(Some(Err(_))) = poll_status {
+            self.terminated = true;
+        }
+        poll_status
     }
 
     fn poll_trailers(
-        self: Pin<&mut Self>,
-        _cx: &mut Context,
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
     ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
-        Poll::Ready(Ok(None))
+        match self.inner {
+            Inner::Wrapped(ref mut body) => body.as_mut().poll_trailers(cx),
+            #[cfg(feature = "http-body-1")]
+            Inner::Frames(ref mut inner) => inner.poll_trailers(),
+            _ => Poll::Ready(Ok(None)),
+        }
     }
 
     fn is_end_stream(&self) -> bool {
+        if self.terminated {
+            // An adapter may still have trailers to yield after its data, which the server only polls if the body
+            // isn't reported as ended.
+            return match self.inner {
+                Inner::Wrapped(ref body) => body.is_end_stream(),
+                #[cfg(feature = "http-body-1")]
+                Inner::Frames(ref inner) => inner.is_end_stream(),
+                _ => true,
+            };
+        }
+
         match self.inner {
-            Inner::Once(ref inner) => inner.reached_eof,
-            Inner::Channel(ref inner) => inner.reached_eof,
+            Inner::Source(ref inner) => inner.is_end_stream(),
+            Inner::Wrapped(ref body) => body.is_end_stream(),
+            #[cfg(feature = "http-body-1")]
+            Inner::Frames(ref inner) => inner.is_end_stream(),
         }
     }
 
     fn size_hint(&self) -> SizeHint {
+        if self.terminated {
+            return SizeHint::with_exact(0);
+        }
+
         match self.inner {
-            Inner::Once(ref inner) => match inner.data {
-                Some(ref data) => SizeHint::with_exact(data.len() as u64),
-                None => SizeHint::with_exact(0),
-            },
-            Inner::Channel(_) => SizeHint::default(),
+            Inner::Source(ref inner) => inner.size_hint(),
+            Inner::Wrapped(ref body) => body.size_hint(),
+            #[cfg(feature = "http-body-1")]
+            Inner::Frames(ref inner) => inner.size_hint(),
         }
     }
 }
@@ -238,16 +462,7 @@ impl From<Bytes> for StreamBody {
         if chunk.is_empty() {
             StreamBody::empty()
         } else {
-            StreamBody {
-                inner: Inner::Once(OnceInner {
-                    data: Some(chunk),
-                    reached_eof: false,
-                    state: Arc::new(Mutex::new(State {
-                        is_current_stream_data_consumed: true,
-                        waker: None,
-                    })),
-                }),
-            }
+            StreamBody::once(Some(chunk), "bytes")
         }
     }
 }
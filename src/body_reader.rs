@@ -0,0 +1,446 @@
+use crate::forward::into_io_error;
+use bytes::{Buf, Bytes, BytesMut};
+use http_body::Body;
+use std::error::Error;
+use std::future::poll_fn;
+use std::marker::Unpin;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{self, AsyncBufRead, AsyncRead, AsyncWrite, AsyncWriteExt};
+
+/// A reader over the data of a body, typically an incoming request body like `hyper::Body`, which turns it into an
+/// [AsyncRead](https://docs.rs/tokio/0.2.16/tokio/io/trait.AsyncRead.html) and offers helpers to read framed
+/// uploads without hand-rolling partial-read state machines.
+///
+/// The body is only polled when the reader is, so a slow handler applies backpressure to the client, and the chunks
+/// are consumed in place without being buffered beyond the frame being read. It also implements
+/// [AsyncBufRead](https://docs.rs/tokio/0.2.16/tokio/io/trait.AsyncBufRead.html) on top of the chunks of the body,
+/// without a buffer of its own, and [copy_to](#method.copy_to) writes an upload to a file without an intermediate
+/// copy.
+///
+/// # Examples
+///
+/// ```no_run
+/// use hyper::{Body, Request};
+/// use stream_body::BodyReader;
+///
+/// # async fn run(req: Request<Body>) -> std::io::Result<()> {
+/// let mut reader = BodyReader::new(req.into_body());
+///
+/// while let Some(message) = reader.read_frame(64 * 1024).await? {
+///     println!("received a message of {} bytes", message.len());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct BodyReader<B: Body> {
+    body: B,
+    // The chunk being read, which is dropped once fully consumed.
+    chunk: Option<B::Data>,
+    reached_eof: bool,
+}
+
+impl<B> BodyReader<B>
+where
+    B: Body + Unpin,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    /// Creates a reader over the data of the body.
+    pub fn new(body: B) -> BodyReader<B> {
+        BodyReader {
+            body,
+            chunk: None,
+            reached_eof: false,
+        }
+    }
+
+    /// Returns a reference to the body.
+    pub fn get_ref(&self) -> &B {
+        &self.body
+    }
+
+    /// Consumes the reader and returns the body. The unread part of the current chunk is lost.
+    pub fn into_inner(self) -> B {
+        self.body
+    }
+
+    /// Writes the rest of the body to `writer` and flushes it, returning the number of bytes written.
+    ///
+    /// Unlike `tokio::io::copy`, the chunks of the body are written as they are instead of being copied into an
+    /// intermediate buffer first, and every chunk is dropped once written, so a `StreamBody` reuses its buffer.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use hyper::{Body, Request};
+    /// use stream_body::BodyReader;
+    /// use tokio::fs::File;
+    ///
+    /// # async fn run(req: Request<Body>) -> std::io::Result<()> {
+    /// let mut f = File::create("upload").await?;
+    /// let written = BodyReader::new(req.into_body()).copy_to(&mut f).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn copy_to<W: AsyncWrite + Unpin + ?Sized>(&mut self, writer: &mut W) -> io::Result<u64> {
+        let mut written = 0;
+        while poll_fn(|cx| self.poll_fill(cx)).await? {
+            let chunk = match self.chunk {
+                Some(ref mut chunk) => chunk,
+                None => unreachable!("a filled reader has a chunk"),
+            };
+
+            let len = chunk.bytes().len();
+            writer.write_all(chunk.bytes()).await?;
+            chunk.advance(len);
+            written += len as u64;
+        }
+
+        writer.flush().await?;
+        Ok(written)
+    }
+
+    /// Reads a message prefixed by its length as a big-endian `u32`, e.g. a gRPC-like or a custom binary framing.
+    ///
+    /// It returns `None` if the body ends right before a frame, fails with an `UnexpectedEof` error if the body ends
+    /// within a frame, and with an `InvalidData` error if the announced length exceeds `max_len`, in which case the
+    /// payload is left unread.
+    pub async fn read_frame(&mut self, max_len: usize) -> io::Result<Option<Bytes>> {
+        let prefix = self.read_up_to(4).await?;
+        if prefix.is_empty() {
+            return Ok(None);
+        }
+        if prefix.len() < 4 {
+            return Err(unexpected_eof("a frame length prefix"));
+        }
+
+        let len = u32::from_be_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize;
+        if len > max_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: BodyReader: A frame of {} bytes exceeds the maximum length of {} bytes",
+                    env!("CARGO_PKG_NAME"),
+                    len,
+                    max_len
+                ),
+            ));
+        }
+
+        let payload = self.read_up_to(len).await?;
+        if payload.len() < len {
+            return Err(unexpected_eof("a frame payload"));
+        }
+        Ok(Some(payload))
+    }
+
+    /// Reads exactly `len` bytes, failing with an `UnexpectedEof` error if the body ends before.
+    pub async fn read_exact_bytes(&mut self, len: usize) -> io::Result<Bytes> {
+        let data = self.read_up_to(len).await?;
+        if data.len() < len {
+            return Err(unexpected_eof("the requested bytes"));
+        }
+        Ok(data)
+    }
+
+    /// Reads a record terminated by `delimiter`, e.g. `b"\n"` or `b"\r\n"`, and returns it without the delimiter.
+    ///
+    /// The last record doesn't need a trailing delimiter, and `None` is returned once the body is fully read. A record
+    /// longer than `max_len` bytes fails the read with an `InvalidData` error, the rest of the record being left unread.
+    ///
+    /// # Panics
+    ///
+    /// It panics if `delimiter` is empty.
+    pub async fn read_until(&mut self, delimiter: &[u8], max_len: usize) -> io::Result<Option<Bytes>> {
+        assert!(!delimiter.is_empty(), "the delimiter must not be empty");
+
+        let mut record = BytesMut::new();
+        loop {
+            if !poll_fn(|cx| self.poll_fill(cx)).await? {
+                return Ok(if record.is_empty() { None } else { Some(record.freeze()) });
+            }
+
+            let chunk = match self.chunk {
+                Some(ref mut chunk) => chunk,
+                None => unreachable!("a filled reader has a chunk"),
+            };
+
+            // Never buffers more than a record of the maximum length followed by the delimiter.
+            let prev_len = record.len();
+            let take = chunk.bytes().len().min(max_len + delimiter.len() - prev_len);
+            record.extend_from_slice(&chunk.bytes()[..take]);
+
+            // The delimiter may straddle two chunks.
+            let search_from = prev_len.saturating_sub(delimiter.len() - 1);
+            let found = record[search_from..]
+                .windows(delimiter.len())
+                .position(|window| window == delimiter)
+                .map(|pos| search_from + pos);
+
+            if let Some(pos) = found {
+                chunk.advance(pos + delimiter.len() - prev_len);
+                record.truncate(pos);
+                return Ok(Some(record.freeze()));
+            }
+
+            chunk.advance(take);
+            if record.len() == max_len + delimiter.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{}: BodyReader: A record exceeds the maximum length of {} bytes",
+                        env!("CARGO_PKG_NAME"),
+                        max_len
+                    ),
+                ));
+            }
+        }
+    }
+
+    /// Turns the reader into a [Stream](https://docs.rs/futures-core/0.3/futures_core/stream/trait.Stream.html) of the
+    /// lines of the body, e.g. for a log ingestion endpoint or the preview of a CSV upload. It requires the `futures`
+    /// feature.
+    ///
+    /// The lines are returned without their `\n` or `\r\n` ending, and the last line doesn't need one. A line which
+    /// isn't valid UTF-8 yields an error of kind `InvalidData` and the stream continues with the next line. A line
+    /// longer than `max_len` bytes yields an `InvalidData` error and ends the stream, as does a body error. At most one
+    /// line is buffered at a time.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use futures_util::stream::StreamExt;
+    /// use hyper::{Body, Request};
+    /// use stream_body::BodyReader;
+    ///
+    /// # async fn run(req: Request<Body>) -> std::io::Result<()> {
+    /// let mut lines = BodyReader::new(req.into_body()).lines(64 * 1024);
+    ///
+    /// while let Some(line) = lines.next().await {
+    ///     println!("{}", line?);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "futures")]
+    pub fn lines(self, max_len: usize) -> BodyLines<B> {
+        BodyLines {
+            reader: self,
+            line: Vec::new(),
+            max_len,
+            terminated: false,
+        }
+    }
+
+    /// Reads up to `len` bytes, fewer only if the body ends before.
+    async fn read_up_to(&mut self, len: usize) -> io::Result<Bytes> {
+        let mut data = BytesMut::with_capacity(len);
+        while data.len() < len {
+            if !poll_fn(|cx| self.poll_fill(cx)).await? {
+                break;
+            }
+
+            if let Some(ref mut chunk) = self.chunk {
+                let take = chunk.bytes().len().min(len - data.len());
+                data.extend_from_slice(&chunk.bytes()[..take]);
+                chunk.advance(take);
+            }
+        }
+        Ok(data.freeze())
+    }
+
+    /// Makes sure the current chunk has data left, returning `false` once the body is fully read.
+    fn poll_fill(&mut self, cx: &mut Context) -> Poll<io::Result<bool>> {
+        loop {
+            if let Some(ref chunk) = self.chunk {
+                if chunk.has_remaining() {
+                    return Poll::Ready(Ok(true));
+                }
+            }
+            // Dropping a consumed chunk lets a `StreamBody` reuse its buffer.
+            self.chunk = None;
+
+            if self.reached_eof {
+                return Poll::Ready(Ok(false));
+            }
+
+            match ready!(Pin::new(&mut self.body).poll_data(cx)) {
+                Some(Ok(chunk)) => self.chunk = Some(chunk),
+                Some(Err(err)) => {
+                    self.reached_eof = true;
+                    return Poll::Ready(Err(into_io_error(err)));
+                }
+                None => self.reached_eof = true,
+            }
+        }
+    }
+}
+
+// The chunk is never pinned, so the reader can be moved whenever the body can.
+impl<B: Body + Unpin> Unpin for BodyReader<B> {}
+
+/// The [Stream](https://docs.rs/futures-core/0.3/futures_core/stream/trait.Stream.html) of the lines of a body,
+/// created via [BodyReader::lines](./struct.BodyReader.html#method.lines).
+#[cfg(feature = "futures")]
+pub struct BodyLines<B: Body> {
+    reader: BodyReader<B>,
+    // The line being read, including its `\r` ending if any.
+    line: Vec<u8>,
+    max_len: usize,
+    terminated: bool,
+}
+
+#[cfg(feature = "futures")]
+impl<B> BodyLines<B>
+where
+    B: Body + Unpin,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    /// Returns the reader, e.g. to read the rest of the body differently. The line being read is lost.
+    pub fn into_inner(self) -> BodyReader<B> {
+        self.reader
+    }
+
+    fn take_line(&mut self) -> io::Result<String> {
+        let mut line = std::mem::take(&mut self.line);
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        if line.len() > self.max_len {
+            return Err(self.line_too_long());
+        }
+
+        String::from_utf8(line).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: BodyLines: A line isn't valid UTF-8: {}",
+                    env!("CARGO_PKG_NAME"),
+                    err
+                ),
+            )
+        })
+    }
+
+    fn line_too_long(&mut self) -> io::Error {
+        self.terminated = true;
+
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{}: BodyLines: A line exceeds the maximum length of {} bytes",
+                env!("CARGO_PKG_NAME"),
+                self.max_len
+            ),
+        )
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<B> futures_core::Stream for BodyLines<B>
+where
+    B: Body + Unpin,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    type Item = io::Result<String>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let me = &mut *self;
+
+        loop {
+            if me.terminated {
+                return Poll::Ready(None);
+            }
+
+            match ready!(me.reader.poll_fill(cx)) {
+                Ok(true) => {}
+                Ok(false) => {
+                    me.terminated = true;
+                    if me.line.is_empty() {
+                        return Poll::Ready(None);
+                    }
+                    return Poll::Ready(Some(me.take_line()));
+                }
+                Err(err) => {
+                    me.terminated = true;
+                    return Poll::Ready(Some(Err(err)));
+                }
+            }
+
+            let chunk = match me.reader.chunk {
+                Some(ref mut chunk) => chunk,
+                None => unreachable!("a filled reader has a chunk"),
+            };
+
+            // Never buffers more than a line of the maximum length followed by a `\r`.
+            let room = (me.max_len + 1).saturating_sub(me.line.len());
+            let bytes = chunk.bytes();
+            match bytes.iter().position(|&b| b == b'\n') {
+                Some(pos) if pos <= room => {
+                    me.line.extend_from_slice(&bytes[..pos]);
+                    chunk.advance(pos + 1);
+                    return Poll::Ready(Some(me.take_line()));
+                }
+                _ if bytes.len() > room => return Poll::Ready(Some(Err(me.line_too_long()))),
+                _ => {
+                    let len = bytes.len();
+                    me.line.extend_from_slice(bytes);
+                    chunk.advance(len);
+                }
+            }
+        }
+    }
+}
+
+fn unexpected_eof(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        format!("{}: BodyReader: The body ended within {}", env!("CARGO_PKG_NAME"), what),
+    )
+}
+
+impl<B> AsyncRead for BodyReader<B>
+where
+    B: Body + Unpin,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        if buf.is_empty() || !ready!(self.poll_fill(cx))? {
+            return Poll::Ready(Ok(0));
+        }
+
+        let chunk = match self.chunk {
+            Some(ref mut chunk) => chunk,
+            None => return Poll::Ready(Ok(0)),
+        };
+
+        let read_count = chunk.bytes().len().min(buf.len());
+        buf[..read_count].copy_from_slice(&chunk.bytes()[..read_count]);
+        chunk.advance(read_count);
+        Poll::Ready(Ok(read_count))
+    }
+}
+
+impl<B> AsyncBufRead for BodyReader<B>
+where
+    B: Body + Unpin,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<&[u8]>> {
+        let me = self.get_mut();
+        if !ready!(me.poll_fill(cx))? {
+            return Poll::Ready(Ok(&[]));
+        }
+
+        match me.chunk {
+            Some(ref chunk) => Poll::Ready(Ok(chunk.bytes())),
+            None => Poll::Ready(Ok(&[])),
+        }
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        if let Some(ref mut chunk) = self.chunk {
+            chunk.advance(amt.min(chunk.remaining()));
+        }
+    }
+}
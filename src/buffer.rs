@@ -0,0 +1,44 @@
+use bytes::{Bytes, BytesMut};
+
+/// The read buffer of a body, whose chunks are handed out as reference-counted slices of it.
+///
+/// Chunks own their memory, so a chunk kept past the lifetime of the body stays valid. Once all the chunks are
+/// dropped the allocation is reclaimed for the next reads, so the buffer is reused without copying the data.
+#[derive(Default)]
+pub(crate) struct ReusableBuf {
+    buf: BytesMut,
+    capacity: usize,
+}
+
+impl ReusableBuf {
+    pub(crate) fn new(capacity: usize) -> ReusableBuf {
+        let mut buf = BytesMut::with_capacity(capacity);
+        buf.resize(capacity, 0);
+        ReusableBuf { buf, capacity }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes the capacity, which applies from the next chunk on.
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
+    /// Returns the buffer to read the next chunk into.
+    pub(crate) fn prepare(&mut self) -> &mut [u8] {
+        if self.buf.len() < self.capacity {
+            // Reclaims the allocation if no chunk holds it anymore, a new one is allocated otherwise.
+            self.buf.clear();
+            self.buf.reserve(self.capacity);
+            self.buf.resize(self.capacity, 0);
+        }
+        &mut self.buf[..self.capacity]
+    }
+
+    /// Splits the first `len` bytes read into the prepared buffer off as a chunk.
+    pub(crate) fn split(&mut self, len: usize) -> Bytes {
+        self.buf.split_to(len).freeze()
+    }
+}
@@ -0,0 +1,241 @@
+use std::future::Future;
+use std::marker::Unpin;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{self, AsyncWrite};
+#[cfg(feature = "tokio-rt")]
+use tokio::runtime::Handle;
+
+const DEFAULT_BUF_SIZE: usize = 8 * 1024;
+
+/// A buffered [AsyncWrite](https://docs.rs/tokio/0.2.16/tokio/io/trait.AsyncWrite.html) for the producer side of
+/// a body, e.g. the writer half of [StreamBody::channel](./struct.StreamBody.html#method.channel).
+///
+/// Producers doing many tiny writes get them batched into writes of up to `capacity` bytes, independently of the
+/// body's read buffer. Writes larger than the capacity bypass the buffer. Call `flush()` to emit the buffered data
+/// right away, e.g. at message boundaries.
+///
+/// Data still buffered when the writer is dropped is written and flushed by a task spawned on the current tokio
+/// runtime, which requires the default `tokio-rt` feature. Prefer calling `shutdown()` or `flush()` explicitly to get
+/// notified about write errors.
+///
+/// # Examples
+///
+/// ```no_run
+/// use stream_body::{BufferedWriter, StreamBody};
+/// use tokio::io::AsyncWriteExt;
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let (writer, body) = StreamBody::channel();
+/// let mut writer = BufferedWriter::with_capacity(16 * 1024, writer);
+///
+/// for i in 0..1000 {
+///     writer.write_all(format!("line {}\n", i).as_bytes()).await?;
+/// }
+/// writer.shutdown().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct BufferedWriter<W: AsyncWrite + Unpin + Send + 'static> {
+    // Only taken by `into_inner`.
+    inner: Option<W>,
+    buf: Vec<u8>,
+    // The position up to which the buffer is already written to the inner writer.
+    pos: usize,
+    // Whether a write of the buffer to the inner writer is pending. The buffer must not change until it completes,
+    // as writers like the channel writer keep pointing to the pending data.
+    draining: bool,
+}
+
+impl<W: AsyncWrite + Unpin + Send + 'static> BufferedWriter<W> {
+    /// Creates a writer with a buffer of 8 KiB.
+    pub fn new(inner: W) -> BufferedWriter<W> {
+        BufferedWriter::with_capacity(DEFAULT_BUF_SIZE, inner)
+    }
+
+    /// Creates a writer with a buffer of the given capacity.
+    pub fn with_capacity(capacity: usize, inner: W) -> BufferedWriter<W> {
+        BufferedWriter {
+            inner: Some(inner),
+            buf: Vec::with_capacity(capacity.max(1)),
+            pos: 0,
+            draining: false,
+        }
+    }
+
+    /// Returns a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().expect("inner writer is present until into_inner")
+    }
+
+    /// Returns the buffered data which isn't written to the inner writer yet.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf[self.pos..]
+    }
+
+    /// Returns the capacity of the buffer.
+    pub fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+
+    /// Consumes the writer and returns the inner writer. Buffered data which isn't flushed yet is lost.
+    pub fn into_inner(mut self) -> W {
+        self.buf.clear();
+        self.pos = 0;
+        self.inner.take().expect("inner writer is present until into_inner")
+    }
+
+    // Writes the buffered data to the inner writer.
+    fn poll_drain(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        let inner = self.inner.as_mut().expect("inner writer is present until into_inner");
+        let poll_status = poll_write_buf(inner, &mut self.buf, &mut self.pos, cx);
+        self.draining = poll_status.is_pending();
+        poll_status
+    }
+}
+
+fn poll_write_buf<W: AsyncWrite + Unpin>(
+    inner: &mut W,
+    buf: &mut Vec<u8>,
+    pos: &mut usize,
+    cx: &mut Context,
+) -> Poll<io::Result<()>> {
+    loop {
+        if *pos >= buf.len() {
+            buf.clear();
+            *pos = 0;
+            return Poll::Ready(Ok(()));
+        }
+
+        match Pin::new(&mut *inner).poll_write(cx, &buf[*pos..]) {
+            Poll::Ready(Ok(0)) => {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    format!(
+                        "{}: BufferedWriter: Failed to write the buffered data to the inner writer",
+                        env!("CARGO_PKG_NAME")
+                    ),
+                )))
+            }
+            Poll::Ready(Ok(n)) => *pos += n,
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Pending => return Poll::Pending,
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin + Send + 'static> AsyncWrite for BufferedWriter<W> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        if self.draining || self.buf.len() + buf.len() > self.buf.capacity() {
+            match self.poll_drain(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        if buf.len() >= self.buf.capacity() {
+            let inner = self.inner.as_mut().expect("inner writer is present until into_inner");
+            return Pin::new(inner).poll_write(cx, buf);
+        }
+
+        self.buf.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        match self.poll_drain(cx) {
+            Poll::Ready(Ok(())) => {
+                let inner = self.inner.as_mut().expect("inner writer is present until into_inner");
+                Pin::new(inner).poll_flush(cx)
+            }
+            poll_status => poll_status,
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        match self.poll_drain(cx) {
+            Poll::Ready(Ok(())) => {
+                let inner = self.inner.as_mut().expect("inner writer is present until into_inner");
+                Pin::new(inner).poll_shutdown(cx)
+            }
+            poll_status => poll_status,
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin + Send + 'static> Drop for BufferedWriter<W> {
+    fn drop(&mut self) {
+        let inner = match self.inner.take() {
+            Some(inner) if self.pos < self.buf.len() => inner,
+            _ => return,
+        };
+
+        // Moving the buffer keeps its heap allocation, so a pending write still points to valid data.
+        let flush = FlushOnDrop {
+            inner,
+            buf: std::mem::take(&mut self.buf),
+            pos: self.pos,
+            flushing: false,
+        };
+
+        #[cfg(feature = "tokio-rt")]
+        match Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(flush);
+            }
+            Err(_) => log::error!(
+                "{}: BufferedWriter: Dropped outside of a tokio runtime, {} buffered bytes are lost",
+                env!("CARGO_PKG_NAME"),
+                flush.buf.len() - flush.pos
+            ),
+        }
+
+        #[cfg(not(feature = "tokio-rt"))]
+        log::error!(
+            "{}: BufferedWriter: Dropped without the tokio-rt feature, {} buffered bytes are lost",
+            env!("CARGO_PKG_NAME"),
+            flush.buf.len() - flush.pos
+        );
+    }
+}
+
+struct FlushOnDrop<W> {
+    inner: W,
+    buf: Vec<u8>,
+    pos: usize,
+    flushing: bool,
+}
+
+impl<W: AsyncWrite + Unpin> Future for FlushOnDrop<W> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let me = &mut *self;
+
+        let result = if me.flushing {
+            Pin::new(&mut me.inner).poll_flush(cx)
+        } else {
+            match poll_write_buf(&mut me.inner, &mut me.buf, &mut me.pos, cx) {
+                Poll::Ready(Ok(())) => {
+                    me.flushing = true;
+                    Pin::new(&mut me.inner).poll_flush(cx)
+                }
+                poll_status => poll_status,
+            }
+        };
+
+        match result {
+            Poll::Ready(Ok(())) => Poll::Ready(()),
+            Poll::Ready(Err(err)) => {
+                log::error!(
+                    "{}: BufferedWriter: Failed to flush the buffered data on drop: {}",
+                    env!("CARGO_PKG_NAME"),
+                    err
+                );
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
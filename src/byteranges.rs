@@ -0,0 +1,375 @@
+use crate::body::StreamBody;
+use crate::buffer::ReusableBuf;
+use crate::data::StreamData;
+use crate::range::ByteRange;
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::SeekFrom;
+use std::marker::Unpin;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::vec;
+use tokio::io::{self, AsyncRead, AsyncSeek};
+
+const DEFAULT_BUF_SIZE: usize = 8 * 1024;
+
+/// A builder of `multipart/byteranges` bodies, which answer requests for several ranges, e.g.
+/// `Range: bytes=0-99,200-299`, as described in [RFC 7233](https://tools.ietf.org/html/rfc7233#appendix-A).
+///
+/// Every range is emitted as a part with its own `Content-Range` header, its bytes being read from an
+/// [AsyncRead](https://docs.rs/tokio/0.2.16/tokio/io/trait.AsyncRead.html) +
+/// [AsyncSeek](https://docs.rs/tokio/0.2.16/tokio/io/trait.AsyncSeek.html) on the task polling the body. As the
+/// framing is known upfront, the total length of the payload is known as well, so the body has an exact size hint.
+///
+/// # Examples
+///
+/// ```no_run
+/// use hyper::{Response, StatusCode};
+/// use stream_body::{MultipartByteRanges, RangeDecision, StreamBody};
+/// use tokio::fs::File;
+///
+/// # async fn run(range_header: &str) -> std::io::Result<Response<StreamBody>> {
+/// let f = File::open("video.mp4").await?;
+/// let len = f.metadata().await?.len();
+///
+/// if let RangeDecision::Partial(ranges) = RangeDecision::from_header(range_header, len) {
+///     let byte_ranges = MultipartByteRanges::new(f, len, ranges).with_content_type("video/mp4".parse().unwrap());
+///
+///     let res = Response::builder()
+///         .status(StatusCode::PARTIAL_CONTENT)
+///         .header("content-type", byte_ranges.content_type())
+///         .header("content-length", byte_ranges.content_length())
+///         .body(byte_ranges.into_body())
+///         .unwrap();
+///     return Ok(res);
+/// }
+/// # unimplemented!()
+/// # }
+/// ```
+pub struct MultipartByteRanges<R> {
+    reader: R,
+    total_len: u64,
+    ranges: Vec<ByteRange>,
+    part_content_type: Option<HeaderValue>,
+    boundary: String,
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin + Send + 'static> MultipartByteRanges<R> {
+    /// Creates a builder of a body with the given ranges of a representation of `total_len` bytes read from `reader`,
+    /// e.g. the ranges of a [RangeDecision::Partial](./enum.RangeDecision.html#variant.Partial). A random boundary is
+    /// generated.
+    pub fn new(reader: R, total_len: u64, ranges: Vec<ByteRange>) -> MultipartByteRanges<R> {
+        MultipartByteRanges {
+            reader,
+            total_len,
+            ranges,
+            part_content_type: None,
+            boundary: random_boundary(),
+        }
+    }
+
+    /// Sets the `Content-Type` header of every part, i.e. the type of the representation.
+    pub fn with_content_type(mut self, content_type: HeaderValue) -> MultipartByteRanges<R> {
+        self.part_content_type = Some(content_type);
+        self
+    }
+
+    /// Sets the boundary delimiting the parts, which must not occur in the data of the ranges.
+    ///
+    /// # Panics
+    ///
+    /// It panics if the boundary isn't valid as per [RFC 2046](https://tools.ietf.org/html/rfc2046#section-5.1.1),
+    /// i.e. 1 to 70 characters among the digits, the letters and `'()+_,-./:=? `, not ending with a space.
+    pub fn with_boundary<B: Into<String>>(mut self, boundary: B) -> MultipartByteRanges<R> {
+        let boundary = boundary.into();
+        assert!(
+            is_valid_boundary(&boundary),
+            "invalid multipart boundary: {:?}",
+            boundary
+        );
+        self.boundary = boundary;
+        self
+    }
+
+    /// Returns the boundary delimiting the parts.
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// Returns the `Content-Type` header value of the response, i.e. `multipart/byteranges; boundary=<boundary>`.
+    pub fn content_type(&self) -> HeaderValue {
+        // Some of the boundary characters aren't allowed in a token, which is then quoted.
+        let value = if self
+            .boundary
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"'+_-.".contains(&b))
+        {
+            format!("multipart/byteranges; boundary={}", self.boundary)
+        } else {
+            format!("multipart/byteranges; boundary=\"{}\"", self.boundary)
+        };
+        HeaderValue::from_str(&value).expect("valid Content-Type header value")
+    }
+
+    /// Returns the total length of the payload, for the `Content-Length` header of the response.
+    pub fn content_length(&self) -> u64 {
+        let headers_len = (0..self.ranges.len())
+            .map(|index| self.part_header(index).len() as u64)
+            .sum::<u64>();
+        let ranges_len = self.ranges.iter().map(ByteRange::len).sum::<u64>();
+
+        headers_len + ranges_len + self.closing_delimiter().len() as u64
+    }
+
+    /// Creates the body emitting the framed parts.
+    pub fn into_body(self) -> StreamBody {
+        let remaining = self.content_length();
+        let parts = (0..self.ranges.len())
+            .map(|index| (self.part_header(index), self.ranges[index]))
+            .collect::<Vec<_>>();
+        let closing = self.closing_delimiter();
+
+        let mut body = StreamBody::wrap(ByteRanges {
+            reader: self.reader,
+            parts: parts.into_iter(),
+            closing: Some(closing),
+            step: Step::NextPart,
+            buf: ReusableBuf::new(DEFAULT_BUF_SIZE),
+            remaining,
+        });
+        body.source = "byteranges";
+        body
+    }
+
+    /// Returns the delimiter and the headers preceding the data of a part.
+    fn part_header(&self, index: usize) -> Bytes {
+        let range = self.ranges[index];
+
+        // The first delimiter starts the payload, the other ones end the data of the previous part.
+        let mut header = if index == 0 {
+            format!("--{}\r\n", self.boundary).into_bytes()
+        } else {
+            format!("\r\n--{}\r\n", self.boundary).into_bytes()
+        };
+        if let Some(ref content_type) = self.part_content_type {
+            header.extend_from_slice(b"Content-Type: ");
+            header.extend_from_slice(content_type.as_bytes());
+            header.extend_from_slice(b"\r\n");
+        }
+        header.extend_from_slice(
+            format!(
+                "Content-Range: bytes {}-{}/{}\r\n\r\n",
+                range.start, range.end, self.total_len
+            )
+            .as_bytes(),
+        );
+
+        Bytes::from(header)
+    }
+
+    fn closing_delimiter(&self) -> Bytes {
+        Bytes::from(format!("\r\n--{}--\r\n", self.boundary))
+    }
+}
+
+fn is_valid_boundary(boundary: &str) -> bool {
+    let bytes = boundary.as_bytes();
+    (1..=70).contains(&bytes.len())
+        && !boundary.ends_with(' ')
+        && bytes
+            .iter()
+            .all(|b| b.is_ascii_alphanumeric() || b"'()+_,-./:=? ".contains(b))
+}
+
+// The hasher of a `RandomState` is randomly seeded, which is enough for a boundary without depending on a RNG.
+fn random_boundary() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u8(0);
+    format!("{:016x}", hasher.finish())
+}
+
+enum Step {
+    NextPart,
+    Seek { start: u64, len: u64, started: bool },
+    Read { remaining: u64 },
+    Done,
+}
+
+struct ByteRanges<R> {
+    reader: R,
+    parts: vec::IntoIter<(Bytes, ByteRange)>,
+    closing: Option<Bytes>,
+    step: Step,
+    buf: ReusableBuf,
+    // The number of bytes left to emit, for the size hint.
+    remaining: u64,
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> ByteRanges<R> {
+    fn emit(&mut self, data: Bytes) -> Poll<Option<io::Result<StreamData>>> {
+        self.remaining = self.remaining.saturating_sub(data.len() as u64);
+        Poll::Ready(Some(Ok(StreamData::from_bytes(data))))
+    }
+
+    fn poll_step(&mut self, cx: &mut Context) -> Poll<Option<io::Result<StreamData>>> {
+        loop {
+            match self.step {
+                Step::NextPart => match self.parts.next() {
+                    Some((header, range)) => {
+                        self.step = Step::Seek {
+                            start: range.start,
+                            len: range.len(),
+                            started: false,
+                        };
+                        return self.emit(header);
+                    }
+                    None => {
+                        self.step = Step::Done;
+                        if let Some(closing) = self.closing.take() {
+                            return self.emit(closing);
+                        }
+                    }
+                },
+                Step::Seek {
+                    start,
+                    len,
+                    ref mut started,
+                } => {
+                    if !*started {
+                        ready!(Pin::new(&mut self.reader).start_seek(cx, SeekFrom::Start(start)))?;
+                        *started = true;
+                    }
+                    ready!(Pin::new(&mut self.reader).poll_complete(cx))?;
+                    self.step = Step::Read { remaining: len };
+                }
+                Step::Read { remaining: 0 } => self.step = Step::NextPart,
+                Step::Read { remaining } => {
+                    let buf = self.buf.prepare();
+                    let limit = remaining.min(buf.len() as u64) as usize;
+
+                    let read_count = ready!(Pin::new(&mut self.reader).poll_read(cx, &mut buf[..limit]))?;
+                    if read_count == 0 {
+                        return Poll::Ready(Some(Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            format!(
+                                "{}: MultipartByteRanges: The reader ended {} bytes before the end of a range",
+                                env!("CARGO_PKG_NAME"),
+                                remaining
+                            ),
+                        ))));
+                    }
+
+                    self.step = Step::Read {
+                        remaining: remaining - read_count as u64,
+                    };
+                    let data = self.buf.split(read_count);
+                    return self.emit(data);
+                }
+                Step::Done => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> Body for ByteRanges<R> {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let poll_status = self.poll_step(cx);
+        if let Poll::Ready(Some(Err(_))) = poll_status {
+            // The body is fused after an error, the reader isn't polled again.
+            self.step = Step::Done;
+            self.remaining = 0;
+        }
+        poll_status
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Poll::Ready(Ok(None))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.remaining == 0
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.remaining)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn from_ranges(ranges: Vec<ByteRange>) -> MultipartByteRanges<Cursor<Vec<u8>>> {
+        let data = (0..100u8).collect::<Vec<_>>();
+        MultipartByteRanges::new(Cursor::new(data), 100, ranges)
+    }
+
+    async fn emitted(byte_ranges: MultipartByteRanges<Cursor<Vec<u8>>>) -> Vec<u8> {
+        hyper::body::to_bytes(byte_ranges.into_body()).await.unwrap().to_vec()
+    }
+
+    #[tokio::test]
+    async fn content_length_matches_the_emitted_bytes() {
+        let ranges = vec![ByteRange { start: 0, end: 9 }, ByteRange { start: 90, end: 99 }];
+
+        let byte_ranges = from_ranges(ranges.clone()).with_boundary("sep");
+        let len = byte_ranges.content_length();
+        let body = emitted(byte_ranges).await;
+        assert_eq!(body.len() as u64, len);
+
+        let mut expected = b"--sep\r\nContent-Range: bytes 0-9/100\r\n\r\n".to_vec();
+        expected.extend(0..10u8);
+        expected.extend_from_slice(b"\r\n--sep\r\nContent-Range: bytes 90-99/100\r\n\r\n");
+        expected.extend(90..100u8);
+        expected.extend_from_slice(b"\r\n--sep--\r\n");
+        assert_eq!(body, expected);
+
+        let byte_ranges = from_ranges(ranges).with_content_type(HeaderValue::from_static("video/mp4"));
+        let len = byte_ranges.content_length();
+        assert_eq!(emitted(byte_ranges).await.len() as u64, len);
+
+        let byte_ranges = from_ranges(vec![ByteRange { start: 42, end: 42 }]);
+        let len = byte_ranges.content_length();
+        assert_eq!(emitted(byte_ranges).await.len() as u64, len);
+    }
+
+    #[test]
+    fn boundaries_are_validated() {
+        assert!(is_valid_boundary("simple-boundary"));
+        assert!(is_valid_boundary("with spaces and (parens)"));
+        assert!(is_valid_boundary(&"a".repeat(70)));
+        assert!(is_valid_boundary(&random_boundary()));
+
+        assert!(!is_valid_boundary(""));
+        assert!(!is_valid_boundary(&"a".repeat(71)));
+        assert!(!is_valid_boundary("trailing "));
+        assert!(!is_valid_boundary("line\r\nbreak"));
+        assert!(!is_valid_boundary("quo\"te"));
+        assert!(!is_valid_boundary("semi;colon"));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid multipart boundary")]
+    fn invalid_boundaries_are_rejected() {
+        from_ranges(Vec::new()).with_boundary("bad\r\nboundary");
+    }
+
+    #[test]
+    fn boundaries_with_separators_are_quoted() {
+        let byte_ranges = from_ranges(Vec::new()).with_boundary("a:b");
+        assert_eq!(byte_ranges.content_type(), "multipart/byteranges; boundary=\"a:b\"");
+
+        let byte_ranges = byte_ranges.with_boundary("a_b");
+        assert_eq!(byte_ranges.content_type(), "multipart/byteranges; boundary=a_b");
+    }
+}
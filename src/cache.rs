@@ -0,0 +1,223 @@
+use crate::body::StreamBody;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{ready, Context, Poll};
+use tokio::fs::{self, File};
+use tokio::io::{self, AsyncWrite};
+use tokio::runtime::Handle;
+
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+type IoFuture<T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send>>;
+
+impl StreamBody {
+    /// Writes every emitted chunk to the cache file at `path` while the body is served, so an origin-pull cache can
+    /// be filled directly from the streaming path.
+    ///
+    /// The chunks are written to a temporary file next to `path`, which is renamed to `path` only once the body
+    /// reaches its end successfully. If the body fails or is dropped before its end, e.g. the client disconnects, the
+    /// temporary file is removed and `path` is left untouched. Errors while writing the cache file are logged via
+    /// [log::warn!](https://docs.rs/log/0.4.10/log/macro.warn.html) and never affect the response.
+    ///
+    /// It's built on [tee](#method.tee): every chunk is written to the file before it's emitted, so a disk slower
+    /// than the client slows the body down instead of queueing the chunks in memory.
+    pub fn tee_to_file<P: Into<PathBuf>>(self, path: P) -> StreamBody {
+        let path = path.into();
+
+        self.tee(CacheFile {
+            temp_path: temp_path(&path),
+            path,
+            state: CacheState::Idle,
+            committed: false,
+        })
+    }
+}
+
+/// The sink of a cache fill, writing to the temporary file and moving it into place on shutdown.
+struct CacheFile {
+    path: PathBuf,
+    temp_path: PathBuf,
+    state: CacheState,
+    // Whether the temporary file was renamed to `path`.
+    committed: bool,
+}
+
+enum CacheState {
+    Idle,
+    Opening(IoFuture<File>),
+    Open(File),
+    Committing(IoFuture<()>),
+    Done,
+}
+
+impl CacheFile {
+    /// Creates the temporary file on the first use.
+    fn poll_file(&mut self, cx: &mut Context) -> Poll<io::Result<&mut File>> {
+        if let CacheState::Idle = self.state {
+            self.state = CacheState::Opening(Box::pin(File::create(self.temp_path.clone())));
+        }
+
+        if let CacheState::Opening(ref mut open) = self.state {
+            match ready!(open.as_mut().poll(cx)) {
+                Ok(file) => self.state = CacheState::Open(file),
+                Err(err) => {
+                    self.state = CacheState::Done;
+                    return Poll::Ready(Err(err));
+                }
+            }
+        }
+
+        match self.state {
+            CacheState::Open(ref mut file) => Poll::Ready(Ok(file)),
+            _ => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::Other,
+                "The cache file is already closed",
+            ))),
+        }
+    }
+
+    fn error(&self, err: io::Error) -> io::Error {
+        io::Error::new(
+            err.kind(),
+            format!(
+                "{}: StreamBody: Failed to write the cache file {}: {}",
+                env!("CARGO_PKG_NAME"),
+                self.path.display(),
+                err
+            ),
+        )
+    }
+}
+
+impl AsyncWrite for CacheFile {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let me = &mut *self;
+
+        let result = match ready!(me.poll_file(cx)) {
+            Ok(file) => ready!(Pin::new(file).poll_write(cx, buf)),
+            Err(err) => Err(err),
+        };
+        Poll::Ready(result.map_err(|err| me.error(err)))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let me = &mut *self;
+
+        let result = match me.state {
+            CacheState::Open(ref mut file) => ready!(Pin::new(file).poll_flush(cx)),
+            _ => Ok(()),
+        };
+        Poll::Ready(result.map_err(|err| me.error(err)))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let me = &mut *self;
+
+        loop {
+            match me.state {
+                CacheState::Idle | CacheState::Opening(_) => {
+                    if let Err(err) = ready!(me.poll_file(cx)) {
+                        return Poll::Ready(Err(me.error(err)));
+                    }
+                }
+                CacheState::Open(_) => {
+                    if let CacheState::Open(file) = std::mem::replace(&mut me.state, CacheState::Done) {
+                        let commit = commit(file, me.temp_path.clone(), me.path.clone());
+                        me.state = CacheState::Committing(Box::pin(commit));
+                    }
+                }
+                CacheState::Committing(ref mut commit) => {
+                    let result = ready!(commit.as_mut().poll(cx));
+                    me.state = CacheState::Done;
+                    me.committed = result.is_ok();
+                    return Poll::Ready(result.map_err(|err| me.error(err)));
+                }
+                CacheState::Done => return Poll::Ready(Ok(())),
+            }
+        }
+    }
+}
+
+impl Drop for CacheFile {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+
+        // The body failed or was dropped before its end, so the partial file is discarded, once it's created if the
+        // creation is still in flight.
+        let opening = match std::mem::replace(&mut self.state, CacheState::Done) {
+            CacheState::Idle => return,
+            CacheState::Opening(open) => Some(open),
+            _ => None,
+        };
+        let temp_path = std::mem::take(&mut self.temp_path);
+
+        match Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    if let Some(open) = opening {
+                        let _ = open.await;
+                    }
+                    let _ = fs::remove_file(&temp_path).await;
+                });
+            }
+            // Outside of a runtime, an in-flight creation can't complete anymore, so whatever exists is removed now.
+            Err(_) => {
+                drop(opening);
+                let _ = std::fs::remove_file(&temp_path);
+            }
+        }
+    }
+}
+
+/// Syncs the temporary file and moves it into place.
+async fn commit(mut file: File, temp_path: PathBuf, path: PathBuf) -> io::Result<()> {
+    file.sync_all().await?;
+    drop(file);
+    fs::rename(temp_path, path).await
+}
+
+/// Returns a unique temporary path in the same directory as `path`, so the final rename stays on one file system.
+fn temp_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    let counter = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    path.with_file_name(format!(".{}.{}.{}.tmp", file_name, std::process::id(), counter))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn partial_files_are_removed_when_dropped_outside_of_a_runtime() {
+        let dir = std::env::temp_dir().join(format!("stream-body-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("partial");
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let cache = rt.block_on(async {
+            let mut cache = CacheFile {
+                temp_path: temp_path(&path),
+                path: path.clone(),
+                state: CacheState::Idle,
+                committed: false,
+            };
+            cache.write_all(b"partial").await.unwrap();
+            cache
+        });
+        drop(rt);
+
+        let temp_path = cache.temp_path.clone();
+        assert!(temp_path.exists());
+        drop(cache);
+        assert!(!temp_path.exists());
+        assert!(!path.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
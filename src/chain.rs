@@ -0,0 +1,113 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io;
+
+impl StreamBody {
+    /// Creates a body emitting the chunks of this body, then the chunks of `other`.
+    ///
+    /// See [concat](#method.concat) for the details.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use stream_body::StreamBody;
+    ///
+    /// # async fn run() -> std::io::Result<()> {
+    /// let fragment = tokio::fs::File::open("fragment.html").await?;
+    ///
+    /// let body = StreamBody::from("<html><body>")
+    ///     .chain(StreamBody::from_reader_inline(fragment))
+    ///     .chain(StreamBody::from("</body></html>"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn chain(self, other: StreamBody) -> StreamBody {
+        self.wrap_with(|inner| Concat {
+            parts: vec![inner, other].into(),
+        })
+    }
+
+    /// Creates a body emitting the chunks of every body of `parts`, in order, e.g. a generated header, a file and a
+    /// generated footer.
+    ///
+    /// Every part is only polled once the previous ones ended, and the first error ends the whole body. The size hint
+    /// is the sum of the size hints of the remaining parts, so it's exact when all of them are sized, and the
+    /// trailers are the ones of the last part.
+    pub fn concat(parts: Vec<StreamBody>) -> StreamBody {
+        let mut body = StreamBody::wrap(Concat { parts: parts.into() });
+        body.source = "concat";
+        body
+    }
+}
+
+struct Concat {
+    // The current part comes first, the last part is kept once it ended for its trailers.
+    parts: VecDeque<StreamBody>,
+}
+
+impl Body for Concat {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let parts = &mut self.parts;
+
+        loop {
+            let part = match parts.front_mut() {
+                Some(part) => part,
+                None => return Poll::Ready(None),
+            };
+
+            match Pin::new(part).poll_data(cx) {
+                Poll::Ready(None) if parts.len() > 1 => {
+                    parts.pop_front();
+                }
+                Poll::Ready(Some(Err(err))) => {
+                    // The body is fused after an error, the next parts aren't polled.
+                    parts.clear();
+                    return Poll::Ready(Some(Err(err)));
+                }
+                poll_status => return poll_status,
+            }
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        match self.parts.back_mut() {
+            Some(part) => Pin::new(part).poll_trailers(cx),
+            None => Poll::Ready(Ok(None)),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.parts.iter().all(|part| part.is_end_stream())
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let mut lower = 0u64;
+        let mut upper = Some(0u64);
+        for part in self.parts.iter() {
+            let hint = part.size_hint();
+            lower = lower.saturating_add(hint.lower());
+            upper = match (upper, hint.upper()) {
+                (Some(sum), Some(part_upper)) => sum.checked_add(part_upper),
+                _ => None,
+            };
+        }
+
+        let mut hint = SizeHint::new();
+        hint.set_lower(lower);
+        if let Some(upper) = upper {
+            hint.set_upper(upper);
+        }
+        hint
+    }
+}
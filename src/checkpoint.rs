@@ -0,0 +1,647 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use async_pipe::PipeWriter;
+use bytes::Buf;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::future::Future;
+use std::io::Write;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{ready, Context, Poll, Waker};
+use tokio::io::{self, AsyncWrite};
+use tokio::task;
+
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// The number of checkpoints kept for a job.
+const HISTORY_LEN: usize = 16;
+
+/// A point of a generated body to resume the generation from: the offset of the data generated up to there and the
+/// opaque state the producer needs to continue, e.g. the id of the next row of an export.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    offset: u64,
+    state: Vec<u8>,
+}
+
+impl Checkpoint {
+    /// Creates a checkpoint at the given offset.
+    pub fn new(offset: u64, state: Vec<u8>) -> Checkpoint {
+        Checkpoint { offset, state }
+    }
+
+    /// Returns the offset of the data generated up to the checkpoint.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the state of the producer at the checkpoint.
+    pub fn state(&self) -> &[u8] {
+        &self.state
+    }
+
+    /// Consumes the checkpoint, returning the state of the producer.
+    pub fn into_state(self) -> Vec<u8> {
+        self.state
+    }
+}
+
+/// A store of the last checkpoints of each generation job, which outlives the process so a job can be
+/// [resumed](./struct.StreamBody.html#method.resume_checkpointed) after a restart.
+///
+/// A body keeps its last 16 checkpoints whose data was emitted rather than only the latest one, as the data emitted
+/// by a body runs ahead of what the client received, e.g. by the buffers of the server and the socket. A resumed body
+/// picks the latest checkpoint at or before the offset the client actually received.
+///
+/// [FileCheckpointStore](./struct.FileCheckpointStore.html) keeps them in files, a store backed by a database can be
+/// plugged in by implementing this trait. The methods may block: `load` is called when the body is created, while
+/// `save` and `remove` run on the blocking thread pool, one at a time for a body, and only the latest checkpoints are
+/// saved when the saves fall behind.
+pub trait CheckpointStore: Send + Sync {
+    /// Replaces the checkpoints of the job, which are ordered by offset.
+    fn save(&self, job: &str, checkpoints: &[Checkpoint]) -> io::Result<()>;
+
+    /// Returns the checkpoints of the job ordered by offset, which are empty if none was saved.
+    fn load(&self, job: &str) -> io::Result<Vec<Checkpoint>>;
+
+    /// Removes the checkpoint of the job, once it completed.
+    fn remove(&self, job: &str) -> io::Result<()>;
+}
+
+impl<S: CheckpointStore + ?Sized> CheckpointStore for Arc<S> {
+    fn save(&self, job: &str, checkpoints: &[Checkpoint]) -> io::Result<()> {
+        (**self).save(job, checkpoints)
+    }
+
+    fn load(&self, job: &str) -> io::Result<Vec<Checkpoint>> {
+        (**self).load(job)
+    }
+
+    fn remove(&self, job: &str) -> io::Result<()> {
+        (**self).remove(job)
+    }
+}
+
+/// A [CheckpointStore](./trait.CheckpointStore.html) keeping the checkpoints of every job in a file of a directory,
+/// named after the job.
+///
+/// The checkpoints are written to a temporary file which is synced and then renamed, and the directory is synced
+/// after the rename, so a crash never leaves partial checkpoints.
+#[derive(Debug, Clone)]
+pub struct FileCheckpointStore {
+    dir: PathBuf,
+}
+
+impl FileCheckpointStore {
+    /// Creates a store in the given directory, which must exist.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> FileCheckpointStore {
+        FileCheckpointStore { dir: dir.into() }
+    }
+
+    fn path(&self, job: &str) -> io::Result<PathBuf> {
+        if job.is_empty() || job.starts_with('.') || job.contains(['/', '\\']) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{}: FileCheckpointStore: Invalid job name for a file: {}",
+                    env!("CARGO_PKG_NAME"),
+                    job
+                ),
+            ));
+        }
+        Ok(self.dir.join(format!("{}.checkpoint", job)))
+    }
+}
+
+impl CheckpointStore for FileCheckpointStore {
+    fn save(&self, job: &str, checkpoints: &[Checkpoint]) -> io::Result<()> {
+        let path = self.path(job)?;
+        // Every checkpoint is stored as its offset and the length of its state, in big-endian, followed by the state.
+        let mut contents = Vec::new();
+        for checkpoint in checkpoints {
+            contents.extend_from_slice(&checkpoint.offset.to_be_bytes());
+            contents.extend_from_slice(&(checkpoint.state.len() as u64).to_be_bytes());
+            contents.extend_from_slice(&checkpoint.state);
+        }
+
+        let counter = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let temp_path = path.with_extension(format!("checkpoint.{}.{}.tmp", std::process::id(), counter));
+        let result = File::create(&temp_path)
+            .and_then(|mut file| {
+                file.write_all(&contents)?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&temp_path, &path));
+        if let Err(err) = result {
+            let _ = fs::remove_file(&temp_path);
+            return Err(err);
+        }
+        sync_dir(&self.dir)
+    }
+
+    fn load(&self, job: &str) -> io::Result<Vec<Checkpoint>> {
+        let contents = match fs::read(self.path(job)?) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+
+        let corrupted = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: FileCheckpointStore: The checkpoints of {} are corrupted",
+                    env!("CARGO_PKG_NAME"),
+                    job
+                ),
+            )
+        };
+
+        let mut checkpoints = Vec::new();
+        let mut rest = &contents[..];
+        while !rest.is_empty() {
+            if rest.len() < 16 {
+                return Err(corrupted());
+            }
+            let mut offset = [0; 8];
+            let mut len = [0; 8];
+            offset.copy_from_slice(&rest[..8]);
+            len.copy_from_slice(&rest[8..16]);
+            rest = &rest[16..];
+
+            let len = u64::from_be_bytes(len);
+            if len > rest.len() as u64 {
+                return Err(corrupted());
+            }
+            let (state, tail) = rest.split_at(len as usize);
+            checkpoints.push(Checkpoint::new(u64::from_be_bytes(offset), state.to_vec()));
+            rest = tail;
+        }
+        Ok(checkpoints)
+    }
+
+    fn remove(&self, job: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(job)?) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Syncs a directory, so a rename into it survives a crash. Directories can't be opened as files on Windows, where
+/// the rename is durable once it returns.
+#[cfg(unix)]
+fn sync_dir(dir: &std::path::Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &std::path::Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// The writer half of a [checkpointed](./struct.StreamBody.html#method.checkpointed) body, which counts the
+/// generated bytes so the producer can record checkpoints along the way.
+pub struct CheckpointWriter {
+    inner: PipeWriter,
+    offset: u64,
+    pending: Arc<Mutex<VecDeque<Checkpoint>>>,
+}
+
+impl CheckpointWriter {
+    /// Records a checkpoint at the current offset, with the state the producer needs to continue from there.
+    ///
+    /// The checkpoint is saved once the body emitted the data written before it, i.e. handed it to the server, which
+    /// doesn't mean the client received it. A resumed body only uses a checkpoint at or before the offset the client
+    /// received, so it never relies on data which is missing on the client.
+    pub fn checkpoint<S: Into<Vec<u8>>>(&mut self, state: S) {
+        let checkpoint = Checkpoint::new(self.offset, state.into());
+        self.pending
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push_back(checkpoint);
+    }
+
+    /// Returns the offset of the data generated so far, including the data generated before the resumed checkpoint.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+impl AsyncWrite for CheckpointWriter {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let poll_status = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll_status {
+            self.offset += n as u64;
+        }
+        poll_status
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl StreamBody {
+    /// Creates a body generated by the producer returned by `f`, which records checkpoints via its
+    /// [CheckpointWriter](./struct.CheckpointWriter.html) so the generation can be resumed after a process restart,
+    /// e.g. a large export. The checkpoints are saved in `store` under the name of the `job`.
+    ///
+    /// It's the same as [resume_checkpointed](#method.resume_checkpointed) from the start of the body.
+    pub fn checkpointed<S, F, Fut>(store: S, job: &str, f: F) -> StreamBody
+    where
+        S: CheckpointStore + 'static,
+        F: FnOnce(CheckpointWriter, Option<Checkpoint>) -> Fut,
+        Fut: Future<Output = io::Result<()>> + Send + 'static,
+    {
+        StreamBody::resume_checkpointed(store, job, 0, f)
+    }
+
+    /// Creates a body resuming a [checkpointed](#method.checkpointed) generation for a client which already received
+    /// `offset` bytes, e.g. from a [ResumeToken](./struct.ResumeToken.html).
+    ///
+    /// The producer is spawned like with [from_producer](#method.from_producer) and gets the latest saved checkpoint
+    /// of the job at or before `offset`, to continue from its state. The data generated again between the checkpoint
+    /// and `offset` is skipped. Without a usable checkpoint, the producer gets `None` and starts over.
+    ///
+    /// The body ends once the producer returned `Ok` and the checkpoints of the completed job are removed. If the
+    /// producer fails, the body fails with its error and the checkpoints are kept for the next attempt. Errors of the
+    /// store are logged via [log::error!](https://docs.rs/log/0.4.10/log/macro.error.html) and never affect the body.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use stream_body::{FileCheckpointStore, StreamBody};
+    /// use tokio::io::AsyncWriteExt;
+    ///
+    /// # fn run(received: u64) -> StreamBody {
+    /// let store = FileCheckpointStore::new("/var/lib/exports");
+    ///
+    /// StreamBody::resume_checkpointed(store, "export-42", received, |mut writer, checkpoint| async move {
+    ///     let mut state = [0; 8];
+    ///     if let Some(ref checkpoint) = checkpoint {
+    ///         state.copy_from_slice(checkpoint.state());
+    ///     }
+    ///
+    ///     for row in u64::from_be_bytes(state)..1_000_000 {
+    ///         writer.write_all(format!("{},row {}\n", row, row).as_bytes()).await?;
+    ///         if row % 1000 == 999 {
+    ///             writer.checkpoint((row + 1).to_be_bytes().to_vec());
+    ///         }
+    ///     }
+    ///     Ok(())
+    /// })
+    /// # }
+    /// ```
+    pub fn resume_checkpointed<S, F, Fut>(store: S, job: &str, offset: u64, f: F) -> StreamBody
+    where
+        S: CheckpointStore + 'static,
+        F: FnOnce(CheckpointWriter, Option<Checkpoint>) -> Fut,
+        Fut: Future<Output = io::Result<()>> + Send + 'static,
+    {
+        let mut history = match store.load(job) {
+            Ok(checkpoints) => checkpoints,
+            Err(err) => {
+                log::error!(
+                    "{}: StreamBody: Failed to load the checkpoints of {}: {}",
+                    env!("CARGO_PKG_NAME"),
+                    job,
+                    err
+                );
+                Vec::new()
+            }
+        };
+        // The checkpoints after `offset` are dropped, the ones before the resumed checkpoint stay valid.
+        history.retain(|checkpoint| checkpoint.offset <= offset);
+        let checkpoint = history.iter().max_by_key(|checkpoint| checkpoint.offset).cloned();
+        let start = checkpoint.as_ref().map_or(0, |checkpoint| checkpoint.offset);
+        history.sort_by_key(|checkpoint| checkpoint.offset);
+
+        let (w, body) = StreamBody::channel();
+        let pending = Arc::new(Mutex::new(VecDeque::new()));
+        let writer = CheckpointWriter {
+            inner: w,
+            offset: start,
+            pending: Arc::clone(&pending),
+        };
+
+        let body = body
+            .owning_fallible_task(f(writer, checkpoint))
+            .wrap_with(|inner| Checkpointed {
+                inner,
+                saver: Arc::new(Saver {
+                    store: Box::new(store),
+                    job: job.to_owned(),
+                    shared: Mutex::new(SaverState::default()),
+                }),
+                emitted: start,
+                pending,
+                history: history.into(),
+                removed: false,
+            });
+        body.skip(offset - start)
+    }
+}
+
+struct Checkpointed {
+    inner: StreamBody,
+    saver: Arc<Saver>,
+    // The offset of the data emitted so far.
+    emitted: u64,
+    pending: Arc<Mutex<VecDeque<Checkpoint>>>,
+    // The last checkpoints whose data was emitted, ordered by offset.
+    history: VecDeque<Checkpoint>,
+    // Whether the removal of the checkpoint was submitted, once the body reached its end.
+    removed: bool,
+}
+
+impl Checkpointed {
+    /// Adds the checkpoints whose data was emitted to the history and saves it.
+    fn save_emitted(&mut self) {
+        let mut added = false;
+        {
+            let mut pending = self.pending.lock().unwrap_or_else(|err| err.into_inner());
+            while let Some(checkpoint) = pending.front() {
+                if checkpoint.offset > self.emitted {
+                    break;
+                }
+                self.history.extend(pending.pop_front());
+                added = true;
+            }
+        }
+
+        if added {
+            while self.history.len() > HISTORY_LEN {
+                self.history.pop_front();
+            }
+            self.saver.submit(Op::Save(self.history.iter().cloned().collect()));
+        }
+    }
+}
+
+/// Runs the operations on the store of a body on the blocking thread pool, one at a time, an operation which didn't
+/// start yet being replaced by the next one.
+struct Saver {
+    store: Box<dyn CheckpointStore>,
+    job: String,
+    shared: Mutex<SaverState>,
+}
+
+#[derive(Default)]
+struct SaverState {
+    pending: Option<Op>,
+    running: bool,
+    // The body waiting for the operations to complete.
+    waker: Option<Waker>,
+}
+
+enum Op {
+    Save(Vec<Checkpoint>),
+    Remove,
+}
+
+impl Saver {
+    fn lock(&self) -> MutexGuard<'_, SaverState> {
+        self.shared.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn submit(self: &Arc<Self>, op: Op) {
+        {
+            let mut state = self.lock();
+            state.pending = Some(op);
+            if state.running {
+                return;
+            }
+            state.running = true;
+        }
+
+        let saver = Arc::clone(self);
+        task::spawn_blocking(move || saver.run());
+    }
+
+    fn run(&self) {
+        loop {
+            let op = {
+                let mut state = self.lock();
+                match state.pending.take() {
+                    Some(op) => op,
+                    None => {
+                        state.running = false;
+                        if let Some(waker) = state.waker.take() {
+                            waker.wake();
+                        }
+                        return;
+                    }
+                }
+            };
+
+            let (result, action) = match op {
+                Op::Save(checkpoints) => (self.store.save(&self.job, &checkpoints), "save the checkpoints"),
+                Op::Remove => (self.store.remove(&self.job), "remove the checkpoints"),
+            };
+            if let Err(err) = result {
+                log::error!(
+                    "{}: StreamBody: Failed to {} of {}: {}",
+                    env!("CARGO_PKG_NAME"),
+                    action,
+                    self.job,
+                    err
+                );
+            }
+        }
+    }
+
+    /// Waits for the submitted operations to complete.
+    fn poll_idle(&self, cx: &mut Context) -> Poll<()> {
+        let mut state = self.lock();
+        if !state.running {
+            return Poll::Ready(());
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Body for Checkpointed {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let poll_status = Pin::new(&mut self.inner).poll_data(cx);
+        match poll_status {
+            Poll::Ready(Some(Ok(ref data))) => {
+                self.emitted += data.remaining() as u64;
+                self.save_emitted();
+            }
+            Poll::Ready(None) => {
+                if !self.removed {
+                    self.removed = true;
+                    self.saver.submit(Op::Remove);
+                }
+                ready!(self.saver.poll_idle(cx));
+            }
+            _ => {}
+        }
+        poll_status
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[derive(Default)]
+    struct MemoryStore {
+        checkpoints: Mutex<Vec<Checkpoint>>,
+        removed: Mutex<bool>,
+    }
+
+    impl CheckpointStore for MemoryStore {
+        fn save(&self, _job: &str, checkpoints: &[Checkpoint]) -> io::Result<()> {
+            *self.checkpoints.lock().unwrap() = checkpoints.to_vec();
+            Ok(())
+        }
+
+        fn load(&self, _job: &str) -> io::Result<Vec<Checkpoint>> {
+            Ok(self.checkpoints.lock().unwrap().clone())
+        }
+
+        fn remove(&self, _job: &str) -> io::Result<()> {
+            *self.removed.lock().unwrap() = true;
+            Ok(())
+        }
+    }
+
+    async fn collect(mut body: StreamBody) -> Vec<u8> {
+        let mut out = Vec::new();
+        while let Some(data) = body.data().await {
+            out.extend_from_slice(data.unwrap().bytes());
+        }
+        out
+    }
+
+    // Writes the bytes 0..100, checkpointing every 10 bytes with the next byte as the state.
+    async fn generate(mut writer: CheckpointWriter, checkpoint: Option<Checkpoint>) -> io::Result<()> {
+        let start = checkpoint.map_or(0, |checkpoint| checkpoint.state()[0]);
+        for byte in start..100 {
+            if byte % 10 == 0 {
+                writer.checkpoint(vec![byte]);
+            }
+            writer.write_all(&[byte]).await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resume_picks_the_latest_checkpoint_before_the_received_offset() {
+        let store = Arc::new(MemoryStore::default());
+        let saved = (0..4)
+            .map(|i| Checkpoint::new(i * 10, vec![i as u8 * 10]))
+            .collect::<Vec<_>>();
+        *store.checkpoints.lock().unwrap() = saved;
+
+        // The client received less than the body emitted, so the latest checkpoint is past its offset.
+        let resumed = Arc::new(Mutex::new(None));
+        let resumed_checkpoint = Arc::clone(&resumed);
+        let body = StreamBody::resume_checkpointed(Arc::clone(&store), "job", 25, move |writer, checkpoint| {
+            *resumed_checkpoint.lock().unwrap() = checkpoint.as_ref().map(Checkpoint::offset);
+            generate(writer, checkpoint)
+        });
+
+        assert_eq!(collect(body).await, (25..100).collect::<Vec<u8>>());
+        assert_eq!(*resumed.lock().unwrap(), Some(20));
+        assert!(*store.removed.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn the_history_is_bounded() {
+        let store = Arc::new(MemoryStore::default());
+        let mut body = StreamBody::checkpointed(Arc::clone(&store), "job", |mut writer, _| async move {
+            for byte in 0..40u8 {
+                writer.checkpoint(vec![byte]);
+                writer.write_all(&[byte]).await?;
+            }
+            // Fails, so the checkpoints are kept.
+            Err(io::Error::new(io::ErrorKind::Other, "crashed"))
+        });
+        while let Some(Ok(_)) = body.data().await {}
+
+        // The checkpoints are saved on the blocking thread pool.
+        let last_offset = || store.checkpoints.lock().unwrap().last().map(Checkpoint::offset);
+        for _ in 0..100 {
+            if last_offset() == Some(39) {
+                break;
+            }
+            tokio::time::delay_for(std::time::Duration::from_millis(10)).await;
+        }
+        let offsets = store
+            .checkpoints
+            .lock()
+            .unwrap()
+            .iter()
+            .map(Checkpoint::offset)
+            .collect::<Vec<_>>();
+        assert_eq!(offsets, (24..40).collect::<Vec<_>>());
+        assert!(!*store.removed.lock().unwrap());
+    }
+
+    #[test]
+    fn file_store_round_trips_the_history() {
+        let dir = std::env::temp_dir().join(format!("stream-body-checkpoint-store-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let store = FileCheckpointStore::new(&dir);
+
+        assert!(store.load("job").unwrap().is_empty());
+        let checkpoints = vec![
+            Checkpoint::new(0, Vec::new()),
+            Checkpoint::new(10, b"ten".to_vec()),
+            Checkpoint::new(u64::MAX, vec![0; 300]),
+        ];
+        store.save("job", &checkpoints).unwrap();
+        assert_eq!(store.load("job").unwrap(), checkpoints);
+
+        // No temporary file is left behind.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // A truncated file is reported instead of yielding a wrong checkpoint.
+        let path = dir.join("job.checkpoint");
+        let contents = fs::read(&path).unwrap();
+        fs::write(&path, &contents[..contents.len() - 1]).unwrap();
+        assert_eq!(store.load("job").unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        store.remove("job").unwrap();
+        store.remove("job").unwrap();
+        assert!(store.load("job").unwrap().is_empty());
+        assert_eq!(
+            store.save("../job", &checkpoints).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,438 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use crate::state::State;
+use bytes::{Buf, Bytes, BytesMut};
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::io::IoSlice;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{ready, Context, Poll, Waker};
+use tokio::io::{self, AsyncWrite};
+
+const DEFAULT_CHUNK_CAPACITY: usize = 8 * 1024;
+
+impl StreamBody {
+    /// Creates a body stream with an associated [ChunkWriter](./struct.ChunkWriter.html), which controls exactly where
+    /// the chunks of the body start and end.
+    ///
+    /// Unlike with [channel](#method.channel), whose pipe coalesces the writes however it's read, every chunk seen by
+    /// the server is either a chunk sent via [send_chunk](./struct.ChunkWriter.html#method.send_chunk) or the data
+    /// written since the previous flush.
+    pub fn chunk_channel() -> (ChunkWriter, StreamBody) {
+        StreamBody::chunk_channel_with_capacity(DEFAULT_CHUNK_CAPACITY)
+    }
+
+    /// Same as [chunk_channel](#method.chunk_channel), but the written data is emitted as a chunk whenever `capacity`
+    /// bytes are buffered, instead of the default 8 KiB.
+    pub fn chunk_channel_with_capacity(capacity: usize) -> (ChunkWriter, StreamBody) {
+        StreamBody::chunk_channel_from_parts(capacity, 0)
+    }
+
+    /// Same as [chunk_channel](#method.chunk_channel), but the writer keeps handing chunks over while the server is
+    /// busy, as long as the queued chunks hold at most `max_queued_bytes` bytes, e.g. 256 KiB.
+    ///
+    /// The queue is bounded by bytes rather than by chunks, so many small chunks, e.g. events, don't wait for each
+    /// other while a few large ones can't exceed the intended memory. A chunk larger than the bound is still queued
+    /// alone once the queue is empty.
+    pub fn chunk_channel_with_queue(max_queued_bytes: usize) -> (ChunkWriter, StreamBody) {
+        StreamBody::chunk_channel_from_parts(DEFAULT_CHUNK_CAPACITY, max_queued_bytes)
+    }
+
+    fn chunk_channel_from_parts(capacity: usize, max_queued_bytes: usize) -> (ChunkWriter, StreamBody) {
+        let shared = Arc::new(Mutex::new(Shared {
+            chunks: VecDeque::with_capacity(1),
+            queued_bytes: 0,
+            max_queued_bytes,
+            writer_closed: false,
+            body_closed: false,
+            writer_waker: None,
+            body_waker: None,
+        }));
+
+        let writer = ChunkWriter {
+            shared: Arc::clone(&shared),
+            buf: BytesMut::new(),
+            capacity: capacity.max(1),
+            pending: None,
+        };
+
+        let mut body = StreamBody::wrap(ChunkBody { shared });
+        body.source = "chunk_channel";
+        (writer, body)
+    }
+}
+
+struct Shared {
+    // The chunks handed over by the writer, at most one unless the queue is bounded by bytes or the writer was
+    // dropped with buffered data.
+    chunks: VecDeque<Queued>,
+    queued_bytes: usize,
+    // The bound of `queued_bytes`, zero for a single chunk.
+    max_queued_bytes: usize,
+    writer_closed: bool,
+    body_closed: bool,
+    writer_waker: Option<Waker>,
+    body_waker: Option<Waker>,
+}
+
+impl Shared {
+    fn push(&mut self, chunk: Queued) {
+        self.queued_bytes += chunk.bytes.len();
+        self.chunks.push_back(chunk);
+    }
+}
+
+struct Queued {
+    bytes: Bytes,
+    // The consumption state of a chunk sent via `send_borrowed`, which waits until it's dropped.
+    state: Option<Arc<Mutex<State>>>,
+}
+
+impl From<Bytes> for Queued {
+    fn from(bytes: Bytes) -> Queued {
+        Queued { bytes, state: None }
+    }
+}
+
+fn lock(shared: &Mutex<Shared>) -> MutexGuard<'_, Shared> {
+    shared.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn broken_pipe() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        format!("{}: ChunkWriter: The body was dropped", env!("CARGO_PKG_NAME")),
+    )
+}
+
+/// The writer half of [StreamBody::chunk_channel](./struct.StreamBody.html#method.chunk_channel), which controls the
+/// chunk boundaries of the body, e.g. one chunk per event of a Server-Sent Events stream or per fragment of a
+/// progressively rendered page.
+///
+/// Its `AsyncWrite` implementation, including the vectored writes of `poll_write_buf`, and the `futures::io::AsyncWrite`
+/// one with the `futures` feature, including `poll_write_vectored`, buffer the written data until it's flushed, or
+/// until the capacity of the channel is reached, and the flush emits the buffered data as a single chunk.
+/// [send_chunk](#method.send_chunk) emits an owned chunk as is, without copying it, and
+/// [send_borrowed](#method.send_borrowed) emits borrowed data and tells when the server is done with it.
+///
+/// At most one chunk is waiting for the body, so the writer waits while the server is busy with the previous one,
+/// unless the channel was created via [chunk_channel_with_queue](./struct.StreamBody.html#method.chunk_channel_with_queue).
+/// Once the body is dropped, the writes fail with a `BrokenPipe` error. Dropping the writer ends the body, the data
+/// still buffered being emitted as a last chunk.
+///
+/// # Examples
+///
+/// ```no_run
+/// use bytes::Bytes;
+/// use stream_body::StreamBody;
+/// use tokio::io::AsyncWriteExt;
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let (mut writer, body) = StreamBody::chunk_channel();
+///
+/// writer.write_all(b"<html><head>").await?;
+/// writer.write_all(b"<title>Hello</title></head>").await?;
+/// // Both writes are emitted as one chunk.
+/// writer.flush_chunk().await?;
+///
+/// writer.send_chunk(Bytes::from_static(b"<body>Hello</body></html>")).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ChunkWriter {
+    shared: Arc<Mutex<Shared>>,
+    buf: BytesMut,
+    capacity: usize,
+    // A chunk waiting for the body to take the previous one.
+    pending: Option<Queued>,
+}
+
+impl ChunkWriter {
+    /// Emits the buffered data, if any, then `chunk` as a separate chunk, waiting until both are handed over to the
+    /// body. An empty chunk is skipped.
+    pub async fn send_chunk(&mut self, chunk: Bytes) -> io::Result<()> {
+        self.flush_chunk().await?;
+        if !chunk.is_empty() {
+            self.pending = Some(chunk.into());
+        }
+        poll_fn(|cx| self.poll_push(cx)).await
+    }
+
+    /// Emits the buffered data, if any, then `data` as a separate chunk, waiting until the server fully consumed and
+    /// dropped that chunk, e.g. to release or reuse the memory backing `data` only once it's sent, or to pace the
+    /// producer on the actual delivery. An empty slice is skipped.
+    ///
+    /// The chunk is a copy of `data`: a chunk referencing `data` itself would outlive it if the future were dropped
+    /// before completion, so it's the completion notification of the body's reusable buffer which is exposed here,
+    /// not the borrowing. It fails with a `BrokenPipe` error if the body, or the chunk, is dropped before the chunk
+    /// was fully consumed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use stream_body::StreamBody;
+    ///
+    /// # async fn run() -> std::io::Result<()> {
+    /// let (mut writer, body) = StreamBody::chunk_channel();
+    ///
+    /// let mut frame = vec![0; 64 * 1024];
+    /// for _ in 0..16 {
+    ///     // Render the next frame into `frame`, then wait until the server sent it.
+    ///     writer.send_borrowed(&frame).await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_borrowed(&mut self, data: &[u8]) -> io::Result<()> {
+        self.flush_chunk().await?;
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let mut state = State::new();
+        state.is_current_stream_data_consumed = false;
+        let state = Arc::new(Mutex::new(state));
+        self.pending = Some(Queued {
+            bytes: Bytes::copy_from_slice(data),
+            state: Some(Arc::clone(&state)),
+        });
+        poll_fn(|cx| self.poll_push(cx)).await?;
+
+        poll_fn(|cx| {
+            let mut state = state.lock().unwrap_or_else(|err| err.into_inner());
+            if !state.is_current_stream_data_consumed {
+                state.park(cx);
+                return Poll::Pending;
+            }
+            if state.abandoned_bytes > 0 {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    format!(
+                        "{}: ChunkWriter: The chunk was dropped with {} bytes left",
+                        env!("CARGO_PKG_NAME"),
+                        state.abandoned_bytes
+                    ),
+                )));
+            }
+            Poll::Ready(Ok(()))
+        })
+        .await
+    }
+
+    /// Emits the data written since the previous flush as a single chunk, waiting until it's handed over to the body.
+    /// It's the same as `AsyncWriteExt::flush`.
+    pub async fn flush_chunk(&mut self) -> io::Result<()> {
+        poll_fn(|cx| self.poll_flush_chunk(cx)).await
+    }
+
+    /// Returns the number of bytes written since the previous flush.
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Returns whether the body was dropped, in which case the writes fail.
+    pub fn is_closed(&self) -> bool {
+        lock(&self.shared).body_closed
+    }
+
+    fn poll_push(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        let mut shared = lock(&self.shared);
+        if shared.body_closed {
+            self.pending = None;
+            return Poll::Ready(Err(broken_pipe()));
+        }
+
+        let chunk = match self.pending.take() {
+            Some(chunk) => chunk,
+            None => return Poll::Ready(Ok(())),
+        };
+        if !shared.chunks.is_empty() && shared.queued_bytes + chunk.bytes.len() > shared.max_queued_bytes {
+            self.pending = Some(chunk);
+            shared.writer_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        shared.push(chunk);
+        if let Some(waker) = shared.body_waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_flush_chunk(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        ready!(self.poll_push(cx))?;
+        if !self.buf.is_empty() {
+            self.pending = Some(self.buf.split().freeze().into());
+        }
+        self.poll_push(cx)
+    }
+
+    /// Copies as many of the slices as fit in the buffer.
+    fn poll_write_slices(&mut self, cx: &mut Context, bufs: &[IoSlice]) -> Poll<io::Result<usize>> {
+        let mut room = ready!(self.poll_reserve(cx))?;
+        let mut written = 0;
+        for buf in bufs {
+            if room == 0 {
+                break;
+            }
+            let len = room.min(buf.len());
+            self.buf.extend_from_slice(&buf[..len]);
+            room -= len;
+            written += len;
+        }
+        Poll::Ready(Ok(written))
+    }
+
+    /// Makes room in the buffer, emitting it once it's full.
+    fn poll_reserve(&mut self, cx: &mut Context) -> Poll<io::Result<usize>> {
+        if lock(&self.shared).body_closed {
+            return Poll::Ready(Err(broken_pipe()));
+        }
+        if self.buf.len() >= self.capacity {
+            ready!(self.poll_flush_chunk(cx))?;
+        }
+        Poll::Ready(Ok(self.capacity - self.buf.len()))
+    }
+}
+
+impl AsyncWrite for ChunkWriter {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.poll_write_slices(cx, &[IoSlice::new(buf)])
+    }
+
+    fn poll_write_buf<B: Buf>(mut self: Pin<&mut Self>, cx: &mut Context, buf: &mut B) -> Poll<io::Result<usize>> {
+        let mut slices = [IoSlice::new(&[]); 64];
+        let count = buf.bytes_vectored(&mut slices);
+        let written = ready!(self.poll_write_slices(cx, &slices[..count]))?;
+        buf.advance(written);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.poll_flush_chunk(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        ready!(self.poll_flush_chunk(cx))?;
+
+        let mut shared = lock(&self.shared);
+        shared.writer_closed = true;
+        if let Some(waker) = shared.body_waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "futures")]
+impl futures_io::AsyncWrite for ChunkWriter {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.poll_write_slices(cx, &[IoSlice::new(buf)])
+    }
+
+    fn poll_write_vectored(mut self: Pin<&mut Self>, cx: &mut Context, bufs: &[IoSlice]) -> Poll<io::Result<usize>> {
+        self.poll_write_slices(cx, bufs)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.poll_flush_chunk(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        io::AsyncWrite::poll_shutdown(self, cx)
+    }
+}
+
+impl Drop for ChunkWriter {
+    fn drop(&mut self) {
+        let mut shared = lock(&self.shared);
+        // The data which didn't get a flush is still delivered, like with a pipe.
+        if let Some(chunk) = self.pending.take() {
+            shared.push(chunk);
+        }
+        if !self.buf.is_empty() {
+            shared.push(self.buf.split().freeze().into());
+        }
+        shared.writer_closed = true;
+        if let Some(waker) = shared.body_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+struct ChunkBody {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Drop for ChunkBody {
+    fn drop(&mut self) {
+        let mut shared = lock(&self.shared);
+        shared.body_closed = true;
+        shared.queued_bytes = 0;
+        for chunk in shared.chunks.drain(..) {
+            // A `send_borrowed` call waiting for a chunk which won't be emitted fails.
+            if let Some(state) = chunk.state {
+                let mut state = state.lock().unwrap_or_else(|err| err.into_inner());
+                state.is_current_stream_data_consumed = true;
+                state.abandoned_bytes += chunk.bytes.len();
+                state.wake();
+            }
+        }
+        if let Some(waker) = shared.writer_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Body for ChunkBody {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let mut shared = lock(&self.shared);
+        if let Some(chunk) = shared.chunks.pop_front() {
+            shared.queued_bytes -= chunk.bytes.len();
+            if let Some(waker) = shared.writer_waker.take() {
+                waker.wake();
+            }
+            let data = match chunk.state {
+                Some(state) => StreamData::new(chunk.bytes, state),
+                None => StreamData::from_bytes(chunk.bytes),
+            };
+            return Poll::Ready(Some(Ok(data)));
+        }
+
+        if shared.writer_closed {
+            return Poll::Ready(None);
+        }
+        shared.body_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Poll::Ready(Ok(None))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        let shared = lock(&self.shared);
+        shared.writer_closed && shared.chunks.is_empty()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let shared = lock(&self.shared);
+        let queued = shared.queued_bytes as u64;
+
+        let mut hint = SizeHint::new();
+        hint.set_lower(queued);
+        if shared.writer_closed {
+            hint.set_upper(queued);
+        }
+        hint
+    }
+}
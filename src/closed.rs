@@ -0,0 +1,197 @@
+use crate::body::StreamBody;
+use async_pipe::PipeWriter;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use tokio::io::{self, AsyncWrite};
+
+/// A future which resolves once a body is dropped, typically because the client went away, created by
+/// [ChannelWriter::closed](./struct.ChannelWriter.html#method.closed) or
+/// [StreamBody::closed](./struct.StreamBody.html#method.closed).
+///
+/// It's cheap to clone and all the clones resolve together.
+#[derive(Clone)]
+pub struct Closed {
+    shared: Arc<Mutex<ClosedState>>,
+}
+
+#[derive(Default)]
+struct ClosedState {
+    closed: bool,
+    wakers: Vec<Waker>,
+}
+
+/// Resolves the `Closed` futures of a body when dropped together with the body.
+pub(crate) struct ClosedGuard {
+    shared: Arc<Mutex<ClosedState>>,
+}
+
+impl Drop for ClosedGuard {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock().unwrap_or_else(|err| err.into_inner());
+        state.closed = true;
+        state.wakers.drain(..).for_each(Waker::wake);
+    }
+}
+
+impl Closed {
+    /// Returns whether the body is dropped.
+    pub fn is_closed(&self) -> bool {
+        self.shared.lock().unwrap_or_else(|err| err.into_inner()).closed
+    }
+}
+
+impl Future for Closed {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let mut state = self.shared.lock().unwrap_or_else(|err| err.into_inner());
+        if state.closed {
+            return Poll::Ready(());
+        }
+
+        if !state.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            state.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+/// The writer half of a channel body created via
+/// [StreamBody::channel_with_close_signal](./struct.StreamBody.html#method.channel_with_close_signal), which also
+/// tells when the body is dropped.
+///
+/// It writes like the [PipeWriter](https://docs.rs/async-pipe/0.1.3/async_pipe/struct.PipeWriter.html) it wraps.
+pub struct ChannelWriter {
+    inner: PipeWriter,
+    closed: Closed,
+}
+
+impl ChannelWriter {
+    /// Returns a future which resolves once the body is dropped, so a producer can stop working as soon as the
+    /// consumer goes away, e.g. when a client aborts a download, instead of waiting for its next write to fail.
+    ///
+    /// The body is also dropped once it's fully sent, so the future resolves then as well.
+    pub fn closed(&self) -> Closed {
+        self.closed.clone()
+    }
+
+    /// Returns whether the body is dropped.
+    pub fn is_closed(&self) -> bool {
+        self.closed.is_closed()
+    }
+
+    /// Returns the wrapped `PipeWriter`.
+    pub fn into_inner(self) -> PipeWriter {
+        self.inner
+    }
+}
+
+impl AsyncWrite for ChannelWriter {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl StreamBody {
+    /// Same as [channel](#method.channel), but the writer half also exposes a
+    /// [closed](./struct.ChannelWriter.html#method.closed) future, so a producer holding only the writer learns when
+    /// the consumer goes away.
+    ///
+    /// Writes to the writer half of a dropped channel body fail with a `BrokenPipe` error, including a pending one.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use stream_body::StreamBody;
+    /// use tokio::io::AsyncWriteExt;
+    ///
+    /// # async fn run() {
+    /// let (mut writer, body) = StreamBody::channel_with_close_signal();
+    ///
+    /// tokio::spawn(async move {
+    ///     let mut closed = writer.closed();
+    ///     loop {
+    ///         tokio::select! {
+    ///             _ = &mut closed => break,
+    ///             _ = tokio::time::delay_for(std::time::Duration::from_secs(1)) => {
+    ///                 if writer.write_all(b"tick\n").await.is_err() {
+    ///                     break;
+    ///                 }
+    ///             }
+    ///         }
+    ///     }
+    /// });
+    /// # }
+    /// ```
+    pub fn channel_with_close_signal() -> (ChannelWriter, StreamBody) {
+        let (inner, mut body) = StreamBody::channel();
+        let closed = body.closed();
+        (ChannelWriter { inner, closed }, body)
+    }
+
+    /// Returns a future which resolves once the body is dropped, like
+    /// [ChannelWriter::closed](./struct.ChannelWriter.html#method.closed) but for any body, e.g. one created from a
+    /// reader. It has to be taken before the body is handed to the server.
+    pub fn closed(&mut self) -> Closed {
+        let guard = self.close_guard.get_or_insert_with(|| ClosedGuard {
+            shared: Arc::new(Mutex::new(ClosedState::default())),
+        });
+        Closed {
+            shared: Arc::clone(&guard.shared),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::FutureExt;
+    use http_body::Body;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn the_writer_learns_when_the_body_is_dropped() {
+        let (mut writer, mut body) = StreamBody::channel_with_close_signal();
+        let closed = writer.closed();
+        assert!(!writer.is_closed());
+        assert!(closed.clone().now_or_never().is_none());
+
+        let write = tokio::spawn(async move {
+            writer.write_all(b"hello").await.unwrap();
+            writer
+        });
+        let data = body.data().await.unwrap().unwrap();
+        assert_eq!(&data[..], b"hello");
+        drop(data);
+        let mut writer = write.await.unwrap();
+        assert!(!writer.is_closed());
+
+        drop(body);
+        closed.await;
+        assert!(writer.is_closed());
+        assert!(writer.closed().now_or_never().is_some());
+        let err = writer.write_all(b"late").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[tokio::test]
+    async fn closed_resolves_once_the_body_is_fully_sent() {
+        let (writer, mut body) = StreamBody::channel_with_close_signal();
+        let closed = writer.closed();
+        drop(writer);
+        assert!(body.data().await.is_none());
+        assert!(!closed.is_closed());
+        drop(body);
+        assert!(closed.now_or_never().is_some());
+    }
+}
@@ -0,0 +1,561 @@
+use crate::body::{size_hint_with_held, StreamBody};
+use crate::data::StreamData;
+use bytes::{Buf, Bytes};
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io;
+
+impl StreamBody {
+    /// Limits the body to the first `n` bytes of the source.
+    ///
+    /// Once `n` bytes are emitted the body ends without polling the source again. Useful for previews and for
+    /// emulating ranges over non-seekable sources.
+    pub fn take(self, n: u64) -> StreamBody {
+        self.wrap_with(|inner| Take { inner, remaining: n })
+    }
+
+    /// Fails the body with an `InvalidData` error instead of emitting a chunk which would exceed `max` bytes in total,
+    /// so a producer writing more than announced doesn't silently corrupt the response. Use [take](#method.take) to
+    /// truncate the source instead.
+    pub fn limited(self, max: u64) -> StreamBody {
+        self.wrap_with(|inner| LengthCheck {
+            inner,
+            len: max,
+            remaining: max,
+            exact: false,
+            failed: false,
+        })
+    }
+
+    /// Enforces that the body is exactly `len` bytes long, e.g. when proxying a response whose `Content-Length` was
+    /// already announced.
+    ///
+    /// The body fails with an `InvalidData` error if the source produces more than `len` bytes, like with
+    /// [limited](#method.limited), and with an `UnexpectedEof` error if it ends before, so the client sees an aborted
+    /// transfer instead of a truncated response. The size hint is exact.
+    pub fn exact(self, len: u64) -> StreamBody {
+        self.wrap_with(|inner| LengthCheck {
+            inner,
+            len,
+            remaining: len,
+            exact: true,
+            failed: false,
+        })
+    }
+
+    /// Discards the first `n` bytes of the source before emitting anything.
+    ///
+    /// Combined with [take](#method.take), it can be used to serve a byte range from a non-seekable source.
+    pub fn skip(self, n: u64) -> StreamBody {
+        self.wrap_with(|inner| Skip { inner, remaining: n })
+    }
+
+    /// Emits `prefix` before the first chunk of the source, e.g. a header line, a BOM or magic bytes.
+    pub fn prepend<B: Into<Bytes>>(self, prefix: B) -> StreamBody {
+        let prefix = prefix.into();
+        self.wrap_with(|inner| Prepend {
+            inner,
+            prefix: PrefixState::Ready(prefix),
+        })
+    }
+
+    /// Same as [prepend](#method.prepend), but the prefix is computed by `f` when the body is polled for the first
+    /// time. The size hint only accounts for the prefix once it's computed.
+    pub fn prepend_with<F>(self, f: F) -> StreamBody
+    where
+        F: FnOnce() -> Bytes + Send + 'static,
+    {
+        self.wrap_with(|inner| Prepend {
+            inner,
+            prefix: PrefixState::Lazy(Box::new(f)),
+        })
+    }
+
+    /// Emits `suffix` after the source reached its end, e.g. to close a JSON array. Nothing is appended if the source
+    /// fails.
+    pub fn append<B: Into<Bytes>>(self, suffix: B) -> StreamBody {
+        let suffix = suffix.into();
+        self.wrap_with(|inner| Append {
+            inner,
+            suffix: SuffixState::Ready(suffix),
+        })
+    }
+
+    /// Same as [append](#method.append), but the suffix is computed by `f` once the source reached its end, e.g. to
+    /// write an archive trailer depending on the streamed data. The size hint only accounts for the suffix once it's
+    /// computed.
+    pub fn append_with<F>(self, f: F) -> StreamBody
+    where
+        F: FnOnce() -> Bytes + Send + 'static,
+    {
+        self.wrap_with(|inner| Append {
+            inner,
+            suffix: SuffixState::Lazy(Box::new(f)),
+        })
+    }
+
+    /// Makes the body report the given size hint, decreased by the bytes emitted so far, instead of the one of the
+    /// source. The hint isn't enforced on the source.
+    ///
+    /// An exact hint lets hyper send a `Content-Length` header instead of using the chunked encoding.
+    pub fn with_size_hint(self, hint: SizeHint) -> StreamBody {
+        self.wrap_with(|inner| WithSizeHint {
+            inner,
+            hint,
+            emitted: 0,
+        })
+    }
+
+    /// Makes the body report an exact size of `len` bytes left to stream, see [with_size_hint](#method.with_size_hint).
+    pub fn set_exact_size(&mut self, len: u64) {
+        if self.is_channel() {
+            self.set_known_len(len);
+        } else {
+            let body = std::mem::replace(self, StreamBody::empty());
+            *self = body.with_size_hint(SizeHint::with_exact(len));
+        }
+    }
+}
+
+struct Take {
+    inner: StreamBody,
+    remaining: u64,
+}
+
+impl Body for Take {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        if self.remaining == 0 {
+            return Poll::Ready(None);
+        }
+
+        match Pin::new(&mut self.inner).poll_data(cx) {
+            Poll::Ready(Some(Ok(mut data))) => {
+                if data.remaining() as u64 > self.remaining {
+                    data.truncate(self.remaining as usize);
+                }
+                self.remaining -= data.remaining() as u64;
+                Poll::Ready(Some(Ok(data)))
+            }
+            poll_status => poll_status,
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        if self.remaining == 0 {
+            return Poll::Ready(Ok(None));
+        }
+
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.remaining == 0 || self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let inner_hint = self.inner.size_hint();
+
+        let mut hint = SizeHint::new();
+        hint.set_lower(inner_hint.lower().min(self.remaining));
+        hint.set_upper(inner_hint.upper().unwrap_or(self.remaining).min(self.remaining));
+        hint
+    }
+}
+
+struct LengthCheck {
+    inner: StreamBody,
+    len: u64,
+    remaining: u64,
+    // Whether the source must produce exactly `len` bytes, or at most.
+    exact: bool,
+    failed: bool,
+}
+
+impl LengthCheck {
+    fn fail(&mut self, kind: io::ErrorKind, message: String) -> Poll<Option<Result<StreamData, io::Error>>> {
+        self.failed = true;
+        Poll::Ready(Some(Err(io::Error::new(
+            kind,
+            format!("{}: StreamBody: {}", env!("CARGO_PKG_NAME"), message),
+        ))))
+    }
+}
+
+impl Body for LengthCheck {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        if self.failed {
+            return Poll::Ready(None);
+        }
+
+        match Pin::new(&mut self.inner).poll_data(cx) {
+            Poll::Ready(Some(Ok(data))) => {
+                if data.remaining() as u64 > self.remaining {
+                    let message = format!("The source produced more than the announced {} bytes", self.len);
+                    return self.fail(io::ErrorKind::InvalidData, message);
+                }
+                self.remaining -= data.remaining() as u64;
+                Poll::Ready(Some(Ok(data)))
+            }
+            Poll::Ready(None) if self.exact && self.remaining > 0 => {
+                let message = format!(
+                    "The source ended {} bytes before the announced {} bytes",
+                    self.remaining, self.len
+                );
+                self.fail(io::ErrorKind::UnexpectedEof, message)
+            }
+            poll_status => poll_status,
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        if self.failed {
+            return Poll::Ready(Ok(None));
+        }
+
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.failed || self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        if self.failed {
+            return SizeHint::with_exact(0);
+        }
+        if self.exact {
+            return SizeHint::with_exact(self.remaining);
+        }
+
+        let inner_hint = self.inner.size_hint();
+
+        let mut hint = SizeHint::new();
+        hint.set_lower(inner_hint.lower().min(self.remaining));
+        hint.set_upper(inner_hint.upper().unwrap_or(self.remaining).min(self.remaining));
+        hint
+    }
+}
+
+struct Skip {
+    inner: StreamBody,
+    remaining: u64,
+}
+
+impl Body for Skip {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        loop {
+            let mut data = match Pin::new(&mut self.inner).poll_data(cx) {
+                Poll::Ready(Some(Ok(data))) => data,
+                poll_status => return poll_status,
+            };
+
+            if self.remaining == 0 {
+                return Poll::Ready(Some(Ok(data)));
+            }
+
+            let len = data.remaining() as u64;
+            if len <= self.remaining {
+                // Dropping the chunk marks it as consumed, so the source can be polled again right away.
+                self.remaining -= len;
+                continue;
+            }
+
+            data.advance(self.remaining as usize);
+            self.remaining = 0;
+            return Poll::Ready(Some(Ok(data)));
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let inner_hint = self.inner.size_hint();
+
+        let mut hint = SizeHint::new();
+        hint.set_lower(inner_hint.lower().saturating_sub(self.remaining));
+        if let Some(upper) = inner_hint.upper() {
+            hint.set_upper(upper.saturating_sub(self.remaining));
+        }
+        hint
+    }
+}
+
+struct Prepend {
+    inner: StreamBody,
+    prefix: PrefixState,
+}
+
+enum PrefixState {
+    Lazy(LazyBytes),
+    Ready(Bytes),
+    Done,
+}
+
+type LazyBytes = Box<dyn FnOnce() -> Bytes + Send>;
+
+impl Body for Prepend {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let prefix = match std::mem::replace(&mut self.prefix, PrefixState::Done) {
+            PrefixState::Lazy(f) => f(),
+            PrefixState::Ready(prefix) => prefix,
+            PrefixState::Done => return Pin::new(&mut self.inner).poll_data(cx),
+        };
+
+        if prefix.is_empty() {
+            return Pin::new(&mut self.inner).poll_data(cx);
+        }
+        Poll::Ready(Some(Ok(StreamData::from_bytes(prefix))))
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self.prefix {
+            PrefixState::Lazy(_) => false,
+            PrefixState::Ready(ref prefix) => prefix.is_empty() && self.inner.is_end_stream(),
+            PrefixState::Done => self.inner.is_end_stream(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let inner_hint = self.inner.size_hint();
+
+        let prefix_len = match self.prefix {
+            // The prefix may be empty, but its length isn't known.
+            PrefixState::Lazy(_) => {
+                let mut hint = SizeHint::new();
+                hint.set_lower(inner_hint.lower());
+                return hint;
+            }
+            PrefixState::Ready(ref prefix) => prefix.len() as u64,
+            PrefixState::Done => 0,
+        };
+
+        size_hint_with_held(inner_hint, prefix_len)
+    }
+}
+
+struct Append {
+    inner: StreamBody,
+    suffix: SuffixState,
+}
+
+enum SuffixState {
+    Lazy(LazyBytes),
+    Ready(Bytes),
+    Done,
+}
+
+impl Body for Append {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        match Pin::new(&mut self.inner).poll_data(cx) {
+            Poll::Ready(None) => {}
+            Poll::Ready(Some(Err(err))) => {
+                self.suffix = SuffixState::Done;
+                return Poll::Ready(Some(Err(err)));
+            }
+            poll_status => return poll_status,
+        }
+
+        let suffix = match std::mem::replace(&mut self.suffix, SuffixState::Done) {
+            SuffixState::Lazy(f) => f(),
+            SuffixState::Ready(suffix) => suffix,
+            SuffixState::Done => return Poll::Ready(None),
+        };
+
+        if suffix.is_empty() {
+            return Poll::Ready(None);
+        }
+        Poll::Ready(Some(Ok(StreamData::from_bytes(suffix))))
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self.suffix {
+            SuffixState::Lazy(_) => false,
+            SuffixState::Ready(ref suffix) => suffix.is_empty() && self.inner.is_end_stream(),
+            SuffixState::Done => self.inner.is_end_stream(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let inner_hint = self.inner.size_hint();
+
+        let suffix_len = match self.suffix {
+            SuffixState::Lazy(_) => {
+                let mut hint = SizeHint::new();
+                hint.set_lower(inner_hint.lower());
+                return hint;
+            }
+            SuffixState::Ready(ref suffix) => suffix.len() as u64,
+            SuffixState::Done => 0,
+        };
+
+        size_hint_with_held(inner_hint, suffix_len)
+    }
+}
+
+struct WithSizeHint {
+    inner: StreamBody,
+    hint: SizeHint,
+    emitted: u64,
+}
+
+impl Body for WithSizeHint {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        match Pin::new(&mut self.inner).poll_data(cx) {
+            Poll::Ready(Some(Ok(data))) => {
+                self.emitted += data.remaining() as u64;
+                Poll::Ready(Some(Ok(data)))
+            }
+            poll_status => poll_status,
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        if self.inner.is_end_stream() {
+            return SizeHint::with_exact(0);
+        }
+
+        let mut hint = SizeHint::new();
+        hint.set_lower(self.hint.lower().saturating_sub(self.emitted));
+        if let Some(upper) = self.hint.upper() {
+            hint.set_upper(upper.saturating_sub(self.emitted));
+        }
+        hint
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello_world() -> StreamBody {
+        StreamBody::concat(vec![StreamBody::from("hello "), StreamBody::from("world")])
+    }
+
+    async fn chunks(mut body: StreamBody) -> Vec<Vec<u8>> {
+        let mut chunks = Vec::new();
+        while let Some(data) = body.data().await {
+            chunks.push(data.unwrap().bytes().to_vec());
+        }
+        chunks
+    }
+
+    #[tokio::test]
+    async fn take_cuts_the_chunk_crossing_the_limit() {
+        let body = hello_world().take(8);
+        assert_eq!(body.size_hint().exact(), Some(8));
+        assert_eq!(chunks(body).await, vec![b"hello ".to_vec(), b"wo".to_vec()]);
+
+        let body = hello_world().take(6);
+        assert_eq!(chunks(body).await, vec![b"hello ".to_vec()]);
+
+        let body = hello_world().take(100);
+        assert_eq!(body.size_hint().exact(), Some(11));
+        assert_eq!(chunks(body).await, vec![b"hello ".to_vec(), b"world".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn take_zero_ends_without_polling_the_source() {
+        // The writer stays open, so polling the source would never complete.
+        let (_writer, body) = StreamBody::channel();
+        let mut body = body.take(0);
+        assert!(body.is_end_stream());
+        assert_eq!(body.size_hint().exact(), Some(0));
+        assert!(body.data().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn take_bounds_the_hint_of_an_unknown_length() {
+        let (_writer, body) = StreamBody::channel();
+        let hint = body.take(10).size_hint();
+        assert_eq!(hint.lower(), 0);
+        assert_eq!(hint.upper(), Some(10));
+    }
+
+    #[tokio::test]
+    async fn skip_starts_in_the_middle_of_a_chunk() {
+        let body = hello_world().skip(8);
+        assert_eq!(body.size_hint().exact(), Some(3));
+        assert_eq!(chunks(body).await, vec![b"rld".to_vec()]);
+
+        let body = hello_world().skip(6);
+        assert_eq!(chunks(body).await, vec![b"world".to_vec()]);
+
+        let body = hello_world().skip(100);
+        assert_eq!(body.size_hint().exact(), Some(0));
+        assert!(chunks(body).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn skip_zero_emits_the_source_as_is() {
+        let body = hello_world().skip(0);
+        assert_eq!(body.size_hint().exact(), Some(11));
+        assert_eq!(chunks(body).await, vec![b"hello ".to_vec(), b"world".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn skip_then_take_emits_a_range() {
+        let body = hello_world().skip(3).take(5);
+        assert_eq!(body.size_hint().exact(), Some(5));
+        assert_eq!(chunks(body).await, vec![b"lo ".to_vec(), b"wo".to_vec()]);
+    }
+}
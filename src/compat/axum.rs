@@ -0,0 +1,71 @@
+use super::http_body_1::convert_header_map;
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use axum_core::body::Body;
+use axum_core::response::{IntoResponse, Response};
+use bytes_1::Bytes;
+use http_body_1::{Frame, SizeHint};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io;
+
+impl From<StreamBody> for Body {
+    /// Converts the body into an axum body, whose data frames own the chunks of the body without copying them.
+    fn from(body: StreamBody) -> Body {
+        Body::new(IntoBytes { body })
+    }
+}
+
+impl IntoResponse for StreamBody {
+    /// Creates a `200 OK` response with the body and its advisory [headers](./struct.StreamBody.html#method.headers),
+    /// like [StreamBody::into_response](./struct.StreamBody.html#method.into_response) does for hyper.
+    ///
+    /// A handler can return the body as is. Calling `body.into_response()` picks the inherent method, so the trait
+    /// method is called as `IntoResponse::into_response(body)`.
+    fn into_response(mut self) -> Response {
+        let headers = convert_header_map(std::mem::take(&mut self.headers));
+        let mut res = Response::new(Body::from(self));
+        *res.headers_mut() = headers;
+        res
+    }
+}
+
+/// Maps the data frames of a body to `bytes` 1.x `Bytes`, which axum requires.
+struct IntoBytes {
+    body: StreamBody,
+}
+
+impl http_body_1::Body for IntoBytes {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match Pin::new(&mut self.body).poll_frame(cx) {
+            Poll::Ready(Some(Ok(frame))) => Poll::Ready(Some(Ok(frame.map_data(into_bytes)))),
+            Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(err))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        http_body_1::Body::is_end_stream(&self.body)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        http_body_1::Body::size_hint(&self.body)
+    }
+}
+
+/// Owns a chunk on behalf of the `Bytes` handed to axum, so the buffer of the body is only reused once they're dropped.
+struct Chunk(StreamData);
+
+impl AsRef<[u8]> for Chunk {
+    fn as_ref(&self) -> &[u8] {
+        bytes::Buf::bytes(&self.0)
+    }
+}
+
+fn into_bytes(data: StreamData) -> Bytes {
+    Bytes::from_owner(Chunk(data))
+}
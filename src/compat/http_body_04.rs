@@ -0,0 +1,38 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use http::{HeaderMap, HeaderValue};
+use http_body_04::{Body, SizeHint};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io;
+
+impl Body for StreamBody {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        http_body::Body::poll_data(self, cx)
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        http_body::Body::poll_trailers(self, cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        http_body::Body::is_end_stream(self)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let hint = http_body::Body::size_hint(self);
+
+        let mut converted = SizeHint::new();
+        converted.set_lower(hint.lower());
+        if let Some(upper) = hint.upper() {
+            converted.set_upper(upper);
+        }
+        converted
+    }
+}
@@ -0,0 +1,220 @@
+use crate::abort;
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use bytes::Bytes;
+use bytes_1::Buf;
+use http_body_1::{Body, Frame, SizeHint};
+use std::error::Error;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io;
+
+impl StreamBody {
+    /// Wraps an `http-body` 1.x body, e.g. an upstream `hyper::body::Incoming` which a proxy forwards.
+    ///
+    /// The `http-body` 1.x implementation of the returned body forwards the frames of the wrapped one as they are,
+    /// including frame kinds unknown to this crate, so a proxy stays correct as the ecosystem adds new ones. Only the
+    /// data and the trailers go through the `http-body` 0.3 implementation and the adapters, e.g.
+    /// [gzip](#method.gzip), as its model has no room for the other frames.
+    ///
+    /// The data frames are converted to [StreamData](./struct.StreamData.html) without copying when their buffer isn't
+    /// shared, and the errors are converted to `io::Error`s.
+    pub fn wrap_body<B>(body: B) -> StreamBody
+    where
+        B: Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn Error + Send + Sync>>,
+    {
+        StreamBody::wrap_frames(FramesInner {
+            body: Box::pin(ConvertFrames { body: Box::pin(body) }),
+            trailers: None,
+        })
+    }
+}
+
+/// An `http-body` 1.x body wrapped by [StreamBody::wrap_body](./struct.StreamBody.html#method.wrap_body).
+pub(crate) struct FramesInner {
+    body: Pin<Box<dyn Body<Data = StreamData, Error = io::Error> + Send + 'static>>,
+    // The trailers met while polling the data through the `http-body` 0.3 implementation.
+    trailers: Option<http::HeaderMap>,
+}
+
+impl FramesInner {
+    /// Polls the data in the `http-body` 0.3 way, the trailers being kept for `poll_trailers`.
+    pub(crate) fn poll_data(&mut self, cx: &mut Context) -> Poll<Option<Result<StreamData, io::Error>>> {
+        loop {
+            let frame = match ready!(self.body.as_mut().poll_frame(cx)) {
+                Some(Ok(frame)) => frame,
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => return Poll::Ready(None),
+            };
+
+            let frame = match frame.into_data() {
+                Ok(data) => return Poll::Ready(Some(Ok(data))),
+                Err(frame) => frame,
+            };
+
+            // Any other frame kind can't be represented by `http-body` 0.3, so it's skipped.
+            if let Ok(trailers) = frame.into_trailers() {
+                self.trailers = Some(convert_header_map_back(trailers));
+                return Poll::Ready(None);
+            }
+        }
+    }
+
+    pub(crate) fn poll_trailers(&mut self) -> Poll<Result<Option<http::HeaderMap>, io::Error>> {
+        Poll::Ready(Ok(self.trailers.take()))
+    }
+
+    pub(crate) fn is_end_stream(&self) -> bool {
+        self.trailers.is_none() && self.body.is_end_stream()
+    }
+
+    pub(crate) fn size_hint(&self) -> http_body::SizeHint {
+        let hint = self.body.size_hint();
+
+        let mut converted = http_body::SizeHint::new();
+        converted.set_lower(hint.lower());
+        if let Some(upper) = hint.upper() {
+            converted.set_upper(upper);
+        }
+        converted
+    }
+}
+
+/// Converts the data and the errors of a wrapped body, leaving the other frames untouched.
+struct ConvertFrames<B> {
+    body: Pin<Box<B>>,
+}
+
+impl<B> Body for ConvertFrames<B>
+where
+    B: Body,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match ready!(self.body.as_mut().poll_frame(cx)) {
+            Some(Ok(frame)) => Poll::Ready(Some(Ok(frame.map_data(into_stream_data)))),
+            Some(Err(err)) => Poll::Ready(Some(Err(io::Error::new(io::ErrorKind::Other, err)))),
+            None => Poll::Ready(None),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+fn into_stream_data<D: Buf>(mut data: D) -> StreamData {
+    let len = data.remaining();
+    // Both generations of `Bytes` convert from and into a `Vec` without copying when the buffer isn't shared.
+    let bytes = Vec::<u8>::from(data.copy_to_bytes(len));
+    StreamData::from_bytes(Bytes::from(bytes))
+}
+
+impl Body for StreamBody {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        if self.frames_mut().is_some() {
+            return poll_wrapped_frame(self, cx);
+        }
+
+        match http_body::Body::poll_data(self.as_mut(), cx) {
+            Poll::Ready(Some(Ok(data))) => return Poll::Ready(Some(Ok(Frame::data(data)))),
+            Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+            Poll::Ready(None) => {}
+            Poll::Pending => return Poll::Pending,
+        }
+
+        match http_body::Body::poll_trailers(self, cx) {
+            Poll::Ready(Ok(Some(trailers))) => Poll::Ready(Some(Ok(Frame::trailers(convert_header_map(trailers))))),
+            Poll::Ready(Ok(None)) => Poll::Ready(None),
+            Poll::Ready(Err(err)) => Poll::Ready(Some(Err(err))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        http_body::Body::is_end_stream(self)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let hint = http_body::Body::size_hint(self);
+
+        let mut converted = SizeHint::new();
+        converted.set_lower(hint.lower());
+        if let Some(upper) = hint.upper() {
+            converted.set_upper(upper);
+        }
+        converted
+    }
+}
+
+/// Forwards the frames of a body created by `wrap_body` as they are, with the same fusing and aborting as `poll_data`.
+fn poll_wrapped_frame(
+    mut body: Pin<&mut StreamBody>,
+    cx: &mut Context,
+) -> Poll<Option<Result<Frame<StreamData>, io::Error>>> {
+    let me = &mut *body;
+    if me.terminated {
+        return Poll::Ready(None);
+    }
+
+    if let Some(ref shared) = me.abort {
+        if let Some(err) = abort::take_abort_error(shared, cx) {
+            me.terminated = true;
+            return Poll::Ready(Some(Err(err)));
+        }
+    }
+
+    let poll_status = match me.frames_mut() {
+        Some(inner) => inner.body.as_mut().poll_frame(cx),
+        None => Poll::Ready(None),
+    };
+    if let Poll::Ready(None) | Poll::Ready(Some(Err(_))) = poll_status {
+        me.terminated = true;
+    }
+    poll_status
+}
+
+/// Converts the trailers from the `http` 0.2 types used internally to the `http` 1.x ones.
+pub(crate) fn convert_header_map(map: http::HeaderMap) -> http_1::HeaderMap {
+    let mut converted = http_1::HeaderMap::with_capacity(map.len());
+
+    for (name, value) in map.iter() {
+        let name = http_1::HeaderName::from_bytes(name.as_str().as_bytes());
+        let value = http_1::HeaderValue::from_bytes(value.as_bytes());
+
+        // Both generations accept exactly the same names and values.
+        if let (Ok(name), Ok(value)) = (name, value) {
+            converted.append(name, value);
+        }
+    }
+
+    converted
+}
+
+/// Converts the trailers from the `http` 1.x types of a wrapped body to the `http` 0.2 ones.
+fn convert_header_map_back(map: http_1::HeaderMap) -> http::HeaderMap {
+    let mut converted = http::HeaderMap::with_capacity(map.len());
+
+    for (name, value) in map.iter() {
+        let name = http::header::HeaderName::from_bytes(name.as_str().as_bytes());
+        let value = http::HeaderValue::from_bytes(value.as_bytes());
+
+        if let (Ok(name), Ok(value)) = (name, value) {
+            converted.append(name, value);
+        }
+    }
+
+    converted
+}
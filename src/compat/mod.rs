@@ -0,0 +1,28 @@
+//! Implementations of the newer generations of the `http-body` and `bytes` traits, so `StreamBody` can be used with
+//! hyper 0.14 and hyper 1.x as well.
+
+#[cfg(feature = "axum")]
+mod axum;
+#[cfg(feature = "http-body-04")]
+mod http_body_04;
+#[cfg(feature = "http-body-1")]
+mod http_body_1;
+
+#[cfg(feature = "http-body-1")]
+pub(crate) use self::http_body_1::FramesInner;
+
+use crate::data::StreamData;
+
+impl bytes_1::Buf for StreamData {
+    fn remaining(&self) -> usize {
+        bytes::Buf::remaining(self)
+    }
+
+    fn chunk(&self) -> &[u8] {
+        bytes::Buf::bytes(self)
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        bytes::Buf::advance(self, cnt)
+    }
+}
@@ -0,0 +1,65 @@
+use crate::data::StreamData;
+use crate::state::State;
+use bytes::Bytes;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+// The compressed bytes an encoder may have emitted but not seen consumed yet before it stops reading its source.
+pub(crate) const DEFAULT_MAX_OUTSTANDING: usize = 64 * 1024;
+
+/// The compressed chunks emitted by an encoder which the consumer didn't drop yet, so the encoder doesn't read its
+/// source ahead of what the consumer drained.
+pub(crate) struct Outstanding {
+    chunks: Vec<(usize, Arc<Mutex<State>>)>,
+    max_bytes: usize,
+}
+
+impl Outstanding {
+    pub(crate) fn new(max_bytes: usize) -> Outstanding {
+        Outstanding {
+            chunks: Vec::new(),
+            max_bytes,
+        }
+    }
+
+    /// Turns compressed data into a chunk, which counts as outstanding until it's dropped.
+    pub(crate) fn track(&mut self, output: Vec<u8>) -> StreamData {
+        let mut state = State::new();
+        state.is_current_stream_data_consumed = false;
+        let state = Arc::new(Mutex::new(state));
+
+        self.chunks.push((output.len(), Arc::clone(&state)));
+        StreamData::new(Bytes::from(output), state)
+    }
+
+    /// Returns `Ready` once the outstanding chunks are within the bound, and otherwise wakes the task up once one of
+    /// them is dropped.
+    pub(crate) fn poll_ready(&mut self, cx: &mut Context) -> Poll<()> {
+        self.chunks.retain(|(_, state)| !is_consumed(state));
+        if self.bytes() <= self.max_bytes {
+            return Poll::Ready(());
+        }
+
+        for (_, state) in self.chunks.iter() {
+            let mut state = state.lock().unwrap_or_else(|err| err.into_inner());
+            if state.is_current_stream_data_consumed {
+                // Dropped before the registration.
+                cx.waker().wake_by_ref();
+                break;
+            }
+            state.park(cx);
+        }
+        Poll::Pending
+    }
+
+    fn bytes(&self) -> usize {
+        self.chunks.iter().map(|&(len, _)| len).sum()
+    }
+}
+
+fn is_consumed(state: &Mutex<State>) -> bool {
+    state
+        .lock()
+        .map(|state| state.is_current_stream_data_consumed)
+        .unwrap_or(true)
+}
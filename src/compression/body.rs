@@ -0,0 +1,289 @@
+use super::backpressure::{Outstanding, DEFAULT_MAX_OUTSTANDING};
+use super::feedback::CompressionFeedback;
+use super::size::SizeTransform;
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use bytes::Buf;
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Crc};
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::io::Write;
+use std::mem;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io;
+
+// The gzip header without a file name, timestamp or extra flags, and with an unknown OS.
+const GZIP_HEADER: [u8; 10] = [0x1f, 0x8b, 0x08, 0, 0, 0, 0, 0, 0, 0xff];
+
+// The CRC-32 and the length of the data ending a gzip stream.
+const GZIP_TRAILER_LEN: u64 = 8;
+
+type FeedbackCallback = Box<dyn FnMut(&CompressionFeedback) -> u32 + Send>;
+
+impl StreamBody {
+    /// Gzip-compresses the body with the given compression level, from 0 (no compression) to 9 (best compression).
+    ///
+    /// The compressed data is emitted whenever the encoder produces output and whenever the source has to wait, so
+    /// live streams don't stall in the encoder. Don't forget to set the `Content-Encoding: gzip` header, or use
+    /// [CompressionPolicy](./struct.CompressionPolicy.html) which takes care of the headers.
+    ///
+    /// The encoder stops reading the source while more than 64 KiB of compressed chunks wait to be consumed, so a
+    /// stalled client doesn't make the server buffer the whole compressed body, see
+    /// [compressed_with_max_outstanding](#method.compressed_with_max_outstanding) to change the bound.
+    ///
+    /// At level 0 the data is only stored, so the size hint keeps the lower bound of the source plus the gzip framing,
+    /// see [SizeTransform](./enum.SizeTransform.html).
+    pub fn gzip(self, level: u32) -> StreamBody {
+        self.gzip_with_max_outstanding(level, DEFAULT_MAX_OUTSTANDING)
+    }
+
+    pub(crate) fn gzip_with_max_outstanding(self, level: u32, max_outstanding_bytes: usize) -> StreamBody {
+        self.wrap_with(|inner| Gzip::new(inner, level, None, max_outstanding_bytes))
+    }
+
+    /// Same as [gzip](#method.gzip), but calls `feedback` with the achieved ratio and encoding time of every emitted
+    /// chunk. The callback returns the level to compress the following data with, so it can lower the level when the
+    /// ratio is poor or the CPU is constrained, and return [level](./struct.CompressionFeedback.html#method.level)
+    /// to keep it.
+    ///
+    /// Changing the level flushes the encoder, which costs a few bytes, so the callback shouldn't flip it on every
+    /// chunk.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use stream_body::StreamBody;
+    ///
+    /// let body = StreamBody::from("hello").gzip_with_feedback(9, |feedback| {
+    ///     if feedback.total_input_bytes() > 64 * 1024 && feedback.total_ratio() > 0.9 {
+    ///         // The data barely compresses, don't waste CPU on it.
+    ///         1
+    ///     } else {
+    ///         feedback.level()
+    ///     }
+    /// });
+    /// ```
+    pub fn gzip_with_feedback<F>(self, level: u32, feedback: F) -> StreamBody
+    where
+        F: FnMut(&CompressionFeedback) -> u32 + Send + 'static,
+    {
+        self.wrap_with(|inner| Gzip::new(inner, level, Some(Box::new(feedback)), DEFAULT_MAX_OUTSTANDING))
+    }
+}
+
+struct Gzip {
+    inner: StreamBody,
+    // The raw deflate encoder, which is taken once the source reached its end and the gzip trailer is emitted. The
+    // gzip framing is written by hand, so the encoder can be replaced to change the level mid-stream.
+    encoder: Option<DeflateEncoder<Vec<u8>>>,
+    level: u32,
+    crc: Crc,
+    // Whether data was written to the encoder since the last sync flush.
+    unflushed: bool,
+    feedback: Option<FeedbackCallback>,
+    input_bytes: u64,
+    encode_time: Duration,
+    total_input_bytes: u64,
+    total_output_bytes: u64,
+    outstanding: Outstanding,
+}
+
+impl Gzip {
+    fn new(inner: StreamBody, level: u32, feedback: Option<FeedbackCallback>, max_outstanding_bytes: usize) -> Gzip {
+        let level = level.min(9);
+        let mut output = Vec::new();
+        output.extend_from_slice(&GZIP_HEADER);
+
+        Gzip {
+            inner,
+            encoder: Some(DeflateEncoder::new(output, Compression::new(level))),
+            level,
+            crc: Crc::new(),
+            unflushed: false,
+            feedback,
+            input_bytes: 0,
+            encode_time: Duration::default(),
+            total_input_bytes: 0,
+            total_output_bytes: 0,
+            outstanding: Outstanding::new(max_outstanding_bytes),
+        }
+    }
+
+    /// Runs an encoder operation, accounting the time it takes.
+    fn encode<T>(&mut self, f: impl FnOnce(&mut DeflateEncoder<Vec<u8>>) -> io::Result<T>) -> io::Result<T> {
+        let started_at = Instant::now();
+        let result = match self.encoder {
+            Some(ref mut encoder) => f(encoder),
+            None => unreachable!("the encoder is only used before the end of the source"),
+        };
+        self.encode_time += started_at.elapsed();
+        result
+    }
+
+    /// Moves the compressed data produced so far into a chunk.
+    fn take_output(&mut self) -> io::Result<Option<StreamData>> {
+        let output = match self.encoder {
+            Some(ref mut encoder) => mem::take(encoder.get_mut()),
+            None => return Ok(None),
+        };
+        self.emit(output)
+    }
+
+    /// Turns the compressed data into a chunk, reporting it to the feedback callback which may change the level.
+    fn emit(&mut self, output: Vec<u8>) -> io::Result<Option<StreamData>> {
+        if output.is_empty() {
+            return Ok(None);
+        }
+
+        self.total_input_bytes += self.input_bytes;
+        self.total_output_bytes += output.len() as u64;
+
+        let level = match self.feedback {
+            Some(ref mut feedback) => feedback(&CompressionFeedback {
+                level: self.level,
+                input_bytes: self.input_bytes,
+                output_bytes: output.len() as u64,
+                encode_time: self.encode_time,
+                total_input_bytes: self.total_input_bytes,
+                total_output_bytes: self.total_output_bytes,
+            })
+            .min(9),
+            None => self.level,
+        };
+
+        self.input_bytes = 0;
+        self.encode_time = Duration::default();
+
+        if level != self.level && self.encoder.is_some() {
+            self.change_level(level)?;
+        }
+
+        Ok(Some(self.outstanding.track(output)))
+    }
+
+    /// Continues the deflate stream with a new encoder using the given level.
+    fn change_level(&mut self, level: u32) -> io::Result<()> {
+        // The sync flush ends the current deflate block on a byte boundary, so the blocks of the new encoder can
+        // simply follow. The flushed data is emitted with the next chunk.
+        if self.unflushed {
+            self.encode(|encoder| encoder.flush())?;
+            self.unflushed = false;
+        }
+
+        if let Some(mut encoder) = self.encoder.take() {
+            // The final empty block the old encoder writes once dropped goes to its emptied buffer and is discarded.
+            let output = mem::take(encoder.get_mut());
+            self.encoder = Some(DeflateEncoder::new(output, Compression::new(level)));
+        }
+        self.level = level;
+        Ok(())
+    }
+
+    /// Declares how the encoder changes the size of the data still to be encoded.
+    fn size_transform(&self) -> SizeTransform {
+        if self.level > 0 {
+            return SizeTransform::Unknown;
+        }
+
+        // The stored blocks contain the data as is, plus headers depending on the flushes.
+        let header_len = if self.total_output_bytes == 0 {
+            GZIP_HEADER.len() as u64
+        } else {
+            0
+        };
+        SizeTransform::Expanding {
+            overhead: header_len + GZIP_TRAILER_LEN,
+        }
+    }
+
+    /// Ends the deflate stream and appends the gzip trailer.
+    fn finish(&mut self) -> io::Result<Option<StreamData>> {
+        let encoder = match self.encoder.take() {
+            Some(encoder) => encoder,
+            None => return Ok(None),
+        };
+
+        let started_at = Instant::now();
+        let mut output = encoder.finish()?;
+        self.encode_time += started_at.elapsed();
+
+        output.extend_from_slice(&self.crc.sum().to_le_bytes());
+        output.extend_from_slice(&self.crc.amount().to_le_bytes());
+        self.emit(output)
+    }
+}
+
+impl Body for Gzip {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let me = &mut *self;
+
+        loop {
+            if me.encoder.is_none() {
+                return Poll::Ready(None);
+            }
+            // The source is only read once the client drained the compressed data.
+            ready!(me.outstanding.poll_ready(cx));
+
+            match Pin::new(&mut me.inner).poll_data(cx) {
+                Poll::Ready(Some(Ok(data))) => {
+                    // Dropping the chunk right after copying it into the encoder lets the source continue.
+                    if let Err(err) = me.encode(|encoder| encoder.write_all(data.bytes())) {
+                        return Poll::Ready(Some(Err(err)));
+                    }
+                    me.crc.update(data.bytes());
+                    me.input_bytes += data.remaining() as u64;
+                    me.unflushed = true;
+
+                    match me.take_output() {
+                        Ok(Some(data)) => return Poll::Ready(Some(Ok(data))),
+                        Ok(None) => {}
+                        Err(err) => return Poll::Ready(Some(Err(err))),
+                    }
+                }
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                // The encoder is taken, so the body ends even if finishing fails.
+                Poll::Ready(None) => return Poll::Ready(me.finish().transpose()),
+                Poll::Pending => {
+                    if me.unflushed {
+                        // Emits a sync flush block, so the client can decompress everything received so far.
+                        if let Err(err) = me.encode(|encoder| encoder.flush()) {
+                            return Poll::Ready(Some(Err(err)));
+                        }
+                        me.unflushed = false;
+
+                        match me.take_output() {
+                            Ok(Some(data)) => return Poll::Ready(Some(Ok(data))),
+                            Ok(None) => {}
+                            Err(err) => return Poll::Ready(Some(Err(err))),
+                        }
+                    }
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.encoder.is_none() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        if self.encoder.is_none() {
+            return SizeHint::with_exact(0);
+        }
+        self.size_transform().apply(&self.inner.size_hint())
+    }
+}
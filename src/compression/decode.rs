@@ -0,0 +1,161 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use bytes::{Buf, Bytes};
+use flate2::write::{MultiGzDecoder, ZlibDecoder};
+use http::header::{self, HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::io::Write;
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io;
+
+impl StreamBody {
+    /// Decompresses a gzip-encoded body, e.g. an upstream response with `Content-Encoding: gzip` which a proxy
+    /// re-serves identity-encoded. Concatenated gzip members are decoded as one stream.
+    ///
+    /// The decompressed data is emitted as the compressed chunks arrive, so the body keeps streaming.
+    pub fn decode_gzip(self) -> StreamBody {
+        self.wrap_with(|inner| Decode {
+            inner,
+            decoder: Some(Decoder::Gzip(MultiGzDecoder::new(Vec::new()))),
+        })
+    }
+
+    /// Decompresses a body with `Content-Encoding: deflate`, i.e. the zlib format.
+    pub fn decode_deflate(self) -> StreamBody {
+        self.wrap_with(|inner| Decode {
+            inner,
+            decoder: Some(Decoder::Deflate(ZlibDecoder::new(Vec::new()))),
+        })
+    }
+
+    /// Decompresses the body according to the `Content-Encoding` of the given headers, and updates the headers for
+    /// the decoded body, i.e. removes `Content-Encoding` and `Content-Length`.
+    ///
+    /// The body and the headers are left unchanged for an encoding other than `gzip`, `x-gzip` and `deflate`, or
+    /// for several encodings.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use hyper::Response;
+    /// use stream_body::StreamBody;
+    ///
+    /// fn serve_decoded(upstream: Response<StreamBody>) -> Response<StreamBody> {
+    ///     let (mut parts, body) = upstream.into_parts();
+    ///     let body = body.decode_content(&mut parts.headers);
+    ///     Response::from_parts(parts, body)
+    /// }
+    /// ```
+    pub fn decode_content(self, headers: &mut HeaderMap<HeaderValue>) -> StreamBody {
+        let encoding = headers
+            .get(header::CONTENT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().to_ascii_lowercase());
+
+        let body = match encoding.as_deref() {
+            Some("gzip") | Some("x-gzip") => self.decode_gzip(),
+            Some("deflate") => self.decode_deflate(),
+            _ => return self,
+        };
+
+        headers.remove(header::CONTENT_ENCODING);
+        headers.remove(header::CONTENT_LENGTH);
+        body
+    }
+}
+
+enum Decoder {
+    Gzip(MultiGzDecoder<Vec<u8>>),
+    Deflate(ZlibDecoder<Vec<u8>>),
+}
+
+impl Decoder {
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            Decoder::Gzip(decoder) => decoder.write_all(data),
+            Decoder::Deflate(decoder) => decoder.write_all(data),
+        }
+    }
+
+    fn try_finish(&mut self) -> io::Result<()> {
+        match self {
+            Decoder::Gzip(decoder) => decoder.try_finish(),
+            Decoder::Deflate(decoder) => decoder.try_finish(),
+        }
+    }
+
+    /// Moves the decompressed data produced so far into a chunk.
+    fn take_output(&mut self) -> Option<StreamData> {
+        let output = match self {
+            Decoder::Gzip(decoder) => decoder.get_mut(),
+            Decoder::Deflate(decoder) => decoder.get_mut(),
+        };
+        if output.is_empty() {
+            return None;
+        }
+
+        Some(StreamData::from_bytes(Bytes::from(mem::take(output))))
+    }
+}
+
+struct Decode {
+    inner: StreamBody,
+    // The decoder is taken once the source reached its end and the remaining output is emitted.
+    decoder: Option<Decoder>,
+}
+
+impl Body for Decode {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let me = &mut *self;
+
+        loop {
+            let decoder = match me.decoder {
+                Some(ref mut decoder) => decoder,
+                None => return Poll::Ready(None),
+            };
+
+            match Pin::new(&mut me.inner).poll_data(cx) {
+                Poll::Ready(Some(Ok(data))) => {
+                    if let Err(err) = decoder.write_all(data.bytes()) {
+                        me.decoder = None;
+                        return Poll::Ready(Some(Err(err)));
+                    }
+
+                    if let Some(data) = decoder.take_output() {
+                        return Poll::Ready(Some(Ok(data)));
+                    }
+                }
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                Poll::Ready(None) => {
+                    let result = decoder.try_finish().map(|_| decoder.take_output());
+                    me.decoder = None;
+                    return Poll::Ready(result.transpose());
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.decoder.is_none() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        if self.decoder.is_none() {
+            return SizeHint::with_exact(0);
+        }
+        SizeHint::default()
+    }
+}
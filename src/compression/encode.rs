@@ -0,0 +1,233 @@
+use super::backpressure::{Outstanding, DEFAULT_MAX_OUTSTANDING};
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use bytes::Buf;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::io::Write;
+use std::mem;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io;
+
+// The window size recommended for general purpose data, as used by the `brotli` command line tool.
+#[cfg(feature = "brotli")]
+const BROTLI_WINDOW_BITS: u32 = 22;
+
+// The quality most servers use for on-the-fly compression, the higher ones are too slow for live responses.
+#[cfg(feature = "brotli")]
+const BROTLI_QUALITY: u32 = 5;
+
+/// A content coding a body can be compressed with via [StreamBody::compressed](./struct.StreamBody.html#method.compressed).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// The gzip format.
+    Gzip,
+    /// The zlib format, which is what `Content-Encoding: deflate` means.
+    Deflate,
+    /// The brotli format, it requires the `brotli` feature.
+    #[cfg(feature = "brotli")]
+    Brotli,
+}
+
+impl Encoding {
+    /// Returns the `Content-Encoding` value of the coding.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => "br",
+        }
+    }
+
+    /// Returns the `Content-Encoding` header value of the coding.
+    pub fn header_value(&self) -> HeaderValue {
+        HeaderValue::from_static(self.as_str())
+    }
+}
+
+impl StreamBody {
+    /// Compresses the body with the given coding at a level suited for on-the-fly compression, the chunks being
+    /// compressed as they are produced.
+    ///
+    /// Like with [gzip](#method.gzip), the compressed data is flushed whenever the source has to wait, and the source
+    /// isn't read while more than 64 KiB of compressed data wait to be consumed. Don't forget to set the
+    /// `Content-Encoding` header, e.g. to [Encoding::header_value](./enum.Encoding.html#method.header_value).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use hyper::Response;
+    /// use stream_body::{Encoding, StreamBody};
+    ///
+    /// let (writer, body) = StreamBody::channel();
+    ///
+    /// let mut res = Response::new(StreamBody::compressed(body, Encoding::Deflate));
+    /// res.headers_mut().insert("content-encoding", Encoding::Deflate.header_value());
+    /// ```
+    pub fn compressed(self, encoding: Encoding) -> StreamBody {
+        self.compressed_with_max_outstanding(encoding, DEFAULT_MAX_OUTSTANDING)
+    }
+
+    /// Same as [compressed](#method.compressed), but the source isn't read while more than `max_outstanding_bytes` of
+    /// compressed data wait to be consumed, instead of 64 KiB.
+    ///
+    /// The compressed chunks are tracked until the server drops them, so a stalled client keeps at most about that
+    /// much compressed data, plus the source chunk being encoded, buffered in the server for this body.
+    pub fn compressed_with_max_outstanding(self, encoding: Encoding, max_outstanding_bytes: usize) -> StreamBody {
+        let encoder = match encoding {
+            Encoding::Gzip => {
+                return self.gzip_with_max_outstanding(Compression::default().level(), max_outstanding_bytes)
+            }
+            Encoding::Deflate => Encoder::Deflate(ZlibEncoder::new(Vec::new(), Compression::default())),
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                4096,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW_BITS,
+            ))),
+        };
+
+        self.wrap_with(|inner| Encode {
+            inner,
+            encoder: Some(encoder),
+            unflushed: false,
+            outstanding: Outstanding::new(max_outstanding_bytes),
+        })
+    }
+}
+
+enum Encoder {
+    Deflate(ZlibEncoder<Vec<u8>>),
+    #[cfg(feature = "brotli")]
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+}
+
+impl Encoder {
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            Encoder::Deflate(encoder) => encoder.write_all(data),
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(encoder) => encoder.write_all(data),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Deflate(encoder) => encoder.flush(),
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(encoder) => encoder.flush(),
+        }
+    }
+
+    /// Moves the compressed data produced so far into a chunk.
+    fn take_output(&mut self, outstanding: &mut Outstanding) -> Option<StreamData> {
+        let output = match self {
+            Encoder::Deflate(encoder) => encoder.get_mut(),
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(encoder) => encoder.get_mut(),
+        };
+        into_chunk(mem::take(output), outstanding)
+    }
+
+    /// Ends the compressed stream and returns its remaining data.
+    fn finish(self, outstanding: &mut Outstanding) -> io::Result<Option<StreamData>> {
+        let output = match self {
+            Encoder::Deflate(encoder) => encoder.finish()?,
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(encoder) => encoder.into_inner(),
+        };
+        Ok(into_chunk(output, outstanding))
+    }
+}
+
+fn into_chunk(output: Vec<u8>, outstanding: &mut Outstanding) -> Option<StreamData> {
+    if output.is_empty() {
+        return None;
+    }
+    Some(outstanding.track(output))
+}
+
+struct Encode {
+    inner: StreamBody,
+    // The encoder is taken once the source reached its end and the end of the compressed stream is emitted.
+    encoder: Option<Encoder>,
+    // Whether data was written to the encoder since the last flush.
+    unflushed: bool,
+    outstanding: Outstanding,
+}
+
+impl Body for Encode {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let me = &mut *self;
+
+        loop {
+            let encoder = match me.encoder {
+                Some(ref mut encoder) => encoder,
+                None => return Poll::Ready(None),
+            };
+            // The source is only read once the client drained the compressed data.
+            ready!(me.outstanding.poll_ready(cx));
+
+            match Pin::new(&mut me.inner).poll_data(cx) {
+                Poll::Ready(Some(Ok(data))) => {
+                    if let Err(err) = encoder.write_all(data.bytes()) {
+                        return Poll::Ready(Some(Err(err)));
+                    }
+                    me.unflushed = true;
+
+                    if let Some(data) = encoder.take_output(&mut me.outstanding) {
+                        return Poll::Ready(Some(Ok(data)));
+                    }
+                }
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                Poll::Ready(None) => {
+                    let result = match me.encoder.take() {
+                        Some(encoder) => encoder.finish(&mut me.outstanding),
+                        None => Ok(None),
+                    };
+                    return Poll::Ready(result.transpose());
+                }
+                Poll::Pending => {
+                    if me.unflushed {
+                        // Flushes the encoder, so the client can decompress everything received so far.
+                        if let Err(err) = encoder.flush() {
+                            return Poll::Ready(Some(Err(err)));
+                        }
+                        me.unflushed = false;
+
+                        if let Some(data) = encoder.take_output(&mut me.outstanding) {
+                            return Poll::Ready(Some(Ok(data)));
+                        }
+                    }
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.encoder.is_none() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        if self.encoder.is_none() {
+            return SizeHint::with_exact(0);
+        }
+        SizeHint::default()
+    }
+}
@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+/// The compression statistics of a chunk emitted by the gzip adapter, passed to the callback of
+/// [StreamBody::gzip_with_feedback](./struct.StreamBody.html#method.gzip_with_feedback).
+#[derive(Debug, Clone)]
+pub struct CompressionFeedback {
+    pub(crate) level: u32,
+    pub(crate) input_bytes: u64,
+    pub(crate) output_bytes: u64,
+    pub(crate) encode_time: Duration,
+    pub(crate) total_input_bytes: u64,
+    pub(crate) total_output_bytes: u64,
+}
+
+impl CompressionFeedback {
+    /// The compression level the chunk was compressed with.
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    /// The number of uncompressed bytes which went into the chunk.
+    pub fn input_bytes(&self) -> u64 {
+        self.input_bytes
+    }
+
+    /// The number of compressed bytes of the chunk.
+    pub fn output_bytes(&self) -> u64 {
+        self.output_bytes
+    }
+
+    /// The achieved ratio of the chunk, i.e. compressed size divided by uncompressed size, so lower is better.
+    ///
+    /// It is `1.0` for chunks without input, e.g. the final one holding the gzip trailer.
+    pub fn ratio(&self) -> f64 {
+        ratio(self.input_bytes, self.output_bytes)
+    }
+
+    /// The time spent in the encoder for the chunk, i.e. the CPU time the compression took on the polling thread.
+    pub fn encode_time(&self) -> Duration {
+        self.encode_time
+    }
+
+    /// The number of uncompressed bytes which went into the body so far.
+    pub fn total_input_bytes(&self) -> u64 {
+        self.total_input_bytes
+    }
+
+    /// The number of compressed bytes emitted by the body so far.
+    pub fn total_output_bytes(&self) -> u64 {
+        self.total_output_bytes
+    }
+
+    /// The achieved ratio of the body so far.
+    pub fn total_ratio(&self) -> f64 {
+        ratio(self.total_input_bytes, self.total_output_bytes)
+    }
+}
+
+fn ratio(input_bytes: u64, output_bytes: u64) -> f64 {
+    if input_bytes == 0 {
+        return 1.0;
+    }
+    output_bytes as f64 / input_bytes as f64
+}
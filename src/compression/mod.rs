@@ -0,0 +1,14 @@
+pub use self::encode::Encoding;
+pub use self::feedback::CompressionFeedback;
+pub use self::policy::CompressionPolicy;
+pub use self::size::SizeTransform;
+pub use self::writer::GzipWriter;
+
+mod backpressure;
+mod body;
+mod decode;
+mod encode;
+mod feedback;
+mod policy;
+mod size;
+mod writer;
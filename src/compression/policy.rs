@@ -0,0 +1,154 @@
+use super::backpressure::DEFAULT_MAX_OUTSTANDING;
+use crate::body::StreamBody;
+use flate2::Compression;
+use http::header::{self, HeaderMap, HeaderValue};
+use http_body::Body;
+
+const DEFAULT_MIN_SIZE: u64 = 1024;
+
+/// Decides whether a response body is worth compressing and inserts the gzip adapter only then.
+///
+/// A body is compressed when the client accepts gzip, the response isn't encoded already, its `Content-Type` is a
+/// compressible one (text, JSON, XML, JavaScript, SVG and the like) and its size hint doesn't rule out reaching the
+/// minimum size. So tiny bodies, e.g. the ones created from owned bytes, stay on the fast uncompressed path, while
+/// bodies of unknown length are compressed.
+///
+/// # Examples
+///
+/// ```no_run
+/// use hyper::{Body, Request, Response};
+/// use stream_body::{CompressionPolicy, StreamBody};
+///
+/// fn respond(req: &Request<Body>, body: StreamBody) -> Response<StreamBody> {
+///     let mut res = Response::new(body);
+///     res.headers_mut().insert("content-type", "application/json".parse().unwrap());
+///
+///     let (mut parts, body) = res.into_parts();
+///     let body = CompressionPolicy::new().apply(body, req.headers(), &mut parts.headers);
+///     Response::from_parts(parts, body)
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct CompressionPolicy {
+    min_size: u64,
+    level: u32,
+    max_outstanding_bytes: usize,
+}
+
+impl CompressionPolicy {
+    /// Creates a policy which compresses bodies of at least 1 KiB with the default compression level.
+    pub fn new() -> CompressionPolicy {
+        CompressionPolicy {
+            min_size: DEFAULT_MIN_SIZE,
+            level: Compression::default().level(),
+            max_outstanding_bytes: DEFAULT_MAX_OUTSTANDING,
+        }
+    }
+
+    /// Sets the minimum body size worth compressing.
+    pub fn with_min_size(mut self, min_size: u64) -> CompressionPolicy {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Sets the compression level, from 0 (no compression) to 9 (best compression).
+    pub fn with_level(mut self, level: u32) -> CompressionPolicy {
+        self.level = level.min(9);
+        self
+    }
+
+    /// Sets how much compressed data the client may leave unconsumed before the source stops being read, 64 KiB by
+    /// default, see [StreamBody::compressed_with_max_outstanding](./struct.StreamBody.html#method.compressed_with_max_outstanding).
+    pub fn with_max_outstanding_bytes(mut self, max_outstanding_bytes: usize) -> CompressionPolicy {
+        self.max_outstanding_bytes = max_outstanding_bytes;
+        self
+    }
+
+    /// Returns whether a body with the given response headers should be compressed for a request with the given
+    /// headers.
+    pub fn should_compress(&self, body: &StreamBody, req_headers: &HeaderMap, res_headers: &HeaderMap) -> bool {
+        if res_headers.contains_key(header::CONTENT_ENCODING) || !accepts_gzip(req_headers) {
+            return false;
+        }
+
+        let compressible = res_headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(is_compressible)
+            .unwrap_or(false);
+        if !compressible {
+            return false;
+        }
+
+        match body.size_hint().upper() {
+            Some(upper) => upper >= self.min_size,
+            None => true,
+        }
+    }
+
+    /// Wraps the body in the gzip adapter if [should_compress](#method.should_compress) says so, and updates the
+    /// `Content-Encoding`, `Content-Length` and `Vary` response headers accordingly.
+    pub fn apply(&self, body: StreamBody, req_headers: &HeaderMap, res_headers: &mut HeaderMap) -> StreamBody {
+        // The choice depends on the request, so caches must key on it either way.
+        res_headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+
+        if !self.should_compress(&body, req_headers, res_headers) {
+            return body;
+        }
+
+        res_headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        res_headers.remove(header::CONTENT_LENGTH);
+
+        body.gzip_with_max_outstanding(self.level, self.max_outstanding_bytes)
+    }
+}
+
+impl Default for CompressionPolicy {
+    fn default() -> Self {
+        CompressionPolicy::new()
+    }
+}
+
+fn accepts_gzip(req_headers: &HeaderMap) -> bool {
+    req_headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut params = coding.split(';');
+            let name = params.next().unwrap_or("").trim();
+            if !name.eq_ignore_ascii_case("gzip") && name != "*" {
+                return false;
+            }
+
+            // A zero quality value explicitly refuses the coding.
+            !params.any(|param| {
+                let param = param.trim();
+                (param.starts_with("q=") || param.starts_with("Q="))
+                    && param[2..].trim().parse::<f32>().map(|q| q <= 0.0).unwrap_or(false)
+            })
+        })
+}
+
+fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+
+    mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || matches!(
+            mime.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/ecmascript"
+                | "application/xml"
+                | "application/x-ndjson"
+                | "application/wasm"
+                | "image/svg+xml"
+                | "image/x-icon"
+                | "image/bmp"
+                | "font/ttf"
+                | "font/otf"
+        )
+}
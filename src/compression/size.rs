@@ -0,0 +1,52 @@
+use http_body::SizeHint;
+
+/// How an encoder changes the size of the data it encodes, so its size hint can be derived from the size hint of its
+/// source instead of degrading to an unknown length.
+///
+/// The encoders of the crate declare it, e.g. [gzip](./struct.StreamBody.html#method.gzip) at level 0 only stores
+/// the data, and a custom encoder can use [apply](#method.apply) in its own `size_hint`.
+///
+/// # Examples
+///
+/// ```
+/// use http_body::SizeHint;
+/// use stream_body::SizeTransform;
+///
+/// // A framing adding a 4 bytes header and a 4 bytes checksum.
+/// let hint = SizeTransform::Exact { overhead: 8 }.apply(&SizeHint::with_exact(100));
+/// assert_eq!(hint.exact(), Some(108));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeTransform {
+    /// The encoded size can't be predicted from the size of the source, e.g. with actual compression.
+    Unknown,
+    /// The encoded data is the data of the source plus a fixed overhead, e.g. an identity coding or a framing.
+    Exact {
+        /// The number of bytes the encoder adds.
+        overhead: u64,
+    },
+    /// The encoded data is at least the data of the source plus the given overhead, e.g. the stored blocks of
+    /// deflate, whose flushes add an unpredictable number of block headers.
+    Expanding {
+        /// The minimum number of bytes the encoder adds.
+        overhead: u64,
+    },
+}
+
+impl SizeTransform {
+    /// Returns the size hint of the encoded data, given the size hint of the data still to be encoded.
+    pub fn apply(&self, source: &SizeHint) -> SizeHint {
+        let mut hint = SizeHint::new();
+        match *self {
+            SizeTransform::Unknown => {}
+            SizeTransform::Exact { overhead } => {
+                hint.set_lower(source.lower().saturating_add(overhead));
+                if let Some(upper) = source.upper() {
+                    hint.set_upper(upper.saturating_add(overhead));
+                }
+            }
+            SizeTransform::Expanding { overhead } => hint.set_lower(source.lower().saturating_add(overhead)),
+        }
+        hint
+    }
+}
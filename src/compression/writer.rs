@@ -0,0 +1,179 @@
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::Write;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{self, AsyncWrite};
+
+// The amount of compressed output buffered before further writes wait for it to be drained.
+const MAX_PENDING_OUTPUT: usize = 32 * 1024;
+
+/// An [AsyncWrite](https://docs.rs/tokio/0.2.16/tokio/io/trait.AsyncWrite.html) which gzip-compresses everything
+/// written to it before passing it to the inner writer, e.g. the writer half of
+/// [StreamBody::channel](./struct.StreamBody.html#method.channel).
+///
+/// Compressing on the producer side keeps the body encoding-agnostic and lets the producer decide where the
+/// compressed stream is flushed: calling `flush()` emits everything written so far (e.g. at record boundaries),
+/// and `shutdown()` writes the gzip trailer. Don't forget to set the `Content-Encoding: gzip` header.
+///
+/// # Examples
+///
+/// ```no_run
+/// use stream_body::{GzipWriter, StreamBody};
+/// use tokio::io::AsyncWriteExt;
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let (writer, body) = StreamBody::channel();
+/// let mut writer = GzipWriter::new(writer);
+///
+/// writer.write_all(b"first record\n").await?;
+/// writer.flush().await?;
+/// writer.write_all(b"second record\n").await?;
+/// writer.shutdown().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct GzipWriter<W> {
+    inner: W,
+    encoder: GzEncoder<Vec<u8>>,
+    // The position up to which the encoder output is already written to the inner writer.
+    pos: usize,
+    // Whether a write of the output to the inner writer is pending. The output must not change until it completes,
+    // as writers like the channel writer keep pointing to the pending data.
+    draining: bool,
+    // Whether the encoder is already flushed for the ongoing flush.
+    flushing: bool,
+    finished: bool,
+}
+
+impl<W: AsyncWrite + Unpin> GzipWriter<W> {
+    /// Creates a writer with the default compression level.
+    pub fn new(inner: W) -> GzipWriter<W> {
+        GzipWriter::with_level(inner, Compression::default().level())
+    }
+
+    /// Creates a writer with the given compression level, from 0 (no compression) to 9 (best compression).
+    pub fn with_level(inner: W, level: u32) -> GzipWriter<W> {
+        GzipWriter {
+            inner,
+            encoder: GzEncoder::new(Vec::new(), Compression::new(level.min(9))),
+            pos: 0,
+            draining: false,
+            flushing: false,
+            finished: false,
+        }
+    }
+
+    /// Returns a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Consumes the writer and returns the inner writer. Compressed data which isn't flushed yet is lost.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    // Writes the buffered encoder output to the inner writer.
+    fn poll_drain(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        let poll_status = self.poll_drain_output(cx);
+        self.draining = poll_status.is_pending();
+        poll_status
+    }
+
+    fn poll_drain_output(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        loop {
+            let output = self.encoder.get_mut();
+            if self.pos >= output.len() {
+                output.clear();
+                self.pos = 0;
+                return Poll::Ready(Ok(()));
+            }
+
+            match Pin::new(&mut self.inner).poll_write(cx, &output[self.pos..]) {
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        format!(
+                            "{}: GzipWriter: Failed to write the compressed data to the inner writer",
+                            env!("CARGO_PKG_NAME")
+                        ),
+                    )))
+                }
+                Poll::Ready(Ok(n)) => self.pos += n,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for GzipWriter<W> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        if self.finished {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                format!(
+                    "{}: GzipWriter: The writer is already shut down",
+                    env!("CARGO_PKG_NAME")
+                ),
+            )));
+        }
+
+        if self.draining || self.encoder.get_ref().len() - self.pos >= MAX_PENDING_OUTPUT {
+            match self.poll_drain(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        Poll::Ready(self.encoder.write(buf))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        if self.draining && !self.flushing {
+            match self.poll_drain(cx) {
+                Poll::Ready(Ok(())) => {}
+                poll_status => return poll_status,
+            }
+        }
+
+        if !self.finished && !self.flushing {
+            // Emits a sync flush block, so the data written so far can be decompressed by the client.
+            if let Err(err) = self.encoder.flush() {
+                return Poll::Ready(Err(err));
+            }
+            self.flushing = true;
+        }
+
+        match self.poll_drain(cx) {
+            Poll::Ready(Ok(())) => {
+                self.flushing = false;
+                Pin::new(&mut self.inner).poll_flush(cx)
+            }
+            poll_status => poll_status,
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        if self.draining && !self.finished {
+            match self.poll_drain(cx) {
+                Poll::Ready(Ok(())) => {}
+                poll_status => return poll_status,
+            }
+        }
+
+        if !self.finished {
+            if let Err(err) = self.encoder.try_finish() {
+                return Poll::Ready(Err(err));
+            }
+            self.finished = true;
+        }
+
+        match self.poll_drain(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut self.inner).poll_shutdown(cx),
+            poll_status => poll_status,
+        }
+    }
+}
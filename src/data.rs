@@ -1,27 +1,120 @@
+use crate::in_flight;
+use crate::progress::{self, ProgressState};
 use crate::state::State;
-use bytes::Buf;
+use bytes::{Buf, Bytes};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use tokio::io;
 
 /// The data chunk type produced by `StreamBody`.
+///
+/// Besides `Buf`, it dereferences to its remaining bytes, so it can be passed to any API expecting a byte slice, e.g.
+/// a checksum or `std::io::Write::write_all`.
 pub struct StreamData {
-    ptr: *const u8,
     len: usize,
     pos: usize,
-    state: Arc<Mutex<State>>,
+    owner: Owner,
+    // The progress counters the consumed bytes are reported to, one per adapter tracking the chunk.
+    progress: Vec<Arc<Mutex<ProgressState>>>,
+    // The gauges to take the bytes of the chunk off once it's dropped, one per adapter tracking the chunk.
+    in_flight: Vec<(Arc<AtomicU64>, u64)>,
+}
+
+enum Owner {
+    // The chunk shares the buffer of the body, which is reclaimed for the next chunks once the chunk is dropped.
+    Body(Bytes, Arc<Mutex<State>>),
+    // The chunk owns its data.
+    Bytes(Bytes),
 }
 
 impl StreamData {
-    pub(crate) fn new(s: &[u8], state: Arc<Mutex<State>>) -> StreamData {
+    pub(crate) fn new(bytes: Bytes, state: Arc<Mutex<State>>) -> StreamData {
+        in_flight::add_global(bytes.len());
         StreamData {
-            ptr: s.as_ptr(),
-            len: s.len(),
+            len: bytes.len(),
             pos: 0,
-            state,
+            owner: Owner::Body(bytes, state),
+            progress: Vec::new(),
+            in_flight: Vec::new(),
         }
     }
-}
 
-unsafe impl std::marker::Send for StreamData {}
+    /// Creates a chunk which owns its data, so it doesn't take part in the body's buffer reuse.
+    pub(crate) fn from_bytes(bytes: Bytes) -> StreamData {
+        StreamData {
+            len: bytes.len(),
+            pos: 0,
+            owner: Owner::Bytes(bytes),
+            progress: Vec::new(),
+            in_flight: Vec::new(),
+        }
+    }
+
+    /// Advances the read position by `cnt` bytes, failing instead of panicking if `cnt` is greater than the
+    /// remaining length. The chunk is left unchanged on failure.
+    pub fn try_advance(&mut self, cnt: usize) -> io::Result<()> {
+        if cnt > self.remaining() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{}: StreamData: Cannot advance {} bytes past the remaining {} bytes",
+                    env!("CARGO_PKG_NAME"),
+                    cnt,
+                    self.remaining()
+                ),
+            ));
+        }
+
+        self.pos += cnt;
+        Ok(())
+    }
+
+    /// Takes the remaining bytes of the chunk as `Bytes`, e.g. to keep them after the chunk is emitted.
+    ///
+    /// A chunk owning its data is converted without copying. A chunk sharing the buffer of the body is copied, so the
+    /// body can reclaim the buffer for the next chunks as usual. The bytes count as consumed for the progress
+    /// tracking.
+    pub fn copy_to_bytes(mut self) -> Bytes {
+        let bytes = match self.owner {
+            Owner::Body(ref bytes, _) => Bytes::copy_from_slice(&bytes[self.pos..self.len]),
+            Owner::Bytes(ref bytes) => bytes.slice(self.pos..self.len),
+        };
+        self.pos = self.len;
+        bytes
+    }
+
+    /// Reports the consumed bytes of the chunk to a progress counter once the chunk is dropped, along with the
+    /// counters it already reports to.
+    pub(crate) fn track_progress(&mut self, progress: Arc<Mutex<ProgressState>>) {
+        self.progress.push(progress);
+    }
+
+    /// Splits the first `len` remaining bytes off as a chunk which owns its data, without copying it. This chunk is
+    /// left with the rest, and still holds the body's buffer until it's dropped.
+    pub(crate) fn split_to(&mut self, len: usize) -> StreamData {
+        let len = len.min(self.remaining());
+        let bytes = match self.owner {
+            Owner::Body(ref bytes, _) | Owner::Bytes(ref bytes) => bytes.slice(self.pos..self.pos + len),
+        };
+        self.pos += len;
+        StreamData::from_bytes(bytes)
+    }
+
+    /// Counts `len` bytes of the chunk in a gauge until the chunk is dropped, along with the gauges it's already
+    /// counted in.
+    pub(crate) fn track_in_flight(&mut self, in_flight: Arc<AtomicU64>, len: u64) {
+        self.in_flight.push((in_flight, len));
+    }
+
+    /// Shortens the remaining part of the chunk to `len` bytes, it has no effect if `len` is greater than
+    /// the remaining length.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        if len < self.remaining() {
+            self.len = self.pos + len;
+        }
+    }
+}
 
 impl Buf for StreamData {
     fn remaining(&self) -> usize {
@@ -29,23 +122,65 @@ impl Buf for StreamData {
     }
 
     fn bytes(&self) -> &[u8] {
-        unsafe { std::slice::from_raw_parts(self.ptr.add(self.pos), self.len - self.pos) }
+        match self.owner {
+            Owner::Body(ref bytes, _) | Owner::Bytes(ref bytes) => &bytes[self.pos..self.len],
+        }
     }
 
     fn advance(&mut self, cnt: usize) {
+        assert!(
+            cnt <= self.remaining(),
+            "cannot advance past `remaining`: {} <= {}",
+            cnt,
+            self.remaining()
+        );
         self.pos += cnt;
     }
 }
 
+impl Deref for StreamData {
+    type Target = [u8];
+
+    /// Returns the remaining bytes of the chunk, like `Buf::bytes`.
+    fn deref(&self) -> &[u8] {
+        self.bytes()
+    }
+}
+
+impl AsRef<[u8]> for StreamData {
+    fn as_ref(&self) -> &[u8] {
+        self.bytes()
+    }
+}
+
 impl Drop for StreamData {
     fn drop(&mut self) {
-        match self.state.lock() {
+        for progress in self.progress.iter() {
+            progress::record_delivered(progress, self.pos);
+        }
+
+        for (in_flight, len) in self.in_flight.iter() {
+            in_flight.fetch_sub(*len, Ordering::Relaxed);
+        }
+
+        let state = match self.owner {
+            Owner::Body(ref mut bytes, ref state) => {
+                in_flight::sub_global(bytes.len());
+                // Releases the buffer before the body is woken up, so it can reclaim it for the next chunk.
+                drop(std::mem::take(bytes));
+                state
+            }
+            Owner::Bytes(_) => return,
+        };
+
+        match state.lock() {
             Ok(mut state) => {
                 state.is_current_stream_data_consumed = true;
-                if let Some(ref waker) = state.waker {
-                    waker.clone().wake();
+                state.abandoned_bytes += self.len - self.pos;
+                if let Some((at, len)) = state.emitted.take() {
+                    state.drained = Some((len, at.elapsed()));
                 }
-                state.waker = None;
+                state.wake();
             }
             Err(err) => log::error!(
                 "{}: StreamData: Failed to update the drop state: {}",
@@ -55,3 +190,115 @@ impl Drop for StreamData {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::StreamBody;
+    use crate::buffer::ReusableBuf;
+    use futures_util::task::{self, ArcWake};
+    use http_body::Body;
+    use std::pin::Pin;
+    use std::sync::atomic::AtomicUsize;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+
+    struct WakeCounter(AtomicUsize);
+
+    impl ArcWake for WakeCounter {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn in_flight_state() -> Arc<Mutex<State>> {
+        let mut state = State::new();
+        state.is_current_stream_data_consumed = false;
+        Arc::new(Mutex::new(state))
+    }
+
+    fn read_into(buf: &mut ReusableBuf, data: &[u8]) -> (*const u8, Bytes) {
+        let prepared = buf.prepare();
+        prepared[..data.len()].copy_from_slice(data);
+        let ptr = prepared.as_ptr();
+        (ptr, buf.split(data.len()))
+    }
+
+    #[test]
+    fn the_buffer_is_reclaimed_once_the_chunk_is_dropped() {
+        let mut buf = ReusableBuf::new(16);
+        let (ptr, bytes) = read_into(&mut buf, b"hello");
+        drop(StreamData::new(bytes, in_flight_state()));
+
+        let (next_ptr, _) = read_into(&mut buf, b"world");
+        assert_eq!(next_ptr, ptr);
+    }
+
+    #[test]
+    fn a_clone_of_the_chunk_keeps_the_buffer_alive() {
+        let mut buf = ReusableBuf::new(16);
+        let (ptr, bytes) = read_into(&mut buf, b"hello");
+        let held = bytes.clone();
+        let mut data = StreamData::new(bytes, in_flight_state());
+        let head = data.split_to(2);
+        drop(data);
+
+        // The next read gets a new allocation instead of overwriting the bytes still referenced.
+        let (next_ptr, next) = read_into(&mut buf, b"world");
+        assert_ne!(next_ptr, ptr);
+        assert_eq!(&next[..], b"world");
+        assert_eq!(&held[..], b"hello");
+        assert_eq!(head.bytes(), b"he");
+    }
+
+    #[test]
+    fn dropping_a_partially_consumed_chunk_records_the_abandoned_bytes() {
+        let state = in_flight_state();
+        let mut data = StreamData::new(Bytes::from_static(b"hello"), Arc::clone(&state));
+        data.advance(2);
+        drop(data);
+        {
+            let state = state.lock().unwrap();
+            assert!(state.is_current_stream_data_consumed);
+            assert_eq!(state.abandoned_bytes, 3);
+        }
+
+        state.lock().unwrap().is_current_stream_data_consumed = false;
+        let mut data = StreamData::new(Bytes::from_static(b"world"), Arc::clone(&state));
+        data.advance(5);
+        drop(data);
+        assert_eq!(state.lock().unwrap().abandoned_bytes, 3);
+
+        // A chunk owning its data doesn't take part in the body's bookkeeping.
+        state.lock().unwrap().is_current_stream_data_consumed = false;
+        drop(StreamData::from_bytes(Bytes::from_static(b"owned")));
+        assert!(!state.lock().unwrap().is_current_stream_data_consumed);
+    }
+
+    #[tokio::test]
+    async fn the_body_waits_for_the_chunk_to_be_dropped() {
+        let (mut writer, mut body) = StreamBody::channel_with_capacity(4);
+        tokio::spawn(async move {
+            writer.write_all(b"abcdefgh").await.unwrap();
+        });
+
+        let first = body.data().await.unwrap().unwrap();
+        assert_eq!(first.bytes(), b"abcd");
+        // Lets the writer hand over the next chunk.
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+
+        let wakes = Arc::new(WakeCounter(AtomicUsize::new(0)));
+        let waker = task::waker(Arc::clone(&wakes));
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut body).poll_data(&mut cx).is_pending());
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 0);
+
+        drop(first);
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+        match Pin::new(&mut body).poll_data(&mut cx) {
+            Poll::Ready(Some(Ok(data))) => assert_eq!(data.bytes(), b"efgh"),
+            _ => panic!("the body didn't read the next chunk"),
+        }
+    }
+}
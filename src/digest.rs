@@ -0,0 +1,518 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::Buf;
+use http::header::HeaderName;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fmt;
+use std::marker::Unpin;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{self, AsyncRead};
+
+/// A digest an upload is expected to match, as declared by the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExpectedDigest {
+    /// An MD5 digest.
+    Md5([u8; 16]),
+    /// A SHA-256 digest.
+    Sha256([u8; 32]),
+}
+
+impl ExpectedDigest {
+    /// Collects the digests declared via the `Content-MD5`, `Digest`
+    /// ([RFC 3230](https://tools.ietf.org/html/rfc3230)) and `Content-Digest`
+    /// ([RFC 9530](https://www.rfc-editor.org/rfc/rfc9530)) headers. Unsupported algorithms and malformed values are
+    /// skipped.
+    pub fn from_headers(headers: &HeaderMap) -> Vec<ExpectedDigest> {
+        let mut digests = Vec::new();
+
+        for value in headers.get_all(HeaderName::from_static("content-md5")).iter() {
+            if let Some(digest) = value.to_str().ok().and_then(|v| ExpectedDigest::parse("md5", v.trim())) {
+                digests.push(digest);
+            }
+        }
+
+        let lists = headers
+            .get_all(HeaderName::from_static("digest"))
+            .iter()
+            .chain(headers.get_all(HeaderName::from_static("content-digest")).iter());
+
+        for value in lists {
+            let value = match value.to_str() {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+
+            for item in value.split(',') {
+                let mut parts = item.trim().splitn(2, '=');
+                let (algorithm, encoded) = match (parts.next(), parts.next()) {
+                    (Some(algorithm), Some(encoded)) => (algorithm.trim(), encoded.trim().trim_matches(':')),
+                    _ => continue,
+                };
+
+                if let Some(digest) = ExpectedDigest::parse(algorithm, encoded) {
+                    digests.push(digest);
+                }
+            }
+        }
+
+        digests
+    }
+
+    fn parse(algorithm: &str, encoded: &str) -> Option<ExpectedDigest> {
+        let decoded = STANDARD.decode(encoded).ok()?;
+
+        if algorithm.eq_ignore_ascii_case("md5") {
+            let mut digest = [0_u8; 16];
+            if decoded.len() != digest.len() {
+                return None;
+            }
+            digest.copy_from_slice(&decoded);
+            Some(ExpectedDigest::Md5(digest))
+        } else if algorithm.eq_ignore_ascii_case("sha-256") {
+            let mut digest = [0_u8; 32];
+            if decoded.len() != digest.len() {
+                return None;
+            }
+            digest.copy_from_slice(&decoded);
+            Some(ExpectedDigest::Sha256(digest))
+        } else {
+            None
+        }
+    }
+}
+
+/// The error returned by a [DigestReader](./struct.DigestReader.html) when the upload doesn't match a declared
+/// digest, or by a body adapted via
+/// [verify_digest_trailer](./struct.StreamBody.html#method.verify_digest_trailer) when the forwarded data doesn't match
+/// the digest of the trailers. It is wrapped in an `io::Error` of kind `InvalidData` and can be retrieved via
+/// `get_ref()` and `downcast_ref()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigestMismatch {
+    pub(crate) algorithm: &'static str,
+    pub(crate) forwarded: bool,
+}
+
+impl DigestMismatch {
+    /// Returns the algorithm of the mismatching digest, e.g. `md5` or `sha-256`.
+    pub fn algorithm(&self) -> &'static str {
+        self.algorithm
+    }
+}
+
+impl fmt::Display for DigestMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.forwarded {
+            write!(
+                f,
+                "{}: StreamBody [Digest]: The forwarded data doesn't match the {} digest of the trailers",
+                env!("CARGO_PKG_NAME"),
+                self.algorithm
+            )
+        } else {
+            write!(
+                f,
+                "{}: DigestReader: The uploaded data doesn't match the declared {} digest",
+                env!("CARGO_PKG_NAME"),
+                self.algorithm
+            )
+        }
+    }
+}
+
+impl Error for DigestMismatch {}
+
+/// An [AsyncRead](https://docs.rs/tokio/0.2.16/tokio/io/trait.AsyncRead.html) which hashes an incoming upload while
+/// it is read and compares it against the declared digests.
+///
+/// On a mismatch the read which would have reported the end of the stream fails with a
+/// [DigestMismatch](./struct.DigestMismatch.html) error instead, so the handler never treats a corrupted upload as
+/// complete.
+///
+/// # Examples
+///
+/// ```no_run
+/// use hyper::{Body, Request};
+/// use stream_body::DigestReader;
+/// use tokio::fs::File;
+///
+/// # async fn run<R: tokio::io::AsyncRead + Unpin>(req: Request<Body>, upload: R) -> std::io::Result<()> {
+/// let mut reader = DigestReader::from_headers(upload, req.headers());
+///
+/// let mut f = File::create("upload").await?;
+/// tokio::io::copy(&mut reader, &mut f).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct DigestReader<R> {
+    inner: R,
+    md5: Option<([u8; 16], Md5)>,
+    sha256: Option<([u8; 32], Sha256)>,
+    // The algorithm of a detected mismatch, kept so that every further read fails as well.
+    mismatch: Option<&'static str>,
+}
+
+impl<R: AsyncRead + Unpin> DigestReader<R> {
+    /// Creates a reader verifying the given digests.
+    pub fn new(inner: R, expected: Vec<ExpectedDigest>) -> DigestReader<R> {
+        let mut reader = DigestReader {
+            inner,
+            md5: None,
+            sha256: None,
+            mismatch: None,
+        };
+
+        for digest in expected {
+            match digest {
+                ExpectedDigest::Md5(digest) => reader.md5 = Some((digest, Md5::new())),
+                ExpectedDigest::Sha256(digest) => reader.sha256 = Some((digest, Sha256::new())),
+            }
+        }
+
+        reader
+    }
+
+    /// Creates a reader verifying the digests declared in the request headers, see
+    /// [ExpectedDigest::from_headers](./enum.ExpectedDigest.html#method.from_headers).
+    pub fn from_headers(inner: R, headers: &HeaderMap) -> DigestReader<R> {
+        DigestReader::new(inner, ExpectedDigest::from_headers(headers))
+    }
+
+    /// Returns whether the reader verifies any digest.
+    pub fn is_checked(&self) -> bool {
+        self.md5.is_some() || self.sha256.is_some()
+    }
+
+    /// Consumes the reader and returns the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn verify(&mut self) -> io::Result<()> {
+        if let Some((expected, hasher)) = self.md5.take() {
+            if hasher.finalize()[..] != expected[..] {
+                self.mismatch = Some("md5");
+            }
+        }
+
+        if let Some((expected, hasher)) = self.sha256.take() {
+            if hasher.finalize()[..] != expected[..] {
+                self.mismatch = self.mismatch.or(Some("sha-256"));
+            }
+        }
+
+        match self.mismatch {
+            Some(algorithm) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                DigestMismatch {
+                    algorithm,
+                    forwarded: false,
+                },
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for DigestReader<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let read_count = match Pin::new(&mut self.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(read_count)) => read_count,
+            poll_status => return poll_status,
+        };
+
+        if read_count == 0 && !buf.is_empty() {
+            return Poll::Ready(self.verify().map(|_| 0));
+        }
+
+        let data = &buf[..read_count];
+        if let Some((_, ref mut hasher)) = self.md5 {
+            hasher.update(data);
+        }
+        if let Some((_, ref mut hasher)) = self.sha256 {
+            hasher.update(data);
+        }
+
+        Poll::Ready(Ok(read_count))
+    }
+}
+
+impl StreamBody {
+    /// Verifies the data of the body against the digest declared in its trailers, e.g. the `Content-Digest` trailer of
+    /// an upstream response forwarded via [wrap_body](#method.wrap_body), see
+    /// [ExpectedDigest::from_headers](./enum.ExpectedDigest.html#method.from_headers).
+    ///
+    /// The chunks are hashed while they're forwarded. On a mismatch the body fails with a
+    /// [DigestMismatch](./struct.DigestMismatch.html) error instead of ending, so the downstream connection is reset and
+    /// a corrupted response isn't relayed as complete. As the algorithm is only known once the trailers are received,
+    /// the data is hashed with every supported algorithm. A body without a digest in its trailers isn't checked.
+    ///
+    /// It requires the `digest` feature.
+    pub fn verify_digest_trailer(self) -> StreamBody {
+        self.wrap_with(|inner| VerifyDigestTrailer {
+            inner,
+            md5: Md5::new(),
+            sha256: Sha256::new(),
+            data_ended: false,
+            verified: None,
+        })
+    }
+}
+
+struct VerifyDigestTrailer {
+    inner: StreamBody,
+    md5: Md5,
+    sha256: Sha256,
+    data_ended: bool,
+    // The verified trailers, forwarded once the data ended.
+    verified: Option<Option<HeaderMap<HeaderValue>>>,
+}
+
+impl VerifyDigestTrailer {
+    fn verify(&mut self, trailers: &HeaderMap) -> io::Result<()> {
+        let md5 = self.md5.finalize_reset();
+        let sha256 = self.sha256.finalize_reset();
+
+        for digest in ExpectedDigest::from_headers(trailers) {
+            let algorithm = match digest {
+                ExpectedDigest::Md5(expected) if md5[..] != expected[..] => "md5",
+                ExpectedDigest::Sha256(expected) if sha256[..] != expected[..] => "sha-256",
+                _ => continue,
+            };
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                DigestMismatch {
+                    algorithm,
+                    forwarded: true,
+                },
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Body for VerifyDigestTrailer {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let me = &mut *self;
+        if me.verified.is_some() {
+            return Poll::Ready(None);
+        }
+
+        if !me.data_ended {
+            match ready!(Pin::new(&mut me.inner).poll_data(cx)) {
+                Some(Ok(data)) => {
+                    me.md5.update(data.bytes());
+                    me.sha256.update(data.bytes());
+                    return Poll::Ready(Some(Ok(data)));
+                }
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => me.data_ended = true,
+            }
+        }
+
+        let trailers = match ready!(Pin::new(&mut me.inner).poll_trailers(cx)) {
+            Ok(trailers) => trailers,
+            Err(err) => {
+                me.verified = Some(None);
+                return Poll::Ready(Some(Err(err)));
+            }
+        };
+
+        let result = match trailers {
+            Some(ref trailers) => me.verify(trailers),
+            None => Ok(()),
+        };
+        match result {
+            Ok(()) => {
+                me.verified = Some(trailers);
+                Poll::Ready(None)
+            }
+            Err(err) => {
+                // The body is fused after a mismatch, its trailers aren't forwarded.
+                me.verified = Some(None);
+                Poll::Ready(Some(Err(err)))
+            }
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        match self.verified {
+            Some(ref mut trailers) => Poll::Ready(Ok(trailers.take())),
+            None => Pin::new(&mut self.inner).poll_trailers(cx),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.verified.is_some()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        if self.data_ended {
+            return SizeHint::with_exact(0);
+        }
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    const DATA: &[u8] = b"The quick brown fox jumps over the lazy dog";
+
+    // A reader returning the data in chunks of the given sizes.
+    struct Chunks(Vec<Vec<u8>>);
+
+    impl AsyncRead for Chunks {
+        fn poll_read(mut self: Pin<&mut Self>, _cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+            if self.0.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+            let len = self.0[0].len().min(buf.len());
+            buf[..len].copy_from_slice(&self.0[0][..len]);
+            self.0[0].drain(..len);
+            if self.0[0].is_empty() {
+                self.0.remove(0);
+            }
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    fn split(at: &[usize]) -> Chunks {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        for &end in at.iter().chain(std::iter::once(&DATA.len())) {
+            if end > start {
+                chunks.push(DATA[start..end].to_vec());
+                start = end;
+            }
+        }
+        Chunks(chunks)
+    }
+
+    fn headers(pairs: &[(&'static str, String)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(HeaderName::from_static(name), value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn md5_b64(data: &[u8]) -> String {
+        STANDARD.encode(Md5::digest(data))
+    }
+
+    fn sha256_b64(data: &[u8]) -> String {
+        STANDARD.encode(Sha256::digest(data))
+    }
+
+    async fn read(mut reader: DigestReader<Chunks>) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).await?;
+        Ok(data)
+    }
+
+    #[test]
+    fn declared_digests_are_parsed_from_every_header() {
+        let headers = headers(&[
+            ("content-md5", md5_b64(DATA)),
+            (
+                "digest",
+                format!("SHA-256={}, unknown=abc, md5=not-base64", sha256_b64(DATA)),
+            ),
+            ("content-digest", format!("sha-256=:{}:", sha256_b64(b"other"))),
+        ]);
+
+        let digests = ExpectedDigest::from_headers(&headers);
+        assert_eq!(digests.len(), 3);
+        assert!(matches!(digests[0], ExpectedDigest::Md5(_)));
+        assert!(matches!(digests[1], ExpectedDigest::Sha256(_)));
+        assert!(matches!(digests[2], ExpectedDigest::Sha256(_)));
+
+        // A digest of the wrong length is skipped.
+        let headers = self::headers(&[("content-md5", sha256_b64(DATA))]);
+        assert!(ExpectedDigest::from_headers(&headers).is_empty());
+    }
+
+    #[tokio::test]
+    async fn uploads_are_verified_across_chunk_boundaries() {
+        let headers = headers(&[
+            ("content-md5", md5_b64(DATA)),
+            ("content-digest", format!("sha-256=:{}:", sha256_b64(DATA))),
+        ]);
+
+        for i in 0..=DATA.len() {
+            for j in (i..=DATA.len()).step_by(7) {
+                let reader = DigestReader::from_headers(split(&[i, j]), &headers);
+                assert!(reader.is_checked());
+                assert_eq!(read(reader).await.unwrap(), DATA, "split at {} and {}", i, j);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn mismatching_uploads_fail_at_their_end() {
+        for &(name, ref value, algorithm) in [
+            ("content-md5", md5_b64(b"other"), "md5"),
+            ("digest", format!("sha-256={}", sha256_b64(b"other")), "sha-256"),
+        ]
+        .iter()
+        {
+            let reader = DigestReader::from_headers(split(&[10]), &headers(&[(name, value.clone())]));
+            let err = read(reader).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            let mismatch = err.get_ref().unwrap().downcast_ref::<DigestMismatch>().unwrap();
+            assert_eq!(mismatch.algorithm(), algorithm);
+        }
+
+        let reader = DigestReader::new(split(&[]), Vec::new());
+        assert!(!reader.is_checked());
+        assert_eq!(read(reader).await.unwrap(), DATA);
+    }
+
+    async fn forwarded(trailers: HeaderMap) -> (Vec<u8>, io::Result<Option<HeaderMap>>) {
+        let body = StreamBody::concat(vec![
+            StreamBody::from(DATA[..10].to_vec()),
+            StreamBody::from(DATA[10..].to_vec()),
+            StreamBody::trailers_only(trailers),
+        ]);
+        let mut body = body.verify_digest_trailer();
+
+        let mut data = Vec::new();
+        while let Some(chunk) = body.data().await {
+            match chunk {
+                Ok(chunk) => data.extend_from_slice(chunk.bytes()),
+                Err(err) => return (data, Err(err)),
+            }
+        }
+        (data, body.trailers().await)
+    }
+
+    #[tokio::test]
+    async fn forwarded_bodies_are_verified_against_their_trailers() {
+        let trailers = headers(&[("content-digest", format!("sha-256=:{}:", sha256_b64(DATA)))]);
+        let (data, result) = forwarded(trailers.clone()).await;
+        assert_eq!(data, DATA);
+        assert_eq!(result.unwrap(), Some(trailers));
+
+        let trailers = headers(&[("content-digest", format!("md5=:{}:", md5_b64(b"other")))]);
+        let (data, result) = forwarded(trailers).await;
+        assert_eq!(data, DATA);
+        let err = result.unwrap_err();
+        let mismatch = err.get_ref().unwrap().downcast_ref::<DigestMismatch>().unwrap();
+        assert_eq!(mismatch.algorithm(), "md5");
+    }
+}
@@ -0,0 +1,132 @@
+use crate::body::{self, StreamBody};
+use futures_util::stream::{FuturesUnordered, Stream};
+use std::future::Future;
+use std::marker::Unpin;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::AsyncRead;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+type Job = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A small pool of driver tasks which multiplex the reader to body copies of many bodies.
+///
+/// [StreamBody::from_reader](./struct.StreamBody.html#method.from_reader) spawns one task per body, whereas the
+/// bodies created via [from_reader_with_driver](./struct.StreamBody.html#method.from_reader_with_driver) are copied
+/// by the driver's tasks, which cuts the per-request task overhead for servers streaming thousands of files at once.
+///
+/// The driver tasks exit once every handle of the driver is dropped and all of the queued copies are finished.
+///
+/// # Examples
+///
+/// ```no_run
+/// use stream_body::{Driver, StreamBody};
+/// use tokio::fs::File;
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let driver = Driver::new();
+///
+/// let f = File::open("large-file").await?;
+/// let body = StreamBody::from_reader_with_driver(f, &driver);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Driver {
+    senders: Arc<[UnboundedSender<Job>]>,
+    next: Arc<AtomicUsize>,
+}
+
+impl Driver {
+    /// Spawns a single driver task on the current tokio runtime.
+    pub fn new() -> Driver {
+        Driver::with_tasks(1)
+    }
+
+    /// Spawns `count` driver tasks on the current tokio runtime, the copies are distributed among them in a
+    /// round-robin fashion.
+    pub fn with_tasks(count: usize) -> Driver {
+        let senders = (0..count.max(1))
+            .map(|_| {
+                let (tx, rx) = mpsc::unbounded_channel();
+                tokio::spawn(DriverTask {
+                    receiver: Some(rx),
+                    jobs: FuturesUnordered::new(),
+                });
+                tx
+            })
+            .collect::<Vec<_>>();
+
+        Driver {
+            senders: senders.into(),
+            next: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn submit(&self, job: Job) {
+        let idx = self.next.fetch_add(1, Ordering::Relaxed) % self.senders.len();
+        if let Err(err) = self.senders[idx].send(job) {
+            // The driver task is gone, e.g. its runtime is shutting down, so fall back to a dedicated task.
+            log::warn!(
+                "{}: Driver: The driver task is not running, spawning a dedicated task instead",
+                env!("CARGO_PKG_NAME")
+            );
+            tokio::spawn(err.0);
+        }
+    }
+}
+
+impl Default for Driver {
+    fn default() -> Driver {
+        Driver::new()
+    }
+}
+
+impl StreamBody {
+    /// Same as [from_reader](#method.from_reader), but the reader is copied by the provided
+    /// [Driver](./struct.Driver.html) instead of a dedicated task.
+    pub fn from_reader_with_driver<R: AsyncRead + Unpin + Send + 'static>(r: R, driver: &Driver) -> StreamBody {
+        let (w, mut body) = StreamBody::channel();
+        body.source = "reader";
+
+        driver.submit(Box::pin(body::pipe_reader(r, w)));
+
+        body
+    }
+}
+
+struct DriverTask {
+    receiver: Option<UnboundedReceiver<Job>>,
+    jobs: FuturesUnordered<Job>,
+}
+
+impl Future for DriverTask {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let me = &mut *self;
+
+        if let Some(ref mut receiver) = me.receiver {
+            loop {
+                match receiver.poll_recv(cx) {
+                    Poll::Ready(Some(job)) => me.jobs.push(job),
+                    Poll::Ready(None) => {
+                        me.receiver = None;
+                        break;
+                    }
+                    Poll::Pending => break,
+                }
+            }
+        }
+
+        while let Poll::Ready(Some(())) = Pin::new(&mut me.jobs).poll_next(cx) {}
+
+        if me.receiver.is_none() && me.jobs.is_empty() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
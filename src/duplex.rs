@@ -0,0 +1,68 @@
+use crate::body::StreamBody;
+use crate::body_reader::BodyReader;
+use async_pipe::PipeWriter;
+use http_body::Body;
+use std::error::Error;
+use std::future::Future;
+use tokio::io;
+
+impl StreamBody {
+    /// Creates a response body produced by `transform` out of a request body, the core of echo, transcoding or
+    /// filtering endpoints streaming in both directions at once.
+    ///
+    /// `transform` gets a [BodyReader](./struct.BodyReader.html) over the request body and the writer half of the
+    /// response body, and runs on a spawned task owned by the response body:
+    ///
+    /// - The response body ends once `transform` returns `Ok`. If it returns an error instead, e.g. a request body
+    ///   error propagated with `?`, the response body fails with it rather than ending as if it was complete.
+    /// - Dropping the response body, e.g. because the client went away, cancels `transform` at its next suspension
+    ///   point, which drops the request body too.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use hyper::{Body, Request, Response};
+    /// use stream_body::StreamBody;
+    /// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    ///
+    /// # fn run(req: Request<Body>) -> Response<StreamBody> {
+    /// // Upper-cases the uploaded text as it arrives.
+    /// let body = StreamBody::duplex(req.into_body(), |mut reader, mut writer| async move {
+    ///     let mut buf = vec![0; 16 * 1024];
+    ///     loop {
+    ///         let n = reader.read(&mut buf).await?;
+    ///         if n == 0 {
+    ///             return Ok(());
+    ///         }
+    ///         writer.write_all(&buf[..n].to_ascii_uppercase()).await?;
+    ///     }
+    /// });
+    /// Response::new(body)
+    /// # }
+    /// ```
+    pub fn duplex<B, F, Fut>(request: B, transform: F) -> StreamBody
+    where
+        B: Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn Error + Send + Sync>>,
+        F: FnOnce(BodyReader<StreamBody>, PipeWriter) -> Fut,
+        Fut: Future<Output = io::Result<()>> + Send + 'static,
+    {
+        StreamBody::duplex_with_limit(request, u64::MAX, transform)
+    }
+
+    /// Same as [duplex](#method.duplex), but the request body yields an `InvalidData` error once it exceeds
+    /// `max_request_bytes`, see [limited](#method.limited).
+    pub fn duplex_with_limit<B, F, Fut>(request: B, max_request_bytes: u64, transform: F) -> StreamBody
+    where
+        B: Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn Error + Send + Sync>>,
+        F: FnOnce(BodyReader<StreamBody>, PipeWriter) -> Fut,
+        Fut: Future<Output = io::Result<()>> + Send + 'static,
+    {
+        let reader = BodyReader::new(StreamBody::forward(request).limited(max_request_bytes));
+        let (w, body) = StreamBody::channel();
+        body.owning_fallible_task(transform(reader, w))
+    }
+}
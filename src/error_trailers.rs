@@ -0,0 +1,112 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io;
+
+/// The in-band replacement for a terminal body error, returned by the hook passed to
+/// [StreamBody::map_err_to_trailers](./struct.StreamBody.html#method.map_err_to_trailers).
+pub struct ErrorTrailers {
+    trailers: HeaderMap<HeaderValue>,
+    chunk: Option<Bytes>,
+}
+
+impl ErrorTrailers {
+    /// Ends the body cleanly and emits the provided trailers.
+    pub fn new(trailers: HeaderMap<HeaderValue>) -> ErrorTrailers {
+        ErrorTrailers { trailers, chunk: None }
+    }
+
+    /// Emits a final data chunk before the trailers, e.g. an SSE error event.
+    pub fn with_chunk<C: Into<Bytes>>(mut self, chunk: C) -> ErrorTrailers {
+        let chunk = chunk.into();
+        if !chunk.is_empty() {
+            self.chunk = Some(chunk);
+        }
+        self
+    }
+}
+
+impl StreamBody {
+    /// Registers a hook which converts a terminal source error into trailers instead of an HTTP-level stream error.
+    ///
+    /// The hook is called with the first error the source yields. Returning `Ok` ends the body cleanly with the
+    /// given trailers (and optional final chunk), returning `Err` propagates the error as usual.
+    ///
+    /// Note that trailers only reach the client on protocols which support them, e.g. HTTP/2.
+    pub fn map_err_to_trailers<F>(self, hook: F) -> StreamBody
+    where
+        F: FnOnce(io::Error) -> Result<ErrorTrailers, io::Error> + Send + 'static,
+    {
+        self.wrap_with(|inner| MapErrToTrailers {
+            inner,
+            hook: Some(Box::new(hook)),
+            recovered: None,
+        })
+    }
+}
+
+type Hook = Box<dyn FnOnce(io::Error) -> Result<ErrorTrailers, io::Error> + Send>;
+
+struct MapErrToTrailers {
+    inner: StreamBody,
+    hook: Option<Hook>,
+    recovered: Option<ErrorTrailers>,
+}
+
+impl Body for MapErrToTrailers {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        if let Some(ref mut recovered) = self.recovered {
+            return Poll::Ready(recovered.chunk.take().map(|chunk| Ok(StreamData::from_bytes(chunk))));
+        }
+
+        let err = match Pin::new(&mut self.inner).poll_data(cx) {
+            Poll::Ready(Some(Err(err))) => err,
+            poll_status => return poll_status,
+        };
+
+        let hook = match self.hook.take() {
+            Some(hook) => hook,
+            None => return Poll::Ready(Some(Err(err))),
+        };
+
+        match hook(err) {
+            Ok(mut recovered) => {
+                let chunk = recovered.chunk.take();
+                self.recovered = Some(recovered);
+                Poll::Ready(chunk.map(|chunk| Ok(StreamData::from_bytes(chunk))))
+            }
+            Err(err) => Poll::Ready(Some(Err(err))),
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        if let Some(ref mut recovered) = self.recovered {
+            let trailers = std::mem::take(&mut recovered.trailers);
+            return Poll::Ready(Ok(if trailers.is_empty() { None } else { Some(trailers) }));
+        }
+
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self.recovered {
+            Some(ref recovered) => recovered.chunk.is_none() && recovered.trailers.is_empty(),
+            None => self.inner.is_end_stream(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        // A recovered error may cut the source short or append a final chunk.
+        SizeHint::default()
+    }
+}
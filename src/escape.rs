@@ -0,0 +1,220 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use bytes::{Buf, Bytes, BytesMut};
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io;
+
+/// The rules of an escaping transform applied via [StreamBody::escape](./struct.StreamBody.html#method.escape), e.g.
+/// to embed raw binary data in a delimiter-framed protocol.
+///
+/// Every occurrence of the pattern of a rule is replaced by its replacement. The rules are tried in order at every
+/// position, so a rule escaping the escape byte itself must come before or alongside the other ones as needed.
+///
+/// # Examples
+///
+/// ```
+/// use stream_body::Escaping;
+///
+/// // The byte stuffing of HDLC framing: the flag and escape bytes are escaped within the frames.
+/// let escaping = Escaping::new()
+///     .rule(&b"\x7e"[..], &b"\x7d\x5e"[..])
+///     .rule(&b"\x7d"[..], &b"\x7d\x5d"[..]);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Escaping {
+    rules: Vec<(Bytes, Bytes)>,
+}
+
+impl Escaping {
+    /// Creates an escaping without any rule, which leaves the data unchanged.
+    pub fn new() -> Escaping {
+        Escaping::default()
+    }
+
+    /// Adds a rule replacing every occurrence of `pattern` with `replacement`. An empty pattern is ignored.
+    pub fn rule<P: Into<Bytes>, R: Into<Bytes>>(mut self, pattern: P, replacement: R) -> Escaping {
+        let pattern = pattern.into();
+        if !pattern.is_empty() {
+            self.rules.push((pattern, replacement.into()));
+        }
+        self
+    }
+
+    /// Escapes `data` into `out`, returning the number of bytes consumed. Unless `eof` is set, a trailing part of
+    /// `data` which may be the start of a pattern is left unconsumed, to be completed by the next chunk.
+    fn escape(&self, data: &[u8], out: &mut BytesMut, eof: bool) -> usize {
+        let mut pos = 0;
+        'outer: while pos < data.len() {
+            let rest = &data[pos..];
+
+            for (pattern, replacement) in self.rules.iter() {
+                if rest.starts_with(pattern) {
+                    out.extend_from_slice(replacement);
+                    pos += pattern.len();
+                    continue 'outer;
+                }
+
+                // A rule which may still match once the next chunk arrives takes precedence over the later ones, so
+                // the output doesn't depend on where the chunks are split.
+                if !eof && pattern.starts_with(rest) {
+                    break 'outer;
+                }
+            }
+
+            out.extend_from_slice(&rest[..1]);
+            pos += 1;
+        }
+        pos
+    }
+}
+
+impl StreamBody {
+    /// Applies an [Escaping](./struct.Escaping.html) to the data of the body.
+    ///
+    /// The patterns are also found when they're split across chunks, the bytes which may start a pattern at the end
+    /// of a chunk being held back until the next chunk tells. The escaped chunks are copies, and the size hint of the
+    /// body is unknown as the escaped length depends on the data.
+    pub fn escape(self, escaping: Escaping) -> StreamBody {
+        self.wrap_with(|inner| Escape {
+            inner,
+            escaping,
+            held: BytesMut::new(),
+            reached_eof: false,
+        })
+    }
+}
+
+struct Escape {
+    inner: StreamBody,
+    escaping: Escaping,
+    // The end of the previous chunk, which may be the start of a pattern.
+    held: BytesMut,
+    reached_eof: bool,
+}
+
+impl Body for Escape {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let me = &mut *self;
+
+        loop {
+            if me.reached_eof {
+                if me.held.is_empty() {
+                    return Poll::Ready(None);
+                }
+                let mut out = BytesMut::with_capacity(me.held.len());
+                me.escaping.escape(&me.held, &mut out, true);
+                me.held.clear();
+                return Poll::Ready(Some(Ok(StreamData::from_bytes(out.freeze()))));
+            }
+
+            let data = match Pin::new(&mut me.inner).poll_data(cx) {
+                Poll::Ready(Some(Ok(data))) => data,
+                Poll::Ready(None) => {
+                    me.reached_eof = true;
+                    continue;
+                }
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                Poll::Pending => return Poll::Pending,
+            };
+
+            let mut out = BytesMut::with_capacity(me.held.len() + data.remaining());
+            if me.held.is_empty() {
+                let consumed = me.escaping.escape(data.bytes(), &mut out, false);
+                me.held.extend_from_slice(&data.bytes()[consumed..]);
+            } else {
+                me.held.extend_from_slice(data.bytes());
+                let consumed = me.escaping.escape(&me.held, &mut out, false);
+                let _ = me.held.split_to(consumed);
+            }
+            // Dropping the chunk lets the source continue.
+            drop(data);
+
+            if !out.is_empty() {
+                return Poll::Ready(Some(Ok(StreamData::from_bytes(out.freeze()))));
+            }
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.held.is_empty() && (self.reached_eof || self.inner.is_end_stream())
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        if self.is_end_stream() {
+            return SizeHint::with_exact(0);
+        }
+        SizeHint::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn escaped(escaping: &Escaping, chunks: &[&[u8]]) -> Vec<u8> {
+        let body = StreamBody::concat(chunks.iter().map(|chunk| StreamBody::from(chunk.to_vec())).collect());
+        let mut body = body.escape(escaping.clone());
+        let mut out = Vec::new();
+        while let Some(data) = body.data().await {
+            out.extend_from_slice(data.unwrap().bytes());
+        }
+        out
+    }
+
+    // Checks that every split of `input` into two or three chunks gives the output of the whole input.
+    async fn assert_split_invariant(escaping: &Escaping, input: &[u8], expected: &[u8]) {
+        assert_eq!(escaped(escaping, &[input]).await, expected);
+        for i in 0..=input.len() {
+            assert_eq!(
+                escaped(escaping, &[&input[..i], &input[i..]]).await,
+                expected,
+                "split at {}",
+                i
+            );
+            for j in i..=input.len() {
+                let chunks = [&input[..i], &input[i..j], &input[j..]];
+                assert_eq!(escaped(escaping, &chunks).await, expected, "split at {} and {}", i, j);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn earlier_rules_win_across_chunk_boundaries() {
+        let escaping = Escaping::new().rule("abc", "[abc]").rule("a", "[a]");
+        assert_eq!(escaped(&escaping, &[b"xxa", b"bc"]).await, b"xx[abc]");
+        assert_split_invariant(&escaping, b"xabcaabx", b"x[abc][a][a]bx").await;
+    }
+
+    #[tokio::test]
+    async fn a_partial_pattern_at_the_end_is_emitted_as_is() {
+        let escaping = Escaping::new().rule("abc", "[abc]").rule("a", "[a]");
+        assert_eq!(escaped(&escaping, &[b"xx", b"ab"]).await, b"xx[a]b");
+        assert_split_invariant(&escaping, b"xxab", b"xx[a]b").await;
+    }
+
+    #[tokio::test]
+    async fn byte_stuffing_escapes_the_escape_byte() {
+        let escaping = Escaping::new()
+            .rule(&b"\x7e"[..], &b"\x7d\x5e"[..])
+            .rule(&b"\x7d"[..], &b"\x7d\x5d"[..]);
+        assert_split_invariant(&escaping, b"\x01\x7e\x7d\x02", b"\x01\x7d\x5e\x7d\x5d\x02").await;
+    }
+
+    #[tokio::test]
+    async fn no_rules_leave_the_data_unchanged() {
+        assert_split_invariant(&Escaping::new(), b"hello", b"hello").await;
+    }
+}
@@ -0,0 +1,288 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use bytes::{Buf, Bytes};
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+#[cfg(not(feature = "test-util"))]
+use std::time::Instant;
+use tokio::io;
+// The idle times follow the clock paused and advanced by the `test` module.
+#[cfg(feature = "test-util")]
+use tokio::time::Instant;
+
+type EvictCallback = Arc<dyn Fn(Duration) + Send + Sync>;
+
+/// A cap on the number of long-lived bodies, e.g. SSE connections on a fan-out server, which makes room for new
+/// bodies by closing the ones whose producer has been idle the longest, so memory and file descriptors stay bounded.
+///
+/// Bodies are attached via [StreamBody::with_idle_eviction](./struct.StreamBody.html#method.with_idle_eviction).
+/// Attaching a body while `max_bodies` bodies are attached evicts the body which emitted no data for the longest
+/// time, provided it's idle for at least the [min_idle](#method.min_idle) time. Otherwise the new body is attached
+/// anyway, so the cap is exceeded rather than closing active streams. An evicted body emits the
+/// [farewell](#method.farewell) chunk if any and ends cleanly, so an `EventSource` reconnects later.
+///
+/// The handle is cheap to clone and all the clones share the same bodies.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use stream_body::{IdleEviction, StreamBody};
+///
+/// let eviction = IdleEviction::new(10_000)
+///     .min_idle(Duration::from_secs(60))
+///     .ignore_sse_comments()
+///     .farewell("retry: 30000\n\n")
+///     .on_evict(|idle| log::info!("evicted a stream idle for {:?}", idle));
+///
+/// let (writer, body) = StreamBody::channel();
+/// let body = body.with_idle_eviction(&eviction);
+/// ```
+#[derive(Clone)]
+pub struct IdleEviction {
+    inner: Arc<Mutex<EvictionState>>,
+    max_bodies: usize,
+    min_idle: Duration,
+    ignore_sse_comments: bool,
+    farewell: Option<Bytes>,
+    on_evict: Option<EvictCallback>,
+}
+
+struct EvictionState {
+    next_id: u64,
+    bodies: BTreeMap<u64, Arc<Mutex<Entry>>>,
+    evicted: u64,
+}
+
+struct Entry {
+    // When the body last emitted data, or was attached.
+    last_activity: Instant,
+    evicted: bool,
+    waker: Option<Waker>,
+}
+
+impl IdleEviction {
+    /// Creates a cap of `max_bodies` attached bodies, any idle body being evictable.
+    pub fn new(max_bodies: usize) -> IdleEviction {
+        IdleEviction {
+            inner: Arc::new(Mutex::new(EvictionState {
+                next_id: 0,
+                bodies: BTreeMap::new(),
+                evicted: 0,
+            })),
+            max_bodies,
+            min_idle: Duration::default(),
+            ignore_sse_comments: false,
+            farewell: None,
+            on_evict: None,
+        }
+    }
+
+    /// Only evicts the bodies which emitted no data for at least `min_idle`.
+    pub fn min_idle(mut self, min_idle: Duration) -> IdleEviction {
+        self.min_idle = min_idle;
+        self
+    }
+
+    /// Doesn't count the chunks made of SSE comment lines only as activity, so the heartbeats of an
+    /// [EventWriter](./struct.EventWriter.html) don't keep an idle stream from being evicted.
+    pub fn ignore_sse_comments(mut self) -> IdleEviction {
+        self.ignore_sse_comments = true;
+        self
+    }
+
+    /// Sets a final chunk the evicted bodies emit before they end, e.g. an SSE `retry:` field delaying the
+    /// reconnection.
+    pub fn farewell<C: Into<Bytes>>(mut self, chunk: C) -> IdleEviction {
+        let chunk = chunk.into();
+        self.farewell = if chunk.is_empty() { None } else { Some(chunk) };
+        self
+    }
+
+    /// Sets a callback called with the idle time of every evicted body, e.g. to log or count the evictions.
+    pub fn on_evict<F>(mut self, callback: F) -> IdleEviction
+    where
+        F: Fn(Duration) + Send + Sync + 'static,
+    {
+        self.on_evict = Some(Arc::new(callback));
+        self
+    }
+
+    /// Returns the number of attached bodies.
+    pub fn len(&self) -> usize {
+        self.lock().bodies.len()
+    }
+
+    /// Returns whether no body is attached.
+    pub fn is_empty(&self) -> bool {
+        self.lock().bodies.is_empty()
+    }
+
+    /// Returns the number of bodies evicted so far.
+    pub fn evicted(&self) -> u64 {
+        self.lock().evicted
+    }
+
+    fn lock(&self) -> MutexGuard<'_, EvictionState> {
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Attaches a new body, evicting the longest idle one if the cap is reached.
+    fn attach(&self) -> (u64, Arc<Mutex<Entry>>) {
+        let now = Instant::now();
+        let mut evicted_idle = None;
+
+        let attached = {
+            let mut state = self.lock();
+
+            if state.bodies.len() >= self.max_bodies {
+                let idlest = state
+                    .bodies
+                    .iter()
+                    .map(|(&id, entry)| (id, lock_entry(entry).last_activity))
+                    .min_by_key(|&(_, last_activity)| last_activity);
+
+                if let Some((id, last_activity)) = idlest {
+                    let idle = now.saturating_duration_since(last_activity);
+                    if idle >= self.min_idle {
+                        if let Some(entry) = state.bodies.remove(&id) {
+                            let mut entry = lock_entry(&entry);
+                            entry.evicted = true;
+                            if let Some(waker) = entry.waker.take() {
+                                waker.wake();
+                            }
+                        }
+                        state.evicted += 1;
+                        evicted_idle = Some(idle);
+                    }
+                }
+            }
+
+            let id = state.next_id;
+            state.next_id += 1;
+            let entry = Arc::new(Mutex::new(Entry {
+                last_activity: now,
+                evicted: false,
+                waker: None,
+            }));
+            state.bodies.insert(id, Arc::clone(&entry));
+            (id, entry)
+        };
+
+        // The callback is called without holding the lock, so it can use the handle.
+        if let (Some(idle), Some(ref on_evict)) = (evicted_idle, &self.on_evict) {
+            on_evict(idle);
+        }
+        attached
+    }
+}
+
+fn lock_entry(entry: &Mutex<Entry>) -> MutexGuard<'_, Entry> {
+    entry.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Returns whether the chunk is made of SSE comment lines only, e.g. a heartbeat.
+fn is_sse_comment(chunk: &[u8]) -> bool {
+    chunk
+        .split(|&byte| byte == b'\n')
+        .all(|line| line.is_empty() || line == b"\r" || line.starts_with(b":"))
+}
+
+impl StreamBody {
+    /// Attaches the body to an [IdleEviction](./struct.IdleEviction.html) cap, which may close it to make room for
+    /// new bodies once its producer is idle. Attaching it may evict the longest idle body already attached. The body
+    /// is detached once it's dropped.
+    pub fn with_idle_eviction(self, eviction: &IdleEviction) -> StreamBody {
+        let (id, entry) = eviction.attach();
+
+        self.wrap_with(|inner| Evictable {
+            inner,
+            eviction: eviction.clone(),
+            id,
+            entry,
+            farewell_sent: false,
+        })
+    }
+}
+
+struct Evictable {
+    inner: StreamBody,
+    eviction: IdleEviction,
+    id: u64,
+    entry: Arc<Mutex<Entry>>,
+    farewell_sent: bool,
+}
+
+impl Body for Evictable {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let me = &mut *self;
+
+        {
+            let mut entry = lock_entry(&me.entry);
+            if entry.evicted {
+                if me.farewell_sent {
+                    return Poll::Ready(None);
+                }
+                me.farewell_sent = true;
+                return Poll::Ready(
+                    me.eviction
+                        .farewell
+                        .clone()
+                        .map(|chunk| Ok(StreamData::from_bytes(chunk))),
+                );
+            }
+            entry.waker = Some(cx.waker().clone());
+        }
+
+        let poll_status = Pin::new(&mut me.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(ref data))) = poll_status {
+            if !(me.eviction.ignore_sse_comments && is_sse_comment(data.bytes())) {
+                lock_entry(&me.entry).last_activity = Instant::now();
+            }
+        }
+        poll_status
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        if lock_entry(&self.entry).evicted {
+            return Poll::Ready(Ok(None));
+        }
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        let entry = lock_entry(&self.entry);
+        if entry.evicted {
+            return self.farewell_sent || self.eviction.farewell.is_none();
+        }
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        if lock_entry(&self.entry).evicted {
+            let len = match self.eviction.farewell {
+                Some(ref chunk) if !self.farewell_sent => chunk.len() as u64,
+                _ => 0,
+            };
+            return SizeHint::with_exact(len);
+        }
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for Evictable {
+    fn drop(&mut self) {
+        self.eviction.lock().bodies.remove(&self.id);
+    }
+}
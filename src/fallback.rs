@@ -0,0 +1,95 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use bytes::Buf;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io;
+
+type FallbackFn = Box<dyn FnOnce(io::Error) -> StreamBody + Send>;
+
+impl StreamBody {
+    /// Switches to the body returned by `f` if the source fails before emitting any byte, e.g. to serve a canned
+    /// error document when the origin of a static file or a cache miss can't be read, like CDNs do.
+    ///
+    /// An error after the first byte still ends the body with that error, as the client already received part of the
+    /// original content. The fallback body is served as is, with its own trailers, and its errors aren't caught.
+    ///
+    /// The size hint is the one of the source until the fallback is used, so a source with an exact size, which makes
+    /// hyper send a `Content-Length` header, needs a fallback of the same size.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use stream_body::StreamBody;
+    ///
+    /// # async fn run() -> std::io::Result<()> {
+    /// let file = tokio::fs::File::open("index.html").await?;
+    ///
+    /// let body = StreamBody::from_reader_inline(file).with_fallback(|err| {
+    ///     log::warn!("serving the fallback page: {}", err);
+    ///     StreamBody::from("<html><body>Temporarily unavailable</body></html>")
+    /// });
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_fallback<F>(self, f: F) -> StreamBody
+    where
+        F: FnOnce(io::Error) -> StreamBody + Send + 'static,
+    {
+        self.wrap_with(|inner| WithFallback {
+            inner,
+            fallback: Some(Box::new(f)),
+        })
+    }
+}
+
+struct WithFallback {
+    // The source, replaced by the fallback body once it's used.
+    inner: StreamBody,
+    // Taken once the source emitted a byte or the fallback is used.
+    fallback: Option<FallbackFn>,
+}
+
+impl Body for WithFallback {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let me = &mut *self;
+
+        match Pin::new(&mut me.inner).poll_data(cx) {
+            Poll::Ready(Some(Err(err))) => match me.fallback.take() {
+                Some(fallback) => {
+                    me.inner = fallback(err);
+                    Pin::new(&mut me.inner).poll_data(cx)
+                }
+                None => Poll::Ready(Some(Err(err))),
+            },
+            Poll::Ready(Some(Ok(data))) => {
+                // Empty chunks don't commit the body to its source.
+                if data.has_remaining() {
+                    me.fallback = None;
+                }
+                Poll::Ready(Some(Ok(data)))
+            }
+            poll_status => poll_status,
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
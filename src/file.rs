@@ -0,0 +1,102 @@
+use crate::body::StreamBody;
+use bytes::Bytes;
+use std::io::SeekFrom;
+use std::ops::Range;
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::{self, AsyncReadExt};
+
+impl StreamBody {
+    /// Opens the file at `path` and streams it, with an exact size hint so hyper can send a `Content-Length` header.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use hyper::Response;
+    /// use stream_body::StreamBody;
+    ///
+    /// # async fn run() -> std::io::Result<Response<StreamBody>> {
+    /// let body = StreamBody::from_file("large-file").await?;
+    /// Ok(Response::new(body))
+    /// # }
+    /// ```
+    pub async fn from_file<P: AsRef<Path>>(path: P) -> io::Result<StreamBody> {
+        StreamBody::from_file_range(path, 0..u64::MAX).await
+    }
+
+    /// Opens the file at `path` and streams the bytes in `range`, seeking to its start and stopping at its end, with an
+    /// exact size hint. The range is clamped to the length of the file.
+    ///
+    /// It's the building block of `Range: bytes=` responses. As a [ByteRange](./struct.ByteRange.html) is inclusive,
+    /// it's passed as `range.start..range.end + 1`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use hyper::{Response, StatusCode};
+    /// use stream_body::{RangeDecision, StreamBody};
+    ///
+    /// # async fn run(range_header: &str, len: u64) -> std::io::Result<Response<StreamBody>> {
+    /// if let RangeDecision::Partial(ranges) = RangeDecision::from_header(range_header, len) {
+    ///     let range = ranges[0];
+    ///     let body = StreamBody::from_file_range("large-file", range.start..range.end + 1).await?;
+    ///
+    ///     let mut res = Response::new(body);
+    ///     *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+    ///     res.headers_mut().insert("content-range", range.content_range(len));
+    ///     return Ok(res);
+    /// }
+    /// # unimplemented!()
+    /// # }
+    /// ```
+    pub async fn from_file_range<P: AsRef<Path>>(path: P, range: Range<u64>) -> io::Result<StreamBody> {
+        let mut file = File::open(path).await?;
+        let file_len = file.metadata().await?.len();
+
+        let end = range.end.min(file_len);
+        let start = range.start.min(end);
+        if start > 0 {
+            file.seek(SeekFrom::Start(start)).await?;
+        }
+
+        let len = end - start;
+        let mut body = StreamBody::from_reader_with_len(file.take(len), len);
+        body.source = "file";
+        Ok(body)
+    }
+
+    /// Serves the bytes in `range` of an object held in a hot-object cache, falling back to streaming them from the
+    /// file at `path` on a cache miss, i.e. when `cached` is `None`. The range is clamped to the length of the object.
+    ///
+    /// A cached range is sliced off the cached `Bytes` without copying and emitted as a single chunk, so both paths
+    /// give a body with an exact size hint.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use bytes::Bytes;
+    /// use std::collections::HashMap;
+    /// use stream_body::{ByteRange, StreamBody};
+    ///
+    /// # async fn run(cache: &HashMap<String, Bytes>, range: ByteRange) -> std::io::Result<()> {
+    /// let path = "assets/app.js";
+    /// let body = StreamBody::serve_cached_range(cache.get(path), path, range.start..range.end + 1).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn serve_cached_range<P: AsRef<Path>>(
+        cached: Option<&Bytes>,
+        path: P,
+        range: Range<u64>,
+    ) -> io::Result<StreamBody> {
+        let bytes = match cached {
+            Some(bytes) => bytes,
+            None => return StreamBody::from_file_range(path, range).await,
+        };
+
+        let len = bytes.len() as u64;
+        let end = range.end.min(len);
+        let start = range.start.min(end);
+        Ok(StreamBody::from(bytes.slice(start as usize..end as usize)))
+    }
+}
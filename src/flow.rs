@@ -0,0 +1,132 @@
+use crate::body::{size_hint_with_held, StreamBody};
+use crate::data::StreamData;
+use bytes::Buf;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io;
+
+pub(crate) type CapacitySignal = Arc<dyn Fn() -> usize + Send + Sync>;
+
+/// A capacity signal shared with a body via [StreamBody::with_flow_capacity](./struct.StreamBody.html#method.with_flow_capacity),
+/// e.g. updated from the HTTP/2 flow-control window of the stream as the peer grants capacity.
+///
+/// The handle is cheap to clone and all the clones share the same value.
+///
+/// # Examples
+///
+/// ```no_run
+/// use stream_body::{FlowCapacity, StreamBody};
+///
+/// let capacity = FlowCapacity::new(64 * 1024);
+///
+/// let (writer, body) = StreamBody::channel_with_capacity(64 * 1024);
+/// let body = body.with_flow_capacity(&capacity);
+///
+/// // The peer's window shrank, the next chunks follow.
+/// capacity.set_max_outstanding_bytes(4 * 1024);
+/// ```
+#[derive(Debug, Clone)]
+pub struct FlowCapacity {
+    max: Arc<AtomicUsize>,
+}
+
+impl FlowCapacity {
+    /// Creates a signal allowing `max_outstanding_bytes` per chunk.
+    pub fn new(max_outstanding_bytes: usize) -> FlowCapacity {
+        FlowCapacity {
+            max: Arc::new(AtomicUsize::new(max_outstanding_bytes)),
+        }
+    }
+
+    /// Sets the number of bytes the next chunks may hold, taken into account from the next read of the body.
+    pub fn set_max_outstanding_bytes(&self, max_outstanding_bytes: usize) {
+        self.max.store(max_outstanding_bytes, Ordering::Relaxed);
+    }
+
+    /// Returns the number of bytes the next chunks may hold.
+    pub fn max_outstanding_bytes(&self) -> usize {
+        self.max.load(Ordering::Relaxed)
+    }
+}
+
+impl StreamBody {
+    /// Sizes the chunks of the body after `signal`, a callback consulted before each read which returns the number of
+    /// bytes the peer can take right now, e.g. the flow-control window of an HTTP/2 stream, so the body doesn't fill
+    /// whole buffers which hyper would only hold on to.
+    ///
+    /// A channel body reads at most that many bytes into its buffer, so the producer adapts to the window. Other
+    /// bodies have their larger chunks split without copying. A signal of zero counts as one byte, as the body has no
+    /// way to be woken up once capacity is granted.
+    pub fn with_capacity_signal<F>(mut self, signal: F) -> StreamBody
+    where
+        F: Fn() -> usize + Send + Sync + 'static,
+    {
+        let signal: CapacitySignal = Arc::new(signal);
+        if self.set_read_limit(Arc::clone(&signal)) {
+            return self;
+        }
+
+        self.wrap_with(|inner| CapacityLimited {
+            inner,
+            signal,
+            pending: None,
+        })
+    }
+
+    /// Sizes the chunks of the body after a [FlowCapacity](./struct.FlowCapacity.html) handle, see
+    /// [with_capacity_signal](#method.with_capacity_signal).
+    pub fn with_flow_capacity(self, capacity: &FlowCapacity) -> StreamBody {
+        let capacity = capacity.clone();
+        self.with_capacity_signal(move || capacity.max_outstanding_bytes())
+    }
+}
+
+struct CapacityLimited {
+    inner: StreamBody,
+    signal: CapacitySignal,
+    // The rest of a chunk larger than the capacity.
+    pending: Option<StreamData>,
+}
+
+impl Body for CapacityLimited {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let mut data = match self.pending.take() {
+            Some(data) => data,
+            None => match Pin::new(&mut self.inner).poll_data(cx) {
+                Poll::Ready(Some(Ok(data))) => data,
+                poll_status => return poll_status,
+            },
+        };
+
+        let capacity = (self.signal)().max(1);
+        if data.remaining() > capacity {
+            let part = data.split_to(capacity);
+            self.pending = Some(data);
+            return Poll::Ready(Some(Ok(part)));
+        }
+        Poll::Ready(Some(Ok(data)))
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.pending.is_none() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let pending = self.pending.as_ref().map(|data| data.remaining() as u64).unwrap_or(0);
+        size_hint_with_held(self.inner.size_hint(), pending)
+    }
+}
@@ -0,0 +1,119 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use bytes::Buf;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::any::Any;
+use std::error::Error;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io;
+
+impl StreamBody {
+    /// Creates a body forwarding `body`, e.g. the response of an upstream service which a proxy relays downstream.
+    ///
+    /// When `body` is itself a `StreamBody`, it's returned as is, so the chunks keep their ownership all the way, i.e.
+    /// they're neither copied nor re-buffered and the upstream buffers are reused once the downstream consumed them.
+    /// Otherwise the chunks are moved over without copying when they're `StreamData` or `Bytes`, e.g. the chunks of
+    /// `hyper::Body`, and copied for other chunk types. The trailers and the size hint are forwarded, the errors are
+    /// turned into `io::Error`s.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use hyper::{Client, Response};
+    /// use stream_body::StreamBody;
+    ///
+    /// # async fn run() -> Result<Response<StreamBody>, hyper::Error> {
+    /// let upstream = Client::new().get("http://upstream/file".parse().unwrap()).await?;
+    ///
+    /// let (parts, body) = upstream.into_parts();
+    /// Ok(Response::from_parts(parts, StreamBody::forward(body)))
+    /// # }
+    /// ```
+    pub fn forward<B>(body: B) -> StreamBody
+    where
+        B: Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn Error + Send + Sync>>,
+    {
+        let mut body = Some(body);
+        if let Some(body) = (&mut body as &mut dyn Any).downcast_mut::<Option<StreamBody>>() {
+            if let Some(body) = body.take() {
+                return body;
+            }
+        }
+
+        let mut forwarded = StreamBody::wrap(Forwarded {
+            inner: Box::pin(body.expect("the body is only taken if it's a StreamBody")),
+        });
+        forwarded.source = "forward";
+        forwarded
+    }
+}
+
+struct Forwarded<B> {
+    inner: Pin<Box<B>>,
+}
+
+/// Moves a chunk over as a `StreamData`, only copying it if it's neither a `StreamData` nor `Bytes`.
+fn into_stream_data<D: Buf + 'static>(data: D) -> StreamData {
+    let mut data = Some(data);
+    let any = &mut data as &mut dyn Any;
+
+    if let Some(data) = any.downcast_mut::<Option<StreamData>>() {
+        if let Some(data) = data.take() {
+            return data;
+        }
+    }
+    if let Some(data) = any.downcast_mut::<Option<bytes::Bytes>>() {
+        if let Some(bytes) = data.take() {
+            return StreamData::from_bytes(bytes);
+        }
+    }
+
+    match data {
+        Some(mut data) => StreamData::from_bytes(data.to_bytes()),
+        None => unreachable!("the chunk is only taken once downcast"),
+    }
+}
+
+pub(crate) fn into_io_error<E: Into<Box<dyn Error + Send + Sync>>>(err: E) -> io::Error {
+    match err.into().downcast::<io::Error>() {
+        Ok(err) => *err,
+        Err(err) => io::Error::new(io::ErrorKind::Other, err),
+    }
+}
+
+impl<B> Body for Forwarded<B>
+where
+    B: Body + Send + 'static,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        match self.inner.as_mut().poll_data(cx) {
+            Poll::Ready(Some(Ok(data))) => Poll::Ready(Some(Ok(into_stream_data(data)))),
+            Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(into_io_error(err)))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        self.inner.as_mut().poll_trailers(cx).map_err(into_io_error)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
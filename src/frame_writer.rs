@@ -0,0 +1,343 @@
+use std::marker::Unpin;
+use tokio::io::{self, AsyncWrite, AsyncWriteExt};
+
+// Frames up to this size are written together with their length prefix, so they reach the body as one chunk.
+const COALESCE_LIMIT: usize = 4 * 1024;
+const MAX_PREFIX_LEN: usize = 10;
+
+/// The encoding of the length prefix written by a [FrameWriter](./struct.FrameWriter.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthPrefix {
+    /// A 4 bytes big-endian length.
+    U32Be,
+    /// A 4 bytes little-endian length.
+    U32Le,
+    /// An 8 bytes big-endian length.
+    U64Be,
+    /// An 8 bytes little-endian length.
+    U64Le,
+    /// An unsigned LEB128 varint length, as used by protobuf.
+    Varint,
+}
+
+impl LengthPrefix {
+    /// Encodes `len` into `buf`, returning the number of bytes used.
+    fn encode(self, len: u64, buf: &mut [u8; MAX_PREFIX_LEN]) -> io::Result<usize> {
+        let len32 = || {
+            if len > u64::from(u32::MAX) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{}: FrameWriter: The frame length {} doesn't fit in a 32 bits prefix",
+                        env!("CARGO_PKG_NAME"),
+                        len
+                    ),
+                ));
+            }
+            Ok(len as u32)
+        };
+
+        match self {
+            LengthPrefix::U32Be => buf[..4].copy_from_slice(&len32()?.to_be_bytes()),
+            LengthPrefix::U32Le => buf[..4].copy_from_slice(&len32()?.to_le_bytes()),
+            LengthPrefix::U64Be => buf[..8].copy_from_slice(&len.to_be_bytes()),
+            LengthPrefix::U64Le => buf[..8].copy_from_slice(&len.to_le_bytes()),
+            LengthPrefix::Varint => return Ok(encode_varint(len, buf)),
+        }
+
+        match self {
+            LengthPrefix::U32Be | LengthPrefix::U32Le => Ok(4),
+            _ => Ok(8),
+        }
+    }
+}
+
+fn encode_varint(mut len: u64, buf: &mut [u8; MAX_PREFIX_LEN]) -> usize {
+    let mut i = 0;
+    while len >= 0x80 {
+        buf[i] = (len & 0x7f) as u8 | 0x80;
+        len >>= 7;
+        i += 1;
+    }
+    buf[i] = len as u8;
+    i + 1
+}
+
+/// Writes length-prefixed frames to a writer, e.g. the writer half of
+/// [StreamBody::channel](./struct.StreamBody.html#method.channel), for custom binary streaming protocols.
+///
+/// A frame can be written at once via [write_frame](#method.write_frame), or in parts via
+/// [begin_frame](#method.begin_frame) and [write_frame_part](#method.write_frame_part) when its payload is produced
+/// incrementally or doesn't fit in memory. Frames larger than the body's buffer simply span several chunks.
+///
+/// # Examples
+///
+/// ```no_run
+/// use stream_body::{FrameWriter, LengthPrefix, StreamBody};
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let (writer, body) = StreamBody::channel();
+///
+/// let mut frames = FrameWriter::new(writer, LengthPrefix::U32Be);
+/// frames.write_frame(b"first message").await?;
+///
+/// frames.begin_frame(10).await?;
+/// frames.write_frame_part(b"01234").await?;
+/// frames.write_frame_part(b"56789").await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct FrameWriter<W> {
+    inner: W,
+    prefix: LengthPrefix,
+    // The number of payload bytes the current frame still expects.
+    frame_remaining: u64,
+    scratch: Vec<u8>,
+}
+
+impl<W: AsyncWrite + Unpin> FrameWriter<W> {
+    /// Creates a frame writer using the given length prefix encoding.
+    pub fn new(inner: W, prefix: LengthPrefix) -> FrameWriter<W> {
+        FrameWriter {
+            inner,
+            prefix,
+            frame_remaining: 0,
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Writes a complete frame, i.e. its length prefix followed by the payload.
+    pub async fn write_frame(&mut self, payload: &[u8]) -> io::Result<()> {
+        self.check_no_open_frame()?;
+
+        let mut prefix = [0_u8; MAX_PREFIX_LEN];
+        let prefix_len = self.prefix.encode(payload.len() as u64, &mut prefix)?;
+
+        if payload.len() <= COALESCE_LIMIT {
+            let mut scratch = std::mem::take(&mut self.scratch);
+            scratch.clear();
+            scratch.extend_from_slice(&prefix[..prefix_len]);
+            scratch.extend_from_slice(payload);
+            let result = self.inner.write_all(&scratch).await;
+            self.scratch = scratch;
+            return result;
+        }
+
+        self.inner.write_all(&prefix[..prefix_len]).await?;
+        self.inner.write_all(payload).await
+    }
+
+    /// Starts a frame with a payload of `len` bytes, which is then written via
+    /// [write_frame_part](#method.write_frame_part).
+    pub async fn begin_frame(&mut self, len: u64) -> io::Result<()> {
+        self.check_no_open_frame()?;
+
+        let mut prefix = [0_u8; MAX_PREFIX_LEN];
+        let prefix_len = self.prefix.encode(len, &mut prefix)?;
+        self.inner.write_all(&prefix[..prefix_len]).await?;
+
+        self.frame_remaining = len;
+        Ok(())
+    }
+
+    /// Writes a part of the payload of the frame started via [begin_frame](#method.begin_frame). The parts must not
+    /// exceed the announced length.
+    pub async fn write_frame_part(&mut self, part: &[u8]) -> io::Result<()> {
+        if part.len() as u64 > self.frame_remaining {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{}: FrameWriter: The part of {} bytes exceeds the {} bytes left in the frame",
+                    env!("CARGO_PKG_NAME"),
+                    part.len(),
+                    self.frame_remaining
+                ),
+            ));
+        }
+
+        self.inner.write_all(part).await?;
+        self.frame_remaining -= part.len() as u64;
+        Ok(())
+    }
+
+    /// Returns the number of payload bytes the current frame still expects, zero if no frame is in progress.
+    pub fn frame_remaining(&self) -> u64 {
+        self.frame_remaining
+    }
+
+    /// Flushes the underlying writer.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().await
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying writer. Writing to it directly breaks the framing.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Unwraps the frame writer, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn check_no_open_frame(&self) -> io::Result<()> {
+        if self.frame_remaining == 0 {
+            return Ok(());
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{}: FrameWriter: The current frame still expects {} bytes",
+                env!("CARGO_PKG_NAME"),
+                self.frame_remaining
+            ),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    // A writer accepting at most a few bytes per write, so the frames are split at every position.
+    struct ShortWrites {
+        written: Vec<u8>,
+        max_write: usize,
+    }
+
+    impl AsyncWrite for ShortWrites {
+        fn poll_write(mut self: Pin<&mut Self>, _cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+            let len = buf.len().min(self.max_write);
+            self.written.extend_from_slice(&buf[..len]);
+            Poll::Ready(Ok(len))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn decode_len(prefix: LengthPrefix, buf: &[u8]) -> (u64, usize) {
+        match prefix {
+            LengthPrefix::U32Be => (u64::from(u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]])), 4),
+            LengthPrefix::U32Le => (u64::from(u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]])), 4),
+            LengthPrefix::U64Be | LengthPrefix::U64Le => {
+                let mut bytes = [0_u8; 8];
+                bytes.copy_from_slice(&buf[..8]);
+                if prefix == LengthPrefix::U64Be {
+                    (u64::from_be_bytes(bytes), 8)
+                } else {
+                    (u64::from_le_bytes(bytes), 8)
+                }
+            }
+            LengthPrefix::Varint => {
+                let mut len = 0;
+                for (i, byte) in buf.iter().enumerate() {
+                    len |= u64::from(byte & 0x7f) << (7 * i);
+                    if byte & 0x80 == 0 {
+                        return (len, i + 1);
+                    }
+                }
+                panic!("truncated varint")
+            }
+        }
+    }
+
+    fn decode(prefix: LengthPrefix, mut buf: &[u8]) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+        while !buf.is_empty() {
+            let (len, prefix_len) = decode_len(prefix, buf);
+            let end = prefix_len + len as usize;
+            frames.push(buf[prefix_len..end].to_vec());
+            buf = &buf[end..];
+        }
+        frames
+    }
+
+    const PREFIXES: [LengthPrefix; 5] = [
+        LengthPrefix::U32Be,
+        LengthPrefix::U32Le,
+        LengthPrefix::U64Be,
+        LengthPrefix::U64Le,
+        LengthPrefix::Varint,
+    ];
+
+    #[tokio::test]
+    async fn frames_round_trip_with_every_prefix() {
+        let large = (0..COALESCE_LIMIT + 300).map(|i| i as u8).collect::<Vec<_>>();
+
+        for &prefix in PREFIXES.iter() {
+            for &max_write in [1, 3, usize::MAX].iter() {
+                let mut frames = FrameWriter::new(
+                    ShortWrites {
+                        written: Vec::new(),
+                        max_write,
+                    },
+                    prefix,
+                );
+                frames.write_frame(b"first").await.unwrap();
+                frames.write_frame(b"").await.unwrap();
+                frames.write_frame(&large).await.unwrap();
+                frames.begin_frame(10).await.unwrap();
+                frames.write_frame_part(b"01234").await.unwrap();
+                assert_eq!(frames.frame_remaining(), 5);
+                frames.write_frame_part(b"56789").await.unwrap();
+                assert_eq!(frames.frame_remaining(), 0);
+
+                let written = frames.into_inner().written;
+                let expected = vec![b"first".to_vec(), Vec::new(), large.clone(), b"0123456789".to_vec()];
+                assert_eq!(decode(prefix, &written), expected, "{:?} by {}", prefix, max_write);
+            }
+        }
+    }
+
+    #[test]
+    fn varints_use_seven_bits_per_byte() {
+        let mut buf = [0_u8; MAX_PREFIX_LEN];
+        assert_eq!(encode_varint(0, &mut buf), 1);
+        assert_eq!(buf[0], 0);
+        assert_eq!(encode_varint(300, &mut buf), 2);
+        assert_eq!(&buf[..2], &[0xac, 0x02]);
+        assert_eq!(encode_varint(u64::MAX, &mut buf), MAX_PREFIX_LEN);
+        assert_eq!(decode_len(LengthPrefix::Varint, &buf), (u64::MAX, MAX_PREFIX_LEN));
+    }
+
+    #[tokio::test]
+    async fn invalid_frames_are_rejected() {
+        let mut buf = [0_u8; MAX_PREFIX_LEN];
+        let err = LengthPrefix::U32Be
+            .encode(u64::from(u32::MAX) + 1, &mut buf)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            LengthPrefix::U64Le.encode(u64::from(u32::MAX) + 1, &mut buf).unwrap(),
+            8
+        );
+
+        let mut frames = FrameWriter::new(Vec::new(), LengthPrefix::U32Be);
+        frames.begin_frame(4).await.unwrap();
+        let err = frames.write_frame_part(b"12345").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        // A frame can't start before the current one is complete.
+        let err = frames.write_frame(b"next").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = frames.begin_frame(1).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        frames.write_frame_part(b"1234").await.unwrap();
+        frames.write_frame(b"next").await.unwrap();
+        assert_eq!(frames.get_ref(), b"\0\0\0\x041234\0\0\0\x04next");
+    }
+}
@@ -0,0 +1,112 @@
+use crate::body::{size_hint_with_held, StreamBody};
+use crate::data::StreamData;
+use bytes::{Buf, BytesMut};
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io;
+
+// The initial SETTINGS_MAX_FRAME_SIZE of HTTP/2, which most peers keep.
+const DEFAULT_FRAME_SIZE: usize = 16 * 1024;
+
+impl StreamBody {
+    /// Same as [align_to_frame_size](#method.align_to_frame_size) with the default HTTP/2 frame size of 16 KiB.
+    pub fn align_to_frames(self) -> StreamBody {
+        self.align_to_frame_size(DEFAULT_FRAME_SIZE)
+    }
+
+    /// Re-chunks the body into chunks whose sizes are multiples of `frame_size`, so on HTTP/2 the data maps onto
+    /// full DATA frames without being split into partial frames. Set it to the peer's `SETTINGS_MAX_FRAME_SIZE`.
+    ///
+    /// Source chunks which already are a multiple of the frame size are passed through without a copy, the other ones
+    /// are coalesced until a full frame is available, only the last chunk may be shorter. As coalesced data waits for
+    /// the rest of its frame, it's meant for bulk transfers rather than live streams. A `frame_size` of zero is
+    /// treated as the default.
+    pub fn align_to_frame_size(self, frame_size: usize) -> StreamBody {
+        let frame_size = if frame_size == 0 {
+            DEFAULT_FRAME_SIZE
+        } else {
+            frame_size
+        };
+
+        self.wrap_with(|inner| Aligned {
+            inner,
+            frame_size,
+            buf: BytesMut::new(),
+            reached_eof: false,
+        })
+    }
+}
+
+struct Aligned {
+    inner: StreamBody,
+    frame_size: usize,
+    buf: BytesMut,
+    reached_eof: bool,
+}
+
+impl Aligned {
+    // Emits the full frames of the coalesced data, or all of it if `partial` is set.
+    fn take_frames(&mut self, partial: bool) -> Option<StreamData> {
+        let len = if partial {
+            self.buf.len()
+        } else {
+            self.buf.len() / self.frame_size * self.frame_size
+        };
+
+        if len == 0 {
+            return None;
+        }
+        Some(StreamData::from_bytes(self.buf.split_to(len).freeze()))
+    }
+}
+
+impl Body for Aligned {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        loop {
+            if self.reached_eof {
+                return Poll::Ready(self.take_frames(true).map(Ok));
+            }
+
+            match Pin::new(&mut self.inner).poll_data(cx) {
+                Poll::Ready(Some(Ok(data))) => {
+                    let len = data.remaining();
+                    if self.buf.is_empty() && len % self.frame_size == 0 {
+                        return Poll::Ready(Some(Ok(data)));
+                    }
+
+                    // Dropping the copied chunk lets the source continue.
+                    self.buf.extend_from_slice(data.bytes());
+                    drop(data);
+
+                    if let Some(frames) = self.take_frames(false) {
+                        return Poll::Ready(Some(Ok(frames)));
+                    }
+                }
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                Poll::Ready(None) => self.reached_eof = true,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.buf.is_empty() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let buffered = self.buf.len() as u64;
+        size_hint_with_held(self.inner.size_hint(), buffered)
+    }
+}
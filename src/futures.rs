@@ -0,0 +1,137 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use bytes::Buf;
+use futures_core::Stream;
+use futures_io::{AsyncBufRead, AsyncRead};
+use http_body::Body;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use tokio::io;
+
+impl Stream for StreamBody {
+    type Item = io::Result<StreamData>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.poll_data(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, None)
+    }
+}
+
+impl StreamBody {
+    /// Converts the body into a [futures AsyncBufRead](https://docs.rs/futures/0.3/futures/io/trait.AsyncBufRead.html),
+    /// e.g. to serve it from [tide](https://docs.rs/tide) or other async-std based frameworks.
+    ///
+    /// Chunks are read in place, so no additional copy happens when the reader is consumed via `AsyncBufRead`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let body = tide::Body::from_reader(StreamBody::from_reader(file).into_async_read(), Some(len));
+    /// ```
+    pub fn into_async_read(self) -> IntoAsyncRead {
+        IntoAsyncRead {
+            state: Mutex::new(ReadState {
+                body: self,
+                chunk: None,
+            }),
+        }
+    }
+}
+
+impl StreamBody {
+    /// Creates a body which reads the provided [futures AsyncRead](https://docs.rs/futures/0.3/futures/io/trait.AsyncRead.html),
+    /// e.g. an async-std or smol file or socket.
+    ///
+    /// Like [from_reader_inline](#method.from_reader_inline), the reader is read on the task polling the body, so it
+    /// neither spawns a task nor needs a tokio runtime.
+    pub fn from_futures_reader<R: AsyncRead + Unpin + Send + 'static>(r: R) -> StreamBody {
+        StreamBody::from_reader_inline(FuturesReader(r))
+    }
+}
+
+/// Exposes a futures `AsyncRead` as a tokio one, both only differ in their buffer initialization contract.
+pub(crate) struct FuturesReader<R>(pub(crate) R);
+
+impl<R: AsyncRead + Unpin> io::AsyncRead for FuturesReader<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+/// A [futures AsyncBufRead](https://docs.rs/futures/0.3/futures/io/trait.AsyncBufRead.html) over the chunks of a
+/// `StreamBody`, created by [StreamBody::into_async_read](./struct.StreamBody.html#method.into_async_read).
+///
+/// It is `Sync`, as frameworks like tide require for their body readers.
+pub struct IntoAsyncRead {
+    // The mutex is never locked, it only makes the reader `Sync` as it is always accessed via `&mut self`.
+    state: Mutex<ReadState>,
+}
+
+struct ReadState {
+    body: StreamBody,
+    chunk: Option<StreamData>,
+}
+
+impl IntoAsyncRead {
+    fn state(&mut self) -> &mut ReadState {
+        match self.state.get_mut() {
+            Ok(state) => state,
+            Err(err) => err.into_inner(),
+        }
+    }
+}
+
+impl AsyncBufRead for IntoAsyncRead {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<&[u8]>> {
+        let state = self.get_mut().state();
+
+        while state.chunk.as_ref().map(|chunk| !chunk.has_remaining()).unwrap_or(true) {
+            // Dropping the previous chunk releases the body's buffer before the next read.
+            state.chunk = None;
+
+            match Pin::new(&mut state.body).poll_data(cx) {
+                Poll::Ready(Some(Ok(chunk))) => state.chunk = Some(chunk),
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Err(err)),
+                Poll::Ready(None) => return Poll::Ready(Ok(&[])),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        match state.chunk {
+            Some(ref chunk) => Poll::Ready(Ok(chunk.bytes())),
+            None => Poll::Ready(Ok(&[])),
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let state = self.get_mut().state();
+
+        if let Some(ref mut chunk) = state.chunk {
+            chunk.advance(amt.min(chunk.remaining()));
+            if !chunk.has_remaining() {
+                state.chunk = None;
+            }
+        }
+    }
+}
+
+impl AsyncRead for IntoAsyncRead {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let read_count = match self.as_mut().poll_fill_buf(cx) {
+            Poll::Ready(Ok(bytes)) => {
+                let read_count = bytes.len().min(buf.len());
+                buf[..read_count].copy_from_slice(&bytes[..read_count]);
+                read_count
+            }
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Pending => return Poll::Pending,
+        };
+
+        self.consume(read_count);
+        Poll::Ready(Ok(read_count))
+    }
+}
@@ -0,0 +1,200 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use bytes::Buf;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::fmt;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io;
+
+// The inclusive upper bounds of the chunk size buckets, the last bucket takes everything larger.
+const SIZE_BOUNDS: [u64; 7] = [64, 256, 1024, 4 * 1024, 16 * 1024, 64 * 1024, 256 * 1024];
+
+// The inclusive upper bounds of the inter-chunk gap buckets in microseconds, the last bucket takes everything longer.
+const GAP_BOUNDS: [u64; 5] = [100, 1_000, 10_000, 100_000, 1_000_000];
+
+// The share of small chunks from which coalescing them is suggested.
+const SMALL_CHUNK_RATIO: f64 = 0.9;
+const SMALL_CHUNK_SIZE: u64 = 1024;
+const MIN_REPORTED_CHUNKS: u64 = 16;
+
+impl StreamBody {
+    /// Records a histogram of the emitted chunk sizes and of the gaps between the chunks, and passes it to `on_eof`
+    /// once the body reaches its end.
+    ///
+    /// It's an opt-in diagnostic to help picking the buffer settings of a streaming path, see
+    /// [with_tuning_report](#method.with_tuning_report) for logging a summary directly.
+    pub fn with_chunk_histogram<F>(self, on_eof: F) -> StreamBody
+    where
+        F: FnOnce(&ChunkHistogram) + Send + 'static,
+    {
+        self.wrap_with(|inner| Histogram {
+            inner,
+            histogram: ChunkHistogram::new(),
+            last_chunk_at: None,
+            on_eof: Some(Box::new(on_eof)),
+        })
+    }
+
+    /// Logs a tuning summary of the chunk histogram via [log::info!](https://docs.rs/log/0.4.10/log/macro.info.html)
+    /// once the body reaches its end, e.g. `90% of chunks under 1KiB, consider coalescing`.
+    pub fn with_tuning_report(self) -> StreamBody {
+        self.with_chunk_histogram(|histogram| {
+            log::info!("{}: StreamBody: {}", env!("CARGO_PKG_NAME"), histogram);
+        })
+    }
+}
+
+/// A histogram of the chunk sizes and the inter-chunk gaps of a body, see
+/// [StreamBody::with_chunk_histogram](./struct.StreamBody.html#method.with_chunk_histogram).
+///
+/// Its `Display` implementation renders a short tuning summary.
+#[derive(Debug, Clone)]
+pub struct ChunkHistogram {
+    sizes: [u64; SIZE_BOUNDS.len() + 1],
+    gaps: [u64; GAP_BOUNDS.len() + 1],
+    chunks: u64,
+    bytes: u64,
+}
+
+impl ChunkHistogram {
+    fn new() -> ChunkHistogram {
+        ChunkHistogram {
+            sizes: [0; SIZE_BOUNDS.len() + 1],
+            gaps: [0; GAP_BOUNDS.len() + 1],
+            chunks: 0,
+            bytes: 0,
+        }
+    }
+
+    fn record(&mut self, size: u64, gap: Option<Duration>) {
+        self.sizes[bucket(&SIZE_BOUNDS, size)] += 1;
+        if let Some(gap) = gap {
+            self.gaps[bucket(&GAP_BOUNDS, gap.as_micros() as u64)] += 1;
+        }
+        self.chunks += 1;
+        self.bytes += size;
+    }
+
+    /// The number of emitted chunks.
+    pub fn chunks(&self) -> u64 {
+        self.chunks
+    }
+
+    /// The number of emitted bytes.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Returns the chunk size buckets as pairs of the inclusive upper bound in bytes and the number of chunks, the
+    /// bound of the last bucket is `None`.
+    pub fn size_buckets(&self) -> impl Iterator<Item = (Option<u64>, u64)> + '_ {
+        with_bounds(&SIZE_BOUNDS, &self.sizes)
+    }
+
+    /// Returns the buckets of the gaps between consecutive chunks as pairs of the inclusive upper bound and the number
+    /// of gaps, the bound of the last bucket is `None`.
+    pub fn gap_buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        with_bounds(&GAP_BOUNDS, &self.gaps).map(|(bound, count)| (bound.map(Duration::from_micros), count))
+    }
+
+    /// Returns the share of chunks with a size of at most `size` bytes, rounded up to the bucket containing `size`.
+    pub fn ratio_up_to(&self, size: u64) -> f64 {
+        if self.chunks == 0 {
+            return 0.0;
+        }
+
+        let small = self.sizes[..=bucket(&SIZE_BOUNDS, size)].iter().sum::<u64>();
+        small as f64 / self.chunks as f64
+    }
+
+    /// The average chunk size in bytes.
+    pub fn average_size(&self) -> u64 {
+        self.bytes.checked_div(self.chunks).unwrap_or(0)
+    }
+}
+
+impl fmt::Display for ChunkHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} chunks, {} bytes, {} bytes per chunk on average",
+            self.chunks,
+            self.bytes,
+            self.average_size()
+        )?;
+
+        let small_ratio = self.ratio_up_to(SMALL_CHUNK_SIZE);
+        if self.chunks >= MIN_REPORTED_CHUNKS && small_ratio >= SMALL_CHUNK_RATIO {
+            write!(
+                f,
+                "; {:.0}% of chunks under 1KiB, consider coalescing, e.g. with a BufferedWriter",
+                small_ratio * 100.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn bucket(bounds: &[u64], value: u64) -> usize {
+    bounds.iter().position(|&bound| value <= bound).unwrap_or(bounds.len())
+}
+
+fn with_bounds<'a>(bounds: &'a [u64], counts: &'a [u64]) -> impl Iterator<Item = (Option<u64>, u64)> + 'a {
+    counts
+        .iter()
+        .enumerate()
+        .map(move |(idx, &count)| (bounds.get(idx).copied(), count))
+}
+
+struct Histogram {
+    inner: StreamBody,
+    histogram: ChunkHistogram,
+    last_chunk_at: Option<Instant>,
+    on_eof: Option<EofCallback>,
+}
+
+type EofCallback = Box<dyn FnOnce(&ChunkHistogram) + Send>;
+
+impl Body for Histogram {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let poll_status = Pin::new(&mut self.inner).poll_data(cx);
+
+        match poll_status {
+            Poll::Ready(Some(Ok(ref data))) => {
+                let now = Instant::now();
+                let gap = self.last_chunk_at.map(|at| now.duration_since(at));
+                self.histogram.record(data.remaining() as u64, gap);
+                self.last_chunk_at = Some(now);
+            }
+            Poll::Ready(None) => {
+                if let Some(on_eof) = self.on_eof.take() {
+                    on_eof(&self.histogram);
+                }
+            }
+            _ => {}
+        }
+
+        poll_status
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
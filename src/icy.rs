@@ -0,0 +1,272 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use tokio::io;
+
+// A metadata block is at most 255 units of 16 bytes.
+const MAX_METADATA_LEN: usize = 255 * 16;
+
+/// The metadata injected into an audio stream via [StreamBody::icy_metadata](./struct.StreamBody.html#method.icy_metadata),
+/// e.g. the title of the current track of an internet radio.
+///
+/// The handle is cheap to clone and all the clones share the same metadata, so the producer of the audio can update
+/// it while the body streams.
+#[derive(Debug, Clone, Default)]
+pub struct IcyMetadata {
+    shared: Arc<Mutex<IcyState>>,
+}
+
+#[derive(Debug, Default)]
+struct IcyState {
+    metadata: Bytes,
+    // Incremented on every update, so every body sends the new metadata once.
+    version: u64,
+}
+
+impl IcyMetadata {
+    /// Creates an empty metadata, which sends empty blocks until it's set.
+    pub fn new() -> IcyMetadata {
+        IcyMetadata::default()
+    }
+
+    /// Sets the title of the current track, sent as `StreamTitle='title';`.
+    pub fn set_title(&self, title: &str) {
+        self.set_raw(format!("StreamTitle='{}';", title));
+    }
+
+    /// Sets the raw metadata, e.g. `StreamTitle='title';StreamUrl='url';`. It's truncated to the 4080 bytes a block
+    /// can hold.
+    pub fn set_raw<M: Into<Bytes>>(&self, metadata: M) {
+        let mut metadata = metadata.into();
+        metadata.truncate(MAX_METADATA_LEN);
+
+        let mut state = self.lock();
+        state.metadata = metadata;
+        state.version += 1;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, IcyState> {
+        self.shared.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Encodes the next metadata block, with the metadata if it changed since the version `sent`.
+    fn block(&self, sent: &mut u64) -> Bytes {
+        let state = self.lock();
+        if state.version == *sent {
+            return Bytes::from_static(&[0]);
+        }
+        *sent = state.version;
+
+        let units = (state.metadata.len() + 15) / 16;
+        let mut block = BytesMut::with_capacity(1 + units * 16);
+        block.put_u8(units as u8);
+        block.extend_from_slice(&state.metadata);
+        block.resize(1 + units * 16, 0);
+        block.freeze()
+    }
+}
+
+impl StreamBody {
+    /// Injects ICY metadata blocks into an audio stream every `metaint` bytes of audio, as expected by the
+    /// internet-radio clients which requested them with the `Icy-MetaData: 1` header.
+    ///
+    /// The response has to announce the interval with the `icy-metaint` header. A block holds the
+    /// [metadata](./struct.IcyMetadata.html) only the first time it's sent after an update, the other blocks are
+    /// empty. The audio chunks are split at the block positions without copying.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use hyper::Response;
+    /// use stream_body::{IcyMetadata, StreamBody};
+    ///
+    /// let metadata = IcyMetadata::new();
+    /// metadata.set_title("Artist - Song");
+    ///
+    /// let (writer, body) = StreamBody::channel();
+    /// let res = Response::builder()
+    ///     .header("content-type", "audio/mpeg")
+    ///     .header("icy-metaint", "16000")
+    ///     .body(body.icy_metadata(16000, &metadata))
+    ///     .unwrap();
+    /// ```
+    pub fn icy_metadata(self, metaint: usize, metadata: &IcyMetadata) -> StreamBody {
+        let metaint = metaint.max(1);
+        let metadata = metadata.clone();
+
+        self.wrap_with(|inner| IcyInjection {
+            inner,
+            metadata,
+            sent: 0,
+            metaint,
+            until_block: metaint,
+            pending: None,
+        })
+    }
+}
+
+struct IcyInjection {
+    inner: StreamBody,
+    metadata: IcyMetadata,
+    // The version of the metadata last sent.
+    sent: u64,
+    metaint: usize,
+    // The number of audio bytes left before the next block.
+    until_block: usize,
+    // The rest of an audio chunk spanning a block position.
+    pending: Option<StreamData>,
+}
+
+impl Body for IcyInjection {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let me = &mut *self;
+
+        if me.until_block == 0 {
+            me.until_block = me.metaint;
+            let block = me.metadata.block(&mut me.sent);
+            return Poll::Ready(Some(Ok(StreamData::from_bytes(block))));
+        }
+
+        let mut data = match me.pending.take() {
+            Some(data) => data,
+            None => match Pin::new(&mut me.inner).poll_data(cx) {
+                Poll::Ready(Some(Ok(data))) => data,
+                poll_status => return poll_status,
+            },
+        };
+
+        if data.remaining() > me.until_block {
+            let part = data.split_to(me.until_block);
+            me.pending = Some(data);
+            me.until_block = 0;
+            return Poll::Ready(Some(Ok(part)));
+        }
+
+        me.until_block -= data.remaining();
+        Poll::Ready(Some(Ok(data)))
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        // A block is still due after the last audio bytes if they complete an interval.
+        self.pending.is_none() && self.until_block > 0 && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        if self.is_end_stream() {
+            return SizeHint::with_exact(0);
+        }
+        SizeHint::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn injected(chunks: &[&[u8]], metaint: usize, metadata: &IcyMetadata) -> Vec<u8> {
+        let body = StreamBody::concat(chunks.iter().map(|chunk| StreamBody::from(chunk.to_vec())).collect());
+        let mut body = body.icy_metadata(metaint, metadata);
+        let mut out = Vec::new();
+        while let Some(data) = body.data().await {
+            out.extend_from_slice(data.unwrap().bytes());
+        }
+        assert!(body.is_end_stream());
+        out
+    }
+
+    // Splits a stream into its audio and the metadata of its blocks, as a client does.
+    fn parse(mut stream: &[u8], metaint: usize) -> (Vec<u8>, Vec<Vec<u8>>) {
+        let mut audio = Vec::new();
+        let mut blocks = Vec::new();
+        while !stream.is_empty() {
+            let len = metaint.min(stream.len());
+            audio.extend_from_slice(&stream[..len]);
+            stream = &stream[len..];
+            if stream.is_empty() {
+                break;
+            }
+
+            let block_len = 1 + stream[0] as usize * 16;
+            blocks.push(stream[1..block_len].to_vec());
+            stream = &stream[block_len..];
+        }
+        (audio, blocks)
+    }
+
+    #[tokio::test]
+    async fn metadata_blocks_are_injected_every_interval() {
+        let metadata = IcyMetadata::new();
+        metadata.set_title("Artist - Song");
+        let audio = (0..50u8).collect::<Vec<_>>();
+
+        let (parsed_audio, blocks) = parse(&injected(&[&audio], 16, &metadata).await, 16);
+        assert_eq!(parsed_audio, audio);
+        assert_eq!(blocks.len(), 3);
+        let mut expected = b"StreamTitle='Artist - Song';".to_vec();
+        expected.resize(32, 0);
+        assert_eq!(blocks[0], expected);
+        // The metadata is only sent once until it's updated.
+        assert!(blocks[1].is_empty());
+        assert!(blocks[2].is_empty());
+
+        // A block is due after audio completing an interval.
+        let (parsed_audio, blocks) = parse(&injected(&[&audio[..32]], 16, &metadata).await, 16);
+        assert_eq!(parsed_audio, &audio[..32]);
+        assert_eq!(blocks.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn chunk_boundaries_dont_change_the_stream() {
+        let metadata = IcyMetadata::new();
+        metadata.set_raw("StreamTitle='x';");
+        let audio = (0..40u8).collect::<Vec<_>>();
+        let expected = injected(&[&audio], 7, &metadata).await;
+
+        for i in 0..=audio.len() {
+            for j in i..=audio.len() {
+                let metadata = IcyMetadata::new();
+                metadata.set_raw("StreamTitle='x';");
+                let chunks = [&audio[..i], &audio[i..j], &audio[j..]];
+                assert_eq!(
+                    injected(&chunks, 7, &metadata).await,
+                    expected,
+                    "split at {} and {}",
+                    i,
+                    j
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn metadata_updates_are_sent_once_and_truncated() {
+        let metadata = IcyMetadata::new();
+        let mut sent = 0;
+        assert_eq!(metadata.block(&mut sent), Bytes::from_static(&[0]));
+
+        metadata.set_raw(vec![b'a'; MAX_METADATA_LEN + 100]);
+        let block = metadata.block(&mut sent);
+        assert_eq!(block[0], 255);
+        assert_eq!(block.len(), 1 + MAX_METADATA_LEN);
+        assert_eq!(metadata.block(&mut sent), Bytes::from_static(&[0]));
+
+        // Every body sends the update once.
+        let mut other_sent = 0;
+        assert_eq!(metadata.block(&mut other_sent).len(), 1 + MAX_METADATA_LEN);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use bytes::Buf;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io;
+
+// The bytes of all the chunks emitted from body buffers which are still alive.
+static GLOBAL_IN_FLIGHT: AtomicU64 = AtomicU64::new(0);
+
+/// A gauge of the bytes emitted by bodies but not consumed yet, i.e. held in chunks queued by the server, so
+/// operators can alert on the memory tied up by slow clients before it becomes an OOM.
+///
+/// A body reports to it once attached via [StreamBody::with_in_flight](./struct.StreamBody.html#method.with_in_flight),
+/// one gauge per body gives a per-body view, a gauge shared by many bodies gives their total.
+/// [global_bytes](#method.global_bytes) tracks all the bodies of the process without attaching them. The handle is
+/// cheap to clone and all the clones share the same gauge.
+///
+/// # Examples
+///
+/// ```no_run
+/// use stream_body::{InFlight, StreamBody};
+///
+/// let in_flight = InFlight::new();
+///
+/// let body = StreamBody::from("hello").with_in_flight(&in_flight);
+///
+/// println!("{} bytes in flight, {} in total", in_flight.bytes(), InFlight::global_bytes());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct InFlight {
+    shared: Arc<AtomicU64>,
+}
+
+impl InFlight {
+    /// Creates a gauge at zero.
+    pub fn new() -> InFlight {
+        InFlight::default()
+    }
+
+    /// The number of bytes in the chunks of the attached bodies which weren't dropped yet.
+    pub fn bytes(&self) -> u64 {
+        self.shared.load(Ordering::Relaxed)
+    }
+
+    /// The number of bytes in the chunks of all the bodies of the process which weren't dropped yet.
+    ///
+    /// It counts the chunks emitted from the buffer of a body, i.e. by bodies created from bytes, via a channel or
+    /// from a reader, and the compressed chunks, but not the chunks produced by the other adapters.
+    pub fn global_bytes() -> u64 {
+        GLOBAL_IN_FLIGHT.load(Ordering::Relaxed)
+    }
+}
+
+pub(crate) fn add_global(len: usize) {
+    GLOBAL_IN_FLIGHT.fetch_add(len as u64, Ordering::Relaxed);
+}
+
+pub(crate) fn sub_global(len: usize) {
+    GLOBAL_IN_FLIGHT.fetch_sub(len as u64, Ordering::Relaxed);
+}
+
+impl StreamBody {
+    /// Attaches the body to an [InFlight](./struct.InFlight.html) gauge, which counts the bytes of its emitted chunks
+    /// until they are dropped.
+    pub fn with_in_flight(self, in_flight: &InFlight) -> StreamBody {
+        let shared = Arc::clone(&in_flight.shared);
+        self.wrap_with(|inner| WithInFlight { inner, shared })
+    }
+}
+
+struct WithInFlight {
+    inner: StreamBody,
+    shared: Arc<AtomicU64>,
+}
+
+impl Body for WithInFlight {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        match Pin::new(&mut self.inner).poll_data(cx) {
+            Poll::Ready(Some(Ok(mut data))) => {
+                let len = data.remaining() as u64;
+                self.shared.fetch_add(len, Ordering::Relaxed);
+                data.track_in_flight(Arc::clone(&self.shared), len);
+                Poll::Ready(Some(Ok(data)))
+            }
+            poll_status => poll_status,
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stacked_gauges_all_count_the_chunks_until_they_are_dropped() {
+        let per_route = InFlight::new();
+        let per_tenant = InFlight::new();
+        let body = StreamBody::concat(vec![StreamBody::from("hello "), StreamBody::from("world")]);
+        let mut body = body.with_in_flight(&per_route).with_in_flight(&per_tenant);
+
+        let first = body.data().await.unwrap().unwrap();
+        assert_eq!(per_route.bytes(), 6);
+        assert_eq!(per_tenant.bytes(), 6);
+
+        drop(first);
+        assert_eq!(per_route.bytes(), 0);
+        assert_eq!(per_tenant.bytes(), 0);
+
+        let second = body.data().await.unwrap().unwrap();
+        assert_eq!(per_route.bytes(), 5);
+        assert_eq!(per_tenant.bytes(), 5);
+
+        drop(second);
+        assert_eq!(per_route.bytes(), 0);
+        assert_eq!(per_tenant.bytes(), 0);
+    }
+}
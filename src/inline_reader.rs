@@ -0,0 +1,45 @@
+use crate::body::StreamBody;
+use std::marker::Unpin;
+use tokio::io::AsyncRead;
+
+impl StreamBody {
+    /// Creates a body which reads the provided [AsyncRead](https://docs.rs/tokio/0.2.16/tokio/io/trait.AsyncRead.html)
+    /// on the task polling the body, instead of spawning a task copying it into a channel like
+    /// [from_reader](#method.from_reader) does.
+    ///
+    /// It suits request bodies of a `hyper::Client`, where the connection task polls the body anyway, e.g. to upload a
+    /// large file without one extra task per request.
+    pub fn from_reader_inline<R: AsyncRead + Unpin + Send + 'static>(r: R) -> StreamBody {
+        let mut body = StreamBody::new_source(Box::new(r), None);
+        body.source = "reader";
+        body
+    }
+
+    /// Same as [from_reader_inline](#method.from_reader_inline), but for readers whose total length is known upfront.
+    ///
+    /// The body has an exact [size_hint](#method.size_hint), so hyper sends a `Content-Length` header with the
+    /// request, and it reports its end as soon as `len` bytes are read. The body fails with an `UnexpectedEof` error
+    /// if the reader ends early, and it doesn't read past `len`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use hyper::{Client, Request};
+    /// use stream_body::StreamBody;
+    /// use tokio::fs::File;
+    ///
+    /// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    /// let f = File::open("large-file").await?;
+    /// let len = f.metadata().await?.len();
+    ///
+    /// let req = Request::post("http://127.0.0.1:3000/upload").body(StreamBody::from_reader_inline_with_len(f, len))?;
+    /// let res = Client::builder().build_http::<StreamBody>().request(req).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_reader_inline_with_len<R: AsyncRead + Unpin + Send + 'static>(r: R, len: u64) -> StreamBody {
+        let mut body = StreamBody::new_source(Box::new(r), Some(len));
+        body.source = "reader";
+        body
+    }
+}
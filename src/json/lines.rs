@@ -0,0 +1,301 @@
+use futures_core::Stream;
+use serde::de::DeserializeOwned;
+use std::marker::{PhantomData, Unpin};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{self, AsyncRead};
+
+const DEFAULT_MAX_LINE_LEN: usize = 1024 * 1024;
+const READ_SIZE: usize = 8 * 1024;
+
+/// A [Stream](https://docs.rs/futures-core/0.3/futures_core/stream/trait.Stream.html) which incrementally parses
+/// a [JSON Lines](https://jsonlines.org/) (NDJSON) upload read from an
+/// [AsyncRead](https://docs.rs/tokio/0.2.16/tokio/io/trait.AsyncRead.html) into deserialized values.
+///
+/// The reader is only polled when the stream is, so a slow consumer applies backpressure to the client, and at most
+/// one line of up to `max_line_len` bytes is buffered at a time. Empty lines are skipped and `\r\n` line endings are
+/// accepted.
+///
+/// A line which fails to deserialize yields an error of kind `InvalidData` and the stream continues with the next
+/// line. A read error or a line exceeding the maximum length yields an error and ends the stream.
+///
+/// # Examples
+///
+/// ```no_run
+/// use futures_util::stream::StreamExt;
+/// use stream_body::JsonLinesReader;
+/// # use tokio::io::AsyncRead;
+///
+/// # async fn run(upload: impl AsyncRead + Unpin) -> std::io::Result<()> {
+/// let mut records = JsonLinesReader::<_, serde_json::Value>::new(upload);
+///
+/// while let Some(record) = records.next().await {
+///     println!("{}", record?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct JsonLinesReader<R, T> {
+    inner: R,
+    buf: Vec<u8>,
+    // The start of the current line in the buffer, the consumed lines before it are discarded on the next read.
+    start: usize,
+    // The position up to which the current line is already searched for a newline.
+    scanned: usize,
+    max_line_len: usize,
+    reached_eof: bool,
+    terminated: bool,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<R: AsyncRead + Unpin, T: DeserializeOwned> JsonLinesReader<R, T> {
+    /// Creates a reader which accepts lines of up to 1 MiB.
+    pub fn new(inner: R) -> JsonLinesReader<R, T> {
+        JsonLinesReader::with_max_line_len(inner, DEFAULT_MAX_LINE_LEN)
+    }
+
+    /// Creates a reader which accepts lines of up to `max_line_len` bytes, excluding the line ending.
+    pub fn with_max_line_len(inner: R, max_line_len: usize) -> JsonLinesReader<R, T> {
+        JsonLinesReader {
+            inner,
+            buf: Vec::new(),
+            start: 0,
+            scanned: 0,
+            max_line_len,
+            reached_eof: false,
+            terminated: false,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the inner reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Consumes the reader and returns the inner reader. Data which is read but not parsed yet is lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    // Returns the next complete line, if any is buffered.
+    fn next_line(&mut self) -> Option<(usize, usize)> {
+        let newline = self.buf[self.scanned..].iter().position(|&b| b == b'\n');
+
+        match newline {
+            Some(idx) => {
+                let end = self.scanned + idx;
+                let line = (self.start, end);
+                self.start = end + 1;
+                self.scanned = self.start;
+                Some(line)
+            }
+            None => {
+                self.scanned = self.buf.len();
+                None
+            }
+        }
+    }
+
+    fn parse(&self, (start, end): (usize, usize)) -> Option<io::Result<T>> {
+        let mut line = &self.buf[start..end];
+        if line.last() == Some(&b'\r') {
+            line = &line[..line.len() - 1];
+        }
+
+        if line.iter().all(|b| b.is_ascii_whitespace()) {
+            return None;
+        }
+
+        Some(serde_json::from_slice(line).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: JsonLinesReader: Failed to deserialize a line: {}",
+                    env!("CARGO_PKG_NAME"),
+                    err
+                ),
+            )
+        }))
+    }
+
+    fn line_too_long(&mut self) -> io::Error {
+        self.terminated = true;
+
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{}: JsonLinesReader: A line exceeds the maximum length of {} bytes",
+                env!("CARGO_PKG_NAME"),
+                self.max_line_len
+            ),
+        )
+    }
+
+    fn poll_fill_buf(&mut self, cx: &mut Context) -> Poll<io::Result<usize>> {
+        // Discards the consumed lines before reading more, so the buffer stays bounded by the line length.
+        if self.start > 0 {
+            self.buf.drain(..self.start);
+            self.scanned -= self.start;
+            self.start = 0;
+        }
+
+        let len = self.buf.len();
+        self.buf.resize(len + READ_SIZE, 0);
+
+        let poll_status = Pin::new(&mut self.inner).poll_read(cx, &mut self.buf[len..]);
+        let read_count = match poll_status {
+            Poll::Ready(Ok(read_count)) => read_count,
+            _ => 0,
+        };
+        self.buf.truncate(len + read_count);
+
+        poll_status
+    }
+}
+
+impl<R: AsyncRead + Unpin, T: DeserializeOwned> Stream for JsonLinesReader<R, T> {
+    type Item = io::Result<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            if self.terminated {
+                return Poll::Ready(None);
+            }
+
+            while let Some((start, end)) = self.next_line() {
+                let len = if end > start && self.buf[end - 1] == b'\r' {
+                    end - start - 1
+                } else {
+                    end - start
+                };
+                if len > self.max_line_len {
+                    return Poll::Ready(Some(Err(self.line_too_long())));
+                }
+
+                if let Some(item) = self.parse((start, end)) {
+                    return Poll::Ready(Some(item));
+                }
+            }
+
+            // The pending line may still end with a `\r`.
+            if self.buf.len() - self.start > self.max_line_len + 1 {
+                return Poll::Ready(Some(Err(self.line_too_long())));
+            }
+
+            if self.reached_eof {
+                // The last line doesn't need a trailing newline.
+                self.terminated = true;
+                let line = (self.start, self.buf.len());
+                return Poll::Ready(self.parse(line));
+            }
+
+            match self.poll_fill_buf(cx) {
+                Poll::Ready(Ok(0)) => self.reached_eof = true,
+                Poll::Ready(Ok(_)) => {}
+                Poll::Ready(Err(err)) => {
+                    self.terminated = true;
+                    return Poll::Ready(Some(Err(err)));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream::StreamExt;
+    use serde_json::{json, Value};
+
+    // A reader returning the data in the given chunks.
+    struct Chunks(Vec<Vec<u8>>);
+
+    impl AsyncRead for Chunks {
+        fn poll_read(mut self: Pin<&mut Self>, _cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+            if self.0.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+            let len = self.0[0].len().min(buf.len());
+            buf[..len].copy_from_slice(&self.0[0][..len]);
+            self.0[0].drain(..len);
+            if self.0[0].is_empty() {
+                self.0.remove(0);
+            }
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    fn chunks(data: &[u8], splits: &[usize]) -> Chunks {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        for &end in splits.iter().chain(std::iter::once(&data.len())) {
+            if end > start {
+                chunks.push(data[start..end].to_vec());
+                start = end;
+            }
+        }
+        Chunks(chunks)
+    }
+
+    async fn parse_all(reader: JsonLinesReader<Chunks, Value>) -> Vec<Result<Value, io::ErrorKind>> {
+        reader.map(|item| item.map_err(|err| err.kind())).collect().await
+    }
+
+    #[tokio::test]
+    async fn lines_round_trip_across_chunk_boundaries() {
+        let values = vec![
+            json!({"id": 1, "name": "a\nb"}),
+            json!([1, 2, 3]),
+            json!("text"),
+            json!(null),
+        ];
+        let mut data = Vec::new();
+        for (idx, value) in values.iter().enumerate() {
+            data.extend_from_slice(&serde_json::to_vec(value).unwrap());
+            // Mixed line endings and empty lines, the last line has no newline.
+            match idx % 3 {
+                0 => data.extend_from_slice(b"\r\n"),
+                1 => data.extend_from_slice(b"\n\n  \n"),
+                _ if idx + 1 < values.len() => data.push(b'\n'),
+                _ => {}
+            }
+        }
+        let expected = values.into_iter().map(Ok).collect::<Vec<_>>();
+
+        for i in 0..=data.len() {
+            for j in i..=data.len() {
+                let reader = JsonLinesReader::new(chunks(&data, &[i, j]));
+                assert_eq!(parse_all(reader).await, expected, "split at {} and {}", i, j);
+            }
+        }
+
+        let one_byte_splits = (1..data.len()).collect::<Vec<_>>();
+        let reader = JsonLinesReader::new(chunks(&data, &one_byte_splits));
+        assert_eq!(parse_all(reader).await, expected);
+    }
+
+    #[tokio::test]
+    async fn invalid_lines_are_reported_and_skipped() {
+        let reader = JsonLinesReader::new(chunks(b"1\n{oops\n2", &[3]));
+        assert_eq!(
+            parse_all(reader).await,
+            vec![Ok(json!(1)), Err(io::ErrorKind::InvalidData), Ok(json!(2))]
+        );
+    }
+
+    #[tokio::test]
+    async fn long_lines_end_the_stream() {
+        // The limit excludes the line ending.
+        let reader = JsonLinesReader::with_max_line_len(chunks(b"\"abc\"\r\n\"abcd\"\n1\n", &[2, 7]), 5);
+        assert_eq!(
+            parse_all(reader).await,
+            vec![Ok(json!("abc")), Err(io::ErrorKind::InvalidData)]
+        );
+
+        // A pending line is rejected before its end is read.
+        let reader = JsonLinesReader::with_max_line_len(chunks(&[b' '; 64], &[8, 16]), 5);
+        assert_eq!(parse_all(reader).await, vec![Err(io::ErrorKind::InvalidData)]);
+    }
+}
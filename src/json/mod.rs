@@ -0,0 +1,7 @@
+pub use self::lines::JsonLinesReader;
+pub use self::query::{stream_query_results, QueryLimits, RowErrorPolicy, RowFormat};
+pub use self::writer::{JsonFormat, JsonWriter};
+
+mod lines;
+mod query;
+mod writer;
@@ -0,0 +1,540 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use crate::forward::into_io_error;
+use bytes::Bytes;
+use futures_core::Stream;
+use http::{HeaderMap, HeaderValue, Response};
+use http_body::{Body, SizeHint};
+use serde::Serialize;
+use serde_json::Value;
+use std::error::Error;
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io;
+
+// The serialized rows are coalesced into chunks of about this size while the stream has rows ready.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// The serialization of the rows streamed by [stream_query_results](./fn.stream_query_results.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowFormat {
+    /// A single JSON array containing every row.
+    Array,
+    /// [JSON Lines](https://jsonlines.org/) (NDJSON): every row is followed by a newline.
+    Lines,
+    /// A JSON text sequence as described in [RFC 7464](https://tools.ietf.org/html/rfc7464).
+    Seq,
+    /// CSV as described in [RFC 4180](https://tools.ietf.org/html/rfc4180). Rows serializing to JSON objects are
+    /// written under a header line made of the keys of the first row, in the order of `serde_json::Map`, i.e. sorted
+    /// unless its `preserve_order` feature is enabled. Rows serializing to JSON arrays are written as is, without a
+    /// header.
+    Csv,
+}
+
+impl RowFormat {
+    /// Returns the `Content-Type` header value of the format.
+    pub fn content_type(&self) -> HeaderValue {
+        match self {
+            RowFormat::Array => HeaderValue::from_static("application/json"),
+            RowFormat::Lines => HeaderValue::from_static("application/x-ndjson"),
+            RowFormat::Seq => HeaderValue::from_static("application/json-seq"),
+            RowFormat::Csv => HeaderValue::from_static("text/csv; charset=utf-8"),
+        }
+    }
+}
+
+/// What [stream_query_results](./fn.stream_query_results.html) does when the rows fail mid-stream or exceed the
+/// limits, once the response headers are long gone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowErrorPolicy {
+    /// The rows emitted so far are sent, then the body fails, so the client sees a broken response rather than a
+    /// truncated one which looks complete. It's the default.
+    Abort,
+    /// A failing row is skipped, and exceeding a limit ends the result cleanly, e.g. a JSON array is closed.
+    Skip,
+    /// An error record `{"error":"<message>"}` is emitted as the last row and the result ends cleanly, so the client
+    /// can tell a failed result from a complete one. CSV has no place for it, so it aborts instead.
+    Report,
+}
+
+/// The limits and the error policy of [stream_query_results](./fn.stream_query_results.html).
+///
+/// By default the number of rows and bytes are unlimited and the body is aborted on error.
+#[derive(Debug, Clone)]
+pub struct QueryLimits {
+    max_rows: Option<u64>,
+    max_bytes: Option<u64>,
+    on_error: RowErrorPolicy,
+}
+
+impl Default for QueryLimits {
+    fn default() -> QueryLimits {
+        QueryLimits {
+            max_rows: None,
+            max_bytes: None,
+            on_error: RowErrorPolicy::Abort,
+        }
+    }
+}
+
+impl QueryLimits {
+    /// Creates the default limits.
+    pub fn new() -> QueryLimits {
+        QueryLimits::default()
+    }
+
+    /// Stops the result after `max_rows` rows, the row exceeding it is handled according to the error policy.
+    pub fn max_rows(mut self, max_rows: u64) -> QueryLimits {
+        self.max_rows = Some(max_rows);
+        self
+    }
+
+    /// Stops the result before the row which would make the body exceed `max_bytes`, framing included.
+    pub fn max_bytes(mut self, max_bytes: u64) -> QueryLimits {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Sets what happens on a row error or an exceeded limit.
+    pub fn on_error(mut self, policy: RowErrorPolicy) -> QueryLimits {
+        self.on_error = policy;
+        self
+    }
+}
+
+/// Creates a `200 OK` response streaming the rows of a database query, or any other stream of serializable
+/// records, with the given format and limits. It requires the `json` feature.
+///
+/// The stream is polled directly by the body, so the rows are only fetched as fast as the client reads them, and
+/// dropped once the result ends, e.g. when a limit is reached. The rows ready at once are coalesced into chunks of
+/// about 16 KiB. The `Content-Type` header is set according to the format.
+///
+/// A row failing to serialize counts as a row error, which is handled according to
+/// [QueryLimits::on_error](./struct.QueryLimits.html#method.on_error) like the errors of the stream.
+///
+/// # Examples
+///
+/// ```no_run
+/// use futures_util::stream;
+/// use stream_body::{stream_query_results, QueryLimits, RowErrorPolicy, RowFormat};
+///
+/// let rows = stream::iter(vec![
+///     Ok::<_, std::io::Error>(serde_json::json!({ "id": 1, "name": "alice" })),
+///     Ok(serde_json::json!({ "id": 2, "name": "bob" })),
+/// ]);
+///
+/// let limits = QueryLimits::new().max_rows(10_000).on_error(RowErrorPolicy::Report);
+/// let res = stream_query_results(rows, RowFormat::Lines, &limits);
+/// ```
+pub fn stream_query_results<S, T, E>(rows: S, format: RowFormat, limits: &QueryLimits) -> Response<StreamBody>
+where
+    S: Stream<Item = Result<T, E>> + Send + 'static,
+    T: Serialize,
+    E: Into<Box<dyn Error + Send + Sync>>,
+{
+    let mut buf = Vec::new();
+    if format == RowFormat::Array {
+        buf.push(b'[');
+    }
+
+    let mut body = StreamBody::wrap(QueryRows {
+        rows: Some(Box::pin(rows)),
+        format,
+        limits: limits.clone(),
+        buf,
+        columns: None,
+        rows_count: 0,
+        bytes_count: 0,
+        error: None,
+    });
+    body.source = "query";
+    body.into_response_with_content_type(format.content_type())
+}
+
+struct QueryRows<S> {
+    // The stream is dropped once the result ended.
+    rows: Option<Pin<Box<S>>>,
+    format: RowFormat,
+    limits: QueryLimits,
+    // The serialized rows not emitted yet.
+    buf: Vec<u8>,
+    // The CSV columns, taken from the first row.
+    columns: Option<Vec<String>>,
+    rows_count: u64,
+    // The bytes emitted so far, or buffered to be.
+    bytes_count: u64,
+    // The error ending the body once the buffered rows are emitted.
+    error: Option<io::Error>,
+}
+
+impl<S> QueryRows<S> {
+    /// Serializes a row at the end of the buffer, with its framing.
+    fn write_row(&mut self, value: &Value) {
+        let first = self.rows_count == 0;
+        match self.format {
+            RowFormat::Array => {
+                if !first {
+                    self.buf.push(b',');
+                }
+                write_json(&mut self.buf, value);
+            }
+            RowFormat::Lines => {
+                write_json(&mut self.buf, value);
+                self.buf.push(b'\n');
+            }
+            RowFormat::Seq => {
+                self.buf.push(0x1e);
+                write_json(&mut self.buf, value);
+                self.buf.push(b'\n');
+            }
+            RowFormat::Csv => {
+                if let Value::Object(ref object) = value {
+                    if first && self.columns.is_none() {
+                        let columns = object.keys().cloned().collect::<Vec<_>>();
+                        write_csv_record(&mut self.buf, columns.iter().cloned().map(Some));
+                        self.columns = Some(columns);
+                    }
+                }
+
+                match (value, &self.columns) {
+                    (Value::Object(object), Some(columns)) => {
+                        let fields = columns.iter().map(|column| object.get(column).map(csv_field));
+                        write_csv_record(&mut self.buf, fields);
+                    }
+                    (Value::Array(fields), _) => {
+                        write_csv_record(&mut self.buf, fields.iter().map(|field| Some(csv_field(field))))
+                    }
+                    (field, _) => write_csv_record(&mut self.buf, Some(Some(csv_field(field))).into_iter()),
+                }
+            }
+        }
+    }
+
+    /// Appends a row unless it exceeds the limits, in which case the result is stopped.
+    fn push_row(&mut self, value: Value) {
+        if let Some(max_rows) = self.limits.max_rows {
+            if self.rows_count >= max_rows {
+                return self.stop(limit_error(format!("The result exceeds {} rows", max_rows)));
+            }
+        }
+
+        let len = self.buf.len();
+        self.write_row(&value);
+
+        let bytes_count = self.bytes_count + (self.buf.len() - len) as u64;
+        if let Some(max_bytes) = self.limits.max_bytes {
+            // The closing bracket of an array has to fit too.
+            let closing_len = (self.format == RowFormat::Array) as u64;
+            if bytes_count + closing_len > max_bytes {
+                self.buf.truncate(len);
+                if self.rows_count == 0 {
+                    // The CSV header was written along with the row.
+                    self.columns = None;
+                }
+                return self.stop(limit_error(format!("The result exceeds {} bytes", max_bytes)));
+            }
+        }
+
+        self.bytes_count = bytes_count;
+        self.rows_count += 1;
+    }
+
+    /// Handles a row error according to the policy.
+    fn row_error(&mut self, err: io::Error) {
+        match self.limits.on_error {
+            RowErrorPolicy::Skip => {
+                log::warn!(
+                    "{}: stream_query_results: Skipped a row: {}",
+                    env!("CARGO_PKG_NAME"),
+                    err
+                );
+            }
+            _ => self.stop(err),
+        }
+    }
+
+    /// Ends the result because of an error or an exceeded limit.
+    fn stop(&mut self, err: io::Error) {
+        match self.limits.on_error {
+            RowErrorPolicy::Report if self.format != RowFormat::Csv => {
+                let record = serde_json::json!({ "error": err.to_string() });
+                // The record is written even past the byte limit, it's the only way to report it.
+                self.write_row(&record);
+                self.finish();
+            }
+            RowErrorPolicy::Skip => self.finish(),
+            _ => {
+                self.rows = None;
+                self.error = Some(err);
+            }
+        }
+    }
+
+    /// Ends the result cleanly.
+    fn finish(&mut self) {
+        if self.format == RowFormat::Array {
+            self.buf.push(b']');
+        }
+        self.rows = None;
+    }
+
+    fn take_chunk(&mut self) -> Option<StreamData> {
+        if self.buf.is_empty() {
+            return None;
+        }
+        Some(StreamData::from_bytes(Bytes::from(mem::take(&mut self.buf))))
+    }
+}
+
+fn write_json(buf: &mut Vec<u8>, value: &Value) {
+    serde_json::to_writer(buf, value).expect("a JSON value always serializes into a vector");
+}
+
+/// Returns the text of a CSV field, the strings without their JSON quotes and the nested values as JSON.
+fn csv_field(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Writes a CSV record, quoting the fields which need it.
+fn write_csv_record<I: Iterator<Item = Option<String>>>(buf: &mut Vec<u8>, fields: I) {
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            buf.push(b',');
+        }
+        let field = field.unwrap_or_default();
+        if field.contains(&[',', '"', '\r', '\n'][..]) {
+            buf.push(b'"');
+            buf.extend_from_slice(field.replace('"', "\"\"").as_bytes());
+            buf.push(b'"');
+        } else {
+            buf.extend_from_slice(field.as_bytes());
+        }
+    }
+    buf.extend_from_slice(b"\r\n");
+}
+
+fn limit_error(message: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("{}: stream_query_results: {}", env!("CARGO_PKG_NAME"), message),
+    )
+}
+
+impl<S, T, E> Body for QueryRows<S>
+where
+    S: Stream<Item = Result<T, E>>,
+    T: Serialize,
+    E: Into<Box<dyn Error + Send + Sync>>,
+{
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let me = &mut *self;
+
+        while me.buf.len() < CHUNK_SIZE {
+            let rows = match me.rows {
+                Some(ref mut rows) => rows,
+                None => break,
+            };
+
+            match rows.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(row))) => match serde_json::to_value(&row) {
+                    Ok(value) => me.push_row(value),
+                    Err(err) => me.row_error(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "{}: stream_query_results: Failed to serialize a row: {}",
+                            env!("CARGO_PKG_NAME"),
+                            err
+                        ),
+                    )),
+                },
+                Poll::Ready(Some(Err(err))) => me.row_error(into_io_error(err)),
+                Poll::Ready(None) => me.finish(),
+                Poll::Pending => {
+                    return match me.take_chunk() {
+                        Some(data) => Poll::Ready(Some(Ok(data))),
+                        None => Poll::Pending,
+                    };
+                }
+            }
+        }
+
+        match me.take_chunk() {
+            Some(data) => Poll::Ready(Some(Ok(data))),
+            None => Poll::Ready(me.error.take().map(Err)),
+        }
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Poll::Ready(Ok(None))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.rows.is_none() && self.buf.is_empty() && self.error.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        if self.is_end_stream() {
+            return SizeHint::with_exact(0);
+        }
+        SizeHint::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+    use serde_json::json;
+
+    // A stream of rows returning `Pending` before every row, so every row is emitted in its own chunk.
+    struct Interleaved {
+        rows: std::vec::IntoIter<Result<Value, io::Error>>,
+        ready: bool,
+    }
+
+    impl Stream for Interleaved {
+        type Item = Result<Value, io::Error>;
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            Poll::Ready(self.rows.next())
+        }
+    }
+
+    fn rows() -> Vec<Value> {
+        vec![
+            json!({"id": 1, "name": "alice, \"al\"", "note": null}),
+            json!({"id": 2, "name": "bob\nsmith", "note": [1, 2]}),
+            json!({"id": 3, "name": "carol", "note": "ok"}),
+        ]
+    }
+
+    async fn chunks<S>(rows: S, format: RowFormat, limits: &QueryLimits) -> (Vec<Bytes>, Option<io::Error>)
+    where
+        S: Stream<Item = Result<Value, io::Error>> + Send + 'static,
+    {
+        let mut body = stream_query_results(rows, format, limits).into_body();
+        let mut chunks = Vec::new();
+        while let Some(data) = body.data().await {
+            match data {
+                Ok(data) => chunks.push(data.copy_to_bytes()),
+                Err(err) => return (chunks, Some(err)),
+            }
+        }
+        assert!(body.is_end_stream());
+        (chunks, None)
+    }
+
+    async fn output(format: RowFormat, limits: &QueryLimits) -> Vec<u8> {
+        let ready = stream::iter(rows().into_iter().map(Ok));
+        let (ready_chunks, err) = chunks(ready, format, limits).await;
+        assert!(err.is_none());
+
+        // The chunk boundaries depend on the readiness of the rows, not the output.
+        let interleaved = Interleaved {
+            rows: rows().into_iter().map(Ok).collect::<Vec<_>>().into_iter(),
+            ready: false,
+        };
+        let (interleaved_chunks, err) = chunks(interleaved, format, limits).await;
+        assert!(err.is_none());
+        assert!(ready_chunks.is_empty() || interleaved_chunks.len() > ready_chunks.len());
+        assert_eq!(interleaved_chunks.concat(), ready_chunks.concat());
+
+        ready_chunks.concat()
+    }
+
+    #[tokio::test]
+    async fn json_rows_round_trip() {
+        let array = output(RowFormat::Array, &QueryLimits::new()).await;
+        assert_eq!(serde_json::from_slice::<Value>(&array).unwrap(), Value::Array(rows()));
+
+        let lines = output(RowFormat::Lines, &QueryLimits::new()).await;
+        let parsed = lines
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(parsed, rows());
+
+        let seq = output(RowFormat::Seq, &QueryLimits::new()).await;
+        let parsed = seq
+            .split(|&b| b == 0x1e)
+            .filter(|record| !record.is_empty())
+            .map(|record| {
+                assert_eq!(record.last(), Some(&b'\n'));
+                serde_json::from_slice::<Value>(record).unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(parsed, rows());
+    }
+
+    #[tokio::test]
+    async fn csv_rows_are_quoted() {
+        let csv = output(RowFormat::Csv, &QueryLimits::new()).await;
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "id,name,note\r\n\
+             1,\"alice, \"\"al\"\"\",\r\n\
+             2,\"bob\nsmith\",\"[1,2]\"\r\n\
+             3,carol,ok\r\n"
+        );
+
+        let rows = stream::iter(vec![Ok(json!([1, "a,b"])), Ok(json!("single"))]);
+        let (chunks, _) = chunks(rows, RowFormat::Csv, &QueryLimits::new()).await;
+        assert_eq!(chunks.concat(), b"1,\"a,b\"\r\nsingle\r\n".to_vec());
+    }
+
+    #[tokio::test]
+    async fn large_results_are_split_into_bounded_chunks() {
+        let row = json!({ "data": "x".repeat(1000) });
+        let rows = stream::iter((0..100).map(move |_| Ok(row.clone())));
+        let (chunks, _) = chunks(rows, RowFormat::Lines, &QueryLimits::new()).await;
+
+        assert!(chunks.len() > 1);
+        for chunk in chunks.iter() {
+            assert!(chunk.len() < CHUNK_SIZE + 1100);
+        }
+        assert_eq!(
+            chunks.concat().split(|&b| b == b'\n').filter(|l| !l.is_empty()).count(),
+            100
+        );
+    }
+
+    #[tokio::test]
+    async fn byte_limits_include_the_framing() {
+        let array = output(RowFormat::Array, &QueryLimits::new()).await;
+        let first_row_len = serde_json::to_vec(&rows()[0]).unwrap().len() as u64;
+
+        // Room for the brackets and the first row only.
+        let limits = QueryLimits::new()
+            .max_bytes(first_row_len + 2)
+            .on_error(RowErrorPolicy::Skip);
+        let limited = output(RowFormat::Array, &limits).await;
+        assert_eq!(serde_json::from_slice::<Value>(&limited).unwrap(), json!([rows()[0]]));
+        assert!(limited.len() < array.len());
+
+        // The header of the CSV is dropped along with a first row exceeding the limit.
+        let limits = QueryLimits::new().max_bytes(5).on_error(RowErrorPolicy::Skip);
+        assert!(output(RowFormat::Csv, &limits).await.is_empty());
+
+        let limits = QueryLimits::new().max_bytes(5);
+        let (chunks, err) = chunks(stream::iter(rows().into_iter().map(Ok)), RowFormat::Csv, &limits).await;
+        assert!(chunks.is_empty());
+        assert!(err.unwrap().to_string().contains("exceeds 5 bytes"));
+    }
+}
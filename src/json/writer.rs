@@ -0,0 +1,116 @@
+use serde::Serialize;
+use std::marker::Unpin;
+use tokio::io::{self, AsyncWrite, AsyncWriteExt};
+
+// The record separator starting every JSON text of a JSON text sequence.
+const RECORD_SEPARATOR: u8 = 0x1e;
+
+/// The framing of the values written by a [JsonWriter](./struct.JsonWriter.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonFormat {
+    /// [JSON Lines](https://jsonlines.org/) (NDJSON): every value is followed by a newline.
+    Lines,
+    /// A JSON text sequence as described in [RFC 7464](https://tools.ietf.org/html/rfc7464): every value is preceded
+    /// by an ASCII record separator and followed by a newline.
+    Seq,
+}
+
+impl JsonFormat {
+    /// Returns the `Content-Type` header value of the format, i.e. `application/x-ndjson` or `application/json-seq`.
+    pub fn content_type(&self) -> http::HeaderValue {
+        match self {
+            JsonFormat::Lines => http::HeaderValue::from_static("application/x-ndjson"),
+            JsonFormat::Seq => http::HeaderValue::from_static("application/json-seq"),
+        }
+    }
+}
+
+/// Writes serialized values to a writer, e.g. the writer half of
+/// [StreamBody::channel](./struct.StreamBody.html#method.channel), as JSON Lines or as a JSON text sequence.
+///
+/// Every value is serialized into a reused buffer and written at once together with its framing, so it reaches the
+/// body as a whole.
+///
+/// # Examples
+///
+/// ```no_run
+/// use hyper::Response;
+/// use stream_body::{JsonFormat, JsonWriter, StreamBody};
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let (writer, body) = StreamBody::channel();
+/// let res = Response::builder()
+///     .header("content-type", JsonFormat::Seq.content_type())
+///     .body(body)
+///     .unwrap();
+///
+/// let mut records = JsonWriter::new(writer, JsonFormat::Seq);
+/// records.write(&serde_json::json!({ "id": 1 })).await?;
+/// records.write(&serde_json::json!({ "id": 2 })).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct JsonWriter<W> {
+    inner: W,
+    format: JsonFormat,
+    buf: Vec<u8>,
+}
+
+impl<W: AsyncWrite + Unpin> JsonWriter<W> {
+    /// Creates a writer using the given framing.
+    pub fn new(inner: W, format: JsonFormat) -> JsonWriter<W> {
+        JsonWriter {
+            inner,
+            format,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Serializes a value and writes it with its framing. A value failing to serialize yields an error of kind
+    /// `InvalidInput` and nothing is written.
+    pub async fn write<T: Serialize + ?Sized>(&mut self, value: &T) -> io::Result<()> {
+        self.buf.clear();
+        if self.format == JsonFormat::Seq {
+            self.buf.push(RECORD_SEPARATOR);
+        }
+
+        serde_json::to_writer(&mut self.buf, value).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{}: JsonWriter: Failed to serialize a value: {}",
+                    env!("CARGO_PKG_NAME"),
+                    err
+                ),
+            )
+        })?;
+        self.buf.push(b'\n');
+
+        self.inner.write_all(&self.buf).await
+    }
+
+    /// Returns the framing of the writer.
+    pub fn format(&self) -> JsonFormat {
+        self.format
+    }
+
+    /// Flushes the underlying writer.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().await
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying writer. Writing to it directly may break the framing.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Unwraps the writer, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
@@ -0,0 +1,71 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io;
+
+impl StreamBody {
+    /// Calls `callback` with the first-byte latency of the body once hyper takes its first chunk, i.e. the time from
+    /// the creation of the body to the moment its first chunk is consumed.
+    ///
+    /// It's the body's contribution to the time to first byte of the response, which helps finding slow producers.
+    /// The callback isn't called for bodies which end or fail before emitting any data.
+    pub fn on_first_byte<F>(self, callback: F) -> StreamBody
+    where
+        F: FnOnce(Duration) + Send + 'static,
+    {
+        self.wrap_with(|inner| {
+            let created_at = inner.created_at;
+            FirstByte {
+                inner,
+                created_at,
+                callback: Some(Box::new(callback)),
+            }
+        })
+    }
+}
+
+struct FirstByte {
+    inner: StreamBody,
+    created_at: Instant,
+    callback: Option<Box<dyn FnOnce(Duration) + Send>>,
+}
+
+impl Body for FirstByte {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let poll_status = Pin::new(&mut self.inner).poll_data(cx);
+
+        match poll_status {
+            Poll::Ready(Some(Ok(_))) => {
+                if let Some(callback) = self.callback.take() {
+                    callback(self.created_at.elapsed());
+                }
+            }
+            Poll::Ready(_) => self.callback = None,
+            Poll::Pending => {}
+        }
+
+        poll_status
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
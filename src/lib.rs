@@ -17,7 +17,7 @@
 //!
 //! # Examples
 //!
-//! ```
+//! ```no_run
 //! use hyper::service::{make_service_fn, service_fn};
 //! use hyper::{Body, Request, Response, Server};
 //! use std::{convert::Infallible, net::SocketAddr};
@@ -58,10 +58,238 @@
 //!     }
 //! }
 //! ```
+//!
+//! # Request Bodies
+//!
+//! `StreamBody` can also be the body of an outgoing request of a `hyper::Client`, e.g. to upload a large file to
+//! another service. Bodies with a known length, like the ones created via
+//! [channel_with_len](./struct.StreamBody.html#method.channel_with_len) or
+//! [from_reader_inline_with_len](./struct.StreamBody.html#method.from_reader_inline_with_len), have an exact size hint,
+//! so hyper sends a `Content-Length` header instead of using chunked encoding. A channel body ends once its writer half
+//! is shut down or dropped, and [from_reader_inline](./struct.StreamBody.html#method.from_reader_inline) reads its
+//! source on the connection task, so no extra task is spawned per request.
+//!
+//! # Runtimes
+//!
+//! The core `StreamBody` only relies on the I/O traits of tokio and doesn't need a runtime. The helpers spawning tasks,
+//! like [from_reader](./struct.StreamBody.html#method.from_reader) and
+//! [from_producer](./struct.StreamBody.html#method.from_producer), need the tokio runtime and are gated behind the
+//! `tokio-rt` feature, enabled by default. With `default-features = false`, e.g. on async-std or smol, the
+//! bodies reading their source on the polling task are still available:
+//! [from_reader_inline](./struct.StreamBody.html#method.from_reader_inline), and
+//! [from_futures_reader](./struct.StreamBody.html#method.from_futures_reader) for a `futures::io::AsyncRead` with the
+//! `futures` feature.
+//!
+//! With `default-features = false` the crate links tokio with its `io-util` feature only, i.e. for the
+//! `AsyncRead`/`AsyncWrite` traits of the channel writer and the readers and their extensions, with no runtime and no
+//! timer. Everything else is additive:
+//!
+//! - `tokio-rt`, or its alias `spawn`: the helpers spawning tasks, with `tokio/rt-core` and `tokio/blocking`.
+//! - `fs`, `cache`, `segments`, `shared-reads`, `upload`: the file bodies and uploads, which also enable `tokio-rt`.
+//! - `gzip`, `brotli`, or `compression` for both: the encoders.
+//! - `timeout`, `pacing`, `scheduler`, `sse`, `test-util`: the features needing the tokio timer, and only it.
+//! - `futures`, `json`, `digest`, `multipart`, `zstd-seekable`, `http-body-04`, `http-body-1`, `axum`, etc.: no tokio
+//!   feature at all.
+//!
+//! # Safe Mode
+//!
+//! The emitted chunks are reference-counted slices of the body's internal buffer, so they own their memory without
+//! copying it and stay valid even if they are kept past the lifetime of the body. The buffer is reclaimed for the next
+//! chunks once they are dropped. Enabling the `safe` feature compiles the crate with `#![forbid(unsafe_code)]`, which
+//! is kept for the users who want the compiler to enforce it, as it has no cost anymore.
+//!
+//! # Kernel-Side Transfers
+//!
+//! `sendfile`, `splice` and io_uring fixed buffers can't back a `StreamBody`: the body hands its chunks to hyper,
+//! which owns the socket and writes them itself, so the data has to be in userspace memory, and tokio-uring requires a
+//! newer tokio than the 0.2 this crate is built on. File bodies read into reused buffers instead, so the cost is one
+//! copy per byte without an allocation per chunk. [SharedFile](./struct.SharedFile.html), with the `segments` feature,
+//! reads with positional reads on blocking threads in 64 KiB blocks, which suits large files served on many requests.
+//!
+//! # HTTP Stack Generations
+//!
+//! `StreamBody` implements the `http-body` 0.3 trait used by hyper 0.13. The `http-body-04` and `http-body-1` features
+//! additionally implement the `http-body` 0.4 trait (hyper 0.14) and the frame-based `http-body` 1.x trait (hyper 1.x)
+//! together with the matching `bytes` 1.x `Buf` for `StreamData`. The features are additive, so a library depending on
+//! this crate doesn't force its users onto one generation of the HTTP stack. With `http-body-1`,
+//! [StreamBody::wrap_body](./struct.StreamBody.html#method.wrap_body) wraps an `http-body` 1.x body and forwards all
+//! its frames, including the kinds this crate doesn't know about.
+//!
+//! With the `axum` feature, `StreamBody` implements the `IntoResponse` trait of axum 0.7, setting its advisory
+//! [headers](./struct.StreamBody.html#method.headers) on the response, and converts into `axum::body::Body` via `From`.
+//! The chunks are handed to axum as `bytes` 1.x `Bytes` owning them, so they're neither copied nor the buffer reused
+//! before axum is done with them, and the `io::Error`s of the body convert into a `BoxError` as is. Other stacks built
+//! on hyper 1.x use the `http-body-1` implementation directly.
+
+#![cfg_attr(feature = "safe", forbid(unsafe_code))]
 
+pub use self::abort::AbortHandle;
 pub use self::body::StreamBody;
+#[cfg(feature = "futures")]
+pub use self::body_reader::BodyLines;
+pub use self::body_reader::BodyReader;
+pub use self::buffered::BufferedWriter;
+pub use self::byteranges::MultipartByteRanges;
+#[cfg(feature = "tokio-rt")]
+pub use self::checkpoint::{Checkpoint, CheckpointStore, CheckpointWriter, FileCheckpointStore};
+pub use self::chunk_writer::ChunkWriter;
+pub use self::closed::{ChannelWriter, Closed};
+#[cfg(feature = "gzip")]
+pub use self::compression::{CompressionFeedback, CompressionPolicy, Encoding, GzipWriter, SizeTransform};
 pub use self::data::StreamData;
+#[cfg(feature = "digest")]
+pub use self::digest::{DigestMismatch, DigestReader, ExpectedDigest};
+#[cfg(feature = "driver")]
+pub use self::driver::Driver;
+pub use self::error_trailers::ErrorTrailers;
+pub use self::escape::Escaping;
+pub use self::eviction::IdleEviction;
+pub use self::flow::FlowCapacity;
+pub use self::frame_writer::{FrameWriter, LengthPrefix};
+#[cfg(feature = "futures")]
+pub use self::futures::IntoAsyncRead;
+pub use self::histogram::ChunkHistogram;
+pub use self::icy::IcyMetadata;
+pub use self::in_flight::InFlight;
+#[cfg(feature = "json")]
+pub use self::json::{
+    stream_query_results, JsonFormat, JsonLinesReader, JsonWriter, QueryLimits, RowErrorPolicy, RowFormat,
+};
+#[cfg(feature = "local")]
+pub use self::local::{LocalChunkWriter, LocalStreamBody, LocalStreamData};
+#[cfg(feature = "metrics")]
+pub use self::metrics::Metrics;
+#[cfg(feature = "multipart")]
+pub use self::multipart::{Multipart, MultipartPart};
+pub use self::pool::BodyPool;
+pub use self::priority::Priority;
+pub use self::profile::{Phase, Profile};
+pub use self::progress::{Progress, TransferOutcome, TransferSummary};
+pub use self::quota::{ClientQuotas, Quota};
+pub use self::range::{ByteRange, RangeDecision};
+#[cfg(feature = "registry")]
+pub use self::registry::{BodySnapshot, BodyState, Registry};
+pub use self::resume::ResumeToken;
+pub use self::retry::RetryBuffer;
+#[cfg(feature = "scheduler")]
+pub use self::scheduler::Scheduler;
+pub use self::seekable::RestartHandle;
+#[cfg(feature = "segments")]
+pub use self::segments::SharedFile;
+pub use self::shaping::StreamBodyBuilder;
+#[cfg(feature = "shared-reads")]
+pub use self::shared_reads::SharedReads;
+pub use self::source::{Fill, Source};
+#[cfg(feature = "sse")]
+pub use self::sse::{Event, EventStore, EventWriter, MemoryEventStore, Replay};
+#[cfg(feature = "timeout")]
+pub use self::timeout::TimeoutAction;
+pub use self::trailers::TrailerSender;
+#[cfg(feature = "futures")]
+pub use self::try_stream::{StreamSource, TryStreamBody};
+pub use self::tuning::AutoTuning;
+#[cfg(feature = "upload")]
+pub use self::upload::{save_body_to_file, FsyncPolicy, SavedFile, UploadLimits};
+pub use self::wake::WakeStrategy;
+pub use self::watchdog::AbandonAction;
 
+mod abort;
+mod adaptive;
 mod body;
+mod body_reader;
+mod buffer;
+mod buffered;
+mod byteranges;
+#[cfg(feature = "cache")]
+mod cache;
+mod chain;
+#[cfg(feature = "tokio-rt")]
+mod checkpoint;
+mod chunk_writer;
+mod closed;
+mod combinators;
+#[cfg(any(feature = "http-body-04", feature = "http-body-1"))]
+mod compat;
+#[cfg(feature = "gzip")]
+mod compression;
 mod data;
+#[cfg(feature = "digest")]
+mod digest;
+#[cfg(feature = "driver")]
+mod driver;
+#[cfg(feature = "tokio-rt")]
+mod duplex;
+mod error_trailers;
+mod escape;
+mod eviction;
+mod fallback;
+#[cfg(feature = "fs")]
+mod file;
+mod flow;
+mod forward;
+mod frame_writer;
+mod frames;
+#[cfg(feature = "futures")]
+mod futures;
+mod histogram;
+mod icy;
+mod in_flight;
+mod inline_reader;
+#[cfg(feature = "json")]
+mod json;
+mod latency;
+#[cfg(feature = "local")]
+mod local;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "multipart")]
+mod multipart;
+#[cfg(feature = "pacing")]
+mod pacing;
+mod pool;
+#[cfg(feature = "tokio-rt")]
+mod prewarm;
+mod priority;
+#[cfg(feature = "tokio-rt")]
+mod producer;
+mod profile;
+mod progress;
+mod quota;
+mod range;
+#[cfg(feature = "registry")]
+mod registry;
+mod response;
+mod resume;
+mod retry;
+mod runs;
+#[cfg(feature = "scheduler")]
+mod scheduler;
+mod seekable;
+#[cfg(feature = "segments")]
+mod segments;
+mod server_timing;
+mod shaping;
+#[cfg(feature = "shared-reads")]
+mod shared_reads;
+mod source;
+#[cfg(feature = "sse")]
+mod sse;
 mod state;
+mod stats;
+mod tee;
+#[cfg(feature = "test-util")]
+pub mod test;
+mod throughput;
+#[cfg(feature = "timeout")]
+mod timeout;
+mod trailers;
+#[cfg(feature = "futures")]
+mod try_stream;
+mod tuning;
+mod upgrade;
+#[cfg(feature = "upload")]
+mod upload;
+mod wake;
+mod watchdog;
+#[cfg(feature = "zstd-seekable")]
+mod zstd_seekable;
@@ -0,0 +1,417 @@
+use crate::buffer::ReusableBuf;
+use crate::data::StreamData;
+use crate::source::{Fill, Source};
+use bytes::{Buf, Bytes};
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::cell::{Cell, RefCell};
+use std::future::poll_fn;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+use tokio::io::{self, AsyncRead};
+
+const DEFAULT_BUF_SIZE: usize = 8 * 1024;
+
+/// A `!Send` variant of `StreamBody` for thread-per-core runtimes like [monoio](https://docs.rs/monoio) or
+/// [glommio](https://docs.rs/glommio).
+///
+/// It never spawns a task and uses no cross-thread synchronization: the source is polled directly from
+/// `poll_data` into a reused buffer, and the consumed state of the emitted chunk is tracked with an `Rc`.
+///
+/// It's fed by any [Source](./trait.Source.html), which doesn't depend on a runtime, by a futures `AsyncRead` with
+/// the `futures` feature, e.g. the files and sockets of glommio, or by a
+/// [LocalChunkWriter](./struct.LocalChunkWriter.html) for the runtimes with completion-based IO like monoio, whose
+/// owned buffers are handed over as chunks without copying.
+pub struct LocalStreamBody {
+    inner: LocalInner,
+}
+
+enum LocalInner {
+    Once(Option<Bytes>),
+    Source(SourceInner),
+}
+
+struct SourceInner {
+    source: Pin<Box<dyn Source>>,
+    buf: ReusableBuf,
+    reached_eof: bool,
+    state: Rc<LocalState>,
+}
+
+struct LocalState {
+    is_current_stream_data_consumed: Cell<bool>,
+    waker: Cell<Option<Waker>>,
+}
+
+impl LocalStreamBody {
+    /// Creates an empty body.
+    pub fn empty() -> LocalStreamBody {
+        LocalStreamBody {
+            inner: LocalInner::Once(None),
+        }
+    }
+
+    /// Creates a body which polls the provided [Source](./trait.Source.html) on the current task whenever the body is
+    /// polled.
+    pub fn from_source<S: Source + 'static>(source: S) -> LocalStreamBody {
+        LocalStreamBody::from_source_with_capacity(source, DEFAULT_BUF_SIZE)
+    }
+
+    /// Same as [from_source](#method.from_source), but with a specific size of internal buffer.
+    pub fn from_source_with_capacity<S: Source + 'static>(source: S, capacity: usize) -> LocalStreamBody {
+        LocalStreamBody {
+            inner: LocalInner::Source(SourceInner {
+                source: Box::pin(source),
+                buf: ReusableBuf::new(capacity),
+                reached_eof: false,
+                state: Rc::new(LocalState {
+                    is_current_stream_data_consumed: Cell::new(true),
+                    waker: Cell::new(None),
+                }),
+            }),
+        }
+    }
+
+    /// Creates a body which reads the provided [AsyncRead](https://docs.rs/tokio/0.2.16/tokio/io/trait.AsyncRead.html)
+    /// on the current task whenever the body is polled.
+    pub fn from_reader<R: AsyncRead + 'static>(r: R) -> LocalStreamBody {
+        LocalStreamBody::from_source(r)
+    }
+
+    /// Same as [from_reader](#method.from_reader), but with a specific size of internal buffer.
+    pub fn from_reader_with_capacity<R: AsyncRead + 'static>(r: R, capacity: usize) -> LocalStreamBody {
+        LocalStreamBody::from_source_with_capacity(r, capacity)
+    }
+
+    /// Creates a body which reads the provided [futures AsyncRead](https://docs.rs/futures/0.3/futures/io/trait.AsyncRead.html),
+    /// e.g. a glommio file or socket, on the current task whenever the body is polled. It requires the `futures`
+    /// feature.
+    #[cfg(feature = "futures")]
+    pub fn from_futures_reader<R: futures_io::AsyncRead + Unpin + 'static>(r: R) -> LocalStreamBody {
+        LocalStreamBody::from_source(crate::futures::FuturesReader(r))
+    }
+
+    /// Creates a body stream with an associated [LocalChunkWriter](./struct.LocalChunkWriter.html), which hands over
+    /// owned chunks from a task of the same thread.
+    pub fn chunk_channel() -> (LocalChunkWriter, LocalStreamBody) {
+        let shared = Rc::new(RefCell::new(ChannelState::default()));
+        let writer = LocalChunkWriter {
+            shared: Rc::clone(&shared),
+        };
+        (writer, LocalStreamBody::from_source(ChunkReceiver { shared }))
+    }
+}
+
+impl LocalStreamBody {
+    /// Returns whether the body is terminated, i.e. `poll_data` already returned `None` or an error. Polling a
+    /// terminated body keeps returning `None`.
+    pub fn is_terminated(&self) -> bool {
+        match self.inner {
+            LocalInner::Once(ref data) => data.is_none(),
+            LocalInner::Source(ref inner) => inner.reached_eof,
+        }
+    }
+}
+
+impl Body for LocalStreamBody {
+    type Data = LocalStreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        match self.inner {
+            LocalInner::Once(ref mut data) => Poll::Ready(data.take().map(|bytes| {
+                Ok(LocalStreamData {
+                    data: StreamData::from_bytes(bytes),
+                    state: None,
+                })
+            })),
+            LocalInner::Source(ref mut inner) => loop {
+                if inner.reached_eof {
+                    return Poll::Ready(None);
+                }
+
+                if !inner.state.is_current_stream_data_consumed.get() {
+                    inner.state.waker.set(Some(cx.waker().clone()));
+                    return Poll::Pending;
+                }
+
+                let bytes = match inner.source.as_mut().poll_fill(cx, inner.buf.prepare()) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Ok(Fill::Filled(read_count))) if read_count > 0 => inner.buf.split(read_count),
+                    Poll::Ready(Ok(Fill::Chunk(chunk))) if !chunk.is_empty() => chunk,
+                    // Empty chunks are skipped.
+                    Poll::Ready(Ok(Fill::Chunk(_))) => continue,
+                    Poll::Ready(Ok(_)) => {
+                        inner.reached_eof = true;
+                        return Poll::Ready(None);
+                    }
+                    Poll::Ready(Err(err)) => {
+                        // The body is fused after an error, the source isn't polled again.
+                        inner.reached_eof = true;
+                        return Poll::Ready(Some(Err(err)));
+                    }
+                };
+
+                inner.state.is_current_stream_data_consumed.set(false);
+                return Poll::Ready(Some(Ok(LocalStreamData {
+                    data: StreamData::from_bytes(bytes),
+                    state: Some(Rc::clone(&inner.state)),
+                })));
+            },
+        }
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Poll::Ready(Ok(None))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.is_terminated()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self.inner {
+            LocalInner::Once(ref data) => {
+                SizeHint::with_exact(data.as_ref().map(|data| data.len() as u64).unwrap_or(0))
+            }
+            LocalInner::Source(ref inner) if inner.reached_eof => SizeHint::with_exact(0),
+            LocalInner::Source(_) => SizeHint::default(),
+        }
+    }
+}
+
+impl From<Bytes> for LocalStreamBody {
+    #[inline]
+    fn from(chunk: Bytes) -> LocalStreamBody {
+        if chunk.is_empty() {
+            LocalStreamBody::empty()
+        } else {
+            LocalStreamBody {
+                inner: LocalInner::Once(Some(chunk)),
+            }
+        }
+    }
+}
+
+/// The data chunk type produced by `LocalStreamBody`.
+///
+/// It's a [StreamData](./struct.StreamData.html) which also lets the body know when it's dropped, so the body reuses
+/// its buffer without any cross-thread synchronization. It dereferences to the `StreamData`.
+pub struct LocalStreamData {
+    data: StreamData,
+    state: Option<Rc<LocalState>>,
+}
+
+impl Buf for LocalStreamData {
+    fn remaining(&self) -> usize {
+        self.data.remaining()
+    }
+
+    fn bytes(&self) -> &[u8] {
+        self.data.bytes()
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        self.data.advance(cnt)
+    }
+}
+
+impl Deref for LocalStreamData {
+    type Target = StreamData;
+
+    fn deref(&self) -> &StreamData {
+        &self.data
+    }
+}
+
+impl AsRef<[u8]> for LocalStreamData {
+    fn as_ref(&self) -> &[u8] {
+        self.data.bytes()
+    }
+}
+
+impl Drop for LocalStreamData {
+    fn drop(&mut self) {
+        // The buffer is released right after, along with `data`, before the woken body can be polled on this thread.
+        if let Some(ref state) = self.state {
+            state.is_current_stream_data_consumed.set(true);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// The writer half of a [LocalStreamBody::chunk_channel](./struct.LocalStreamBody.html#method.chunk_channel), which
+/// hands owned chunks over to the body, e.g. the buffers returned by the completion-based IO of monoio.
+///
+/// Like the body, it's `!Send` and must be used on the thread of the body. Dropping it ends the body.
+pub struct LocalChunkWriter {
+    shared: Rc<RefCell<ChannelState>>,
+}
+
+#[derive(Default)]
+struct ChannelState {
+    chunk: Option<Bytes>,
+    writer_closed: bool,
+    body_closed: bool,
+    writer_waker: Option<Waker>,
+    body_waker: Option<Waker>,
+}
+
+impl LocalChunkWriter {
+    /// Hands a chunk over to the body, waiting until the body took the previous one. It fails with a `BrokenPipe`
+    /// error once the body is dropped. Empty chunks are skipped.
+    pub async fn send_chunk(&mut self, chunk: Bytes) -> io::Result<()> {
+        let mut chunk = Some(chunk);
+        poll_fn(|cx| {
+            let mut state = self.shared.borrow_mut();
+            if state.body_closed {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    format!("{}: LocalChunkWriter: The body is dropped", env!("CARGO_PKG_NAME")),
+                )));
+            }
+
+            if state.chunk.is_some() {
+                state.writer_waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+
+            state.chunk = chunk.take();
+            if let Some(waker) = state.body_waker.take() {
+                waker.wake();
+            }
+            Poll::Ready(Ok(()))
+        })
+        .await
+    }
+}
+
+impl Drop for LocalChunkWriter {
+    fn drop(&mut self) {
+        let mut state = self.shared.borrow_mut();
+        state.writer_closed = true;
+        if let Some(waker) = state.body_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+struct ChunkReceiver {
+    shared: Rc<RefCell<ChannelState>>,
+}
+
+impl Source for ChunkReceiver {
+    fn poll_fill(self: Pin<&mut Self>, cx: &mut Context, _buf: &mut [u8]) -> Poll<io::Result<Fill>> {
+        let mut state = self.shared.borrow_mut();
+        if let Some(chunk) = state.chunk.take() {
+            if let Some(waker) = state.writer_waker.take() {
+                waker.wake();
+            }
+            return Poll::Ready(Ok(Fill::Chunk(chunk)));
+        }
+
+        if state.writer_closed {
+            return Poll::Ready(Ok(Fill::Eof));
+        }
+
+        state.body_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for ChunkReceiver {
+    fn drop(&mut self) {
+        let mut state = self.shared.borrow_mut();
+        state.body_closed = true;
+        if let Some(waker) = state.writer_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::FutureExt;
+
+    fn consume(body: &mut LocalStreamBody) -> Option<io::Result<Vec<u8>>> {
+        let data = body.data().now_or_never().expect("the body should be ready")?;
+        Some(data.map(|data| data.bytes().to_vec()))
+    }
+
+    #[test]
+    fn reader_bodies_reuse_their_buffer_once_the_chunk_is_dropped() {
+        let mut body = LocalStreamBody::from_reader_with_capacity(&b"hello world"[..], 6);
+        assert_eq!(consume(&mut body).unwrap().unwrap(), b"hello ");
+        assert_eq!(consume(&mut body).unwrap().unwrap(), b"world");
+        assert!(consume(&mut body).is_none());
+        assert!(body.is_end_stream());
+
+        // The next chunk waits for the previous one to be dropped.
+        let mut body = LocalStreamBody::from_reader_with_capacity(&b"hello world"[..], 6);
+        let first = body.data().now_or_never().unwrap().unwrap().unwrap();
+        assert!(body.data().now_or_never().is_none());
+        drop(first);
+        assert_eq!(consume(&mut body).unwrap().unwrap(), b"world");
+    }
+
+    #[test]
+    fn sources_can_hand_over_owned_chunks() {
+        struct Frames(Vec<Bytes>);
+
+        impl Source for Frames {
+            fn poll_fill(mut self: Pin<&mut Self>, _cx: &mut Context, _buf: &mut [u8]) -> Poll<io::Result<Fill>> {
+                match self.0.pop() {
+                    Some(frame) => Poll::Ready(Ok(Fill::Chunk(frame))),
+                    None => Poll::Ready(Ok(Fill::Eof)),
+                }
+            }
+        }
+
+        let frames = vec![Bytes::from("b"), Bytes::new(), Bytes::from("a")];
+        let mut body = LocalStreamBody::from_source(Frames(frames));
+        assert_eq!(consume(&mut body).unwrap().unwrap(), b"a");
+        assert_eq!(consume(&mut body).unwrap().unwrap(), b"b");
+        assert!(consume(&mut body).is_none());
+    }
+
+    #[test]
+    fn chunk_channels_hand_over_the_chunks_of_the_writer() {
+        let (mut writer, mut body) = LocalStreamBody::chunk_channel();
+        assert!(body.data().now_or_never().is_none());
+
+        writer.send_chunk(Bytes::from("hello")).now_or_never().unwrap().unwrap();
+        // The writer waits until the body took the chunk.
+        assert!(writer.send_chunk(Bytes::from("world")).now_or_never().is_none());
+        assert_eq!(consume(&mut body).unwrap().unwrap(), b"hello");
+
+        writer.send_chunk(Bytes::from("world")).now_or_never().unwrap().unwrap();
+        drop(writer);
+        assert_eq!(consume(&mut body).unwrap().unwrap(), b"world");
+        assert!(consume(&mut body).is_none());
+
+        let (mut writer, body) = LocalStreamBody::chunk_channel();
+        drop(body);
+        let err = writer
+            .send_chunk(Bytes::from("late"))
+            .now_or_never()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn futures_readers_are_read_in_place() {
+        let mut body = LocalStreamBody::from_futures_reader(crate::StreamBody::from("hello").into_async_read());
+        assert_eq!(consume(&mut body).unwrap().unwrap(), b"hello");
+        assert!(consume(&mut body).is_none());
+    }
+}
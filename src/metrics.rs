@@ -0,0 +1,166 @@
+use crate::body::StreamBody;
+use crate::profile::Phase;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A handle aggregating the streaming metrics of many bodies, e.g. to be exported to a monitoring system.
+///
+/// Bodies report to it once attached via [StreamBody::with_metrics](./struct.StreamBody.html#method.with_metrics).
+/// The handle is cheap to clone and all the clones share the same counters.
+///
+/// # Examples
+///
+/// ```no_run
+/// use stream_body::{Metrics, StreamBody};
+///
+/// let metrics = Metrics::new();
+///
+/// let body = StreamBody::from("hello").with_metrics(&metrics);
+///
+/// println!("mean ttfb: {:?}, max ttfb: {:?}", metrics.ttfb_mean(), metrics.ttfb_max());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    inner: Arc<MetricsInner>,
+}
+
+#[derive(Debug, Default)]
+struct MetricsInner {
+    bodies: AtomicU64,
+    ttfb_count: AtomicU64,
+    ttfb_total_micros: AtomicU64,
+    ttfb_max_micros: AtomicU64,
+    producer_wait_micros: AtomicU64,
+    consumer_wait_micros: AtomicU64,
+    #[cfg(feature = "gzip")]
+    compression_input_bytes: AtomicU64,
+    #[cfg(feature = "gzip")]
+    compression_output_bytes: AtomicU64,
+    #[cfg(feature = "gzip")]
+    compression_micros: AtomicU64,
+}
+
+impl Metrics {
+    /// Creates a handle with all the counters at zero.
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// The number of bodies attached to the handle.
+    pub fn bodies(&self) -> u64 {
+        self.inner.bodies.load(Ordering::Relaxed)
+    }
+
+    /// The number of bodies which emitted their first chunk.
+    pub fn ttfb_count(&self) -> u64 {
+        self.inner.ttfb_count.load(Ordering::Relaxed)
+    }
+
+    /// The mean first-byte latency of the bodies, see
+    /// [StreamBody::on_first_byte](./struct.StreamBody.html#method.on_first_byte).
+    pub fn ttfb_mean(&self) -> Option<Duration> {
+        let total = self.inner.ttfb_total_micros.load(Ordering::Relaxed);
+        total.checked_div(self.ttfb_count()).map(Duration::from_micros)
+    }
+
+    /// The highest first-byte latency of the bodies.
+    pub fn ttfb_max(&self) -> Option<Duration> {
+        if self.ttfb_count() == 0 {
+            return None;
+        }
+        Some(Duration::from_micros(
+            self.inner.ttfb_max_micros.load(Ordering::Relaxed),
+        ))
+    }
+
+    /// The total time the bodies waited for their producers, i.e. the source was the bottleneck.
+    pub fn producer_wait_time(&self) -> Duration {
+        Duration::from_micros(self.inner.producer_wait_micros.load(Ordering::Relaxed))
+    }
+
+    /// The total time the bodies waited for the server to take their next chunk, i.e. the clients, the sockets or the
+    /// HTTP/2 flow control were the bottleneck, see [StreamBody::on_backpressure](./struct.StreamBody.html#method.on_backpressure).
+    pub fn consumer_wait_time(&self) -> Duration {
+        Duration::from_micros(self.inner.consumer_wait_micros.load(Ordering::Relaxed))
+    }
+
+    /// Records the statistics of a chunk emitted by a gzip adapter, typically from the callback passed to
+    /// [StreamBody::gzip_with_feedback](./struct.StreamBody.html#method.gzip_with_feedback).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use stream_body::{Metrics, StreamBody};
+    ///
+    /// let metrics = Metrics::new();
+    ///
+    /// let handle = metrics.clone();
+    /// let body = StreamBody::from("hello").gzip_with_feedback(6, move |feedback| {
+    ///     handle.record_compression(feedback);
+    ///     feedback.level()
+    /// });
+    ///
+    /// println!("ratio: {:?}, time: {:?}", metrics.compression_ratio(), metrics.compression_time());
+    /// ```
+    #[cfg(feature = "gzip")]
+    pub fn record_compression(&self, feedback: &crate::CompressionFeedback) {
+        let inner = &self.inner;
+        inner
+            .compression_input_bytes
+            .fetch_add(feedback.input_bytes(), Ordering::Relaxed);
+        inner
+            .compression_output_bytes
+            .fetch_add(feedback.output_bytes(), Ordering::Relaxed);
+        inner
+            .compression_micros
+            .fetch_add(feedback.encode_time().as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// The ratio achieved by the recorded compression so far, i.e. compressed size divided by uncompressed size.
+    #[cfg(feature = "gzip")]
+    pub fn compression_ratio(&self) -> Option<f64> {
+        let input_bytes = self.inner.compression_input_bytes.load(Ordering::Relaxed);
+        if input_bytes == 0 {
+            return None;
+        }
+        Some(self.inner.compression_output_bytes.load(Ordering::Relaxed) as f64 / input_bytes as f64)
+    }
+
+    /// The time spent in the encoders by the recorded compression so far.
+    #[cfg(feature = "gzip")]
+    pub fn compression_time(&self) -> Duration {
+        Duration::from_micros(self.inner.compression_micros.load(Ordering::Relaxed))
+    }
+
+    fn record_wait(&self, phase: Phase, duration: Duration) {
+        let counter = match phase {
+            Phase::WaitingOnProducer => &self.inner.producer_wait_micros,
+            Phase::WaitingOnConsumer => &self.inner.consumer_wait_micros,
+            _ => return,
+        };
+        counter.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn record_ttfb(&self, ttfb: Duration) {
+        let micros = ttfb.as_micros() as u64;
+        self.inner.ttfb_total_micros.fetch_add(micros, Ordering::Relaxed);
+        self.inner.ttfb_max_micros.fetch_max(micros, Ordering::Relaxed);
+        self.inner.ttfb_count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl StreamBody {
+    /// Attaches the body to a [Metrics](./struct.Metrics.html) handle, which aggregates its first-byte latency and
+    /// its producer and consumer wait times with the ones of the other attached bodies.
+    pub fn with_metrics(self, metrics: &Metrics) -> StreamBody {
+        metrics.inner.bodies.fetch_add(1, Ordering::Relaxed);
+
+        let ttfb_metrics = metrics.clone();
+        let wait_metrics = metrics.clone();
+        self.on_first_byte(move |ttfb| ttfb_metrics.record_ttfb(ttfb))
+            .on_backpressure(Duration::from_secs(0), move |phase, waited| {
+                wait_metrics.record_wait(phase, waited)
+            })
+    }
+}
@@ -0,0 +1,585 @@
+use http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use std::future::poll_fn;
+use std::marker::Unpin;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{self, AsyncRead};
+
+const READ_SIZE: usize = 8 * 1024;
+const MAX_HEADERS_LEN: usize = 16 * 1024;
+
+/// A streaming `multipart/form-data` parser over an upload read from an
+/// [AsyncRead](https://docs.rs/tokio/0.2.16/tokio/io/trait.AsyncRead.html).
+///
+/// The parts are yielded one after another by [next_part](#method.next_part), each as its headers together with an
+/// `AsyncRead` of its content, so large file uploads are never buffered fully in memory. The unread content of a part
+/// is skipped when the next one is requested.
+///
+/// The size of every part and of the whole upload can be limited, exceeding a limit fails the read with an error of
+/// kind `InvalidData`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use stream_body::Multipart;
+/// use tokio::fs::File;
+/// # use http::HeaderMap;
+/// # use tokio::io::AsyncRead;
+///
+/// # async fn run(headers: &HeaderMap, upload: impl AsyncRead + Unpin) -> std::io::Result<()> {
+/// let mut multipart = Multipart::from_headers(headers, upload)?
+///     .with_part_limit(100 * 1024 * 1024)
+///     .with_total_limit(500 * 1024 * 1024);
+///
+/// let mut files = 0;
+/// while let Some(mut part) = multipart.next_part().await? {
+///     if part.file_name().is_some() {
+///         // The file name is chosen by the client, so it's never used as a path.
+///         files += 1;
+///         let mut f = File::create(format!("uploads/upload-{}", files)).await?;
+///         tokio::io::copy(&mut part, &mut f).await?;
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Multipart<R> {
+    inner: R,
+    // The delimiter preceding every part, i.e. `\r\n--boundary`.
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    // The start of the unparsed data in the buffer.
+    start: usize,
+    reached_eof: bool,
+    state: State,
+    part_limit: Option<u64>,
+    total_limit: Option<u64>,
+    part_read: u64,
+    total_read: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    // Searching for the first delimiter, skipping the preamble.
+    Preamble,
+    // Right after a delimiter, which is followed either by the headers of a part or by the closing `--`.
+    Delimiter,
+    Content,
+    Done,
+}
+
+impl<R: AsyncRead + Unpin> Multipart<R> {
+    /// Creates a parser for the given boundary, as found in the `Content-Type` header of the upload.
+    pub fn new<B: AsRef<[u8]>>(boundary: B, inner: R) -> Multipart<R> {
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend_from_slice(boundary.as_ref());
+
+        Multipart {
+            inner,
+            delimiter,
+            // The first delimiter may directly start the upload, without the line break.
+            buf: b"\r\n".to_vec(),
+            start: 0,
+            reached_eof: false,
+            state: State::Preamble,
+            part_limit: None,
+            total_limit: None,
+            part_read: 0,
+            total_read: 0,
+        }
+    }
+
+    /// Creates a parser with the boundary taken from the `Content-Type` header, which must be a `multipart` one.
+    pub fn from_headers(headers: &HeaderMap, inner: R) -> io::Result<Multipart<R>> {
+        let boundary = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| value.trim_start().to_ascii_lowercase().starts_with("multipart/"))
+            .and_then(|value| param(value, "boundary"));
+
+        match boundary {
+            Some(boundary) if !boundary.is_empty() => Ok(Multipart::new(boundary, inner)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{}: Multipart: The Content-Type header doesn't declare a multipart boundary",
+                    env!("CARGO_PKG_NAME")
+                ),
+            )),
+        }
+    }
+
+    /// Limits the content of every part to `limit` bytes.
+    pub fn with_part_limit(mut self, limit: u64) -> Multipart<R> {
+        self.part_limit = Some(limit);
+        self
+    }
+
+    /// Limits the whole upload to `limit` bytes.
+    pub fn with_total_limit(mut self, limit: u64) -> Multipart<R> {
+        self.total_limit = Some(limit);
+        self
+    }
+
+    /// Returns the next part, or `None` once the closing boundary is reached. The unread content of the previous
+    /// part is skipped.
+    pub async fn next_part(&mut self) -> io::Result<Option<MultipartPart<'_, R>>> {
+        match poll_fn(|cx| self.poll_next_part(cx)).await? {
+            Some(headers) => Ok(Some(MultipartPart {
+                multipart: self,
+                headers,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    fn poll_next_part(&mut self, cx: &mut Context) -> Poll<io::Result<Option<HeaderMap>>> {
+        loop {
+            match self.state {
+                State::Done => return Poll::Ready(Ok(None)),
+                State::Preamble => match self.find(&self.delimiter) {
+                    Some(idx) => {
+                        self.start += idx + self.delimiter.len();
+                        self.state = State::Delimiter;
+                    }
+                    None => {
+                        // Keeps the tail which may be the beginning of the delimiter.
+                        self.start = self.buf.len().saturating_sub(self.delimiter.len() - 1).max(self.start);
+                        if let Err(err) = ready!(self.poll_fill_buf(cx, "the first boundary")) {
+                            return Poll::Ready(Err(err));
+                        }
+                    }
+                },
+                State::Content => {
+                    let mut scratch = [0_u8; READ_SIZE];
+                    match ready!(self.poll_read_content(cx, &mut scratch)) {
+                        Ok(_) => {}
+                        Err(err) => return Poll::Ready(Err(err)),
+                    }
+                }
+                State::Delimiter => match self.parse_after_delimiter() {
+                    Some(Ok(Some(headers))) => {
+                        self.state = State::Content;
+                        self.part_read = 0;
+                        return Poll::Ready(Ok(Some(headers)));
+                    }
+                    Some(Ok(None)) => {
+                        self.state = State::Done;
+                        return Poll::Ready(Ok(None));
+                    }
+                    Some(Err(err)) => return Poll::Ready(Err(err)),
+                    None => {
+                        if self.buf.len() - self.start > MAX_HEADERS_LEN {
+                            return Poll::Ready(Err(invalid_data("The part headers are too large")));
+                        }
+                        if let Err(err) = ready!(self.poll_fill_buf(cx, "the part headers")) {
+                            return Poll::Ready(Err(err));
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    // Parses what follows a delimiter: `None` if more data is needed, the part headers, or `Ok(None)` at the closing
+    // delimiter.
+    fn parse_after_delimiter(&mut self) -> Option<io::Result<Option<HeaderMap>>> {
+        let data = &self.buf[self.start..];
+        if data.len() < 2 {
+            return None;
+        }
+        if data.starts_with(b"--") {
+            return Some(Ok(None));
+        }
+
+        // The delimiter line may contain transport padding before its line break.
+        let line_end = find(data, b"\r\n")?;
+        if data[..line_end].iter().any(|&b| b != b' ' && b != b'\t') {
+            return Some(Err(invalid_data("Malformed boundary line")));
+        }
+
+        let headers_start = line_end + 2;
+        let (headers_len, skip) = if data[headers_start..].starts_with(b"\r\n") {
+            (0, 2)
+        } else {
+            (find(&data[headers_start..], b"\r\n\r\n")?, 4)
+        };
+
+        let headers = parse_headers(&data[headers_start..headers_start + headers_len]);
+        self.start += headers_start + headers_len + skip;
+        Some(headers.map(Some))
+    }
+
+    fn poll_read_content(&mut self, cx: &mut Context, out: &mut [u8]) -> Poll<io::Result<usize>> {
+        loop {
+            if self.state != State::Content {
+                return Poll::Ready(Ok(0));
+            }
+
+            let (available, at_delimiter) = match self.find(&self.delimiter) {
+                Some(idx) => (idx, idx == 0),
+                None => {
+                    let pending = self.buf.len() - self.start;
+                    (pending.saturating_sub(self.delimiter.len() - 1), false)
+                }
+            };
+
+            if at_delimiter {
+                self.start += self.delimiter.len();
+                self.state = State::Delimiter;
+                return Poll::Ready(Ok(0));
+            }
+
+            if available > 0 && !out.is_empty() {
+                let n = available.min(out.len());
+                out[..n].copy_from_slice(&self.buf[self.start..self.start + n]);
+                self.start += n;
+
+                self.part_read += n as u64;
+                if self.part_limit.map(|limit| self.part_read > limit).unwrap_or(false) {
+                    return Poll::Ready(Err(invalid_data("A part exceeds the size limit")));
+                }
+                return Poll::Ready(Ok(n));
+            }
+
+            if let Err(err) = ready!(self.poll_fill_buf(cx, "the part content")) {
+                return Poll::Ready(Err(err));
+            }
+        }
+    }
+
+    fn find(&self, needle: &[u8]) -> Option<usize> {
+        find(&self.buf[self.start..], needle)
+    }
+
+    fn poll_fill_buf(&mut self, cx: &mut Context, expected: &str) -> Poll<io::Result<()>> {
+        if self.reached_eof {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "{}: Multipart: The upload ended before {}",
+                    env!("CARGO_PKG_NAME"),
+                    expected
+                ),
+            )));
+        }
+
+        if self.start > 0 {
+            self.buf.drain(..self.start);
+            self.start = 0;
+        }
+
+        let len = self.buf.len();
+        self.buf.resize(len + READ_SIZE, 0);
+
+        let poll_status = Pin::new(&mut self.inner).poll_read(cx, &mut self.buf[len..]);
+        let read_count = match poll_status {
+            Poll::Ready(Ok(read_count)) => read_count,
+            _ => 0,
+        };
+        self.buf.truncate(len + read_count);
+
+        match poll_status {
+            Poll::Ready(Ok(0)) => {
+                self.reached_eof = true;
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Ok(read_count)) => {
+                self.total_read += read_count as u64;
+                if self.total_limit.map(|limit| self.total_read > limit).unwrap_or(false) {
+                    return Poll::Ready(Err(invalid_data("The upload exceeds the size limit")));
+                }
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// A part of a `multipart/form-data` upload, see [Multipart::next_part](./struct.Multipart.html#method.next_part).
+///
+/// It reads the content of the part via [AsyncRead](https://docs.rs/tokio/0.2.16/tokio/io/trait.AsyncRead.html).
+pub struct MultipartPart<'a, R> {
+    multipart: &'a mut Multipart<R>,
+    headers: HeaderMap,
+}
+
+impl<'a, R: AsyncRead + Unpin> MultipartPart<'a, R> {
+    /// Returns the headers of the part.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Returns the form field name from the `Content-Disposition` header.
+    pub fn name(&self) -> Option<&str> {
+        self.disposition_param("name")
+    }
+
+    /// Returns the file name from the `Content-Disposition` header, if the part is a file.
+    ///
+    /// The name is chosen by the client and may contain path separators or `..`, so it must not be used as a path
+    /// as is.
+    pub fn file_name(&self) -> Option<&str> {
+        self.disposition_param("filename")
+    }
+
+    /// Returns the `Content-Type` of the part.
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+    }
+
+    fn disposition_param(&self, name: &str) -> Option<&str> {
+        let disposition = self.headers.get(header::CONTENT_DISPOSITION)?.to_str().ok()?;
+        param(disposition, name)
+    }
+}
+
+impl<'a, R: AsyncRead + Unpin> AsyncRead for MultipartPart<'a, R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        self.multipart.poll_read_content(cx, buf)
+    }
+}
+
+// Returns the value of the `name` parameter of a header value like `form-data; name="field"`. The `;` within quoted
+// values don't separate the parameters, the escaped characters of quoted values are returned as is.
+fn param<'a>(value: &'a str, name: &str) -> Option<&'a str> {
+    split_params(value).skip(1).find_map(|item| {
+        let mut parts = item.splitn(2, '=');
+        let key = parts.next()?.trim();
+        let value = parts.next()?.trim();
+
+        if !key.eq_ignore_ascii_case(name) {
+            return None;
+        }
+        match value.strip_prefix('"') {
+            Some(quoted) => Some(quoted.strip_suffix('"').unwrap_or(quoted)),
+            None => Some(value),
+        }
+    })
+}
+
+// Splits a header value at the `;` which are outside of quoted strings.
+fn split_params(value: &str) -> impl Iterator<Item = &str> {
+    let mut rest = Some(value);
+    std::iter::from_fn(move || {
+        let current = rest?;
+        let mut quoted = false;
+        let mut escaped = false;
+        for (idx, c) in current.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if quoted => escaped = true,
+                '"' => quoted = !quoted,
+                ';' if !quoted => {
+                    rest = Some(&current[idx + 1..]);
+                    return Some(&current[..idx]);
+                }
+                _ => {}
+            }
+        }
+        rest = None;
+        Some(current)
+    })
+}
+
+fn parse_headers(data: &[u8]) -> io::Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+
+    for line in data.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            continue;
+        }
+
+        let colon = line
+            .iter()
+            .position(|&b| b == b':')
+            .ok_or_else(|| invalid_data("Malformed part header"))?;
+
+        let name = HeaderName::from_bytes(&line[..colon]).map_err(|_| invalid_data("Invalid part header name"))?;
+        let value =
+            HeaderValue::from_bytes(trim(&line[colon + 1..])).map_err(|_| invalid_data("Invalid part header value"))?;
+        headers.append(name, value);
+    }
+
+    Ok(headers)
+}
+
+fn trim(mut data: &[u8]) -> &[u8] {
+    while let [first, rest @ ..] = data {
+        if !first.is_ascii_whitespace() {
+            break;
+        }
+        data = rest;
+    }
+    while let [rest @ .., last] = data {
+        if !last.is_ascii_whitespace() {
+            break;
+        }
+        data = rest;
+    }
+    data
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: Multipart: {}", env!("CARGO_PKG_NAME"), msg),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use tokio::io::AsyncReadExt;
+
+    // Hands out the upload in the given chunks, one per read.
+    struct Chunks(VecDeque<Vec<u8>>);
+
+    impl AsyncRead for Chunks {
+        fn poll_read(mut self: Pin<&mut Self>, _cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+            let chunk = match self.0.front_mut() {
+                Some(chunk) => chunk,
+                None => return Poll::Ready(Ok(0)),
+            };
+            let n = chunk.len().min(buf.len());
+            buf[..n].copy_from_slice(&chunk[..n]);
+            chunk.drain(..n);
+            if chunk.is_empty() {
+                self.0.pop_front();
+            }
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    const UPLOAD: &[u8] = b"preamble with --bound in it\r\n\
+        --boundary\r\n\
+        Content-Disposition: form-data; name=\"field\"\r\n\
+        \r\n\
+        value\r\n\
+        --boundary  \r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"a;b.txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        line 1\r\n--boundar\r\nline 2\r\n\
+        --boundary--\r\n\
+        epilogue";
+
+    fn upload(chunks: Vec<&[u8]>) -> Multipart<Chunks> {
+        // An empty chunk would read as the end of the upload.
+        let chunks = chunks.into_iter().filter(|chunk| !chunk.is_empty());
+        Multipart::new("boundary", Chunks(chunks.map(<[u8]>::to_vec).collect()))
+    }
+
+    // Returns the name, file name and content of every part.
+    async fn parts(multipart: &mut Multipart<Chunks>) -> io::Result<Vec<(Option<String>, Option<String>, Vec<u8>)>> {
+        let mut parts = Vec::new();
+        while let Some(mut part) = multipart.next_part().await? {
+            let name = part.name().map(str::to_owned);
+            let file_name = part.file_name().map(str::to_owned);
+            let mut content = Vec::new();
+            part.read_to_end(&mut content).await?;
+            parts.push((name, file_name, content));
+        }
+        Ok(parts)
+    }
+
+    fn expected() -> Vec<(Option<String>, Option<String>, Vec<u8>)> {
+        vec![
+            (Some("field".to_owned()), None, b"value".to_vec()),
+            (
+                Some("file".to_owned()),
+                Some("a;b.txt".to_owned()),
+                b"line 1\r\n--boundar\r\nline 2".to_vec(),
+            ),
+        ]
+    }
+
+    #[tokio::test]
+    async fn parses_the_parts_after_the_preamble() {
+        let mut multipart = upload(vec![UPLOAD]);
+        assert_eq!(parts(&mut multipart).await.unwrap(), expected());
+        assert!(multipart.next_part().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn delimiters_split_across_chunks_are_found() {
+        for i in 0..=UPLOAD.len() {
+            let mut multipart = upload(vec![&UPLOAD[..i], &UPLOAD[i..]]);
+            assert_eq!(parts(&mut multipart).await.unwrap(), expected(), "split at {}", i);
+        }
+
+        let bytes = UPLOAD.chunks(1).collect();
+        assert_eq!(parts(&mut upload(bytes)).await.unwrap(), expected());
+    }
+
+    #[tokio::test]
+    async fn unread_content_is_skipped() {
+        let mut multipart = upload(vec![UPLOAD]);
+        assert!(multipart.next_part().await.unwrap().is_some());
+        let part = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(part.content_type(), Some("text/plain"));
+        drop(part);
+        assert!(multipart.next_part().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn limits_fail_the_upload() {
+        let mut multipart = upload(vec![UPLOAD]).with_part_limit(10);
+        let err = parts(&mut multipart).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut multipart = upload(UPLOAD.chunks(16).collect()).with_total_limit(64);
+        let err = parts(&mut multipart).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut multipart = upload(vec![UPLOAD]).with_total_limit(UPLOAD.len() as u64);
+        assert_eq!(parts(&mut multipart).await.unwrap(), expected());
+    }
+
+    #[tokio::test]
+    async fn truncated_uploads_fail() {
+        let closing = UPLOAD.len() - b"--\r\nepilogue".len();
+        for len in [0, 20, 60, 120, closing] {
+            let err = parts(&mut upload(vec![&UPLOAD[..len]])).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof, "truncated at {}", len);
+        }
+    }
+
+    #[test]
+    fn params_keep_the_semicolons_of_quoted_values() {
+        let value = r#"form-data; name="field"; filename="a;b \"c\".txt"; size=3"#;
+        assert_eq!(param(value, "name"), Some("field"));
+        assert_eq!(param(value, "filename"), Some(r#"a;b \"c\".txt"#));
+        assert_eq!(param(value, "size"), Some("3"));
+        assert_eq!(param(value, "missing"), None);
+        assert_eq!(param("multipart/form-data; BOUNDARY=abc", "boundary"), Some("abc"));
+    }
+
+    #[test]
+    fn from_headers_requires_a_multipart_boundary() {
+        let mut headers = HeaderMap::new();
+        assert!(Multipart::from_headers(&headers, Chunks(VecDeque::new())).is_err());
+
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; boundary=abc"),
+        );
+        assert!(Multipart::from_headers(&headers, Chunks(VecDeque::new())).is_err());
+
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("multipart/form-data; boundary=\"abc\""),
+        );
+        let multipart = Multipart::from_headers(&headers, Chunks(VecDeque::new())).unwrap();
+        assert_eq!(multipart.delimiter, b"\r\n--abc");
+    }
+}
@@ -0,0 +1,248 @@
+use crate::body::{size_hint_with_held, StreamBody};
+use crate::data::StreamData;
+use crate::priority::Priority;
+use bytes::Buf;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io;
+use tokio::time::{self, Delay, Instant};
+
+impl StreamBody {
+    /// Releases the chunks at the target rate of `bytes_per_sec` instead of as fast as the client reads them.
+    ///
+    /// The first `burst` bytes are sent right away, so playback can start quickly, and the rest is released such that
+    /// the bytes sent after the burst never get ahead of the rate, measured from the first poll. Useful for media
+    /// pseudo-streaming, e.g. pacing at 1.2 times the media bitrate, so clients which seek away or stop watching don't
+    /// download the entire file.
+    ///
+    /// A rate of zero is treated as one byte per second. Unlike a [Scheduler](./struct.Scheduler.html), the pacing is
+    /// independent of the other bodies. [Interactive](./enum.Priority.html#variant.Interactive) bodies are returned
+    /// unchanged.
+    pub fn paced(self, bytes_per_sec: u64, burst: u64) -> StreamBody {
+        if self.priority == Priority::Interactive {
+            return self;
+        }
+
+        self.wrap_with(|inner| Paced {
+            inner,
+            bytes_per_sec: bytes_per_sec.max(1),
+            burst,
+            sent: 0,
+            started_at: None,
+            delay: None,
+        })
+    }
+
+    /// Caps the rate of the body at `bytes_per_sec` with a token bucket, e.g. to rate limit a download per response.
+    ///
+    /// The bucket holds a tenth of a second worth of bytes, so the data flows smoothly rather than in bursts, and
+    /// time the client spends not reading doesn't accumulate into a burst later. Chunks larger than the bucket are
+    /// split without copying. A rate of zero is treated as one byte per second.
+    /// [Interactive](./enum.Priority.html#variant.Interactive) bodies are returned unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use stream_body::StreamBody;
+    ///
+    /// # async fn run() -> std::io::Result<()> {
+    /// let file = tokio::fs::File::open("video.mp4").await?;
+    /// // Caps the download at 2 MiB/s.
+    /// let body = StreamBody::from_reader(file).throttled(2 * 1024 * 1024);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn throttled(self, bytes_per_sec: u64) -> StreamBody {
+        if self.priority == Priority::Interactive {
+            return self;
+        }
+
+        let bytes_per_sec = bytes_per_sec.max(1);
+        let capacity = (bytes_per_sec / 10).max(1);
+
+        self.wrap_with(|inner| Throttled {
+            inner,
+            bytes_per_sec,
+            capacity,
+            tokens: capacity as f64,
+            refilled_at: Instant::now(),
+            pending: None,
+            delay: None,
+        })
+    }
+}
+
+struct Paced {
+    inner: StreamBody,
+    bytes_per_sec: u64,
+    burst: u64,
+    sent: u64,
+    started_at: Option<Instant>,
+    delay: Option<Delay>,
+}
+
+impl Paced {
+    // Waits until the bytes sent so far are within the rate.
+    fn poll_release(&mut self, cx: &mut Context) -> Poll<()> {
+        let started_at = *self.started_at.get_or_insert_with(Instant::now);
+
+        let paced_bytes = self.sent.saturating_sub(self.burst);
+        if paced_bytes == 0 {
+            return Poll::Ready(());
+        }
+
+        let release_at = started_at + Duration::from_secs_f64(paced_bytes as f64 / self.bytes_per_sec as f64);
+        if release_at <= Instant::now() {
+            self.delay = None;
+            return Poll::Ready(());
+        }
+
+        let delay = match self.delay {
+            Some(ref mut delay) => {
+                delay.reset(release_at);
+                delay
+            }
+            None => self.delay.get_or_insert_with(|| time::delay_until(release_at)),
+        };
+
+        match Pin::new(delay).poll(cx) {
+            Poll::Ready(()) => {
+                self.delay = None;
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Body for Paced {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        if self.poll_release(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        let poll_status = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(ref data))) = poll_status {
+            self.sent += data.remaining() as u64;
+        }
+        poll_status
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+struct Throttled {
+    inner: StreamBody,
+    bytes_per_sec: u64,
+    capacity: u64,
+    tokens: f64,
+    refilled_at: Instant,
+    // The rest of a source chunk which is waiting for tokens.
+    pending: Option<StreamData>,
+    delay: Option<Delay>,
+}
+
+impl Throttled {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.bytes_per_sec as f64).min(self.capacity as f64);
+        self.refilled_at = now;
+    }
+
+    // Waits until the bucket holds `amount` tokens.
+    fn poll_tokens(&mut self, cx: &mut Context, amount: u64) -> Poll<()> {
+        self.refill();
+        if self.tokens >= amount as f64 {
+            self.delay = None;
+            return Poll::Ready(());
+        }
+
+        let missing = amount as f64 - self.tokens;
+        let ready_at = self.refilled_at + Duration::from_secs_f64(missing / self.bytes_per_sec as f64);
+
+        let delay = match self.delay {
+            Some(ref mut delay) => {
+                delay.reset(ready_at);
+                delay
+            }
+            None => self.delay.get_or_insert_with(|| time::delay_until(ready_at)),
+        };
+
+        match Pin::new(delay).poll(cx) {
+            Poll::Ready(()) => {
+                self.delay = None;
+                self.refill();
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Body for Throttled {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let me = &mut *self;
+
+        let mut data = match me.pending.take() {
+            Some(data) => data,
+            None => match Pin::new(&mut me.inner).poll_data(cx) {
+                Poll::Ready(Some(Ok(data))) => data,
+                poll_status => return poll_status,
+            },
+        };
+
+        let amount = (data.remaining() as u64).min(me.capacity);
+        if me.poll_tokens(cx, amount).is_pending() {
+            me.pending = Some(data);
+            return Poll::Pending;
+        }
+        me.tokens -= amount as f64;
+
+        if data.remaining() as u64 > amount {
+            let part = data.split_to(amount as usize);
+            me.pending = Some(data);
+            return Poll::Ready(Some(Ok(part)));
+        }
+        Poll::Ready(Some(Ok(data)))
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.pending.is_none() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let pending = self.pending.as_ref().map(|data| data.remaining() as u64).unwrap_or(0);
+        size_hint_with_held(self.inner.size_hint(), pending)
+    }
+}
@@ -0,0 +1,112 @@
+use crate::body::StreamBody;
+use crate::buffer::ReusableBuf;
+use crate::state::State;
+use async_pipe::PipeWriter;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A pool which recycles the buffer and state allocations of finished channel bodies for new ones.
+///
+/// Each body handed out by [channel](#method.channel) returns its buffer and state to the pool when it is dropped,
+/// which reduces allocator churn on high-RPS endpoints streaming small responses. The pipe connecting the writer
+/// half to the body can't be reused, so it is still allocated per body.
+///
+/// A buffer is only recycled if no chunk emitted from it is alive anymore.
+///
+/// # Examples
+///
+/// ```no_run
+/// use stream_body::BodyPool;
+///
+/// // Keep at most 256 idle buffers of 8 KB.
+/// let pool = BodyPool::new(8 * 1024, 256);
+///
+/// let (writer, body) = pool.channel();
+/// ```
+#[derive(Clone)]
+pub struct BodyPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    capacity: usize,
+    max_idle: usize,
+    idle: Mutex<Vec<Parts>>,
+}
+
+// The recyclable allocations of a channel body.
+pub(crate) type Parts = (ReusableBuf, Arc<Mutex<State>>);
+
+impl BodyPool {
+    /// Creates a pool of buffers having `capacity` bytes, keeping at most `max_idle` of them around.
+    pub fn new(capacity: usize, max_idle: usize) -> BodyPool {
+        BodyPool {
+            inner: Arc::new(PoolInner {
+                capacity,
+                max_idle,
+                idle: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Creates a body stream with an associated writer half, reusing an idle buffer if there is one.
+    pub fn channel(&self) -> (PipeWriter, StreamBody) {
+        let (buf, state) = match self.idle().pop() {
+            Some(parts) => parts,
+            None => (
+                ReusableBuf::new(self.inner.capacity),
+                Arc::new(Mutex::new(State::new())),
+            ),
+        };
+
+        StreamBody::channel_from_parts(buf, state, Some(self.clone()))
+    }
+
+    /// Returns the number of idle buffers in the pool.
+    pub fn idle_count(&self) -> usize {
+        self.idle().len()
+    }
+
+    pub(crate) fn recycle(&self, buf: ReusableBuf, state: &Arc<Mutex<State>>) {
+        // A chunk still holding the state would mark the chunks of the next body as consumed once dropped.
+        if Arc::strong_count(state) > 1 || buf.capacity() != self.inner.capacity {
+            return;
+        }
+
+        match state.lock() {
+            Ok(mut s) => *s = State::new(),
+            Err(_) => return,
+        }
+
+        let mut idle = self.idle();
+        if idle.len() < self.inner.max_idle {
+            idle.push((buf, Arc::clone(state)));
+        }
+    }
+
+    fn idle(&self) -> MutexGuard<'_, Vec<Parts>> {
+        // The list is always left consistent, so a poisoned lock is still usable.
+        match self.inner.idle.lock() {
+            Ok(idle) => idle,
+            Err(err) => err.into_inner(),
+        }
+    }
+}
+
+impl StreamBody {
+    /// Creates a body stream with an associated writer half whose buffer comes from `pool`, and returns to it once the
+    /// body is dropped. It's the same as [BodyPool::channel](./struct.BodyPool.html#method.channel).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use stream_body::{BodyPool, StreamBody};
+    ///
+    /// let pool = BodyPool::new(8 * 1024, 256);
+    ///
+    /// // In every handler, with a clone of the pool:
+    /// let (writer, body) = StreamBody::channel_with_pool(&pool);
+    /// ```
+    pub fn channel_with_pool(pool: &BodyPool) -> (PipeWriter, StreamBody) {
+        pool.channel()
+    }
+}
@@ -0,0 +1,193 @@
+use crate::body::{size_hint_with_held, StreamBody};
+use crate::data::StreamData;
+use bytes::Buf;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use tokio::io;
+
+impl StreamBody {
+    /// Starts producing the first chunk right away, on a spawned task, instead of waiting for the server to poll the
+    /// body.
+    ///
+    /// The server only polls the body once the response head is written, so a source with a high initial latency,
+    /// e.g. a cold file or a remote fetch, delays the first byte by its whole latency. A prewarmed body overlaps it
+    /// with the rest of the handler and the writing of the head, then the first chunk is emitted as soon as it's
+    /// polled and the rest of the body is polled on the task of the server as usual.
+    ///
+    /// Only the first chunk is produced ahead, so at most one chunk is held in memory before the client asks for it.
+    /// Dropping the body before the first chunk is ready cancels the task. It requires the default `tokio-rt` feature
+    /// and panics outside of the runtime, like `tokio::spawn`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use stream_body::StreamBody;
+    ///
+    /// # async fn run() -> std::io::Result<()> {
+    /// let file = tokio::fs::File::open("cold-file").await?;
+    /// let body = StreamBody::from_reader(file).prewarm();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn prewarm(self) -> StreamBody {
+        let hint = self.size_hint();
+        self.wrap_with(|inner| {
+            let shared = Arc::new(Mutex::new(WarmupState::default()));
+            tokio::spawn(Warmup {
+                body: Some(inner),
+                shared: Arc::clone(&shared),
+            });
+
+            Prewarmed {
+                shared,
+                inner: None,
+                first: None,
+                hint,
+            }
+        })
+    }
+}
+
+type FirstChunk = Option<Result<StreamData, io::Error>>;
+
+#[derive(Default)]
+struct WarmupState {
+    // The body and its first chunk, handed back by the task.
+    warmed: Option<(StreamBody, FirstChunk)>,
+    cancelled: bool,
+    body_waker: Option<Waker>,
+    task_waker: Option<Waker>,
+}
+
+/// Polls the body for its first chunk, then hands both back.
+struct Warmup {
+    body: Option<StreamBody>,
+    shared: Arc<Mutex<WarmupState>>,
+}
+
+impl Future for Warmup {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let me = &mut *self;
+        let mut state = me.shared.lock().unwrap_or_else(|err| err.into_inner());
+        if state.cancelled {
+            return Poll::Ready(());
+        }
+
+        let first = match me.body.as_mut().map(|body| Pin::new(body).poll_data(cx)) {
+            Some(Poll::Ready(first)) => first,
+            Some(Poll::Pending) => {
+                state.task_waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            None => return Poll::Ready(()),
+        };
+
+        state.warmed = me.body.take().map(|body| (body, first));
+        if let Some(waker) = state.body_waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(())
+    }
+}
+
+struct Prewarmed {
+    shared: Arc<Mutex<WarmupState>>,
+    inner: Option<StreamBody>,
+    first: Option<FirstChunk>,
+    // The size hint of the body before it was handed to the task.
+    hint: SizeHint,
+}
+
+impl Prewarmed {
+    /// Waits for the task to hand the body back.
+    fn poll_warmed(&mut self, cx: &mut Context) -> Poll<()> {
+        if self.inner.is_some() {
+            return Poll::Ready(());
+        }
+
+        let mut state = self.shared.lock().unwrap_or_else(|err| err.into_inner());
+        match state.warmed.take() {
+            Some((body, first)) => {
+                self.inner = Some(body);
+                self.first = Some(first);
+                Poll::Ready(())
+            }
+            None => {
+                state.body_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for Prewarmed {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock().unwrap_or_else(|err| err.into_inner());
+        state.cancelled = true;
+        if let Some(waker) = state.task_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Body for Prewarmed {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let me = &mut *self;
+        if me.poll_warmed(cx).is_pending() {
+            return Poll::Pending;
+        }
+        if let Some(first) = me.first.take() {
+            return Poll::Ready(first);
+        }
+
+        match me.inner {
+            Some(ref mut inner) => Pin::new(inner).poll_data(cx),
+            None => Poll::Pending,
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        let me = &mut *self;
+        if me.poll_warmed(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        match me.inner {
+            Some(ref mut inner) => Pin::new(inner).poll_trailers(cx),
+            None => Poll::Pending,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self.inner {
+            Some(ref inner) => self.first.is_none() && inner.is_end_stream(),
+            None => false,
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let inner = match self.inner {
+            Some(ref inner) => inner,
+            None => return self.hint.clone(),
+        };
+
+        let held = match self.first {
+            Some(Some(Ok(ref data))) => data.remaining() as u64,
+            _ => 0,
+        };
+        let inner_hint = inner.size_hint();
+        size_hint_with_held(inner_hint, held)
+    }
+}
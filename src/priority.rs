@@ -0,0 +1,28 @@
+use crate::body::StreamBody;
+
+/// The QoS class of a body, consulted by the pacing adapters like [scheduled](./struct.StreamBody.html#method.scheduled).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Priority {
+    /// Latency-sensitive bodies, e.g. small API responses. They are never paced, so they can't queue behind large
+    /// transfers.
+    Interactive,
+    /// Throughput-oriented bodies, e.g. file downloads. This is the default.
+    #[default]
+    Bulk,
+}
+
+impl StreamBody {
+    /// Tags the body with a QoS class.
+    ///
+    /// The tag is kept by the adapters wrapping this body, but it must be set before the body is attached to a
+    /// pacing adapter to take effect.
+    pub fn with_priority(mut self, priority: Priority) -> StreamBody {
+        self.priority = priority;
+        self
+    }
+
+    /// Returns the QoS class of the body.
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+}
@@ -0,0 +1,211 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use async_pipe::PipeWriter;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use tokio::io;
+
+impl StreamBody {
+    /// Creates a channel body and spawns the producer returned by `f` for its writer half, the task being owned by
+    /// the body.
+    ///
+    /// Dropping the body cancels the task at its next suspension point, so a response which is never sent, e.g.
+    /// because the client went away, doesn't leave a task behind still reading a file or waiting on a slow upstream.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use stream_body::StreamBody;
+    /// use tokio::fs::File;
+    ///
+    /// # async fn run() {
+    /// let body = StreamBody::from_producer(|mut writer| async move {
+    ///     let mut f = File::open("large-file").await.unwrap();
+    ///     tokio::io::copy(&mut f, &mut writer).await.unwrap();
+    /// });
+    /// # }
+    /// ```
+    pub fn from_producer<F, Fut>(f: F) -> StreamBody
+    where
+        F: FnOnce(PipeWriter) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (w, body) = StreamBody::channel();
+        body.owning_task(f(w))
+    }
+
+    /// Spawns `future`, which is cancelled at its next suspension point once the returned body is dropped.
+    pub(crate) fn owning_task<Fut>(self, future: Fut) -> StreamBody
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let shared = Arc::new(Mutex::new(TaskState::default()));
+        tokio::spawn(Cancellable {
+            future: Box::pin(future),
+            shared: Arc::clone(&shared),
+        });
+
+        self.wrap_with(|inner| OwnsProducer { inner, shared })
+    }
+
+    /// Same as [owning_task](#method.owning_task), but the body only ends once `future` returned `Ok`, and fails with
+    /// its error otherwise, so a channel body whose producer failed doesn't look complete.
+    pub(crate) fn owning_fallible_task<Fut>(self, future: Fut) -> StreamBody
+    where
+        Fut: Future<Output = io::Result<()>> + Send + 'static,
+    {
+        let outcome = Arc::new(Mutex::new(Outcome::default()));
+        let guard = OutcomeGuard {
+            shared: Arc::clone(&outcome),
+        };
+
+        self.wrap_with(|inner| ReportsOutcome { inner, outcome })
+            .owning_task(async move { guard.set(future.await) })
+    }
+}
+
+#[derive(Default)]
+struct TaskState {
+    cancelled: bool,
+    waker: Option<Waker>,
+}
+
+/// Runs the producer until it completes or the body is dropped.
+struct Cancellable {
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
+    shared: Arc<Mutex<TaskState>>,
+}
+
+impl Future for Cancellable {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        {
+            let mut state = self.shared.lock().unwrap_or_else(|err| err.into_inner());
+            if state.cancelled {
+                return Poll::Ready(());
+            }
+            state.waker = Some(cx.waker().clone());
+        }
+
+        self.future.as_mut().poll(cx)
+    }
+}
+
+struct OwnsProducer {
+    inner: StreamBody,
+    shared: Arc<Mutex<TaskState>>,
+}
+
+impl Drop for OwnsProducer {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock().unwrap_or_else(|err| err.into_inner());
+        state.cancelled = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Body for OwnsProducer {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[derive(Default)]
+struct Outcome {
+    result: Option<io::Result<()>>,
+    waker: Option<Waker>,
+}
+
+/// Reports the result of the producer, or its failure if it panicked or was cancelled.
+struct OutcomeGuard {
+    shared: Arc<Mutex<Outcome>>,
+}
+
+impl OutcomeGuard {
+    fn set(&self, result: io::Result<()>) {
+        let mut outcome = self.shared.lock().unwrap_or_else(|err| err.into_inner());
+        if outcome.result.is_none() {
+            outcome.result = Some(result);
+        }
+        if let Some(waker) = outcome.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for OutcomeGuard {
+    fn drop(&mut self) {
+        self.set(Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("{}: StreamBody: The producer didn't complete", env!("CARGO_PKG_NAME")),
+        )));
+    }
+}
+
+struct ReportsOutcome {
+    inner: StreamBody,
+    outcome: Arc<Mutex<Outcome>>,
+}
+
+impl Body for ReportsOutcome {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        match Pin::new(&mut self.inner).poll_data(cx) {
+            // The writer is dropped when the producer returns, but the body only ends once its result is known.
+            Poll::Ready(None) => {
+                let mut outcome = self.outcome.lock().unwrap_or_else(|err| err.into_inner());
+                match outcome.result.take() {
+                    Some(Ok(())) => Poll::Ready(None),
+                    Some(Err(err)) => Poll::Ready(Some(Err(err))),
+                    None => {
+                        outcome.waker = Some(cx.waker().clone());
+                        Poll::Pending
+                    }
+                }
+            }
+            poll_status => poll_status,
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        false
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
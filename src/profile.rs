@@ -0,0 +1,239 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::fmt;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io;
+
+/// A phase of the streaming of a body, see [Profile](./struct.Profile.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// The body had no data ready while the server asked for it, i.e. the producer was the bottleneck.
+    WaitingOnProducer,
+    /// A chunk was emitted and the server didn't ask for the next one yet, i.e. the client or the socket was the
+    /// bottleneck.
+    WaitingOnConsumer,
+    /// The time spent in the `poll_data` calls of the body, i.e. reading and copying the data, excluding the time
+    /// recorded as compressing.
+    Copying,
+    /// The time spent compressing, recorded via [Profile::record](./struct.Profile.html#method.record), e.g. from the
+    /// feedback of [gzip_with_feedback](./struct.StreamBody.html#method.gzip_with_feedback).
+    Compressing,
+}
+
+const PHASES: [Phase; 4] = [
+    Phase::WaitingOnProducer,
+    Phase::WaitingOnConsumer,
+    Phase::Copying,
+    Phase::Compressing,
+];
+
+impl Phase {
+    fn index(self) -> usize {
+        match self {
+            Phase::WaitingOnProducer => 0,
+            Phase::WaitingOnConsumer => 1,
+            Phase::Copying => 2,
+            Phase::Compressing => 3,
+        }
+    }
+
+    /// Returns a short name of the phase, e.g. for metric labels.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::WaitingOnProducer => "producer",
+            Phase::WaitingOnConsumer => "consumer",
+            Phase::Copying => "copying",
+            Phase::Compressing => "compressing",
+        }
+    }
+}
+
+/// The time a body spent in every [Phase](./enum.Phase.html) of its streaming, so a latency regression can be
+/// attributed to a phase, recorded once attached via [StreamBody::with_profile](./struct.StreamBody.html#method.with_profile).
+///
+/// It's meant to be created per request. The handle is cheap to clone and all the clones share the same timings. Its
+/// `Display` implementation renders the breakdown, e.g. `producer=120.5ms consumer=3.2ms copying=0.8ms compressing=1.6ms`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use stream_body::{Profile, StreamBody};
+///
+/// let profile = Profile::new();
+///
+/// let (writer, body) = StreamBody::channel();
+/// let body = body.with_profile(&profile);
+///
+/// // Once the response is sent:
+/// log::info!("streaming profile: {}", profile);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    nanos: Arc<[AtomicU64; 4]>,
+}
+
+impl Profile {
+    /// Creates a profile with all the phases at zero.
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    /// Adds `duration` to a phase, e.g. from a custom adapter or producer.
+    pub fn record(&self, phase: Phase, duration: Duration) {
+        let nanos = duration.as_nanos().min(u128::from(u64::MAX)) as u64;
+        self.nanos[phase.index()].fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// Returns the time spent in a phase so far.
+    pub fn get(&self, phase: Phase) -> Duration {
+        Duration::from_nanos(self.nanos[phase.index()].load(Ordering::Relaxed))
+    }
+
+    /// Returns the time spent in every phase so far.
+    pub fn breakdown(&self) -> impl Iterator<Item = (Phase, Duration)> + '_ {
+        PHASES.iter().map(move |&phase| (phase, self.get(phase)))
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (phase, duration)) in self.breakdown().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{}={:.1}ms", phase.as_str(), duration.as_secs_f64() * 1000.0)?;
+        }
+        Ok(())
+    }
+}
+
+impl StreamBody {
+    /// Records the time the body spends in every [Phase](./enum.Phase.html) into `profile`.
+    ///
+    /// It should be the outermost adapter, so the waits are measured as the server sees them and the time spent in
+    /// the inner adapters counts as copying. The compressing time recorded into the profile during a `poll_data` call
+    /// is deducted from its copying time.
+    pub fn with_profile(self, profile: &Profile) -> StreamBody {
+        let profile = profile.clone();
+        self.wrap_with(|inner| Profiled {
+            inner,
+            profile,
+            pending_since: None,
+            emitted_at: None,
+            on_wait: None,
+        })
+    }
+
+    /// Calls `callback` with the phase and the duration of every wait of the body longer than `threshold`, once it
+    /// ends, so slow clients can be told apart from slow producers, e.g. for capacity planning.
+    ///
+    /// A [WaitingOnConsumer](./enum.Phase.html#variant.WaitingOnConsumer) wait means hyper held the previous chunk,
+    /// i.e. the client, the socket or the HTTP/2 flow control applied backpressure, while a
+    /// [WaitingOnProducer](./enum.Phase.html#variant.WaitingOnProducer) wait means the source had no data ready. Like
+    /// [with_profile](#method.with_profile), it should be the outermost adapter.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use stream_body::{Phase, StreamBody};
+    ///
+    /// let (writer, body) = StreamBody::channel();
+    /// let body = body.on_backpressure(Duration::from_millis(100), |phase, waited| match phase {
+    ///     Phase::WaitingOnConsumer => log::info!("slow client: waited {:?}", waited),
+    ///     _ => log::info!("slow producer: waited {:?}", waited),
+    /// });
+    /// ```
+    pub fn on_backpressure<F>(self, threshold: Duration, callback: F) -> StreamBody
+    where
+        F: FnMut(Phase, Duration) + Send + 'static,
+    {
+        self.wrap_with(|inner| Profiled {
+            inner,
+            profile: Profile::new(),
+            pending_since: None,
+            emitted_at: None,
+            on_wait: Some((threshold, Box::new(callback))),
+        })
+    }
+}
+
+type WaitCallback = (Duration, Box<dyn FnMut(Phase, Duration) + Send>);
+
+struct Profiled {
+    inner: StreamBody,
+    profile: Profile,
+    // When the body started waiting for its producer.
+    pending_since: Option<Instant>,
+    // When the last chunk was emitted, the next poll tells how long the consumer took.
+    emitted_at: Option<Instant>,
+    // The callback of the waits longer than the threshold.
+    on_wait: Option<WaitCallback>,
+}
+
+impl Profiled {
+    fn record_wait(&mut self, phase: Phase, duration: Duration) {
+        self.profile.record(phase, duration);
+        if let Some((threshold, ref mut callback)) = self.on_wait {
+            if duration > threshold {
+                callback(phase, duration);
+            }
+        }
+    }
+}
+
+impl Body for Profiled {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let start = Instant::now();
+        if let Some(at) = self.emitted_at.take() {
+            self.record_wait(Phase::WaitingOnConsumer, start.duration_since(at));
+        }
+
+        let compressing_before = self.profile.get(Phase::Compressing);
+        let poll_status = Pin::new(&mut self.inner).poll_data(cx);
+        let end = Instant::now();
+
+        let compressing = self.profile.get(Phase::Compressing).saturating_sub(compressing_before);
+        self.profile
+            .record(Phase::Copying, end.duration_since(start).saturating_sub(compressing));
+
+        match poll_status {
+            Poll::Pending => {
+                self.pending_since.get_or_insert(start);
+            }
+            Poll::Ready(ref result) => {
+                if let Some(since) = self.pending_since.take() {
+                    self.record_wait(Phase::WaitingOnProducer, start.duration_since(since));
+                }
+                if let Some(Ok(_)) = result {
+                    self.emitted_at = Some(end);
+                }
+            }
+        }
+        poll_status
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,363 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+use tokio::io;
+
+/// A handle reporting how many bytes of a body were delivered, i.e. consumed by the server from the emitted chunks,
+/// so a producer can follow the progress of the transfer.
+///
+/// A body reports to it once attached via [StreamBody::with_progress](./struct.StreamBody.html#method.with_progress).
+/// With the `futures` feature the handle is also a `Stream` of the cumulative number of delivered bytes, yielding
+/// whenever it changed and ending once the body is dropped, so it can be used in `select!` alongside other work.
+///
+/// The handle is cheap to clone and all the clones share the same counter, but only one task should poll it as a
+/// stream at a time.
+///
+/// # Examples
+///
+/// ```ignore
+/// use futures::StreamExt;
+///
+/// let progress = Progress::new();
+/// let (writer, body) = StreamBody::channel();
+/// let body = body.with_progress(&progress);
+///
+/// tokio::spawn(async move {
+///     while let Some(delivered) = progress.next().await {
+///         println!("{} bytes delivered", delivered);
+///     }
+/// });
+/// ```
+#[derive(Clone, Default)]
+pub struct Progress {
+    shared: Arc<Mutex<ProgressState>>,
+    // The count last yielded by this handle as a stream.
+    #[cfg(feature = "futures")]
+    seen: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct ProgressState {
+    delivered: u64,
+    finished: bool,
+    waker: Option<Waker>,
+    // Called once the body and all its chunks are dropped, as the state is shared by all of them.
+    finish: Option<FinishHook>,
+}
+
+type FinishCallback = Box<dyn FnOnce(&TransferSummary) + Send>;
+
+struct FinishHook {
+    callback: FinishCallback,
+    created_at: Instant,
+    outcome: TransferOutcome,
+}
+
+/// How the transfer of a body ended, see [TransferSummary](./struct.TransferSummary.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferOutcome {
+    /// The body reached its end.
+    Completed,
+    /// The body yielded an error.
+    Failed,
+    /// The body was dropped before its end, e.g. the client went away.
+    Aborted,
+}
+
+/// The summary of the transfer of a body, passed to the callback of
+/// [StreamBody::on_finish](./struct.StreamBody.html#method.on_finish).
+#[derive(Debug, Clone)]
+pub struct TransferSummary {
+    /// The number of bytes the server consumed from the emitted chunks.
+    pub delivered: u64,
+    /// The time from the creation of the body until the body and all its chunks were dropped.
+    pub duration: Duration,
+    /// How the transfer ended.
+    pub outcome: TransferOutcome,
+}
+
+impl Drop for ProgressState {
+    fn drop(&mut self) {
+        if let Some(hook) = self.finish.take() {
+            (hook.callback)(&TransferSummary {
+                delivered: self.delivered,
+                duration: hook.created_at.elapsed(),
+                outcome: hook.outcome,
+            });
+        }
+    }
+}
+
+impl Progress {
+    /// Creates a handle with no delivered bytes.
+    pub fn new() -> Progress {
+        Progress::default()
+    }
+
+    /// The number of bytes delivered so far.
+    pub fn delivered(&self) -> u64 {
+        lock(&self.shared).delivered
+    }
+
+    /// Returns whether the attached body is gone, after which the count doesn't change anymore.
+    pub fn is_finished(&self) -> bool {
+        lock(&self.shared).finished
+    }
+
+    #[cfg(feature = "futures")]
+    fn poll_change(&mut self, cx: &mut Context) -> Poll<Option<u64>> {
+        let mut state = lock(&self.shared);
+        if self.seen != state.delivered {
+            self.seen = state.delivered;
+            return Poll::Ready(Some(state.delivered));
+        }
+
+        if state.finished {
+            return Poll::Ready(None);
+        }
+
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[cfg(feature = "futures")]
+impl futures_core::Stream for Progress {
+    type Item = u64;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.poll_change(cx)
+    }
+}
+
+/// Adds the bytes consumed from a chunk to the count, called when the chunk is dropped.
+pub(crate) fn record_delivered(shared: &Mutex<ProgressState>, len: usize) {
+    if len == 0 {
+        return;
+    }
+
+    let mut state = lock(shared);
+    state.delivered += len as u64;
+    if let Some(waker) = state.waker.take() {
+        waker.wake();
+    }
+}
+
+fn lock(shared: &Mutex<ProgressState>) -> MutexGuard<'_, ProgressState> {
+    // The state is always left consistent, so a poisoned lock is still usable.
+    shared.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+impl StreamBody {
+    /// Attaches the body to a [Progress](./struct.Progress.html) handle, which counts the bytes the server consumed
+    /// from the emitted chunks.
+    pub fn with_progress(self, progress: &Progress) -> StreamBody {
+        let shared = Arc::clone(&progress.shared);
+        self.wrap_with(|inner| WithProgress { inner, shared })
+    }
+}
+
+impl StreamBody {
+    /// Calls `callback` with the number of delivered bytes, the duration and the outcome of the transfer once it's
+    /// over, e.g. to write access logs or export metrics with accurate transferred-bytes counts.
+    ///
+    /// The callback runs once the body and all its chunks are dropped, as the server may still be writing the last
+    /// chunks when it drops the body, and it runs on the thread dropping the last of them, so it should be quick.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use stream_body::StreamBody;
+    ///
+    /// let body = StreamBody::from("hello").on_finish(|summary| {
+    ///     println!("{:?}: {} bytes in {:?}", summary.outcome, summary.delivered, summary.duration);
+    /// });
+    /// ```
+    pub fn on_finish<F>(self, callback: F) -> StreamBody
+    where
+        F: FnOnce(&TransferSummary) + Send + 'static,
+    {
+        self.wrap_with(|inner| {
+            let mut state = ProgressState::default();
+            state.finish = Some(FinishHook {
+                callback: Box::new(callback),
+                created_at: inner.created_at,
+                outcome: TransferOutcome::Aborted,
+            });
+            let shared = Arc::new(Mutex::new(state));
+            OnFinish { inner, shared }
+        })
+    }
+}
+
+struct OnFinish {
+    inner: StreamBody,
+    shared: Arc<Mutex<ProgressState>>,
+}
+
+impl OnFinish {
+    fn set_outcome(&self, outcome: TransferOutcome) {
+        if let Some(ref mut hook) = lock(&self.shared).finish {
+            hook.outcome = outcome;
+        }
+    }
+}
+
+impl Body for OnFinish {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        match Pin::new(&mut self.inner).poll_data(cx) {
+            Poll::Ready(Some(Ok(mut data))) => {
+                data.track_progress(Arc::clone(&self.shared));
+                Poll::Ready(Some(Ok(data)))
+            }
+            Poll::Ready(Some(Err(err))) => {
+                self.set_outcome(TransferOutcome::Failed);
+                Poll::Ready(Some(Err(err)))
+            }
+            Poll::Ready(None) => {
+                self.set_outcome(TransferOutcome::Completed);
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+struct WithProgress {
+    inner: StreamBody,
+    shared: Arc<Mutex<ProgressState>>,
+}
+
+impl Body for WithProgress {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        match Pin::new(&mut self.inner).poll_data(cx) {
+            Poll::Ready(Some(Ok(mut data))) => {
+                data.track_progress(Arc::clone(&self.shared));
+                Poll::Ready(Some(Ok(data)))
+            }
+            poll_status => poll_status,
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for WithProgress {
+    fn drop(&mut self) {
+        let mut state = lock(&self.shared);
+        state.finished = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Buf;
+
+    async fn consume(mut body: StreamBody) {
+        while let Some(data) = body.data().await {
+            let mut data = data.unwrap();
+            let len = data.remaining();
+            data.advance(len);
+        }
+    }
+
+    fn hello_world() -> StreamBody {
+        StreamBody::concat(vec![StreamBody::from("hello "), StreamBody::from("world")])
+    }
+
+    fn recorded() -> (
+        Arc<Mutex<Option<TransferSummary>>>,
+        impl FnOnce(&TransferSummary) + Send + 'static,
+    ) {
+        let summary = Arc::new(Mutex::new(None));
+        let recorded = Arc::clone(&summary);
+        (summary, move |summary: &TransferSummary| {
+            *recorded.lock().unwrap() = Some(summary.clone())
+        })
+    }
+
+    #[tokio::test]
+    async fn progress_then_on_finish_both_count_the_delivered_bytes() {
+        let progress = Progress::new();
+        let (summary, callback) = recorded();
+        consume(hello_world().with_progress(&progress).on_finish(callback)).await;
+
+        assert_eq!(progress.delivered(), 11);
+        assert!(progress.is_finished());
+        let summary = summary.lock().unwrap().take().unwrap();
+        assert_eq!(summary.delivered, 11);
+        assert_eq!(summary.outcome, TransferOutcome::Completed);
+    }
+
+    #[tokio::test]
+    async fn on_finish_then_progress_both_count_the_delivered_bytes() {
+        let progress = Progress::new();
+        let (summary, callback) = recorded();
+        consume(hello_world().on_finish(callback).with_progress(&progress)).await;
+
+        assert_eq!(progress.delivered(), 11);
+        let summary = summary.lock().unwrap().take().unwrap();
+        assert_eq!(summary.delivered, 11);
+        assert_eq!(summary.outcome, TransferOutcome::Completed);
+    }
+
+    #[tokio::test]
+    async fn only_the_consumed_bytes_are_delivered() {
+        let progress = Progress::new();
+        let (summary, callback) = recorded();
+        let mut body = hello_world().with_progress(&progress).on_finish(callback);
+
+        let mut data = body.data().await.unwrap().unwrap();
+        data.advance(4);
+        drop(data);
+        drop(body);
+
+        assert_eq!(progress.delivered(), 4);
+        let summary = summary.lock().unwrap().take().unwrap();
+        assert_eq!(summary.delivered, 4);
+        assert_eq!(summary.outcome, TransferOutcome::Aborted);
+    }
+}
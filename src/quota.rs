@@ -0,0 +1,351 @@
+use crate::body::{size_hint_with_held, StreamBody};
+use crate::data::StreamData;
+use bytes::Buf;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use tokio::io;
+
+/// A byte quota for application-level flow control, e.g. metered downloads.
+///
+/// A body attached via [StreamBody::with_quota](./struct.StreamBody.html#method.with_quota) emits at most the
+/// remaining quota. Once it's used up, the body waits, which pauses its producer, and the `on_exhausted` callback is
+/// called. The body continues as soon as the quota is [refilled](#method.refill), either right from the callback or
+/// later from anywhere else via a clone of the handle.
+///
+/// Unlike a size limit, an exhausted quota doesn't fail the body, unless it's [aborted](#method.abort), e.g. from the
+/// callback. A quota can be shared by several bodies, e.g. all the downloads of one account, see
+/// [ClientQuotas](./struct.ClientQuotas.html).
+///
+/// # Examples
+///
+/// ```no_run
+/// use stream_body::{Quota, StreamBody};
+///
+/// let quota = Quota::new(10 * 1024 * 1024).on_exhausted(|quota| {
+///     // Charge the account for the next 10 MiB.
+///     quota.refill(10 * 1024 * 1024);
+/// });
+///
+/// let body = StreamBody::from("hello").with_quota(&quota);
+/// ```
+#[derive(Clone)]
+pub struct Quota {
+    inner: Arc<Mutex<QuotaState>>,
+}
+
+struct QuotaState {
+    remaining: u64,
+    wakers: Vec<Waker>,
+    // Taken while it's being called, so it can refill the quota.
+    on_exhausted: Option<ExhaustedCallback>,
+    // Whether the callback was already called for the current exhaustion.
+    notified: bool,
+    aborted: bool,
+}
+
+type ExhaustedCallback = Box<dyn FnMut(&Quota) + Send>;
+
+type ExhaustedPolicy<K> = Arc<dyn Fn(&K, &Quota) + Send + Sync>;
+
+impl Quota {
+    /// Creates a quota of `bytes` bytes.
+    pub fn new(bytes: u64) -> Quota {
+        Quota {
+            inner: Arc::new(Mutex::new(QuotaState {
+                remaining: bytes,
+                wakers: Vec::new(),
+                on_exhausted: None,
+                notified: false,
+                aborted: false,
+            })),
+        }
+    }
+
+    /// Sets the callback which is called each time the quota is used up while a body waits for more.
+    pub fn on_exhausted<F>(self, callback: F) -> Quota
+    where
+        F: FnMut(&Quota) + Send + 'static,
+    {
+        self.lock().on_exhausted = Some(Box::new(callback));
+        self
+    }
+
+    /// Adds `bytes` bytes to the quota and resumes the waiting bodies.
+    pub fn refill(&self, bytes: u64) {
+        let wakers = {
+            let mut state = self.lock();
+            state.remaining = state.remaining.saturating_add(bytes);
+            if bytes > 0 {
+                state.notified = false;
+            }
+            std::mem::take(&mut state.wakers)
+        };
+
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Returns the remaining quota in bytes.
+    pub fn remaining(&self) -> u64 {
+        self.lock().remaining
+    }
+
+    /// Fails the bodies attached to the quota at their next chunk instead of letting them wait, e.g. from the
+    /// `on_exhausted` callback to cut off a client over its budget. An aborted quota can't be refilled.
+    pub fn abort(&self) {
+        let wakers = {
+            let mut state = self.lock();
+            state.aborted = true;
+            std::mem::take(&mut state.wakers)
+        };
+
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Returns whether the quota was aborted.
+    pub fn is_aborted(&self) -> bool {
+        self.lock().aborted
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QuotaState> {
+        // The state stays consistent even if a callback panicked while the lock was held.
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    // Takes up to `wanted` bytes of the quota, or registers the waker if it's used up.
+    fn poll_acquire(&self, cx: &mut Context, wanted: u64) -> Poll<io::Result<u64>> {
+        let callback = {
+            let mut state = self.lock();
+            if state.aborted {
+                return Poll::Ready(Err(aborted_error()));
+            }
+            if state.remaining > 0 {
+                let granted = state.remaining.min(wanted);
+                state.remaining -= granted;
+                return Poll::Ready(Ok(granted));
+            }
+
+            if !state.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                state.wakers.push(cx.waker().clone());
+            }
+
+            if state.notified {
+                None
+            } else {
+                state.notified = true;
+                state.on_exhausted.take()
+            }
+        };
+
+        if let Some(mut callback) = callback {
+            callback(self);
+
+            let mut state = self.lock();
+            if state.on_exhausted.is_none() {
+                state.on_exhausted = Some(callback);
+            }
+
+            // The callback may have refilled or aborted the quota right away.
+            if state.aborted {
+                return Poll::Ready(Err(aborted_error()));
+            }
+            if state.remaining > 0 {
+                let granted = state.remaining.min(wanted);
+                state.remaining -= granted;
+                return Poll::Ready(Ok(granted));
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+fn aborted_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("{}: Quota: The quota was aborted", env!("CARGO_PKG_NAME")),
+    )
+}
+
+impl fmt::Debug for Quota {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Quota").field("remaining", &self.remaining()).finish()
+    }
+}
+
+/// The write budgets of the clients of a server, i.e. a [Quota](./struct.Quota.html) per client key provided by the
+/// caller, e.g. an API key or an IP address, shared by all the bodies served to that client.
+///
+/// The `on_exhausted` policy is called with the key and the quota of a client each time it's used up while a body
+/// waits for more. It decides what happens next: nothing throttles the client until its quota is refilled, e.g. by a
+/// periodic task, [refill](./struct.Quota.html#method.refill) lets it continue and
+/// [abort](./struct.Quota.html#method.abort) fails its streams. New requests of an exhausted client can be answered
+/// with a `429 Too Many Requests` upfront, see [is_exhausted](#method.is_exhausted).
+///
+/// # Examples
+///
+/// ```no_run
+/// use hyper::{Response, StatusCode};
+/// use stream_body::{ClientQuotas, StreamBody};
+///
+/// let quotas = ClientQuotas::new(100 * 1024 * 1024).on_exhausted(|client: &String, quota| {
+///     log::warn!("{} is over its budget", client);
+///     quota.abort();
+/// });
+///
+/// # fn respond(quotas: &ClientQuotas<String>, client: String) -> Response<StreamBody> {
+/// if quotas.is_exhausted(&client) {
+///     return Response::builder()
+///         .status(StatusCode::TOO_MANY_REQUESTS)
+///         .body(StreamBody::empty())
+///         .unwrap();
+/// }
+///
+/// let body = StreamBody::from("hello").with_quota(&quotas.quota(client));
+/// Response::new(body)
+/// # }
+/// ```
+pub struct ClientQuotas<K> {
+    budget: u64,
+    quotas: Mutex<HashMap<K, Quota>>,
+    on_exhausted: Option<ExhaustedPolicy<K>>,
+}
+
+impl<K: Eq + Hash + Clone + Send + 'static> ClientQuotas<K> {
+    /// Creates the budgets, every client starting with `budget` bytes.
+    pub fn new(budget: u64) -> ClientQuotas<K> {
+        ClientQuotas {
+            budget,
+            quotas: Mutex::new(HashMap::new()),
+            on_exhausted: None,
+        }
+    }
+
+    /// Sets the policy called when the quota of a client is used up. It applies to the quotas created afterwards.
+    pub fn on_exhausted<F>(mut self, policy: F) -> ClientQuotas<K>
+    where
+        F: Fn(&K, &Quota) + Send + Sync + 'static,
+    {
+        self.on_exhausted = Some(Arc::new(policy));
+        self
+    }
+
+    /// Returns the quota of a client, creating it with the full budget on its first request.
+    pub fn quota(&self, key: K) -> Quota {
+        let mut quotas = self.quotas.lock().unwrap_or_else(|err| err.into_inner());
+        quotas
+            .entry(key)
+            .or_insert_with_key(|key| {
+                let quota = Quota::new(self.budget);
+                match self.on_exhausted {
+                    Some(ref policy) => {
+                        let policy = Arc::clone(policy);
+                        let key = key.clone();
+                        quota.on_exhausted(move |quota| policy(&key, quota))
+                    }
+                    None => quota,
+                }
+            })
+            .clone()
+    }
+
+    /// Returns the remaining budget of a client, the full budget if it wasn't served yet.
+    pub fn remaining(&self, key: &K) -> u64 {
+        let quotas = self.quotas.lock().unwrap_or_else(|err| err.into_inner());
+        quotas.get(key).map(Quota::remaining).unwrap_or(self.budget)
+    }
+
+    /// Returns whether a client used up its budget or was aborted, so its new requests can be rejected.
+    pub fn is_exhausted(&self, key: &K) -> bool {
+        let quotas = self.quotas.lock().unwrap_or_else(|err| err.into_inner());
+        quotas
+            .get(key)
+            .map(|quota| quota.remaining() == 0 || quota.is_aborted())
+            .unwrap_or(self.budget == 0)
+    }
+
+    /// Forgets a client, e.g. at the end of a metering period, so its next request starts with the full budget. The
+    /// bodies still attached to its previous quota keep using it.
+    pub fn remove(&self, key: &K) -> Option<Quota> {
+        self.quotas.lock().unwrap_or_else(|err| err.into_inner()).remove(key)
+    }
+}
+
+impl StreamBody {
+    /// Attaches the body to a byte [Quota](./struct.Quota.html), pausing it whenever the quota is used up.
+    pub fn with_quota(self, quota: &Quota) -> StreamBody {
+        self.wrap_with(|inner| Quoted {
+            inner,
+            quota: quota.clone(),
+            pending: None,
+        })
+    }
+}
+
+struct Quoted {
+    inner: StreamBody,
+    quota: Quota,
+    // The rest of a source chunk which exceeded the quota.
+    pending: Option<StreamData>,
+}
+
+impl Body for Quoted {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let mut data = match self.pending.take() {
+            Some(data) => data,
+            None => match Pin::new(&mut self.inner).poll_data(cx) {
+                Poll::Ready(Some(Ok(data))) => data,
+                poll_status => return poll_status,
+            },
+        };
+
+        let len = data.remaining() as u64;
+        let granted = match self.quota.poll_acquire(cx, len) {
+            Poll::Ready(Ok(granted)) => granted,
+            Poll::Ready(Err(err)) => return Poll::Ready(Some(Err(err))),
+            Poll::Pending => {
+                // Holding the chunk keeps the producer paused until the quota is refilled.
+                self.pending = Some(data);
+                return Poll::Pending;
+            }
+        };
+
+        if granted < len {
+            // Only the granted part is emitted, split off without copying, while the rest of the chunk waits for more
+            // quota.
+            let head = data.split_to(granted as usize);
+            self.pending = Some(data);
+            return Poll::Ready(Some(Ok(head)));
+        }
+
+        Poll::Ready(Some(Ok(data)))
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.pending.is_none() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let pending = self.pending.as_ref().map(|data| data.remaining() as u64).unwrap_or(0);
+        size_hint_with_held(self.inner.size_hint(), pending)
+    }
+}
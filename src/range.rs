@@ -0,0 +1,275 @@
+use http::{HeaderValue, StatusCode};
+
+// The number of ranges served at most, so a header listing many ranges can't amplify a response, see
+// https://tools.ietf.org/html/rfc7233#section-6.1.
+const MAX_RANGES: usize = 16;
+
+/// A satisfiable byte range resolved against the total length of a representation, covering `start..=end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    /// The offset of the first byte.
+    pub start: u64,
+    /// The offset of the last byte, inclusive.
+    pub end: u64,
+}
+
+impl ByteRange {
+    /// Returns the number of bytes in the range.
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Always returns `false`, as a resolved range holds at least one byte.
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Returns the `Content-Range` value of this range, e.g. `bytes 0-99/1000`.
+    pub fn content_range(&self, total_len: u64) -> HeaderValue {
+        HeaderValue::from_str(&format!("bytes {}-{}/{}", self.start, self.end, total_len))
+            .expect("valid Content-Range header value")
+    }
+}
+
+/// The decision on how to answer a request carrying a `Range` header, shared by single-range and
+/// `multipart/byteranges` responses.
+///
+/// # Examples
+///
+/// ```
+/// use stream_body::RangeDecision;
+///
+/// let decision = RangeDecision::from_header("bytes=0-99", 1000);
+/// assert_eq!(decision.status().as_u16(), 206);
+/// assert_eq!(decision.content_range(1000).unwrap(), "bytes 0-99/1000");
+///
+/// let decision = RangeDecision::from_header("bytes=2000-", 1000);
+/// assert_eq!(decision.status().as_u16(), 416);
+/// assert_eq!(decision.content_range(1000).unwrap(), "bytes */1000");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeDecision {
+    /// The header is absent, malformed, holds no range, uses another unit or asks for more than 16 distinct ranges,
+    /// so the full representation is sent with `200 OK`.
+    Full,
+    /// At least one range is satisfiable, so the ranges are sent with `206 Partial Content`. The unsatisfiable ones
+    /// are dropped, the overlapping and adjacent ones are coalesced and the rest keep the order of the header.
+    Partial(Vec<ByteRange>),
+    /// None of the ranges is satisfiable, so `416 Range Not Satisfiable` is sent.
+    NotSatisfiable,
+}
+
+impl RangeDecision {
+    /// Parses a `Range` header value as described in [RFC 7233](https://tools.ietf.org/html/rfc7233#section-3.1)
+    /// and resolves it against the total length.
+    pub fn from_header(value: &str, total_len: u64) -> RangeDecision {
+        let specs = match value.trim().strip_prefix("bytes=") {
+            Some(specs) => specs,
+            None => return RangeDecision::Full,
+        };
+
+        let mut ranges = Vec::new();
+        let mut any_spec = false;
+        for spec in specs.split(',').map(str::trim).filter(|spec| !spec.is_empty()) {
+            any_spec = true;
+            match parse_spec(spec, total_len) {
+                Some(Some(range)) => {
+                    coalesce(&mut ranges, range);
+                    if ranges.len() > MAX_RANGES {
+                        return RangeDecision::Full;
+                    }
+                }
+                Some(None) => {}
+                None => return RangeDecision::Full,
+            }
+        }
+
+        if !any_spec {
+            RangeDecision::Full
+        } else if ranges.is_empty() {
+            RangeDecision::NotSatisfiable
+        } else {
+            RangeDecision::Partial(ranges)
+        }
+    }
+
+    /// Same as [from_header](#method.from_header), but treats an absent or non-UTF-8 header as no range.
+    pub fn from_header_value(value: Option<&HeaderValue>, total_len: u64) -> RangeDecision {
+        match value.and_then(|value| value.to_str().ok()) {
+            Some(value) => RangeDecision::from_header(value, total_len),
+            None => RangeDecision::Full,
+        }
+    }
+
+    /// Returns the status code of the response.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            RangeDecision::Full => StatusCode::OK,
+            RangeDecision::Partial(_) => StatusCode::PARTIAL_CONTENT,
+            RangeDecision::NotSatisfiable => StatusCode::RANGE_NOT_SATISFIABLE,
+        }
+    }
+
+    /// Returns the `Content-Range` value of the response, if it needs one: the range for a single-range response
+    /// and `bytes */<total_len>` for an unsatisfiable one. Full and multi-range responses have none, the parts of a
+    /// multi-range response carry their own via [ByteRange::content_range](./struct.ByteRange.html#method.content_range).
+    pub fn content_range(&self, total_len: u64) -> Option<HeaderValue> {
+        match self {
+            RangeDecision::Partial(ranges) if ranges.len() == 1 => Some(ranges[0].content_range(total_len)),
+            RangeDecision::NotSatisfiable => Some(
+                HeaderValue::from_str(&format!("bytes */{}", total_len)).expect("valid Content-Range header value"),
+            ),
+            _ => None,
+        }
+    }
+}
+
+// Adds a range to disjoint ranges, merging it with the ones it overlaps or touches. The merged range takes the place
+// of the first one it was merged with.
+fn coalesce(ranges: &mut Vec<ByteRange>, mut range: ByteRange) {
+    let mut at = None;
+    let mut idx = 0;
+    while idx < ranges.len() {
+        let other = ranges[idx];
+        if other.start <= range.end.saturating_add(1) && range.start <= other.end.saturating_add(1) {
+            range = ByteRange {
+                start: range.start.min(other.start),
+                end: range.end.max(other.end),
+            };
+            ranges.remove(idx);
+            at.get_or_insert(idx);
+        } else {
+            idx += 1;
+        }
+    }
+
+    match at {
+        Some(idx) => ranges.insert(idx, range),
+        None => ranges.push(range),
+    }
+}
+
+// Returns `None` for a malformed spec and `Some(None)` for a well-formed but unsatisfiable one.
+fn parse_spec(spec: &str, total_len: u64) -> Option<Option<ByteRange>> {
+    let (first, last) = {
+        let mut parts = spec.splitn(2, '-');
+        (parts.next()?.trim(), parts.next()?.trim())
+    };
+
+    if first.is_empty() {
+        let suffix_len = last.parse::<u64>().ok()?;
+        if suffix_len == 0 || total_len == 0 {
+            return Some(None);
+        }
+        return Some(Some(ByteRange {
+            start: total_len.saturating_sub(suffix_len),
+            end: total_len - 1,
+        }));
+    }
+
+    let start = first.parse::<u64>().ok()?;
+    let end = if last.is_empty() {
+        None
+    } else {
+        Some(last.parse::<u64>().ok()?)
+    };
+
+    if let Some(end) = end {
+        if end < start {
+            return None;
+        }
+    }
+
+    if start >= total_len {
+        return Some(None);
+    }
+
+    let end = end.map(|end| end.min(total_len - 1)).unwrap_or(total_len - 1);
+    Some(Some(ByteRange { start, end }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partial(ranges: &[(u64, u64)]) -> RangeDecision {
+        RangeDecision::Partial(ranges.iter().map(|&(start, end)| ByteRange { start, end }).collect())
+    }
+
+    #[test]
+    fn ranges_keep_the_order_of_the_header() {
+        assert_eq!(
+            RangeDecision::from_header("bytes=500-599, 0-99", 1000),
+            partial(&[(500, 599), (0, 99)])
+        );
+        assert_eq!(RangeDecision::from_header("bytes=-100", 1000), partial(&[(900, 999)]));
+        assert_eq!(
+            RangeDecision::from_header("bytes=900-2000", 1000),
+            partial(&[(900, 999)])
+        );
+        assert_eq!(RangeDecision::from_header("bytes=2000-, 0-0", 1000), partial(&[(0, 0)]));
+    }
+
+    #[test]
+    fn overlapping_and_adjacent_ranges_are_coalesced() {
+        let repeated = format!("bytes={}", vec!["0-"; 1000].join(","));
+        assert_eq!(RangeDecision::from_header(&repeated, 1000), partial(&[(0, 999)]));
+
+        assert_eq!(
+            RangeDecision::from_header("bytes=0-9, 10-19", 1000),
+            partial(&[(0, 19)])
+        );
+        assert_eq!(
+            RangeDecision::from_header("bytes=500-599, 0-9, 550-700, -1", 1000),
+            partial(&[(500, 700), (0, 9), (999, 999)])
+        );
+        // A range bridging two earlier ones merges all of them in place of the first.
+        assert_eq!(
+            RangeDecision::from_header("bytes=100-199, 0-9, 300-399, 150-350", 1000),
+            partial(&[(100, 399), (0, 9)])
+        );
+    }
+
+    #[test]
+    fn too_many_ranges_get_the_full_representation() {
+        let specs = (0..MAX_RANGES as u64)
+            .map(|i| format!("{}-{}", i * 10, i * 10 + 1))
+            .collect::<Vec<_>>();
+        let decision = RangeDecision::from_header(&format!("bytes={}", specs.join(",")), 1000);
+        assert!(matches!(decision, RangeDecision::Partial(ref ranges) if ranges.len() == MAX_RANGES));
+
+        let specs = (0..=MAX_RANGES as u64)
+            .map(|i| format!("{}-{}", i * 10, i * 10 + 1))
+            .collect::<Vec<_>>();
+        let decision = RangeDecision::from_header(&format!("bytes={}", specs.join(",")), 1000);
+        assert_eq!(decision, RangeDecision::Full);
+    }
+
+    #[test]
+    fn empty_and_malformed_headers_get_the_full_representation() {
+        for value in [
+            "bytes=",
+            "bytes= , ,",
+            "items=0-1",
+            "bytes=5-1",
+            "bytes=a-b",
+            "bytes=0-1,x",
+        ] {
+            assert_eq!(
+                RangeDecision::from_header(value, 1000),
+                RangeDecision::Full,
+                "{}",
+                value
+            );
+        }
+        assert_eq!(RangeDecision::from_header_value(None, 1000), RangeDecision::Full);
+    }
+
+    #[test]
+    fn unsatisfiable_ranges_get_a_416() {
+        let decision = RangeDecision::from_header("bytes=1000-, -0", 1000);
+        assert_eq!(decision, RangeDecision::NotSatisfiable);
+        assert_eq!(decision.content_range(1000).unwrap(), "bytes */1000");
+        assert_eq!(RangeDecision::from_header("bytes=0-", 0), RangeDecision::NotSatisfiable);
+    }
+}
@@ -0,0 +1,229 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use crate::stats::Stats;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io;
+
+/// A registry of the live bodies, so a debug or admin endpoint can show what's currently streaming and why a
+/// connection looks stuck.
+///
+/// Bodies are added via [StreamBody::registered](./struct.StreamBody.html#method.registered) and removed once they
+/// are dropped. The handle is cheap to clone and all the clones share the same bodies.
+///
+/// # Examples
+///
+/// ```no_run
+/// use stream_body::{Registry, StreamBody};
+///
+/// let registry = Registry::new();
+///
+/// let body = StreamBody::from("hello").registered(&registry, "GET /hello");
+///
+/// for body in registry.snapshot() {
+///     println!("#{} {} ({}): {} bytes, {:?}", body.id, body.label, body.source, body.bytes_sent, body.state);
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    inner: Arc<RegistryInner>,
+}
+
+#[derive(Debug, Default)]
+struct RegistryInner {
+    next_id: AtomicU64,
+    bodies: Mutex<BTreeMap<u64, Arc<Mutex<Entry>>>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    label: String,
+    source: &'static str,
+    created_at: Instant,
+    stats: Stats,
+    // When the body started waiting for its source, while it does.
+    pending_since: Option<Instant>,
+    // When the body was polled last, i.e. since when it waits for its consumer.
+    last_poll_at: Option<Instant>,
+    ended: bool,
+}
+
+/// The state of a live body, see [Registry::snapshot](./struct.Registry.html#method.snapshot).
+#[derive(Debug, Clone)]
+pub struct BodySnapshot {
+    /// The id of the body, unique within its registry.
+    pub id: u64,
+    /// The label the body was registered with.
+    pub label: String,
+    /// Where the data comes from, e.g. `bytes`, `channel`, `reader` or `file`.
+    pub source: &'static str,
+    /// The time since the creation of the body.
+    pub age: Duration,
+    /// The number of emitted bytes.
+    pub bytes_sent: u64,
+    /// The number of emitted chunks.
+    pub chunks: u64,
+    /// How many times the body had to wait for its source after the first chunk.
+    pub stalls: u64,
+    /// What the body is currently doing.
+    pub state: BodyState,
+}
+
+/// What a live body is currently doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyState {
+    /// The body wasn't polled yet, e.g. the response wasn't sent yet.
+    NotStarted,
+    /// The body waits for its source to provide data, for the given time.
+    WaitingForSource(Duration),
+    /// The body waits to be polled again, i.e. for the client or the connection to take more data, for the given
+    /// time.
+    WaitingForConsumer(Duration),
+    /// The body reached its end or failed, but is still held by its consumer.
+    Ended,
+}
+
+impl Registry {
+    /// Creates an empty registry.
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Returns the number of live bodies.
+    pub fn len(&self) -> usize {
+        self.bodies().len()
+    }
+
+    /// Returns whether there are no live bodies.
+    pub fn is_empty(&self) -> bool {
+        self.bodies().is_empty()
+    }
+
+    /// Returns the state of all the live bodies, ordered by their registration.
+    pub fn snapshot(&self) -> Vec<BodySnapshot> {
+        let entries = self
+            .bodies()
+            .iter()
+            .map(|(&id, entry)| (id, Arc::clone(entry)))
+            .collect::<Vec<_>>();
+
+        let now = Instant::now();
+        entries
+            .into_iter()
+            .map(|(id, entry)| {
+                let entry = entry.lock().unwrap_or_else(|err| err.into_inner());
+
+                let state = if entry.ended {
+                    BodyState::Ended
+                } else if let Some(pending_since) = entry.pending_since {
+                    BodyState::WaitingForSource(now.duration_since(pending_since))
+                } else if let Some(last_poll_at) = entry.last_poll_at {
+                    BodyState::WaitingForConsumer(now.duration_since(last_poll_at))
+                } else {
+                    BodyState::NotStarted
+                };
+
+                BodySnapshot {
+                    id,
+                    label: entry.label.clone(),
+                    source: entry.source,
+                    age: now.duration_since(entry.created_at),
+                    bytes_sent: entry.stats.bytes,
+                    chunks: entry.stats.chunks,
+                    stalls: entry.stats.stalls,
+                    state,
+                }
+            })
+            .collect()
+    }
+
+    fn bodies(&self) -> MutexGuard<'_, BTreeMap<u64, Arc<Mutex<Entry>>>> {
+        self.inner.bodies.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl StreamBody {
+    /// Adds the body to a [Registry](./struct.Registry.html) of live bodies under the given label, e.g. the request
+    /// method and path. The body is removed from the registry once it's dropped.
+    pub fn registered<L: Into<String>>(self, registry: &Registry, label: L) -> StreamBody {
+        let id = registry.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let entry = Arc::new(Mutex::new(Entry {
+            label: label.into(),
+            source: self.source,
+            created_at: self.created_at,
+            stats: Stats::new(),
+            pending_since: None,
+            last_poll_at: None,
+            ended: false,
+        }));
+        registry.bodies().insert(id, Arc::clone(&entry));
+
+        self.wrap_with(|inner| Registered {
+            inner,
+            registry: registry.clone(),
+            id,
+            entry,
+        })
+    }
+}
+
+struct Registered {
+    inner: StreamBody,
+    registry: Registry,
+    id: u64,
+    entry: Arc<Mutex<Entry>>,
+}
+
+impl Body for Registered {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let poll_status = Pin::new(&mut self.inner).poll_data(cx);
+
+        let now = Instant::now();
+        let mut entry = self.entry.lock().unwrap_or_else(|err| err.into_inner());
+        entry.stats.record(&poll_status);
+        entry.last_poll_at = Some(now);
+        match poll_status {
+            Poll::Ready(Some(Ok(_))) => entry.pending_since = None,
+            Poll::Ready(_) => {
+                entry.pending_since = None;
+                entry.ended = true;
+            }
+            Poll::Pending => {
+                entry.pending_since.get_or_insert(now);
+            }
+        }
+        drop(entry);
+
+        poll_status
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for Registered {
+    fn drop(&mut self) {
+        self.registry.bodies().remove(&self.id);
+    }
+}
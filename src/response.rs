@@ -0,0 +1,32 @@
+use crate::body::StreamBody;
+use http::header::CONTENT_TYPE;
+use http::{HeaderValue, Response};
+
+impl StreamBody {
+    /// Creates a `200 OK` response with the body and its advisory [headers](#method.headers), e.g. the ones declared
+    /// via [StreamBodyBuilder::header](./struct.StreamBodyBuilder.html#method.header).
+    pub fn into_response(mut self) -> Response<StreamBody> {
+        let headers = std::mem::take(&mut self.headers);
+        let mut res = Response::new(self);
+        *res.headers_mut() = headers;
+        res
+    }
+
+    /// Creates a `200 OK` response with the body, its advisory [headers](#method.headers) and the given
+    /// `Content-Type` header, for handlers which don't need anything else from `Response::builder`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use http::HeaderValue;
+    /// use stream_body::StreamBody;
+    ///
+    /// let (writer, body) = StreamBody::channel();
+    /// let res = body.into_response_with_content_type(HeaderValue::from_static("text/csv"));
+    /// ```
+    pub fn into_response_with_content_type(self, content_type: HeaderValue) -> Response<StreamBody> {
+        let mut res = self.into_response();
+        res.headers_mut().insert(CONTENT_TYPE, content_type);
+        res
+    }
+}
@@ -0,0 +1,201 @@
+use std::fmt;
+use std::fs::Metadata;
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+use tokio::io;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// An opaque token which lets an interrupted download resume safely.
+///
+/// The token carries the byte offset the client has already received together with a hash of a validator, e.g.
+/// an `ETag` or the file's size and modification time. A token is only accepted if the validator still matches,
+/// so a download never resumes over a changed source. The hash detects changes, it doesn't authenticate the token.
+///
+/// The validated offset is meant to be used by seeking the source before creating the body, e.g. the file the token
+/// was validated against or via [from_file_range](./struct.StreamBody.html#method.from_file_range).
+/// [skip](./struct.StreamBody.html#method.skip) reads and discards the data before the offset, so it only suits the
+/// sources which can't seek, e.g. generated ones.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::io::SeekFrom;
+/// use stream_body::{ResumeToken, StreamBody};
+/// use tokio::fs::File;
+/// use tokio::io::AsyncSeekExt;
+///
+/// # async fn run(token: &str) -> std::io::Result<()> {
+/// let mut f = File::open("large-file").await?;
+/// let metadata = f.metadata().await?;
+///
+/// let offset = token
+///     .parse::<ResumeToken>()
+///     .ok()
+///     .and_then(|token| token.validate_metadata(&metadata))
+///     .unwrap_or(0);
+///
+/// f.seek(SeekFrom::Start(offset)).await?;
+/// let body = StreamBody::from_reader(f);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResumeToken {
+    offset: u64,
+    validator: u64,
+}
+
+impl ResumeToken {
+    /// Creates a token for the given offset and validator bytes, e.g. an `ETag` value.
+    pub fn new(offset: u64, validator: &[u8]) -> ResumeToken {
+        ResumeToken {
+            offset,
+            validator: hash(validator),
+        }
+    }
+
+    /// Creates a token for the given offset using the file's size and modification time as the validator.
+    pub fn from_metadata(offset: u64, metadata: &Metadata) -> ResumeToken {
+        ResumeToken::new(offset, &metadata_validator(metadata))
+    }
+
+    /// Returns the offset stored in the token without validating it.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the offset to resume from if the validator still matches the one the token was created with.
+    pub fn validate(&self, validator: &[u8]) -> Option<u64> {
+        if self.validator == hash(validator) {
+            Some(self.offset)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the offset to resume from if the file's size and modification time are unchanged and the offset is
+    /// within the file.
+    pub fn validate_metadata(&self, metadata: &Metadata) -> Option<u64> {
+        self.validate(&metadata_validator(metadata))
+            .filter(|offset| *offset <= metadata.len())
+    }
+}
+
+impl fmt::Display for ResumeToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}{:016x}", self.offset, self.validator)
+    }
+}
+
+impl FromStr for ResumeToken {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<ResumeToken, io::Error> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: ResumeToken: Invalid resume token: {}", env!("CARGO_PKG_NAME"), s),
+            )
+        };
+
+        // `from_str_radix` would accept a sign.
+        if s.len() != 32 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+
+        let offset = u64::from_str_radix(&s[..16], 16).map_err(|_| invalid())?;
+        let validator = u64::from_str_radix(&s[16..], 16).map_err(|_| invalid())?;
+
+        Ok(ResumeToken { offset, validator })
+    }
+}
+
+fn metadata_validator(metadata: &Metadata) -> Vec<u8> {
+    let mut validator = metadata.len().to_be_bytes().to_vec();
+    if let Some(modified) = metadata.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()) {
+        validator.extend_from_slice(&modified.as_nanos().to_be_bytes());
+    }
+    validator
+}
+
+// FNV-1a, which is stable across platforms and compiler versions unlike the std hashers.
+fn hash(data: &[u8]) -> u64 {
+    data.iter()
+        .fold(FNV_OFFSET_BASIS, |h, b| (h ^ u64::from(*b)).wrapping_mul(FNV_PRIME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_round_trip_through_their_string_form() {
+        let token = ResumeToken::new(1234, b"\"etag\"");
+        let encoded = token.to_string();
+        assert_eq!(encoded.len(), 32);
+        assert!(encoded.starts_with("00000000000004d2"));
+        assert_eq!(encoded.parse::<ResumeToken>().unwrap(), token);
+
+        let token = ResumeToken::new(u64::MAX, b"");
+        assert_eq!(token.to_string().parse::<ResumeToken>().unwrap(), token);
+        assert_eq!(token.to_string().to_uppercase().parse::<ResumeToken>().unwrap(), token);
+    }
+
+    #[test]
+    fn tokens_only_validate_against_their_validator() {
+        let token = ResumeToken::new(1234, b"\"v1\"");
+        assert_eq!(token.offset(), 1234);
+        assert_eq!(token.validate(b"\"v1\""), Some(1234));
+        assert_eq!(token.validate(b"\"v2\""), None);
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        let valid = ResumeToken::new(1, b"etag").to_string();
+        let signed = format!("+{}", &valid[1..]);
+        let non_ascii = format!("é{}", &valid[2..]);
+        for token in [
+            &valid[1..],
+            &format!("{}0", valid),
+            "",
+            &signed,
+            &non_ascii,
+            &valid.replace('0', "g"),
+        ] {
+            let err = token.parse::<ResumeToken>().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{}", token);
+        }
+    }
+
+    #[test]
+    fn metadata_tokens_check_the_file_and_the_offset() {
+        let path = std::env::temp_dir().join(format!("stream-body-resume-{}", std::process::id()));
+        std::fs::write(&path, b"hello world").unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+
+        assert_eq!(
+            ResumeToken::from_metadata(5, &metadata).validate_metadata(&metadata),
+            Some(5)
+        );
+        assert_eq!(
+            ResumeToken::from_metadata(11, &metadata).validate_metadata(&metadata),
+            Some(11)
+        );
+        // An offset past the end can't come from this file.
+        assert_eq!(
+            ResumeToken::from_metadata(12, &metadata).validate_metadata(&metadata),
+            None
+        );
+
+        std::fs::write(&path, b"hello world, again").unwrap();
+        let changed = std::fs::metadata(&path).unwrap();
+        assert_eq!(
+            ResumeToken::from_metadata(5, &metadata).validate_metadata(&changed),
+            None
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,206 @@
+use crate::body::{size_hint_with_held, StreamBody};
+use crate::data::StreamData;
+use bytes::{Buf, Bytes};
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use tokio::io;
+
+/// The last emitted chunks of a body created via
+/// [StreamBody::with_retry_buffer](./struct.StreamBody.html#method.with_retry_buffer), which lets a retried stream
+/// resume from its retry point instead of restarting the producer, e.g. after an HTTP/2 `RST_STREAM`.
+///
+/// When the body is dropped before its end, its source is kept by the buffer, so [resume_at](#method.resume_at) can
+/// create a body replaying the buffered data from the given offset and then continuing with the source.
+///
+/// # Examples
+///
+/// ```no_run
+/// use stream_body::StreamBody;
+///
+/// let (writer, body) = StreamBody::channel();
+/// let (retry, body) = body.with_retry_buffer(16);
+///
+/// // The stream was reset after the client received 64 KiB, the retried stream continues from there.
+/// drop(body);
+/// if let Some(body) = retry.resume_at(64 * 1024) {
+///     // Serve `body` on the retried stream.
+/// }
+/// ```
+#[derive(Clone)]
+pub struct RetryBuffer {
+    shared: Arc<Mutex<RetryState>>,
+}
+
+struct RetryState {
+    // The buffered chunks and their offsets, oldest first.
+    chunks: VecDeque<(u64, Bytes)>,
+    max_chunks: usize,
+    // The number of bytes emitted so far, i.e. the offset following the last chunk.
+    emitted: u64,
+    // The source of a body dropped before its end.
+    detached: Option<StreamBody>,
+}
+
+impl RetryBuffer {
+    /// Drops the buffered chunks which end at or before `offset`, e.g. once the peer acknowledged them.
+    pub fn acknowledge(&self, offset: u64) {
+        let mut state = self.lock();
+        while let Some(&(start, ref chunk)) = state.chunks.front() {
+            if start + chunk.len() as u64 > offset {
+                break;
+            }
+            state.chunks.pop_front();
+        }
+    }
+
+    /// Returns the range of offsets a body can be resumed at, from the oldest buffered byte to the end of the emitted
+    /// data.
+    pub fn buffered_range(&self) -> (u64, u64) {
+        let state = self.lock();
+        let start = state.chunks.front().map(|&(start, _)| start).unwrap_or(state.emitted);
+        (start, state.emitted)
+    }
+
+    /// Creates a body emitting the data from `offset`, i.e. the buffered data and then the rest of the source, which
+    /// keeps using this buffer.
+    ///
+    /// It returns `None` if the previous body isn't dropped yet, ended, or if `offset` is out of the
+    /// [buffered range](#method.buffered_range).
+    pub fn resume_at(&self, offset: u64) -> Option<StreamBody> {
+        let mut state = self.lock();
+        let (start, end) = {
+            let start = state.chunks.front().map(|&(start, _)| start).unwrap_or(state.emitted);
+            (start, state.emitted)
+        };
+        if offset < start || offset > end {
+            return None;
+        }
+        let inner = state.detached.take()?;
+
+        let replay = state
+            .chunks
+            .iter()
+            .filter(|&&(start, ref chunk)| start + chunk.len() as u64 > offset)
+            .map(|&(start, ref chunk)| chunk.slice(offset.saturating_sub(start) as usize..))
+            .collect();
+
+        Some(inner.wrap_with(|inner| Retrying {
+            inner: Some(inner),
+            shared: Arc::clone(&self.shared),
+            replay,
+            reached_eof: false,
+        }))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, RetryState> {
+        self.shared.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl StreamBody {
+    /// Keeps copies of the last `max_chunks` emitted chunks until they're acknowledged, so a retried stream can resume
+    /// from its retry point via the returned [RetryBuffer](./struct.RetryBuffer.html).
+    ///
+    /// The chunks are copied, so the buffer holds at most `max_chunks` times the chunk size of the source. The oldest
+    /// chunks are dropped once the buffer is full, even if they aren't acknowledged.
+    pub fn with_retry_buffer(self, max_chunks: usize) -> (RetryBuffer, StreamBody) {
+        let buffer = RetryBuffer {
+            shared: Arc::new(Mutex::new(RetryState {
+                chunks: VecDeque::with_capacity(max_chunks),
+                max_chunks,
+                emitted: 0,
+                detached: None,
+            })),
+        };
+
+        let shared = Arc::clone(&buffer.shared);
+        let body = self.wrap_with(|inner| Retrying {
+            inner: Some(inner),
+            shared,
+            replay: VecDeque::new(),
+            reached_eof: false,
+        });
+        (buffer, body)
+    }
+}
+
+struct Retrying {
+    inner: Option<StreamBody>,
+    shared: Arc<Mutex<RetryState>>,
+    // The buffered data replayed before the source is polled again.
+    replay: VecDeque<Bytes>,
+    reached_eof: bool,
+}
+
+impl Drop for Retrying {
+    fn drop(&mut self) {
+        if self.reached_eof {
+            return;
+        }
+
+        // The source is handed over to the buffer for a retried stream.
+        let mut state = self.shared.lock().unwrap_or_else(|err| err.into_inner());
+        state.detached = self.inner.take();
+    }
+}
+
+impl Body for Retrying {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        if let Some(chunk) = self.replay.pop_front() {
+            return Poll::Ready(Some(Ok(StreamData::from_bytes(chunk))));
+        }
+
+        let poll_status = match self.inner {
+            Some(ref mut inner) => Pin::new(inner).poll_data(cx),
+            None => return Poll::Ready(None),
+        };
+
+        match poll_status {
+            Poll::Ready(Some(Ok(ref data))) => {
+                let chunk = Bytes::copy_from_slice(data.bytes());
+                let mut state = self.shared.lock().unwrap_or_else(|err| err.into_inner());
+                let offset = state.emitted;
+                state.emitted += chunk.len() as u64;
+                if state.max_chunks > 0 {
+                    if state.chunks.len() == state.max_chunks {
+                        state.chunks.pop_front();
+                    }
+                    state.chunks.push_back((offset, chunk));
+                }
+            }
+            Poll::Ready(None) => self.reached_eof = true,
+            _ => {}
+        }
+        poll_status
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        match self.inner {
+            Some(ref mut inner) => Pin::new(inner).poll_trailers(cx),
+            None => Poll::Ready(Ok(None)),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.replay.is_empty() && self.inner.as_ref().map(|inner| inner.is_end_stream()).unwrap_or(true)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let replay = self.replay.iter().map(|chunk| chunk.len() as u64).sum::<u64>();
+        let inner_hint = match self.inner {
+            Some(ref inner) => inner.size_hint(),
+            None => SizeHint::with_exact(0),
+        };
+        size_hint_with_held(inner_hint, replay)
+    }
+}
@@ -0,0 +1,152 @@
+use crate::body::{size_hint_with_held, StreamBody};
+use crate::data::StreamData;
+use bytes::{Buf, Bytes};
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io;
+
+const FILL_SIZE: usize = 64 * 1024;
+
+// The shared chunk used for runs of zeros, which is by far the most common run, e.g. the holes of sparse files.
+static ZEROS: [u8; FILL_SIZE] = [0; FILL_SIZE];
+
+impl StreamBody {
+    /// Sends runs of at least `min_run` identical bytes, e.g. the zeros of sparse disk images, as preallocated
+    /// shared chunks instead of as parts of the source chunks.
+    ///
+    /// Runs covering a whole source chunk or found at its start or end are detected. A source chunk consisting of a
+    /// single run is released right away, so the source can refill its buffer while the run is sent, and in
+    /// [safe mode](./index.html#safe-mode) no copy of the run is made. The emitted bytes are the same as without the
+    /// transform. A `min_run` below 64 bytes is treated as 64.
+    pub fn suppress_runs(self, min_run: usize) -> StreamBody {
+        self.wrap_with(|inner| SuppressRuns {
+            inner,
+            min_run: min_run.max(64),
+            pending_run: None,
+            pending_chunk: None,
+            fill: None,
+        })
+    }
+}
+
+struct SuppressRuns {
+    inner: StreamBody,
+    min_run: usize,
+    // A run which is still to be emitted, before the pending chunk.
+    pending_run: Option<(u8, usize)>,
+    // The rest of a source chunk following a leading run.
+    pending_chunk: Option<StreamData>,
+    // The shared chunk for runs of the last non-zero byte.
+    fill: Option<(u8, Bytes)>,
+}
+
+impl SuppressRuns {
+    fn run_chunk(&mut self, byte: u8, len: usize) -> StreamData {
+        if byte == 0 {
+            return StreamData::from_bytes(Bytes::from_static(&ZEROS[..len]));
+        }
+
+        let fill = match self.fill {
+            Some((fill_byte, ref fill)) if fill_byte == byte => fill,
+            _ => &self.fill.insert((byte, Bytes::from(vec![byte; FILL_SIZE]))).1,
+        };
+        StreamData::from_bytes(fill.slice(..len))
+    }
+
+    // Emits the next part of the pending run.
+    fn next_run_chunk(&mut self) -> Option<StreamData> {
+        let (byte, len) = self.pending_run.take()?;
+
+        let chunk_len = len.min(FILL_SIZE);
+        if len > chunk_len {
+            self.pending_run = Some((byte, len - chunk_len));
+        }
+        Some(self.run_chunk(byte, chunk_len))
+    }
+
+    fn process(&mut self, mut data: StreamData) -> StreamData {
+        let bytes = data.bytes();
+        let len = bytes.len();
+
+        let lead = run_len(bytes.iter());
+        if lead >= self.min_run {
+            let byte = bytes[0];
+            self.pending_run = Some((byte, lead));
+
+            // Dropping a chunk consisting of a single run lets the source continue right away.
+            if lead < len {
+                data.advance(lead);
+                self.pending_chunk = Some(data);
+            }
+            return self.next_run_chunk().expect("pending run is set");
+        }
+
+        self.process_tail(data)
+    }
+
+    fn process_tail(&mut self, mut data: StreamData) -> StreamData {
+        let bytes = data.bytes();
+        let len = bytes.len();
+
+        let trail = run_len(bytes.iter().rev());
+        if trail >= self.min_run && trail < len {
+            self.pending_run = Some((bytes[len - 1], trail));
+            data.truncate(len - trail);
+        }
+        data
+    }
+}
+
+fn run_len<'a, I: Iterator<Item = &'a u8>>(mut bytes: I) -> usize {
+    match bytes.next() {
+        Some(first) => 1 + bytes.take_while(|b| *b == first).count(),
+        None => 0,
+    }
+}
+
+impl Body for SuppressRuns {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        if let Some(data) = self.next_run_chunk() {
+            return Poll::Ready(Some(Ok(data)));
+        }
+
+        if let Some(data) = self.pending_chunk.take() {
+            let data = self.process_tail(data);
+            return Poll::Ready(Some(Ok(data)));
+        }
+
+        match Pin::new(&mut self.inner).poll_data(cx) {
+            Poll::Ready(Some(Ok(data))) => {
+                let data = self.process(data);
+                Poll::Ready(Some(Ok(data)))
+            }
+            poll_status => poll_status,
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.pending_run.is_none() && self.pending_chunk.is_none() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let pending = self.pending_run.map(|(_, len)| len as u64).unwrap_or(0)
+            + self
+                .pending_chunk
+                .as_ref()
+                .map(|data| data.remaining() as u64)
+                .unwrap_or(0);
+        size_hint_with_held(self.inner.size_hint(), pending)
+    }
+}
@@ -0,0 +1,229 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use crate::priority::Priority;
+use bytes::Buf;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io;
+use tokio::time::{self, Delay, Instant};
+
+// The credit a body may accumulate while idle, in seconds of its current share.
+const MAX_BURST_SECS: f64 = 0.1;
+
+/// A shared egress scheduler which splits a total rate among the bodies attached to it in proportion to their
+/// weights.
+///
+/// Each attached body is paced to `rate * weight / total_weight` bytes per second, where `total_weight` is the sum
+/// of the weights of all attached bodies which haven't finished yet. So one giant download can't starve many small
+/// ones on the same process, and the shares grow as other bodies finish.
+///
+/// A body pays for a chunk after emitting it, i.e. a chunk is always emitted once the body's previous debt is paid
+/// off, however large the chunk is.
+///
+/// # Examples
+///
+/// ```no_run
+/// use stream_body::{Scheduler, StreamBody};
+///
+/// // Share 10 MB/s among all the downloads.
+/// let scheduler = Scheduler::new(10 * 1024 * 1024);
+///
+/// let large = StreamBody::from(vec![0_u8; 64 * 1024 * 1024]).scheduled(&scheduler, 1);
+/// let small = StreamBody::from("small").scheduled(&scheduler, 4);
+/// ```
+#[derive(Clone)]
+pub struct Scheduler {
+    state: Arc<Mutex<SchedulerState>>,
+}
+
+struct SchedulerState {
+    rate: u64,
+    total_weight: u64,
+    active_bodies: usize,
+}
+
+impl Scheduler {
+    /// Creates a scheduler sharing `bytes_per_sec` among its bodies.
+    pub fn new(bytes_per_sec: u64) -> Scheduler {
+        Scheduler {
+            state: Arc::new(Mutex::new(SchedulerState {
+                rate: bytes_per_sec,
+                total_weight: 0,
+                active_bodies: 0,
+            })),
+        }
+    }
+
+    /// Changes the total rate shared among the bodies.
+    pub fn set_rate(&self, bytes_per_sec: u64) {
+        self.lock().rate = bytes_per_sec;
+    }
+
+    /// Returns the total rate shared among the bodies.
+    pub fn rate(&self) -> u64 {
+        self.lock().rate
+    }
+
+    /// Returns the number of attached bodies which haven't finished yet.
+    pub fn active_bodies(&self) -> usize {
+        self.lock().active_bodies
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SchedulerState> {
+        // The state only holds counters which are always left consistent, so a poisoned lock is still usable.
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(err) => err.into_inner(),
+        }
+    }
+
+    fn register(&self, weight: u64) {
+        let mut state = self.lock();
+        state.total_weight += weight;
+        state.active_bodies += 1;
+    }
+
+    fn unregister(&self, weight: u64) {
+        let mut state = self.lock();
+        state.total_weight -= weight;
+        state.active_bodies -= 1;
+    }
+
+    // The current rate of a body with the given weight in bytes per second.
+    fn share(&self, weight: u64) -> f64 {
+        let state = self.lock();
+        if state.total_weight == 0 {
+            return state.rate as f64;
+        }
+        state.rate as f64 * weight as f64 / state.total_weight as f64
+    }
+}
+
+impl StreamBody {
+    /// Attaches the body to a shared [Scheduler](./struct.Scheduler.html) with the given weight, so its chunks are
+    /// paced according to its share of the scheduler's rate.
+    ///
+    /// A weight of zero is treated as one. [Interactive](./enum.Priority.html#variant.Interactive) bodies are
+    /// returned unchanged, so they are neither paced nor reduce the shares of the other bodies.
+    pub fn scheduled(self, scheduler: &Scheduler, weight: u32) -> StreamBody {
+        if self.priority == Priority::Interactive {
+            return self;
+        }
+
+        let weight = u64::from(weight.max(1));
+        scheduler.register(weight);
+
+        self.wrap_with(|inner| Scheduled {
+            inner,
+            scheduler: scheduler.clone(),
+            weight,
+            registered: true,
+            credit: 0.0,
+            last_refill: None,
+            delay: None,
+        })
+    }
+}
+
+struct Scheduled {
+    inner: StreamBody,
+    scheduler: Scheduler,
+    weight: u64,
+    registered: bool,
+    credit: f64,
+    last_refill: Option<Instant>,
+    delay: Option<Delay>,
+}
+
+impl Scheduled {
+    fn refill(&mut self, share: f64) {
+        let now = Instant::now();
+        if let Some(last_refill) = self.last_refill {
+            let elapsed = now.duration_since(last_refill).as_secs_f64();
+            self.credit = (self.credit + elapsed * share).min(share * MAX_BURST_SECS);
+        }
+        self.last_refill = Some(now);
+    }
+
+    fn unregister(&mut self) {
+        if self.registered {
+            self.registered = false;
+            self.scheduler.unregister(self.weight);
+        }
+    }
+
+    // Waits until the debt of the previously emitted chunks is paid off.
+    fn poll_credit(&mut self, cx: &mut Context) -> Poll<()> {
+        loop {
+            let share = self.scheduler.share(self.weight);
+            self.refill(share);
+
+            if self.credit >= 0.0 || share <= 0.0 {
+                self.delay = None;
+                return Poll::Ready(());
+            }
+
+            let deadline = Instant::now() + Duration::from_secs_f64(-self.credit / share);
+            let delay = match self.delay {
+                Some(ref mut delay) => {
+                    delay.reset(deadline);
+                    delay
+                }
+                None => self.delay.get_or_insert_with(|| time::delay_until(deadline)),
+            };
+
+            if Pin::new(delay).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+impl Body for Scheduled {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        if self.poll_credit(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        match Pin::new(&mut self.inner).poll_data(cx) {
+            Poll::Ready(Some(Ok(data))) => {
+                self.credit -= data.remaining() as f64;
+                Poll::Ready(Some(Ok(data)))
+            }
+            Poll::Ready(result) => {
+                self.unregister();
+                Poll::Ready(result)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for Scheduled {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
@@ -0,0 +1,187 @@
+use crate::body::StreamBody;
+use crate::buffer::ReusableBuf;
+use crate::data::StreamData;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::io::SeekFrom;
+use std::marker::Unpin;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{ready, Context, Poll, Waker};
+use tokio::io::{self, AsyncRead, AsyncSeek};
+
+const DEFAULT_BUF_SIZE: usize = 8 * 1024;
+
+/// A handle to a body created via [StreamBody::from_seekable](./struct.StreamBody.html#method.from_seekable), which
+/// restarts its emission at another offset of the source.
+#[derive(Clone)]
+pub struct RestartHandle {
+    shared: Arc<Mutex<Shared>>,
+}
+
+#[derive(Default)]
+struct Shared {
+    restart_at: Option<u64>,
+    // The offset of the source following the last emitted chunk.
+    position: u64,
+    waker: Option<Waker>,
+}
+
+impl RestartHandle {
+    /// Makes the body seek its source to `offset` before reading its next chunk, e.g. to emit a part again after an
+    /// internal retry, without reopening the source.
+    ///
+    /// The chunks already emitted are not taken back, so the higher layer is responsible for the consistency of the
+    /// response. A restart requested after the body ended has no effect, as the server doesn't poll it anymore.
+    pub fn restart_at(&self, offset: u64) {
+        let waker = {
+            let mut shared = self.lock();
+            shared.restart_at = Some(offset);
+            shared.waker.take()
+        };
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Returns the offset of the source following the last emitted chunk.
+    pub fn position(&self) -> u64 {
+        self.lock().position
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Shared> {
+        self.shared.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl StreamBody {
+    /// Creates a body reading an [AsyncRead](https://docs.rs/tokio/0.2.16/tokio/io/trait.AsyncRead.html) +
+    /// [AsyncSeek](https://docs.rs/tokio/0.2.16/tokio/io/trait.AsyncSeek.html) source on the task polling the body,
+    /// from its current position, along with a [RestartHandle](./struct.RestartHandle.html) which restarts the
+    /// emission at another offset of the retained source.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use stream_body::StreamBody;
+    ///
+    /// # async fn run() -> std::io::Result<()> {
+    /// let file = tokio::fs::File::open("large-file").await?;
+    /// let (restart, body) = StreamBody::from_seekable(file);
+    ///
+    /// // Later, e.g. once the upper layer detected a corrupted part:
+    /// restart.restart_at(1024 * 1024);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_seekable<R: AsyncRead + AsyncSeek + Unpin + Send + 'static>(reader: R) -> (RestartHandle, StreamBody) {
+        let handle = RestartHandle {
+            shared: Arc::new(Mutex::new(Shared::default())),
+        };
+
+        let mut body = StreamBody::wrap(Seekable {
+            reader,
+            shared: Arc::clone(&handle.shared),
+            buf: ReusableBuf::new(DEFAULT_BUF_SIZE),
+            // Seeking to the current position tells the initial offset.
+            seek: Some((SeekFrom::Current(0), false)),
+            reached_eof: false,
+            failed: false,
+        });
+        body.source = "seekable";
+        (handle, body)
+    }
+}
+
+struct Seekable<R> {
+    reader: R,
+    shared: Arc<Mutex<Shared>>,
+    buf: ReusableBuf,
+    // The target of a seek in progress and whether it was started.
+    seek: Option<(SeekFrom, bool)>,
+    reached_eof: bool,
+    failed: bool,
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> Seekable<R> {
+    fn poll_read_chunk(&mut self, cx: &mut Context) -> Poll<Option<io::Result<StreamData>>> {
+        if self.failed {
+            return Poll::Ready(None);
+        }
+
+        {
+            let mut shared = self.shared.lock().unwrap_or_else(|err| err.into_inner());
+            if let Some(offset) = shared.restart_at.take() {
+                self.seek = Some((SeekFrom::Start(offset), false));
+            }
+            shared.waker = Some(cx.waker().clone());
+        }
+
+        if let Some((target, ref mut started)) = self.seek {
+            if !*started {
+                ready!(Pin::new(&mut self.reader).start_seek(cx, target))?;
+                *started = true;
+            }
+            let position = ready!(Pin::new(&mut self.reader).poll_complete(cx))?;
+            self.seek = None;
+            self.reached_eof = false;
+            self.set_position(position);
+        }
+
+        if self.reached_eof {
+            return Poll::Ready(None);
+        }
+
+        let buf = self.buf.prepare();
+        let read_count = ready!(Pin::new(&mut self.reader).poll_read(cx, buf))?;
+        if read_count == 0 {
+            self.reached_eof = true;
+            return Poll::Ready(None);
+        }
+
+        let position = self.position() + read_count as u64;
+        self.set_position(position);
+        Poll::Ready(Some(Ok(StreamData::from_bytes(self.buf.split(read_count)))))
+    }
+
+    fn position(&self) -> u64 {
+        self.shared.lock().unwrap_or_else(|err| err.into_inner()).position
+    }
+
+    fn set_position(&self, position: u64) {
+        self.shared.lock().unwrap_or_else(|err| err.into_inner()).position = position;
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> Body for Seekable<R> {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let poll_status = self.poll_read_chunk(cx);
+        if let Poll::Ready(Some(Err(_))) = poll_status {
+            // The body is fused after an error, the source isn't polled again, even after a restart.
+            self.failed = true;
+        }
+        poll_status
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Poll::Ready(Ok(None))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.reached_eof || self.failed
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        if self.is_end_stream() {
+            return SizeHint::with_exact(0);
+        }
+        SizeHint::default()
+    }
+}
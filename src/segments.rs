@@ -0,0 +1,169 @@
+use crate::body::StreamBody;
+use crate::range::ByteRange;
+use async_pipe::PipeWriter;
+use std::fs::File;
+use std::sync::Arc;
+use tokio::io::{self, AsyncWriteExt};
+use tokio::task;
+
+const SEGMENT_BUF_SIZE: usize = 64 * 1024;
+
+/// A file shared by many bodies, each streaming a byte range of it, e.g. to serve disjoint segments on parallel
+/// requests or HTTP/2 streams.
+///
+/// The bodies read with positional reads on blocking threads, so they share the single file handle without seeking
+/// it and without interfering with each other.
+///
+/// # Examples
+///
+/// ```no_run
+/// use stream_body::SharedFile;
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let file = SharedFile::new(std::fs::File::open("disk.img")?)?;
+///
+/// for (range, body) in file.segments(4) {
+///     println!("segment {}-{}", range.start, range.end);
+///     // Serve `body` on its own request.
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SharedFile {
+    pub(crate) file: Arc<File>,
+    len: u64,
+}
+
+impl SharedFile {
+    /// Wraps a file, its length is taken from its metadata.
+    pub fn new(file: File) -> io::Result<SharedFile> {
+        let len = file.metadata()?.len();
+
+        Ok(SharedFile {
+            file: Arc::new(file),
+            len,
+        })
+    }
+
+    /// Returns the length of the file.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns whether the file is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Creates a body streaming the given range of the file. The range is clamped to the length of the file.
+    pub fn range_body(&self, range: ByteRange) -> StreamBody {
+        if range.start >= self.len {
+            return StreamBody::empty();
+        }
+
+        let end = range.end.min(self.len - 1);
+        let len = end - range.start + 1;
+
+        let (w, mut body) = StreamBody::channel_with_len(len);
+        body.source = "file";
+        tokio::spawn(copy_range(Arc::clone(&self.file), range.start, len, w));
+
+        body
+    }
+
+    /// Splits the file into `count` disjoint ranges of about the same length, and creates a body streaming each of
+    /// them. Fewer segments are returned for files shorter than `count` bytes.
+    pub fn segments(&self, count: usize) -> Vec<(ByteRange, StreamBody)> {
+        let count = (count.max(1) as u64).min(self.len);
+        if count == 0 {
+            return Vec::new();
+        }
+
+        let segment_len = self.len / count;
+        let remainder = self.len % count;
+
+        let mut start = 0;
+        (0..count)
+            .map(|idx| {
+                // The first segments take one more byte each until the remainder is used up.
+                let len = segment_len + u64::from(idx < remainder);
+                let range = ByteRange {
+                    start,
+                    end: start + len - 1,
+                };
+                start += len;
+
+                (range, self.range_body(range))
+            })
+            .collect()
+    }
+}
+
+async fn copy_range(file: Arc<File>, offset: u64, len: u64, mut w: PipeWriter) {
+    if let Err(err) = try_copy_range(file, offset, len, &mut w).await {
+        log::error!(
+            "{}: SharedFile: Something went wrong while streaming a range of the file: {}",
+            env!("CARGO_PKG_NAME"),
+            err
+        )
+    }
+}
+
+async fn try_copy_range(file: Arc<File>, mut offset: u64, len: u64, w: &mut PipeWriter) -> io::Result<()> {
+    let end = offset + len;
+    let mut buf = vec![0_u8; SEGMENT_BUF_SIZE.min(len as usize)];
+
+    while offset < end {
+        let to_read = ((end - offset) as usize).min(buf.len());
+
+        let (read_buf, result) = read_on_blocking_thread(Arc::clone(&file), buf, to_read, offset).await;
+        buf = read_buf;
+
+        let read_count = result?;
+        if read_count == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "{}: SharedFile: The file is shorter than expected",
+                    env!("CARGO_PKG_NAME")
+                ),
+            ));
+        }
+
+        w.write_all(&buf[..read_count]).await?;
+        offset += read_count as u64;
+    }
+
+    Ok(())
+}
+
+/// Reads up to `len` bytes at `offset` into the start of `buf`. The buffer is moved to the blocking thread and back,
+/// so the callers reuse it.
+pub(crate) async fn read_on_blocking_thread(
+    file: Arc<File>,
+    mut buf: Vec<u8>,
+    len: usize,
+    offset: u64,
+) -> (Vec<u8>, io::Result<usize>) {
+    let result = task::spawn_blocking(move || {
+        let result = read_at(&file, &mut buf[..len], offset);
+        (buf, result)
+    })
+    .await;
+
+    match result {
+        Ok(result) => result,
+        Err(err) => (Vec::new(), Err(io::Error::new(io::ErrorKind::Other, err))),
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
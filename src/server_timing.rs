@@ -0,0 +1,89 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use crate::stats::Stats;
+use http::header::HeaderName;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io;
+
+impl StreamBody {
+    /// Appends a [Server-Timing](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Server-Timing) trailer
+    /// describing how the body streamed, so clients and browser devtools can inspect the streaming performance.
+    ///
+    /// The trailer contains the time to the first chunk (`ttfb`), the total streaming time (`stream`), the number of
+    /// emitted bytes (`bytes`) and how many times the body had to wait for its source after the first chunk
+    /// (`stalls`), e.g. `ttfb;dur=1.2, stream;dur=350.4, bytes;desc="1048576", stalls;desc="3"`. The timings are
+    /// measured from the moment this method is called. The trailers of the source are kept.
+    ///
+    /// Note that trailers only reach the client on protocols which support them, e.g. HTTP/2. Clients should also be
+    /// told about the trailer via the `Trailer: Server-Timing` response header.
+    pub fn with_server_timing(self) -> StreamBody {
+        self.wrap_with(|inner| ServerTiming {
+            inner,
+            stats: Stats::new(),
+            trailers_sent: false,
+        })
+    }
+}
+
+struct ServerTiming {
+    inner: StreamBody,
+    stats: Stats,
+    trailers_sent: bool,
+}
+
+impl ServerTiming {
+    fn header_value(&self) -> HeaderValue {
+        let mut metrics = Vec::with_capacity(4);
+        if let Some(ttfb) = self.stats.ttfb() {
+            metrics.push(format!("ttfb;dur={}", millis(ttfb)));
+        }
+        metrics.push(format!("stream;dur={}", millis(self.stats.duration())));
+        metrics.push(format!("bytes;desc=\"{}\"", self.stats.bytes));
+        metrics.push(format!("stalls;desc=\"{}\"", self.stats.stalls));
+
+        // The value only contains ASCII digits, letters and punctuation.
+        HeaderValue::from_str(&metrics.join(", ")).expect("valid Server-Timing header value")
+    }
+}
+
+fn millis(d: Duration) -> String {
+    format!("{:.1}", d.as_secs_f64() * 1000.0)
+}
+
+impl Body for ServerTiming {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let poll_status = Pin::new(&mut self.inner).poll_data(cx);
+        self.stats.record(&poll_status);
+        poll_status
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        let mut trailers = match Pin::new(&mut self.inner).poll_trailers(cx) {
+            Poll::Ready(Ok(trailers)) => trailers.unwrap_or_default(),
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Pending => return Poll::Pending,
+        };
+
+        trailers.append(HeaderName::from_static("server-timing"), self.header_value());
+        self.trailers_sent = true;
+        Poll::Ready(Ok(Some(trailers)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.trailers_sent
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,289 @@
+use crate::body::{size_hint_with_held, StreamBody};
+use crate::data::StreamData;
+use async_pipe::PipeWriter;
+use bytes::{Buf, BytesMut};
+use http::header::HeaderName;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+#[cfg(feature = "timeout")]
+use std::future::Future;
+use std::marker::Unpin;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+#[cfg(feature = "timeout")]
+use std::time::Duration;
+use tokio::io::{self, AsyncRead};
+#[cfg(feature = "timeout")]
+use tokio::time::{self, Delay};
+
+/// A builder of bodies whose chunks are shaped between a minimum and a maximum size, and which carry default trailers
+/// and advisory headers, created via [StreamBody::builder](./struct.StreamBody.html#method.builder).
+///
+/// Chunks smaller than the minimum are coalesced, as long as the source has data ready or, with the `timeout`
+/// feature, until the [coalesce timeout](#method.coalesce_timeout) passes, which cuts the chunk-framing overhead of
+/// readers returning tiny reads. Chunks larger than the maximum are split without copying.
+///
+/// # Examples
+///
+/// ```no_run
+/// use http::header::ACCEPT_RANGES;
+/// use http::HeaderValue;
+/// use stream_body::StreamBody;
+///
+/// let (writer, body) = StreamBody::builder()
+///     .min_chunk(4096)
+///     .max_chunk(64 * 1024)
+///     .header(ACCEPT_RANGES, HeaderValue::from_static("bytes"))
+///     .channel();
+/// let res = body.into_response();
+/// ```
+#[derive(Debug, Clone)]
+pub struct StreamBodyBuilder {
+    min_chunk: usize,
+    max_chunk: Option<usize>,
+    #[cfg(feature = "timeout")]
+    coalesce_timeout: Option<Duration>,
+    headers: HeaderMap<HeaderValue>,
+    trailers: HeaderMap<HeaderValue>,
+}
+
+impl StreamBody {
+    /// Creates a [StreamBodyBuilder](./struct.StreamBodyBuilder.html) to tune the chunk sizes of a body and declare
+    /// its default trailers and headers.
+    pub fn builder() -> StreamBodyBuilder {
+        StreamBodyBuilder {
+            min_chunk: 0,
+            max_chunk: None,
+            #[cfg(feature = "timeout")]
+            coalesce_timeout: None,
+            headers: HeaderMap::new(),
+            trailers: HeaderMap::new(),
+        }
+    }
+}
+
+impl StreamBodyBuilder {
+    /// Coalesces the chunks smaller than `len` bytes with the following ones. By default chunks aren't coalesced.
+    pub fn min_chunk(mut self, len: usize) -> StreamBodyBuilder {
+        self.min_chunk = len;
+        self
+    }
+
+    /// Splits the chunks larger than `len` bytes. By default chunks aren't split.
+    pub fn max_chunk(mut self, len: usize) -> StreamBodyBuilder {
+        self.max_chunk = Some(len.max(1));
+        self
+    }
+
+    /// Waits up to `timeout` for more data while the coalesced chunk is smaller than the minimum, measured from its
+    /// first byte, instead of emitting it as soon as the source would wait. It requires the `timeout` feature.
+    #[cfg(feature = "timeout")]
+    pub fn coalesce_timeout(mut self, timeout: Duration) -> StreamBodyBuilder {
+        self.coalesce_timeout = Some(timeout);
+        self
+    }
+
+    /// Adds a trailer sent at the end of the body, e.g. a static `grpc-status`. The trailers of the body itself, e.g.
+    /// sent via a [TrailerSender](./struct.TrailerSender.html), take precedence over the ones of the same name.
+    pub fn trailer(mut self, name: HeaderName, value: HeaderValue) -> StreamBodyBuilder {
+        self.trailers.append(name, value);
+        self
+    }
+
+    /// Adds an advisory response header, e.g. `Accept-Ranges`, exposed via
+    /// [StreamBody::headers](./struct.StreamBody.html#method.headers) and set on the response by
+    /// [StreamBody::into_response](./struct.StreamBody.html#method.into_response).
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> StreamBodyBuilder {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Creates a body stream with an associated writer half, like
+    /// [StreamBody::channel](./struct.StreamBody.html#method.channel).
+    ///
+    /// The internal buffer holds the maximum chunk size, if any.
+    pub fn channel(self) -> (PipeWriter, StreamBody) {
+        let (w, body) = match self.max_chunk {
+            Some(max) => StreamBody::channel_with_capacity(max),
+            None => StreamBody::channel(),
+        };
+        (w, self.shape(body))
+    }
+
+    /// Converts an `AsyncRead` to a body, like [StreamBody::from_reader](./struct.StreamBody.html#method.from_reader).
+    #[cfg(feature = "tokio-rt")]
+    pub fn from_reader<R: AsyncRead + Unpin + Send + 'static>(self, r: R) -> StreamBody {
+        self.shape(StreamBody::from_reader(r))
+    }
+
+    /// Converts an `AsyncRead` to a body read on the task polling it, like
+    /// [StreamBody::from_reader_inline](./struct.StreamBody.html#method.from_reader_inline).
+    pub fn from_reader_inline<R: AsyncRead + Unpin + Send + 'static>(self, r: R) -> StreamBody {
+        self.shape(StreamBody::from_reader_inline(r))
+    }
+
+    /// Applies the options of the builder to an existing body.
+    pub fn shape(self, body: StreamBody) -> StreamBody {
+        let mut body = self.shape_chunks(body);
+        if !self.trailers.is_empty() {
+            body = body.with_default_trailers(self.trailers);
+        }
+        for (name, value) in self.headers.iter() {
+            body.headers.append(name, value.clone());
+        }
+        body
+    }
+
+    fn shape_chunks(&self, body: StreamBody) -> StreamBody {
+        // Without a minimum nothing is coalesced, so the coalesce timeout doesn't matter either.
+        if self.min_chunk == 0 && self.max_chunk.is_none() {
+            return body;
+        }
+
+        let max = self.max_chunk.unwrap_or(usize::MAX);
+        let min = self.min_chunk.min(max);
+
+        body.wrap_with(|inner| Shaped {
+            inner,
+            min,
+            max,
+            pending: None,
+            buf: BytesMut::new(),
+            reached_eof: false,
+            #[cfg(feature = "timeout")]
+            timeout: self.coalesce_timeout,
+            #[cfg(feature = "timeout")]
+            delay: None,
+        })
+    }
+}
+
+struct Shaped {
+    inner: StreamBody,
+    min: usize,
+    max: usize,
+    // A source chunk at least as large as the minimum, emitted without a copy, or split if larger than the maximum.
+    pending: Option<StreamData>,
+    // The coalesced source chunks smaller than the minimum.
+    buf: BytesMut,
+    reached_eof: bool,
+    #[cfg(feature = "timeout")]
+    timeout: Option<Duration>,
+    // The deadline of the coalesced chunk, started with its first byte.
+    #[cfg(feature = "timeout")]
+    delay: Option<Delay>,
+}
+
+impl Shaped {
+    fn take_buf(&mut self) -> Option<StreamData> {
+        #[cfg(feature = "timeout")]
+        {
+            self.delay = None;
+        }
+
+        if self.buf.is_empty() {
+            return None;
+        }
+        Some(StreamData::from_bytes(self.buf.split().freeze()))
+    }
+
+    /// Copies as much of `data` as fits in the coalesced chunk, the rest is left in `data`.
+    fn coalesce(&mut self, data: &mut StreamData) {
+        #[cfg(feature = "timeout")]
+        {
+            if let (true, Some(timeout)) = (self.buf.is_empty(), self.timeout) {
+                self.delay = Some(time::delay_for(timeout));
+            }
+        }
+
+        let len = data.remaining().min(self.max - self.buf.len());
+        self.buf.extend_from_slice(&data.bytes()[..len]);
+        data.advance(len);
+    }
+
+    /// Returns whether the coalesced chunk waits for more data while the source is pending.
+    fn poll_wait(&mut self, _cx: &mut Context) -> bool {
+        #[cfg(feature = "timeout")]
+        {
+            if let Some(ref mut delay) = self.delay {
+                return Pin::new(delay).poll(_cx).is_pending();
+            }
+        }
+        false
+    }
+}
+
+impl Body for Shaped {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let me = &mut *self;
+
+        loop {
+            if let Some(mut data) = me.pending.take() {
+                if data.remaining() > me.max {
+                    let part = data.split_to(me.max);
+                    me.pending = Some(data);
+                    return Poll::Ready(Some(Ok(part)));
+                }
+                if data.remaining() >= me.min {
+                    return Poll::Ready(Some(Ok(data)));
+                }
+                // The rest of a split chunk is coalesced with the next ones.
+                me.coalesce(&mut data);
+                continue;
+            }
+
+            if me.reached_eof {
+                return Poll::Ready(me.take_buf().map(Ok));
+            }
+
+            match Pin::new(&mut me.inner).poll_data(cx) {
+                Poll::Ready(Some(Ok(mut data))) => {
+                    if me.buf.is_empty() && data.remaining() >= me.min {
+                        // Large enough chunks are passed through or split, without a copy.
+                        me.pending = Some(data);
+                        continue;
+                    }
+
+                    me.coalesce(&mut data);
+                    if data.remaining() > 0 {
+                        me.pending = Some(data);
+                    } else {
+                        // Dropping the copied chunk lets the source continue.
+                        drop(data);
+                    }
+
+                    if me.buf.len() >= me.min || me.pending.is_some() {
+                        return Poll::Ready(me.take_buf().map(Ok));
+                    }
+                }
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                Poll::Ready(None) => me.reached_eof = true,
+                Poll::Pending => {
+                    if me.buf.is_empty() || me.poll_wait(cx) {
+                        return Poll::Pending;
+                    }
+                    return Poll::Ready(me.take_buf().map(Ok));
+                }
+            }
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.pending.is_none() && self.buf.is_empty() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let held = self.buf.len() as u64 + self.pending.as_ref().map(|data| data.remaining() as u64).unwrap_or(0);
+        size_hint_with_held(self.inner.size_hint(), held)
+    }
+}
@@ -0,0 +1,430 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use crate::range::ByteRange;
+use crate::segments::{self, SharedFile};
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::collections::{HashMap, VecDeque};
+use std::future::poll_fn;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll, Waker};
+use tokio::fs::File;
+use tokio::io;
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+const DEFAULT_WINDOW: usize = 1024 * 1024;
+
+/// Deduplicates the concurrent reads of the same file, e.g. a popular asset during a traffic spike: the bodies opened
+/// on the same path while a read of it is under way share it, so the file is read from the disk once and its chunks
+/// are fanned out to every body without copying. It requires the `shared-reads` feature.
+///
+/// The file is read as fast as the fastest body consumes it, and the last chunks read are kept in a window, 1 MiB by
+/// default, for the slower bodies. A body falling further behind than the window leaves the shared read and reads the
+/// rest of the file on its own, from the handle opened by the shared read, so a stalled client never holds the others
+/// back and still reads the same file if the path was replaced meanwhile. A body can only join a read whose
+/// first chunk is still in the window, otherwise it starts a new read which later bodies join.
+///
+/// The reads are keyed by the path as given, so the same file should always be opened via the same path. The handle
+/// is cheap to clone and meant to be shared by all the request handlers.
+///
+/// # Examples
+///
+/// ```no_run
+/// use hyper::Response;
+/// use stream_body::{SharedReads, StreamBody};
+///
+/// # async fn run(reads: &SharedReads) -> std::io::Result<Response<StreamBody>> {
+/// let body = reads.open("assets/video.mp4").await?;
+/// Ok(Response::new(body))
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct SharedReads {
+    reads: Arc<Mutex<HashMap<PathBuf, Weak<Mutex<SharedRead>>>>>,
+    window: usize,
+    bytes_read: Arc<AtomicU64>,
+}
+
+impl Default for SharedReads {
+    fn default() -> SharedReads {
+        SharedReads::new()
+    }
+}
+
+impl SharedReads {
+    /// Creates a handle keeping a 1 MiB window of every shared read.
+    pub fn new() -> SharedReads {
+        SharedReads::with_window(DEFAULT_WINDOW)
+    }
+
+    /// Creates a handle keeping `window` bytes of every shared read for the slower bodies, at least one chunk is
+    /// always kept.
+    pub fn with_window(window: usize) -> SharedReads {
+        SharedReads {
+            reads: Arc::new(Mutex::new(HashMap::new())),
+            window,
+            bytes_read: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Opens a body streaming the file at `path`, joining the read of it under way if any, with an exact size hint.
+    pub async fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<StreamBody> {
+        let path = path.as_ref();
+
+        if let Some(read) = self.join(path) {
+            return Ok(SharedReads::body(read));
+        }
+
+        // The file is read with positional reads, so the bodies falling behind read the same handle on their own.
+        let file = SharedFile::new(File::open(path).await?.into_std().await)?;
+        let len = file.len();
+
+        let read = Arc::new(Mutex::new(SharedRead {
+            file: file.clone(),
+            len,
+            chunks: VecDeque::new(),
+            base: 0,
+            read: 0,
+            error: None,
+            wanted: false,
+            subscribers: 1,
+            closed: false,
+            reader: None,
+            waiters: Vec::new(),
+        }));
+
+        {
+            let mut reads = self.reads.lock().unwrap_or_else(|err| err.into_inner());
+            reads.retain(|_, read| read.strong_count() > 0);
+            reads.insert(path.to_owned(), Arc::downgrade(&read));
+        }
+        tokio::spawn(read_file(
+            Arc::clone(&file.file),
+            Arc::clone(&read),
+            self.window,
+            Arc::clone(&self.bytes_read),
+        ));
+
+        Ok(SharedReads::body(read))
+    }
+
+    /// Returns the bytes the shared reads read from the disk so far, which excludes the bodies reading on their own
+    /// after falling behind.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::SeqCst)
+    }
+
+    fn join(&self, path: &Path) -> Option<Arc<Mutex<SharedRead>>> {
+        let reads = self.reads.lock().unwrap_or_else(|err| err.into_inner());
+        let read = reads.get(path)?.upgrade()?;
+
+        {
+            let mut shared = read.lock().unwrap_or_else(|err| err.into_inner());
+            if shared.closed || shared.base > 0 || shared.error.is_some() {
+                return None;
+            }
+            shared.subscribers += 1;
+        }
+        Some(read)
+    }
+
+    fn body(read: Arc<Mutex<SharedRead>>) -> StreamBody {
+        let len = read.lock().unwrap_or_else(|err| err.into_inner()).len;
+        let mut body = StreamBody::wrap(SharedReadBody {
+            read: Some(read),
+            len,
+            offset: 0,
+            own: None,
+        });
+        body.source = "file";
+        body
+    }
+}
+
+struct SharedRead {
+    file: SharedFile,
+    len: u64,
+    // The chunks in the window, the first one starting at `base`.
+    chunks: VecDeque<Bytes>,
+    base: u64,
+    // The bytes read so far, the last chunk ending there.
+    read: u64,
+    error: Option<(io::ErrorKind, String)>,
+    // Whether a body waits for the next chunk.
+    wanted: bool,
+    subscribers: usize,
+    // Whether the reader stopped because every body left, bodies can't join anymore.
+    closed: bool,
+    reader: Option<Waker>,
+    waiters: Vec<Waker>,
+}
+
+impl SharedRead {
+    fn wake_waiters(&mut self) {
+        for waker in self.waiters.drain(..) {
+            waker.wake();
+        }
+    }
+
+    fn leave(&mut self) {
+        self.subscribers -= 1;
+        if let Some(waker) = self.reader.take() {
+            waker.wake();
+        }
+    }
+}
+
+async fn read_file(file: Arc<std::fs::File>, read: Arc<Mutex<SharedRead>>, window: usize, bytes_read: Arc<AtomicU64>) {
+    let len = read.lock().unwrap_or_else(|err| err.into_inner()).len;
+    let mut offset = 0;
+
+    while offset < len {
+        // Reads on demand, so the read follows the fastest body.
+        let wanted = poll_fn(|cx| {
+            let mut shared = read.lock().unwrap_or_else(|err| err.into_inner());
+            if shared.subscribers == 0 {
+                shared.closed = true;
+                return Poll::Ready(false);
+            }
+            if shared.wanted {
+                return Poll::Ready(true);
+            }
+            shared.reader = Some(cx.waker().clone());
+            Poll::Pending
+        })
+        .await;
+        if !wanted {
+            return;
+        }
+
+        let to_read = READ_CHUNK_SIZE.min((len - offset) as usize);
+        let (mut buf, result) =
+            segments::read_on_blocking_thread(Arc::clone(&file), vec![0_u8; to_read], to_read, offset).await;
+        let result = match result {
+            Ok(0) => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "{}: SharedReads: The file is shorter than expected",
+                    env!("CARGO_PKG_NAME")
+                ),
+            )),
+            result => result,
+        };
+
+        let mut shared = read.lock().unwrap_or_else(|err| err.into_inner());
+        match result {
+            Ok(read_count) => {
+                buf.truncate(read_count);
+                offset += read_count as u64;
+                bytes_read.fetch_add(read_count as u64, Ordering::SeqCst);
+
+                shared.chunks.push_back(Bytes::from(buf));
+                shared.read = offset;
+                while shared.read - shared.base > window as u64 && shared.chunks.len() > 1 {
+                    if let Some(chunk) = shared.chunks.pop_front() {
+                        shared.base += chunk.len() as u64;
+                    }
+                }
+            }
+            Err(err) => {
+                log::error!(
+                    "{}: SharedReads: Something went wrong while reading the file: {}",
+                    env!("CARGO_PKG_NAME"),
+                    err
+                );
+                shared.error = Some((err.kind(), err.to_string()));
+                shared.closed = true;
+            }
+        }
+        shared.wanted = false;
+        shared.wake_waiters();
+
+        if shared.closed {
+            return;
+        }
+    }
+}
+
+struct SharedReadBody {
+    // The shared read, until the body reaches its end or falls behind the window.
+    read: Option<Arc<Mutex<SharedRead>>>,
+    len: u64,
+    offset: u64,
+    // The body reading the rest of the file on its own after falling behind.
+    own: Option<Box<StreamBody>>,
+}
+
+impl SharedReadBody {
+    fn leave(&mut self) {
+        if let Some(read) = self.read.take() {
+            read.lock().unwrap_or_else(|err| err.into_inner()).leave();
+        }
+    }
+}
+
+impl Drop for SharedReadBody {
+    fn drop(&mut self) {
+        self.leave();
+    }
+}
+
+impl Body for SharedReadBody {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let me = &mut *self;
+
+        loop {
+            if let Some(ref mut body) = me.own {
+                return Pin::new(&mut **body).poll_data(cx);
+            }
+
+            let read = match me.read {
+                Some(ref read) => Arc::clone(read),
+                None => return Poll::Ready(None),
+            };
+            let mut shared = read.lock().unwrap_or_else(|err| err.into_inner());
+
+            if me.offset < shared.base {
+                // Fell behind the window, the rest of the file is read separately.
+                let range = ByteRange {
+                    start: me.offset,
+                    end: shared.len - 1,
+                };
+                let body = shared.file.range_body(range);
+                drop(shared);
+                me.leave();
+                me.own = Some(Box::new(body));
+                continue;
+            }
+
+            if me.offset < shared.read {
+                let mut start = shared.base;
+                for chunk in shared.chunks.iter() {
+                    let end = start + chunk.len() as u64;
+                    if me.offset < end {
+                        let data = chunk.slice((me.offset - start) as usize..);
+                        me.offset = end;
+                        return Poll::Ready(Some(Ok(StreamData::from_bytes(data))));
+                    }
+                    start = end;
+                }
+            }
+
+            if let Some((kind, ref message)) = shared.error {
+                let err = io::Error::new(kind, message.clone());
+                drop(shared);
+                me.leave();
+                return Poll::Ready(Some(Err(err)));
+            }
+            if me.offset >= shared.len {
+                drop(shared);
+                me.leave();
+                return Poll::Ready(None);
+            }
+
+            shared.wanted = true;
+            // A body polled again before the next chunk is registered once.
+            if !shared.waiters.iter().any(|waker| waker.will_wake(cx.waker())) {
+                shared.waiters.push(cx.waker().clone());
+            }
+            if let Some(waker) = shared.reader.take() {
+                waker.wake();
+            }
+            return Poll::Pending;
+        }
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Poll::Ready(Ok(None))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self.own {
+            Some(ref body) => body.is_end_stream(),
+            None => self.read.is_none(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self.own {
+            Some(ref body) => body.size_hint(),
+            None => SizeHint::with_exact(self.len.saturating_sub(self.offset)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Buf;
+    use futures_util::task::noop_waker;
+
+    fn temp_file(name: &str, content: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("stream-body-{}-{}", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    async fn collect(mut body: StreamBody) -> Vec<u8> {
+        let mut data = Vec::new();
+        while let Some(chunk) = body.data().await {
+            data.extend_from_slice(chunk.unwrap().bytes());
+        }
+        data
+    }
+
+    #[tokio::test]
+    async fn bodies_falling_behind_read_the_file_opened_by_the_shared_read() {
+        let content = (0..256 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let path = temp_file("shared-reads-replaced", &content);
+
+        let reads = SharedReads::with_window(64 * 1024);
+        let first = reads.open(&path).await.unwrap();
+        let mut stalled = reads.open(&path).await.unwrap();
+        let stalled_chunk = stalled.data().await.unwrap().unwrap();
+        assert_eq!(collect(first).await, content);
+
+        // The file is replaced before the stalled body reads the rest.
+        std::fs::remove_file(&path).unwrap();
+        std::fs::write(&path, b"replaced").unwrap();
+
+        let mut data = stalled_chunk.bytes().to_vec();
+        drop(stalled_chunk);
+        data.extend(collect(stalled).await);
+        assert_eq!(data, content);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn bodies_polled_again_register_their_waker_once() {
+        let path = temp_file("shared-reads-wakers", b"hello");
+
+        let reads = SharedReads::new();
+        let read = reads.open(&path).await.unwrap();
+        let read_handle = reads.join(&path).unwrap();
+        let mut body = SharedReads::body(Arc::clone(&read_handle));
+
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        // The read task doesn't run before this task yields.
+        for _ in 0..3 {
+            assert!(Pin::new(&mut body).poll_data(&mut cx).is_pending());
+        }
+        assert_eq!(read_handle.lock().unwrap().waiters.len(), 1);
+
+        drop(body);
+        assert_eq!(collect(read).await, b"hello");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,459 @@
+use crate::body::StreamBody;
+use crate::buffer::ReusableBuf;
+use crate::data::StreamData;
+use crate::flow::CapacitySignal;
+use crate::pool::{BodyPool, Parts};
+use crate::state::State;
+use crate::tuning::Tuner;
+use crate::watchdog::{self, AbandonAction};
+use async_pipe::PipeReader;
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::marker::Unpin;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::io::{self, AsyncRead};
+
+const DEFAULT_BUF_SIZE: usize = 8 * 1024;
+
+/// The outcome of a [Source::poll_fill](./trait.Source.html#tymethod.poll_fill) call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fill {
+    /// The given number of bytes was written at the start of the buffer, which is then emitted without copying.
+    Filled(usize),
+    /// The source hands over a chunk it already owns instead of filling the buffer, e.g. a slice of a memory-mapped
+    /// file or a chunk received from another body. Empty chunks are skipped.
+    ///
+    /// Like the data written into the buffer, the source is only polled again once the chunk is consumed.
+    Chunk(Bytes),
+    /// The source reached its end.
+    Eof,
+}
+
+/// A producer of data polled by a body on the task polling the body, created via
+/// [StreamBody::from_source](./struct.StreamBody.html#method.from_source).
+///
+/// The bodies created from bytes and via a channel are driven the same way, so every source gets the buffer reuse,
+/// the rings of buffers, the wake strategies and the abandoned chunk watchdog of the crate.
+///
+/// It's implemented for every [AsyncRead](https://docs.rs/tokio/0.2.16/tokio/io/trait.AsyncRead.html), which covers
+/// files, sockets and the reader half of channels, and for streams of `Bytes` via
+/// [StreamSource](./struct.StreamSource.html) with the `futures` feature.
+///
+/// # Examples
+///
+/// ```no_run
+/// use bytes::Bytes;
+/// use std::pin::Pin;
+/// use std::task::{Context, Poll};
+/// use stream_body::{Fill, Source, StreamBody};
+///
+/// // Hands out the frames of a ring of pre-rendered frames without copying them.
+/// struct Frames {
+///     frames: Vec<Bytes>,
+///     next: usize,
+/// }
+///
+/// impl Source for Frames {
+///     fn poll_fill(mut self: Pin<&mut Self>, _cx: &mut Context, _buf: &mut [u8]) -> Poll<std::io::Result<Fill>> {
+///         let frame = self.frames[self.next % self.frames.len()].clone();
+///         self.next += 1;
+///         Poll::Ready(Ok(Fill::Chunk(frame)))
+///     }
+/// }
+///
+/// let body = StreamBody::from_source(Frames { frames: vec![Bytes::from("frame")], next: 0 });
+/// ```
+pub trait Source {
+    /// Attempts to produce the next data, either by writing it into `buf` or by handing over an owned chunk.
+    ///
+    /// Like `AsyncRead::poll_read`, it returns `Poll::Pending` and arranges for the task to be woken up when no data
+    /// is available yet.
+    fn poll_fill(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<Fill>>;
+}
+
+impl<R: AsyncRead + ?Sized> Source for R {
+    fn poll_fill(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<Fill>> {
+        match self.poll_read(cx, buf) {
+            Poll::Ready(Ok(0)) => Poll::Ready(Ok(Fill::Eof)),
+            Poll::Ready(Ok(read_count)) => Poll::Ready(Ok(Fill::Filled(read_count))),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl StreamBody {
+    /// Creates a body which polls the provided [Source](./trait.Source.html) on the task polling the body.
+    ///
+    /// The data written by the source lands in the reusable buffer of the body, so it's emitted without copying just
+    /// like with a channel body, while the chunks handed over by the source are emitted as they are.
+    pub fn from_source<S: Source + Unpin + Send + 'static>(source: S) -> StreamBody {
+        StreamBody::new_source(Box::new(source), None)
+    }
+
+    /// Same as [from_source](#method.from_source), but for sources whose total length is known upfront.
+    ///
+    /// The body has an exact [size_hint](#method.size_hint) and reports its end as soon as `len` bytes are produced,
+    /// the rest of a chunk exceeding `len` being dropped. It fails with an `UnexpectedEof` error if the source ends
+    /// early.
+    pub fn from_source_with_len<S: Source + Unpin + Send + 'static>(source: S, len: u64) -> StreamBody {
+        StreamBody::new_source(Box::new(source), Some(len))
+    }
+
+    pub(crate) fn new_source(source: Box<dyn Source + Unpin + Send>, len: Option<u64>) -> StreamBody {
+        let mut inner = SourceBody::new(
+            Feed::Source(source),
+            ReusableBuf::new(DEFAULT_BUF_SIZE),
+            Arc::new(Mutex::new(State::new())),
+        );
+        if let Some(len) = len {
+            inner = inner.with_exact_len(len);
+        }
+        StreamBody::from_source_body(inner, "source")
+    }
+}
+
+/// Where a `SourceBody` gets its data from.
+pub(crate) enum Feed {
+    /// The data of a body created from bytes, handed over as a single chunk.
+    Bytes(Option<Bytes>),
+    /// The reader half of a channel.
+    Pipe {
+        reader: PipeReader,
+        eof_on_empty_write: bool,
+    },
+    /// A source provided via `from_source`.
+    Source(Box<dyn Source + Unpin + Send>),
+}
+
+impl Feed {
+    fn poll_fill(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<Fill>> {
+        match *self {
+            Feed::Bytes(ref mut data) => Poll::Ready(Ok(data.take().map_or(Fill::Eof, Fill::Chunk))),
+            Feed::Pipe {
+                ref mut reader,
+                eof_on_empty_write,
+            } => {
+                let mut saw_empty_read = false;
+                loop {
+                    return match Pin::new(&mut *reader).poll_fill(cx, buf) {
+                        // The pipe reports both a zero-length write and a closed writer as an empty read, but only a
+                        // closed writer keeps reporting it, so poll once more to tell them apart.
+                        Poll::Ready(Ok(Fill::Eof)) if !eof_on_empty_write && !saw_empty_read => {
+                            saw_empty_read = true;
+                            continue;
+                        }
+                        poll_status => poll_status,
+                    };
+                }
+            }
+            Feed::Source(ref mut source) => Pin::new(&mut **source).poll_fill(cx, buf),
+        }
+    }
+
+    /// Describes the feed in the error messages.
+    fn kind(&self) -> &'static str {
+        match *self {
+            Feed::Bytes(_) => "Once Data",
+            Feed::Pipe { .. } => "Channel Data",
+            Feed::Source(_) => "Source",
+        }
+    }
+}
+
+/// The body behind the bodies created from bytes, via a channel and from a [Source](./trait.Source.html), which reads
+/// its feed into a reusable buffer, or a ring of them, and waits for the emitted chunk to be consumed before reading
+/// again.
+pub(crate) struct SourceBody {
+    pub(crate) feed: Feed,
+    pub(crate) buf: ReusableBuf,
+    reached_eof: bool,
+    pub(crate) state: Arc<Mutex<State>>,
+    // The other buffers of the ring, which the body switches to while the chunk of the current one is in flight.
+    pub(crate) spare: Vec<Parts>,
+    pub(crate) pool: Option<BodyPool>,
+    pub(crate) remaining: Option<u64>,
+    // Whether the body ends once `remaining` reaches zero and fails if the feed ends before, otherwise the length only
+    // keeps the size hint accurate.
+    exact_len: bool,
+    pub(crate) abandon_action: Option<AbandonAction>,
+    // The signal bounding the size of the next read, see `with_capacity_signal`.
+    pub(crate) read_limit: Option<CapacitySignal>,
+    pub(crate) tuner: Option<Tuner>,
+}
+
+impl SourceBody {
+    pub(crate) fn new(feed: Feed, buf: ReusableBuf, state: Arc<Mutex<State>>) -> SourceBody {
+        SourceBody {
+            feed,
+            buf,
+            reached_eof: false,
+            state,
+            spare: Vec::new(),
+            pool: None,
+            remaining: None,
+            exact_len: false,
+            abandon_action: None,
+            read_limit: None,
+            tuner: None,
+        }
+    }
+
+    /// Makes the body end after `len` bytes.
+    pub(crate) fn with_exact_len(mut self, len: u64) -> SourceBody {
+        self.remaining = Some(len);
+        self.exact_len = true;
+        self.reached_eof = len == 0;
+        self
+    }
+
+    /// Returns whether the body reads from the reader half of a channel.
+    pub(crate) fn is_pipe(&self) -> bool {
+        matches!(self.feed, Feed::Pipe { .. })
+    }
+
+    /// Returns the data of a body created from bytes, which is empty once it was emitted.
+    pub(crate) fn in_memory(&self) -> Option<Bytes> {
+        match self.feed {
+            Feed::Bytes(ref data) => Some(data.clone().unwrap_or_default()),
+            _ => None,
+        }
+    }
+
+    /// Makes a free buffer of the ring the current one if the chunk of the current buffer is still in flight.
+    fn switch_to_free_buffer(&mut self) {
+        if self.spare.is_empty() || is_consumed(&self.state) {
+            return;
+        }
+
+        if let Some(free) = self.spare.iter_mut().find(|(_, state)| is_consumed(state)) {
+            std::mem::swap(&mut self.buf, &mut free.0);
+            std::mem::swap(&mut self.state, &mut free.1);
+        }
+    }
+
+    /// Feeds the auto-tuner, if any, with the consumed chunks and the stalls of the producer, then resizes the buffers
+    /// and the ring after its targets.
+    fn auto_tune(&mut self) {
+        let tuner = match self.tuner {
+            Some(ref mut tuner) => tuner,
+            None => return,
+        };
+
+        for state in std::iter::once(&self.state).chain(self.spare.iter().map(|(_, state)| state)) {
+            let drained = state.lock().ok().and_then(|mut state| state.drained.take());
+            if let Some((len, elapsed)) = drained {
+                tuner.record_drain(len, elapsed);
+            }
+        }
+
+        let stalled = !is_consumed(&self.state) && !self.spare.iter().any(|(_, state)| is_consumed(state));
+        match tuner.stalled_since {
+            None if stalled => tuner.stalled_since = Some(Instant::now()),
+            Some(since) if !stalled => {
+                tuner.record_stall(since.elapsed());
+                tuner.stalled_since = None;
+            }
+            _ => {}
+        }
+
+        let (capacity, chunks) = match tuner.targets() {
+            Some(targets) => targets,
+            None => return,
+        };
+
+        self.buf.set_capacity(capacity);
+        self.spare.iter_mut().for_each(|(buf, _)| buf.set_capacity(capacity));
+
+        let needs_wake = self.state.lock().ok().and_then(|state| state.needs_wake.clone());
+        while self.spare.len() + 1 < chunks {
+            let mut state = State::new();
+            state.needs_wake = needs_wake.clone();
+            self.spare
+                .push((ReusableBuf::new(capacity), Arc::new(Mutex::new(state))));
+        }
+        // Only the buffers whose chunk was consumed are released.
+        while self.spare.len() + 1 > chunks {
+            match self.spare.iter().position(|(_, state)| is_consumed(state)) {
+                Some(pos) => drop(self.spare.swap_remove(pos)),
+                None => break,
+            }
+        }
+    }
+
+    /// Wakes the task up once any of the spare buffers gets free.
+    fn register_spare_wakers(&self, cx: &mut Context) {
+        for (_, state) in self.spare.iter() {
+            if let Ok(mut state) = state.lock() {
+                if !state.is_current_stream_data_consumed {
+                    state.park(cx);
+                }
+            }
+        }
+    }
+
+    /// Ends the body once the chunks of all the spare buffers are consumed.
+    fn poll_spare_consumed(&self, cx: &mut Context) -> Poll<Option<Result<StreamData, io::Error>>> {
+        if self.spare.iter().all(|(_, state)| is_consumed(state)) {
+            return Poll::Ready(None);
+        }
+
+        self.register_spare_wakers(cx);
+        // A chunk may have been consumed between the check and the registration.
+        if self.spare.iter().all(|(_, state)| is_consumed(state)) {
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    }
+}
+
+impl Body for SourceBody {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let me = &mut *self;
+        me.auto_tune();
+        me.switch_to_free_buffer();
+
+        let mut state;
+        match me.state.lock() {
+            Ok(s) => state = s,
+            Err(err) => {
+                return Poll::Ready(Some(Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!(
+                        "{}: StreamBody [{}]: Failed to lock the stream state on poll data: {}",
+                        env!("CARGO_PKG_NAME"),
+                        me.feed.kind(),
+                        err
+                    ),
+                ))));
+            }
+        }
+        state.unpark();
+
+        if !state.is_current_stream_data_consumed {
+            state.park(cx);
+            me.register_spare_wakers(cx);
+            // A spare buffer may have got free before the registration.
+            if me.spare.iter().any(|(_, state)| is_consumed(state)) {
+                cx.waker().wake_by_ref();
+            }
+            return Poll::Pending;
+        }
+
+        if let Some(err) = watchdog::check_abandoned(&mut state, me.abandon_action, me.feed.kind()) {
+            return Poll::Ready(Some(Err(err)));
+        }
+
+        // The body only ends once no chunk points into the buffers anymore.
+        if me.reached_eof {
+            return me.poll_spare_consumed(cx);
+        }
+
+        loop {
+            let buf = me.buf.prepare();
+            let mut limit = match me.read_limit {
+                Some(ref signal) => signal().max(1).min(buf.len()),
+                None => buf.len(),
+            };
+            if let (true, Some(remaining)) = (me.exact_len, me.remaining) {
+                limit = remaining.min(limit as u64) as usize;
+            }
+
+            let mut chunk = match me.feed.poll_fill(cx, &mut buf[..limit]) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(Fill::Filled(0))) | Poll::Ready(Ok(Fill::Eof)) => {
+                    me.reached_eof = true;
+                    return match me.remaining {
+                        Some(remaining) if me.exact_len => Poll::Ready(Some(Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            format!(
+                                "{}: StreamBody [Source]: The source ended {} bytes before the announced length",
+                                env!("CARGO_PKG_NAME"),
+                                remaining
+                            ),
+                        )))),
+                        _ => me.poll_spare_consumed(cx),
+                    };
+                }
+                Poll::Ready(Ok(Fill::Filled(read_count))) => me.buf.split(read_count.min(limit)),
+                Poll::Ready(Ok(Fill::Chunk(chunk))) if chunk.is_empty() => continue,
+                Poll::Ready(Ok(Fill::Chunk(chunk))) => chunk,
+                Poll::Ready(Err(err)) => {
+                    // The body is fused after an error, the feed isn't polled again.
+                    me.reached_eof = true;
+                    return Poll::Ready(Some(Err(err)));
+                }
+            };
+
+            if let Some(ref mut remaining) = me.remaining {
+                if me.exact_len && chunk.len() as u64 > *remaining {
+                    chunk.truncate(*remaining as usize);
+                }
+                *remaining = remaining.saturating_sub(chunk.len() as u64);
+                if me.exact_len && *remaining == 0 {
+                    me.reached_eof = true;
+                }
+            }
+
+            state.is_current_stream_data_consumed = false;
+            if me.tuner.is_some() {
+                state.emitted = Some((Instant::now(), chunk.len()));
+            }
+            return Poll::Ready(Some(Ok(StreamData::new(chunk, Arc::clone(&me.state)))));
+        }
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Poll::Ready(Ok(None))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        if !self.reached_eof {
+            return false;
+        }
+
+        // With a watchdog the body waits to see how the last chunk was dropped.
+        self.abandon_action.is_none()
+            || self
+                .state
+                .lock()
+                .map(|state| state.is_current_stream_data_consumed && state.abandoned_bytes == 0)
+                .unwrap_or(false)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self.remaining {
+            _ if self.reached_eof => SizeHint::with_exact(0),
+            Some(remaining) => SizeHint::with_exact(remaining),
+            None => SizeHint::default(),
+        }
+    }
+}
+
+fn is_consumed(state: &Mutex<State>) -> bool {
+    state
+        .lock()
+        .map(|state| state.is_current_stream_data_consumed)
+        .unwrap_or(false)
+}
+
+impl Drop for SourceBody {
+    fn drop(&mut self) {
+        if let Some(ref pool) = self.pool {
+            pool.recycle(std::mem::take(&mut self.buf), &self.state);
+            for (buf, state) in self.spare.drain(..) {
+                pool.recycle(buf, &state);
+            }
+        }
+    }
+}
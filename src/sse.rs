@@ -0,0 +1,371 @@
+use http::HeaderMap;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::marker::Unpin;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{self, AsyncWrite, AsyncWriteExt};
+use tokio::time::{self, Instant};
+
+/// An event of a `text/event-stream` response, written by an [EventWriter](./struct.EventWriter.html).
+///
+/// # Examples
+///
+/// ```
+/// use stream_body::Event;
+///
+/// let event = Event::new("{\"price\": 42}").event("quote").id("1337");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    data: String,
+    event: Option<String>,
+    id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    /// Creates an event carrying `data`, which is split into several `data:` lines if it spans several lines.
+    pub fn new<D: Into<String>>(data: D) -> Event {
+        Event {
+            data: data.into(),
+            ..Event::default()
+        }
+    }
+
+    /// Sets the type of the event, i.e. its `event:` field, which the client listens to via `addEventListener`.
+    pub fn event<E: Into<String>>(mut self, event: E) -> Event {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// Sets the id of the event, which the client sends back in the `Last-Event-ID` header when it reconnects.
+    pub fn id<I: Into<String>>(mut self, id: I) -> Event {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Sets the reconnection delay of the client, i.e. the `retry:` field.
+    pub fn retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Appends the wire format of the event to `buf`, failing if a single-line field contains a line break.
+    fn encode(&self, buf: &mut Vec<u8>) -> io::Result<()> {
+        if let Some(ref event) = self.event {
+            encode_field(buf, "event", check_single_line("event", event)?);
+        }
+        if let Some(ref id) = self.id {
+            encode_field(buf, "id", check_single_line("id", id)?);
+        }
+        if let Some(retry) = self.retry {
+            encode_field(buf, "retry", &retry.as_millis().to_string());
+        }
+        for line in self.data.split("\r\n").flat_map(|line| line.split(['\r', '\n'])) {
+            encode_field(buf, "data", line);
+        }
+        buf.extend_from_slice(b"\n");
+        Ok(())
+    }
+}
+
+/// A store of the recent events of a stream, which lets [EventWriter::replay](./struct.EventWriter.html#method.replay)
+/// send a reconnecting client the events it missed.
+///
+/// [MemoryEventStore](./struct.MemoryEventStore.html) keeps the last events in memory, a store backed by a database
+/// or a log can be plugged in by implementing this trait.
+pub trait EventStore {
+    /// Returns the events following the event with the given id, oldest first, or `None` if the id is unknown, e.g.
+    /// because the event was already evicted.
+    fn events_after(&self, id: &str) -> Option<Vec<Event>>;
+}
+
+/// An [EventStore](./trait.EventStore.html) keeping the last `capacity` events in memory.
+///
+/// # Examples
+///
+/// ```
+/// use stream_body::{Event, EventStore, MemoryEventStore};
+///
+/// let store = MemoryEventStore::new(2);
+/// store.push(Event::new("a").id("1"));
+/// store.push(Event::new("b").id("2"));
+/// store.push(Event::new("c").id("3"));
+///
+/// assert_eq!(store.events_after("2").unwrap(), vec![Event::new("c").id("3")]);
+/// assert!(store.events_after("1").is_none());
+/// ```
+pub struct MemoryEventStore {
+    events: Mutex<VecDeque<Event>>,
+    capacity: usize,
+}
+
+impl MemoryEventStore {
+    /// Creates a store keeping up to `capacity` events.
+    pub fn new(capacity: usize) -> MemoryEventStore {
+        MemoryEventStore {
+            events: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    /// Appends an event, evicting the oldest one if the store is full. The events need an id to be replayed after.
+    pub fn push(&self, event: Event) {
+        let mut events = self.events.lock().unwrap_or_else(|err| err.into_inner());
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        if self.capacity > 0 {
+            events.push_back(event);
+        }
+    }
+}
+
+impl EventStore for MemoryEventStore {
+    fn events_after(&self, id: &str) -> Option<Vec<Event>> {
+        let events = self.events.lock().unwrap_or_else(|err| err.into_inner());
+        let pos = events.iter().rposition(|event| event.id.as_deref() == Some(id))?;
+        Some(events.iter().skip(pos + 1).cloned().collect())
+    }
+}
+
+/// The outcome of [EventWriter::replay](./struct.EventWriter.html#method.replay).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replay {
+    /// The request has no `Last-Event-ID` header, i.e. it's not a reconnection.
+    NoLastEventId,
+    /// The given number of missed events was sent.
+    Replayed(usize),
+    /// The store doesn't know the last event id, so the client may have missed events which can't be replayed, e.g.
+    /// it needs a full refresh.
+    UnknownLastEventId,
+}
+
+fn encode_field(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.extend_from_slice(name.as_bytes());
+    buf.extend_from_slice(b": ");
+    buf.extend_from_slice(value.as_bytes());
+    buf.extend_from_slice(b"\n");
+}
+
+fn check_single_line<'a>(name: &str, value: &'a str) -> io::Result<&'a str> {
+    if value.contains(['\r', '\n']) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{}: EventWriter: The {} field of an event can't contain a line break",
+                env!("CARGO_PKG_NAME"),
+                name
+            ),
+        ));
+    }
+    Ok(value)
+}
+
+/// Writes [Server-Sent Events](https://html.spec.whatwg.org/multipage/server-sent-events.html) to a writer, e.g. the
+/// writer half of [StreamBody::channel](./struct.StreamBody.html#method.channel).
+///
+/// Every event is written at once and flushed, so it reaches the client as soon as it's sent. With
+/// [with_heartbeat](#method.with_heartbeat), [wait_for](#method.wait_for) writes a comment line whenever nothing was
+/// written for the given interval, which keeps proxies and load balancers from closing the connection. A stream
+/// sending events more often than that never gets a heartbeat.
+///
+/// It requires the `sse` feature.
+///
+/// # Examples
+///
+/// ```no_run
+/// use hyper::Response;
+/// use std::time::Duration;
+/// use stream_body::{Event, EventWriter, StreamBody};
+/// use tokio::sync::mpsc;
+///
+/// # async fn run(mut quotes: mpsc::Receiver<String>) -> std::io::Result<()> {
+/// let (writer, body) = StreamBody::channel();
+/// let res = Response::builder()
+///     .header("content-type", "text/event-stream")
+///     .body(body)
+///     .unwrap();
+///
+/// let mut events = EventWriter::new(writer).with_heartbeat(Duration::from_secs(15));
+/// while let Some(quote) = events.wait_for(quotes.recv()).await? {
+///     events.send(&Event::new(quote).event("quote")).await?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct EventWriter<W> {
+    inner: W,
+    heartbeat: Option<Duration>,
+    // When something was last written, the next heartbeat being due an interval later.
+    last_write_at: Instant,
+    heartbeats_sent: u64,
+    buf: Vec<u8>,
+}
+
+impl<W: AsyncWrite + Unpin> EventWriter<W> {
+    /// Creates an event writer without heartbeats.
+    pub fn new(inner: W) -> EventWriter<W> {
+        EventWriter {
+            inner,
+            heartbeat: None,
+            last_write_at: Instant::now(),
+            heartbeats_sent: 0,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Sets the interval of the heartbeats written by [wait_for](#method.wait_for).
+    pub fn with_heartbeat(mut self, interval: Duration) -> EventWriter<W> {
+        self.heartbeat = Some(interval);
+        self
+    }
+
+    /// Writes an event and flushes it.
+    pub async fn send(&mut self, event: &Event) -> io::Result<()> {
+        self.buf.clear();
+        event.encode(&mut self.buf)?;
+        self.write_buf().await
+    }
+
+    /// Serializes `value` as JSON and writes it as the data of an event of type `event`, then flushes it. It requires
+    /// the `json` feature.
+    ///
+    /// The JSON is compact, so it fits a single `data:` line, the line breaks within strings being escaped. A value
+    /// failing to serialize yields an error of kind `InvalidInput` and nothing is written.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use serde_json::json;
+    /// use stream_body::{EventWriter, StreamBody};
+    ///
+    /// # async fn run() -> std::io::Result<()> {
+    /// let (writer, body) = StreamBody::channel();
+    /// let mut events = EventWriter::new(writer);
+    ///
+    /// events.send_json("quote", &json!({ "symbol": "ACME", "price": 42.5 })).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "json")]
+    pub async fn send_json<T: serde::Serialize + ?Sized>(&mut self, event: &str, value: &T) -> io::Result<()> {
+        let data = serde_json::to_string(value).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{}: EventWriter: Failed to serialize the data of an event: {}",
+                    env!("CARGO_PKG_NAME"),
+                    err
+                ),
+            )
+        })?;
+        self.send(&Event::new(data).event(event)).await
+    }
+
+    /// Writes a comment line, which the client ignores, and flushes it. A comment with a line break is split into
+    /// several comment lines.
+    pub async fn comment(&mut self, text: &str) -> io::Result<()> {
+        self.buf.clear();
+        for line in text.split("\r\n").flat_map(|line| line.split(['\r', '\n'])) {
+            self.buf.extend_from_slice(b": ");
+            self.buf.extend_from_slice(line.as_bytes());
+            self.buf.extend_from_slice(b"\n");
+        }
+        self.write_buf().await
+    }
+
+    /// Writes an empty comment line as a heartbeat and flushes it.
+    pub async fn heartbeat(&mut self) -> io::Result<()> {
+        self.buf.clear();
+        self.buf.extend_from_slice(b":\n");
+        self.write_buf().await?;
+        self.heartbeats_sent += 1;
+        Ok(())
+    }
+
+    /// Returns the number of heartbeats written so far.
+    pub fn heartbeats_sent(&self) -> u64 {
+        self.heartbeats_sent
+    }
+
+    /// Waits for `future`, e.g. the next message of a channel, writing a heartbeat whenever the interval set via
+    /// [with_heartbeat](#method.with_heartbeat) elapses in the meantime without anything written. The interval runs
+    /// from the last write, so the heartbeats are suppressed as long as events flow. Without an interval it simply
+    /// awaits the future.
+    ///
+    /// A failed heartbeat, typically because the client went away, is returned as an error.
+    pub async fn wait_for<F: Future>(&mut self, future: F) -> io::Result<F::Output> {
+        let interval = match self.heartbeat {
+            Some(interval) => interval,
+            None => return Ok(future.await),
+        };
+
+        let mut future = Box::pin(future);
+        loop {
+            match time::timeout_at(self.last_write_at + interval, &mut future).await {
+                Ok(output) => return Ok(output),
+                Err(_) => self.heartbeat().await?,
+            }
+        }
+    }
+
+    /// Sends a reconnecting client the events it missed, i.e. the events of `store` following the one whose id the
+    /// client sent in the `Last-Event-ID` header of the request.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use hyper::Request;
+    /// use stream_body::{EventWriter, MemoryEventStore, Replay, StreamBody};
+    ///
+    /// # async fn run(req: Request<hyper::Body>, store: &MemoryEventStore) -> std::io::Result<()> {
+    /// let (writer, body) = StreamBody::channel();
+    /// let mut events = EventWriter::new(writer);
+    ///
+    /// if events.replay(store, req.headers()).await? == Replay::UnknownLastEventId {
+    ///     // Too far behind, send a snapshot instead.
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn replay<S: EventStore + ?Sized>(&mut self, store: &S, headers: &HeaderMap) -> io::Result<Replay> {
+        let last_event_id = match headers.get("last-event-id").and_then(|value| value.to_str().ok()) {
+            Some(id) => id.trim(),
+            None => return Ok(Replay::NoLastEventId),
+        };
+
+        let events = match store.events_after(last_event_id) {
+            Some(events) => events,
+            None => return Ok(Replay::UnknownLastEventId),
+        };
+        for event in events.iter() {
+            self.send(event).await?;
+        }
+        Ok(Replay::Replayed(events.len()))
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying writer. Writing to it directly may break the event framing.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Unwraps the event writer, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    async fn write_buf(&mut self) -> io::Result<()> {
+        self.inner.write_all(&self.buf).await?;
+        self.inner.flush().await?;
+
+        self.last_write_at = Instant::now();
+        Ok(())
+    }
+}
@@ -1,6 +1,62 @@
-use std::task::Waker;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Waker};
+use std::time::{Duration, Instant};
 
 pub(crate) struct State {
     pub(crate) is_current_stream_data_consumed: bool,
     pub(crate) waker: Option<Waker>,
+    // The number of bytes left in chunks which were dropped before being fully consumed.
+    pub(crate) abandoned_bytes: usize,
+    // With coalesced wakeups, whether the body is parked waiting for a chunk, shared by all the buffers of a body.
+    pub(crate) needs_wake: Option<Arc<AtomicBool>>,
+    // With auto-tuning, when the current chunk was emitted and its length, then its length and how long it took to be
+    // consumed.
+    pub(crate) emitted: Option<(Instant, usize)>,
+    pub(crate) drained: Option<(usize, Duration)>,
+}
+
+impl State {
+    pub(crate) fn new() -> State {
+        State {
+            is_current_stream_data_consumed: true,
+            waker: None,
+            abandoned_bytes: 0,
+            needs_wake: None,
+            emitted: None,
+            drained: None,
+        }
+    }
+
+    /// Registers the task polling the body to be woken up once the chunk is consumed.
+    pub(crate) fn park(&mut self, cx: &mut Context) {
+        match self.waker {
+            Some(ref waker) if waker.will_wake(cx.waker()) => {}
+            _ => self.waker = Some(cx.waker().clone()),
+        }
+
+        if let Some(ref needs_wake) = self.needs_wake {
+            needs_wake.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Marks the body as polled, so the chunks dropped until it parks again don't wake it up.
+    pub(crate) fn unpark(&self) {
+        if let Some(ref needs_wake) = self.needs_wake {
+            needs_wake.store(false, Ordering::SeqCst);
+        }
+    }
+
+    /// Wakes the parked body up, only the first chunk dropped since it parked does with coalesced wakeups.
+    pub(crate) fn wake(&mut self) {
+        let waker = self.waker.take();
+        let wake = match self.needs_wake {
+            Some(ref needs_wake) => needs_wake.swap(false, Ordering::SeqCst),
+            None => true,
+        };
+
+        if let (true, Some(waker)) = (wake, waker) {
+            waker.wake();
+        }
+    }
 }
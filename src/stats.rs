@@ -0,0 +1,67 @@
+use crate::data::StreamData;
+use bytes::Buf;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tokio::io;
+
+/// The streaming statistics of a body, recorded from the results of its `poll_data` calls.
+#[derive(Debug, Clone)]
+pub(crate) struct Stats {
+    pub(crate) created_at: Instant,
+    pub(crate) first_byte_at: Option<Instant>,
+    pub(crate) finished_at: Option<Instant>,
+    pub(crate) bytes: u64,
+    pub(crate) chunks: u64,
+    pub(crate) stalls: u64,
+    stalled: bool,
+}
+
+impl Stats {
+    pub(crate) fn new() -> Stats {
+        Stats {
+            created_at: Instant::now(),
+            first_byte_at: None,
+            finished_at: None,
+            bytes: 0,
+            chunks: 0,
+            stalls: 0,
+            stalled: false,
+        }
+    }
+
+    /// Records the outcome of a `poll_data` call.
+    ///
+    /// A stall is counted each time the body goes from emitting chunks to waiting, after the first byte.
+    pub(crate) fn record(&mut self, poll_status: &Poll<Option<Result<StreamData, io::Error>>>) {
+        match poll_status {
+            Poll::Ready(Some(Ok(data))) => {
+                let now = Instant::now();
+                self.first_byte_at.get_or_insert(now);
+                self.bytes += data.remaining() as u64;
+                self.chunks += 1;
+                self.stalled = false;
+            }
+            Poll::Ready(_) => {
+                self.finished_at.get_or_insert_with(Instant::now);
+            }
+            Poll::Pending => {
+                if self.first_byte_at.is_some() && !self.stalled {
+                    self.stalls += 1;
+                    self.stalled = true;
+                }
+            }
+        }
+    }
+
+    /// The time from the creation of the body to its first chunk.
+    pub(crate) fn ttfb(&self) -> Option<Duration> {
+        self.first_byte_at.map(|at| at.duration_since(self.created_at))
+    }
+
+    /// The time from the creation of the body to its end, or until now if it hasn't ended yet.
+    pub(crate) fn duration(&self) -> Duration {
+        self.finished_at
+            .unwrap_or_else(Instant::now)
+            .duration_since(self.created_at)
+    }
+}
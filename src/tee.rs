@@ -0,0 +1,149 @@
+use crate::body::{size_hint_with_held, StreamBody};
+use crate::data::StreamData;
+use bytes::Buf;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::marker::Unpin;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{self, AsyncWrite};
+
+impl StreamBody {
+    /// Writes a copy of the data of the body to `sink` as it streams, e.g. a cache file or a hashing writer, so
+    /// several consumers observe the same chunks.
+    ///
+    /// Every chunk is written to the sink, from the chunk itself without copying it, before it's emitted, so a slow
+    /// sink slows the body down instead of buffering without bounds. The sink is shut down once the body reached its
+    /// end, and simply dropped if the body fails or is dropped early. Several sinks can be attached by calling `tee`
+    /// several times.
+    ///
+    /// A failing sink is detached and the error logged, the body itself continues, so e.g. a full disk doesn't break
+    /// the response.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use stream_body::StreamBody;
+    ///
+    /// # async fn run() -> std::io::Result<()> {
+    /// let file = tokio::fs::File::open("large-file").await?;
+    /// let copy = tokio::fs::File::create("large-file.copy").await?;
+    ///
+    /// let body = StreamBody::from_reader_inline(file).tee(copy);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn tee<W: AsyncWrite + Unpin + Send + 'static>(self, sink: W) -> StreamBody {
+        self.wrap_with(|inner| Tee {
+            inner,
+            sink: Some(sink),
+            pending: None,
+            written: 0,
+            reached_eof: false,
+        })
+    }
+}
+
+struct Tee<W> {
+    inner: StreamBody,
+    sink: Option<W>,
+    // A chunk being written to the sink before it's emitted.
+    pending: Option<StreamData>,
+    written: usize,
+    reached_eof: bool,
+}
+
+impl<W: AsyncWrite + Unpin> Tee<W> {
+    fn detach(&mut self, err: io::Error) {
+        log::warn!(
+            "{}: StreamBody [Tee]: Detached the sink after a write error: {}",
+            env!("CARGO_PKG_NAME"),
+            err
+        );
+        self.sink = None;
+    }
+
+    /// Writes the rest of the pending chunk to the sink.
+    fn poll_write_pending(&mut self, cx: &mut Context) -> Poll<()> {
+        loop {
+            let (sink, data) = match (self.sink.as_mut(), self.pending.as_ref()) {
+                (Some(sink), Some(data)) => (sink, data),
+                _ => return Poll::Ready(()),
+            };
+
+            let rest = &data.bytes()[self.written..];
+            if rest.is_empty() {
+                return Poll::Ready(());
+            }
+
+            match ready!(Pin::new(sink).poll_write(cx, rest)) {
+                Ok(0) => self.detach(io::ErrorKind::WriteZero.into()),
+                Ok(n) => self.written += n,
+                Err(err) => self.detach(err),
+            }
+        }
+    }
+
+    fn poll_shutdown_sink(&mut self, cx: &mut Context) -> Poll<()> {
+        if let Some(ref mut sink) = self.sink {
+            let result = ready!(Pin::new(sink).poll_shutdown(cx));
+            match result {
+                Ok(()) => self.sink = None,
+                Err(err) => self.detach(err),
+            }
+        }
+        Poll::Ready(())
+    }
+}
+
+impl<W: AsyncWrite + Unpin> Body for Tee<W> {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let me = &mut *self;
+
+        if me.reached_eof {
+            ready!(me.poll_shutdown_sink(cx));
+            return Poll::Ready(None);
+        }
+
+        if me.pending.is_none() {
+            match ready!(Pin::new(&mut me.inner).poll_data(cx)) {
+                Some(Ok(data)) => {
+                    me.pending = Some(data);
+                    me.written = 0;
+                }
+                Some(Err(err)) => {
+                    // The copy is incomplete, the sink isn't shut down to tell.
+                    me.sink = None;
+                    return Poll::Ready(Some(Err(err)));
+                }
+                None => {
+                    me.reached_eof = true;
+                    ready!(me.poll_shutdown_sink(cx));
+                    return Poll::Ready(None);
+                }
+            }
+        }
+
+        ready!(me.poll_write_pending(cx));
+        Poll::Ready(me.pending.take().map(Ok))
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.pending.is_none() && self.sink.is_none() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let pending = self.pending.as_ref().map(|data| data.remaining() as u64).unwrap_or(0);
+        size_hint_with_held(self.inner.size_hint(), pending)
+    }
+}
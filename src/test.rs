@@ -0,0 +1,159 @@
+//! Helpers to test handlers returning a `StreamBody` and the producers feeding it, with the `test-util` feature.
+//!
+//! # Simulated Time
+//!
+//! The timers of the crate, i.e. the timeouts, the pacing, the scheduler, the SSE heartbeats and the coalesce timeout
+//! of the [builder](../struct.StreamBodyBuilder.html), run on the tokio clock. Once it's frozen via [pause](fn.pause.html),
+//! it only moves with [advance](fn.advance.html), so the tests of time-dependent behaviors don't depend on the speed
+//! of the CI machine. The tokio clock can only be frozen on the single-threaded scheduler, the one of
+//! `#[tokio::test]`, whose tasks, e.g. the ones spawned by [from_reader](../struct.StreamBody.html#method.from_reader),
+//! run in a deterministic order.
+//!
+//! With a frozen clock the delays of a [SlowConsumer](struct.SlowConsumer.html) only pass once the clock is advanced.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use stream_body::{test, StreamBody};
+//!
+//! # #[cfg(feature = "timeout")]
+//! #[tokio::test]
+//! async fn idle_timeout() {
+//!     test::pause();
+//!     let (_writer, body) = StreamBody::channel();
+//!     let collected = tokio::spawn(test::collect(body.with_idle_timeout(Duration::from_secs(30))));
+//!
+//!     test::advance(Duration::from_secs(31)).await;
+//!     assert!(collected.await.unwrap().is_err());
+//! }
+//! ```
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use stream_body::test::{self, FailingReader, SlowConsumer};
+//! use stream_body::StreamBody;
+//!
+//! # async fn run() -> std::io::Result<()> {
+//! let body = StreamBody::from("hello world");
+//! assert_eq!(test::collect(body).await?, b"hello world");
+//!
+//! let body = StreamBody::from_reader_inline(FailingReader::new("partial", std::io::ErrorKind::ConnectionReset));
+//! let err = SlowConsumer::new(Duration::from_millis(10)).consume(body).await.unwrap_err();
+//! assert_eq!(err.kind(), std::io::ErrorKind::ConnectionReset);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::body::StreamBody;
+use bytes::{Buf, Bytes};
+use http_body::Body;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{self, AsyncRead};
+use tokio::time;
+
+pub use tokio::time::{advance, pause, resume};
+
+/// Collects the data of a body, failing with the first error of the body.
+pub async fn collect(body: StreamBody) -> io::Result<Vec<u8>> {
+    let chunks = collect_chunks(body).await?;
+    Ok(chunks.concat())
+}
+
+/// Collects the chunks of a body as they're emitted, e.g. to check their boundaries, failing with the first error of
+/// the body. Every chunk is fully consumed before it's dropped, like a server does.
+pub async fn collect_chunks(mut body: StreamBody) -> io::Result<Vec<Bytes>> {
+    let mut chunks = Vec::new();
+    while let Some(data) = body.data().await {
+        let mut data = data?;
+        chunks.push(Bytes::copy_from_slice(data.bytes()));
+        data.advance(data.remaining());
+    }
+    Ok(chunks)
+}
+
+/// A consumer reading a body like a slow client: every chunk is read and held for a delay before it's dropped, which
+/// keeps the body's buffer in use, and the next chunk is polled after another delay.
+///
+/// It exercises the paths where the producer waits for the previous chunk to be consumed.
+#[derive(Debug, Clone)]
+pub struct SlowConsumer {
+    hold: Duration,
+    poll_delay: Duration,
+}
+
+impl SlowConsumer {
+    /// Creates a consumer holding every chunk for `hold` before dropping it.
+    pub fn new(hold: Duration) -> SlowConsumer {
+        SlowConsumer {
+            hold,
+            poll_delay: Duration::from_secs(0),
+        }
+    }
+
+    /// Waits `delay` after dropping a chunk before polling the next one. By default the next chunk is polled right
+    /// away.
+    pub fn poll_delay(mut self, delay: Duration) -> SlowConsumer {
+        self.poll_delay = delay;
+        self
+    }
+
+    /// Reads the whole body and returns its data, failing with the first error of the body.
+    pub async fn consume(&self, mut body: StreamBody) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        while let Some(data) = body.data().await {
+            let mut data = data?;
+            out.extend_from_slice(data.bytes());
+            data.advance(data.remaining());
+
+            time::delay_for(self.hold).await;
+            drop(data);
+            time::delay_for(self.poll_delay).await;
+        }
+        Ok(out)
+    }
+}
+
+/// An [AsyncRead](https://docs.rs/tokio/0.2.16/tokio/io/trait.AsyncRead.html) returning some data and then an error,
+/// to test the error paths of the bodies reading it.
+#[derive(Debug)]
+pub struct FailingReader {
+    data: Bytes,
+    kind: io::ErrorKind,
+    read_size: usize,
+}
+
+impl FailingReader {
+    /// Creates a reader returning `data` and then an error of the given kind.
+    pub fn new<B: Into<Bytes>>(data: B, kind: io::ErrorKind) -> FailingReader {
+        FailingReader {
+            data: data.into(),
+            kind,
+            read_size: usize::MAX,
+        }
+    }
+
+    /// Returns at most `len` bytes per read, so the data spans several chunks.
+    pub fn read_size(mut self, len: usize) -> FailingReader {
+        self.read_size = len.max(1);
+        self
+    }
+}
+
+impl AsyncRead for FailingReader {
+    fn poll_read(mut self: Pin<&mut Self>, _cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        if self.data.is_empty() {
+            return Poll::Ready(Err(io::Error::new(
+                self.kind,
+                format!("{}: FailingReader: The reader failed", env!("CARGO_PKG_NAME")),
+            )));
+        }
+
+        let len = self.data.len().min(buf.len()).min(self.read_size);
+        buf[..len].copy_from_slice(&self.data[..len]);
+        self.data.advance(len);
+        Poll::Ready(Ok(len))
+    }
+}
@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+// The weight of a new sample, so the estimate follows a change of speed within a few chunks.
+const SAMPLE_WEIGHT: f64 = 0.25;
+
+/// A rolling estimate of the rate at which a consumer takes the data of a body, as an exponentially weighted moving
+/// average of the samples.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ThroughputEstimator {
+    bytes_per_sec: Option<f64>,
+}
+
+impl ThroughputEstimator {
+    pub(crate) fn new() -> ThroughputEstimator {
+        ThroughputEstimator::default()
+    }
+
+    /// Records that `bytes` were consumed in `elapsed`.
+    pub(crate) fn record(&mut self, bytes: usize, elapsed: Duration) {
+        // A chunk consumed instantly still took some time, this keeps the sample finite.
+        let secs = elapsed.as_secs_f64().max(1e-6);
+        let sample = bytes as f64 / secs;
+
+        self.bytes_per_sec = Some(match self.bytes_per_sec {
+            Some(rate) => rate + SAMPLE_WEIGHT * (sample - rate),
+            None => sample,
+        });
+    }
+
+    /// The estimated rate, `None` until the first sample.
+    pub(crate) fn bytes_per_sec(&self) -> Option<f64> {
+        self.bytes_per_sec
+    }
+}
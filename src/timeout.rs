@@ -0,0 +1,357 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use http::header::HeaderName;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io;
+use tokio::time::{self, Delay};
+
+/// Describes what a body does when its timeout fires.
+pub enum TimeoutAction {
+    /// Yields a [TimedOut](https://doc.rust-lang.org/std/io/enum.ErrorKind.html#variant.TimedOut) error, so the
+    /// stream is reset and the client sees an aborted transfer.
+    Error,
+    /// Ends the data stream cleanly and emits the provided trailers instead of an error.
+    ///
+    /// Note that trailers only reach the client on protocols which support them, e.g. HTTP/2.
+    Truncate(HeaderMap<HeaderValue>),
+}
+
+impl TimeoutAction {
+    /// Ends the data stream cleanly and emits the `X-Stream-Truncated: timeout` trailer.
+    pub fn truncate() -> TimeoutAction {
+        let mut trailers = HeaderMap::new();
+        trailers.insert(
+            HeaderName::from_static("x-stream-truncated"),
+            HeaderValue::from_static("timeout"),
+        );
+        TimeoutAction::Truncate(trailers)
+    }
+}
+
+impl StreamBody {
+    /// Limits the total time the body is allowed to stream, measured from the first poll.
+    ///
+    /// When the deadline passes, the source is not polled anymore and the body behaves as described by `action`.
+    pub fn with_deadline(self, deadline: Duration, action: TimeoutAction) -> StreamBody {
+        self.wrap_with(|inner| Deadline {
+            inner,
+            duration: deadline,
+            delay: None,
+            action: Some(action),
+            trailers: None,
+            timed_out: false,
+        })
+    }
+
+    /// Limits the time the source may take to produce each chunk, measured from the moment the body is polled for it.
+    ///
+    /// When the source misses the deadline, the body first emits whatever the source can provide right away, e.g.
+    /// the part of a write the producer already handed over, and only then behaves as described by `action` once the
+    /// source would wait again. So time-sensitive streams deliver their partial data and end with a clear signal
+    /// instead of stalling silently.
+    pub fn with_chunk_timeout(self, timeout: Duration, action: TimeoutAction) -> StreamBody {
+        self.wrap_with(|inner| ChunkTimeout {
+            inner,
+            timeout,
+            delay: None,
+            expired: false,
+            action: Some(action),
+            trailers: None,
+            timed_out: false,
+        })
+    }
+
+    /// Fails the body with a `TimedOut` error when the source doesn't produce a chunk within `timeout`, so a stalled
+    /// producer, e.g. one which never writes to its `PipeWriter` nor drops it, resets the stream instead of leaving
+    /// the connection hanging forever.
+    ///
+    /// It's a shorthand for [with_chunk_timeout](#method.with_chunk_timeout) with
+    /// [TimeoutAction::Error](./enum.TimeoutAction.html#variant.Error). It requires the `timeout` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use stream_body::StreamBody;
+    ///
+    /// let (writer, body) = StreamBody::channel();
+    /// let body = body.with_idle_timeout(Duration::from_secs(30));
+    /// ```
+    pub fn with_idle_timeout(self, timeout: Duration) -> StreamBody {
+        self.with_chunk_timeout(timeout, TimeoutAction::Error)
+    }
+}
+
+struct Deadline {
+    inner: StreamBody,
+    duration: Duration,
+    delay: Option<Delay>,
+    action: Option<TimeoutAction>,
+    trailers: Option<HeaderMap<HeaderValue>>,
+    timed_out: bool,
+}
+
+struct ChunkTimeout {
+    inner: StreamBody,
+    timeout: Duration,
+    delay: Option<Delay>,
+    // Whether the deadline of the current chunk passed and the source is being drained.
+    expired: bool,
+    action: Option<TimeoutAction>,
+    trailers: Option<HeaderMap<HeaderValue>>,
+    timed_out: bool,
+}
+
+impl Body for ChunkTimeout {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        loop {
+            if self.timed_out {
+                return Poll::Ready(None);
+            }
+
+            match Pin::new(&mut self.inner).poll_data(cx) {
+                Poll::Ready(Some(Ok(data))) => {
+                    // The deadline of the next chunk starts when the body is polled for it.
+                    self.delay = None;
+                    return Poll::Ready(Some(Ok(data)));
+                }
+                Poll::Ready(result) => return Poll::Ready(result),
+                Poll::Pending => {}
+            }
+
+            if self.expired {
+                break;
+            }
+
+            let timeout = self.timeout;
+            let delay = self.delay.get_or_insert_with(|| time::delay_for(timeout));
+            if Pin::new(delay).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+
+            // Gives the source one more chance to hand over what it has before acting.
+            self.expired = true;
+        }
+
+        self.timed_out = true;
+        match self.action.take() {
+            Some(TimeoutAction::Truncate(trailers)) => {
+                self.trailers = Some(trailers);
+                Poll::Ready(None)
+            }
+            _ => Poll::Ready(Some(Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "{}: StreamBody: The source did not produce a chunk within {:?}",
+                    env!("CARGO_PKG_NAME"),
+                    self.timeout
+                ),
+            )))),
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        if self.timed_out {
+            return Poll::Ready(Ok(self.trailers.take()));
+        }
+
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        if self.timed_out {
+            return self.trailers.is_none();
+        }
+
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        if self.timed_out {
+            return SizeHint::with_exact(0);
+        }
+
+        // The body may end early, so only the upper bound of the source is still valid.
+        let mut hint = SizeHint::new();
+        if let Some(upper) = self.inner.size_hint().upper() {
+            hint.set_upper(upper);
+        }
+        hint
+    }
+}
+
+impl Body for Deadline {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        if self.timed_out {
+            return Poll::Ready(None);
+        }
+
+        let duration = self.duration;
+        let delay = self.delay.get_or_insert_with(|| time::delay_for(duration));
+        // The deadline is checked against the clock before the source is polled, as the timer of a busy task may fire
+        // late and a source which is always ready would keep streaming past the deadline.
+        if time::Instant::now() < delay.deadline() {
+            // Registers the wakeup of the deadline for when the source waits.
+            let _ = Pin::new(delay).poll(cx);
+            return Pin::new(&mut self.inner).poll_data(cx);
+        }
+
+        self.timed_out = true;
+        match self.action.take() {
+            Some(TimeoutAction::Truncate(trailers)) => {
+                self.trailers = Some(trailers);
+                Poll::Ready(None)
+            }
+            _ => Poll::Ready(Some(Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "{}: StreamBody: The body did not finish streaming within {:?}",
+                    env!("CARGO_PKG_NAME"),
+                    self.duration
+                ),
+            )))),
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        if self.timed_out {
+            return Poll::Ready(Ok(self.trailers.take()));
+        }
+
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        if self.timed_out {
+            return self.trailers.is_none();
+        }
+
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        if self.timed_out {
+            return SizeHint::with_exact(0);
+        }
+
+        // The body may end early, so only the upper bound of the source is still valid.
+        let mut hint = SizeHint::new();
+        if let Some(upper) = self.inner.size_hint().upper() {
+            hint.set_upper(upper);
+        }
+        hint
+    }
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use super::*;
+    use crate::source::{Fill, Source};
+    use crate::test;
+    use bytes::Buf;
+    use futures_util::FutureExt;
+    use tokio::io::{AsyncWriteExt, ErrorKind};
+
+    // A source which is always ready, so only the deadline can end the body.
+    struct Endless;
+
+    impl Source for Endless {
+        fn poll_fill(self: Pin<&mut Self>, _cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<Fill>> {
+            buf[0] = b'x';
+            Poll::Ready(Ok(Fill::Filled(1)))
+        }
+    }
+
+    // Polls the body once, without letting the runtime move the paused clock to the next timer.
+    fn next(body: &mut StreamBody) -> Option<io::Result<StreamData>> {
+        body.data().now_or_never().expect("the body should be ready")
+    }
+
+    #[tokio::test]
+    async fn deadline_stops_an_always_ready_source_with_an_error() {
+        test::pause();
+        let mut body = StreamBody::from_source(Endless).with_deadline(Duration::from_secs(1), TimeoutAction::Error);
+
+        assert!(next(&mut body).unwrap().is_ok());
+        test::advance(Duration::from_millis(999)).await;
+        assert!(next(&mut body).unwrap().is_ok());
+
+        test::advance(Duration::from_millis(1)).await;
+        let err = body.data().await.unwrap().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(next(&mut body).is_none());
+        assert_eq!(body.size_hint().exact(), Some(0));
+    }
+
+    #[tokio::test]
+    async fn deadline_truncates_an_always_ready_source_with_trailers() {
+        test::pause();
+        let mut body =
+            StreamBody::from_source(Endless).with_deadline(Duration::from_secs(1), TimeoutAction::truncate());
+
+        assert!(next(&mut body).unwrap().is_ok());
+        test::advance(Duration::from_secs(1)).await;
+        assert!(body.data().await.is_none());
+        assert!(!body.is_end_stream());
+
+        let trailers = body.trailers().await.unwrap().unwrap();
+        assert_eq!(trailers["x-stream-truncated"], "timeout");
+        assert!(body.is_end_stream());
+    }
+
+    #[tokio::test]
+    async fn chunk_timeout_delivers_the_pending_data_before_acting() {
+        test::pause();
+        let (mut writer, body) = StreamBody::channel();
+        let mut body = body.with_chunk_timeout(Duration::from_secs(1), TimeoutAction::truncate());
+
+        assert!(body.data().now_or_never().is_none());
+        // The write is handed over right when the deadline passes.
+        let writer = tokio::spawn(async move {
+            writer.write_all(b"late").await.unwrap();
+            writer
+        });
+        test::advance(Duration::from_secs(1)).await;
+        let data = next(&mut body).unwrap().unwrap();
+        assert_eq!(data.bytes(), b"late");
+        drop(data);
+        let _writer = writer.await.unwrap();
+
+        // The next chunk gets a fresh deadline.
+        assert!(body.data().now_or_never().is_none());
+        test::advance(Duration::from_secs(1)).await;
+        assert!(body.data().await.is_none());
+        let trailers = body.trailers().await.unwrap().unwrap();
+        assert_eq!(trailers["x-stream-truncated"], "timeout");
+    }
+
+    #[tokio::test]
+    async fn idle_timeout_fails_a_stalled_source() {
+        test::pause();
+        let (_writer, body) = StreamBody::channel();
+        let mut body = body.with_idle_timeout(Duration::from_secs(30));
+
+        assert!(body.data().now_or_never().is_none());
+        test::advance(Duration::from_secs(30)).await;
+        let err = body.data().await.unwrap().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(next(&mut body).is_none());
+    }
+}
@@ -0,0 +1,249 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use async_pipe::PipeWriter;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll, Waker};
+use tokio::io;
+
+/// The sending half for the trailers of a body created via
+/// [StreamBody::channel_with_trailers](./struct.StreamBody.html#method.channel_with_trailers).
+///
+/// The trailers are yielded once the body reaches EOF. Dropping the sender without sending anything ends the body
+/// without trailers.
+pub struct TrailerSender {
+    shared: Arc<Mutex<Shared>>,
+}
+
+struct Shared {
+    trailers: Option<HeaderMap<HeaderValue>>,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+impl TrailerSender {
+    /// Sends the trailers, e.g. a checksum computed while streaming the data.
+    ///
+    /// It can be called before or after the writer half is closed.
+    pub fn send(self, trailers: HeaderMap<HeaderValue>) {
+        let mut shared = self.shared.lock().unwrap_or_else(|err| err.into_inner());
+        shared.trailers = Some(trailers);
+        // Dropping the sender right after wakes the body.
+    }
+}
+
+impl Drop for TrailerSender {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock().unwrap_or_else(|err| err.into_inner());
+        shared.closed = true;
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl StreamBody {
+    /// Same as [channel](#method.channel), but with an additional sender for the trailers of the body.
+    ///
+    /// Note that trailers only reach the client on protocols which support them, e.g. HTTP/2. The client should be
+    /// told upfront through the `Trailer` header.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use http::{HeaderMap, HeaderValue};
+    /// use stream_body::StreamBody;
+    /// use tokio::io::AsyncWriteExt;
+    ///
+    /// # async fn run() -> std::io::Result<()> {
+    /// let (mut writer, trailer_sender, body) = StreamBody::channel_with_trailers();
+    ///
+    /// tokio::spawn(async move {
+    ///     writer.write_all(b"Hello world").await.unwrap();
+    ///
+    ///     let mut trailers = HeaderMap::new();
+    ///     trailers.insert("x-checksum", HeaderValue::from_static("3e25960a"));
+    ///     trailer_sender.send(trailers);
+    /// });
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn channel_with_trailers() -> (PipeWriter, TrailerSender, StreamBody) {
+        let (w, body) = StreamBody::channel();
+
+        let shared = Arc::new(Mutex::new(Shared {
+            trailers: None,
+            closed: false,
+            waker: None,
+        }));
+
+        let sender = TrailerSender {
+            shared: Arc::clone(&shared),
+        };
+
+        let body = body.wrap_with(|inner| WithTrailers {
+            inner,
+            shared,
+            trailers_sent: false,
+        });
+
+        (w, sender, body)
+    }
+}
+
+struct WithTrailers {
+    inner: StreamBody,
+    shared: Arc<Mutex<Shared>>,
+    trailers_sent: bool,
+}
+
+impl Body for WithTrailers {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        if self.trailers_sent {
+            return Poll::Ready(Ok(None));
+        }
+
+        let mut shared = self.shared.lock().unwrap_or_else(|err| err.into_inner());
+        if !shared.closed {
+            shared.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let trailers = shared.trailers.take();
+        drop(shared);
+
+        self.trailers_sent = true;
+        Poll::Ready(Ok(trailers))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.trailers_sent && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl StreamBody {
+    /// Adds `defaults` to the trailers of the body, the trailers of the body taking precedence over the defaults of
+    /// the same name.
+    pub(crate) fn with_default_trailers(self, defaults: HeaderMap<HeaderValue>) -> StreamBody {
+        self.wrap_with(|inner| DefaultTrailers {
+            inner,
+            defaults: Some(defaults),
+        })
+    }
+}
+
+struct DefaultTrailers {
+    inner: StreamBody,
+    defaults: Option<HeaderMap<HeaderValue>>,
+}
+
+impl Body for DefaultTrailers {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        let trailers = ready!(Pin::new(&mut self.inner).poll_trailers(cx))?;
+        let defaults = match self.defaults.take() {
+            Some(defaults) => defaults,
+            None => return Poll::Ready(Ok(trailers)),
+        };
+
+        let mut trailers = match trailers {
+            Some(trailers) => trailers,
+            None => return Poll::Ready(Ok(Some(defaults))),
+        };
+        let mut name = None;
+        for (key, value) in defaults {
+            // The values following the first one of a name come without it.
+            if key.is_some() {
+                name = key.filter(|key| !trailers.contains_key(key));
+            }
+            if let Some(ref name) = name {
+                trailers.append(name.clone(), value);
+            }
+        }
+        Poll::Ready(Ok(Some(trailers)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.defaults.is_none() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl StreamBody {
+    /// Creates a body without data which only yields the given trailers, as needed by the gRPC "trailers-only"
+    /// responses, e.g. to report an error status without a message, and by some health-check protocols.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use http::{HeaderMap, HeaderValue};
+    /// use stream_body::StreamBody;
+    ///
+    /// let mut trailers = HeaderMap::new();
+    /// trailers.insert("grpc-status", HeaderValue::from_static("5"));
+    ///
+    /// let body = StreamBody::trailers_only(trailers);
+    /// ```
+    pub fn trailers_only(trailers: HeaderMap<HeaderValue>) -> StreamBody {
+        StreamBody::wrap(TrailersOnly {
+            trailers: Some(trailers),
+        })
+    }
+}
+
+struct TrailersOnly {
+    trailers: Option<HeaderMap<HeaderValue>>,
+}
+
+impl Body for TrailersOnly {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Poll::Ready(None)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Poll::Ready(Ok(self.trailers.take()))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        // The body isn't over until the trailers are taken, otherwise the server would end the stream with the headers.
+        self.trailers.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(0)
+    }
+}
@@ -0,0 +1,174 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use crate::source::{Fill, Source};
+use bytes::Bytes;
+use futures_core::Stream;
+use http::{HeaderMap, HeaderValue};
+use http_body::Body;
+use std::error::Error;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io;
+
+impl StreamBody {
+    /// Creates a body from a stream of [Bytes](https://docs.rs/bytes/0.5.4/bytes/struct.Bytes.html) chunks, e.g. an
+    /// async channel or a database cursor.
+    ///
+    /// The stream is polled directly from `poll_data`, so unlike [from_reader](#method.from_reader) no task is
+    /// spawned and the chunks are handed over without copying them. Empty chunks are skipped. Use
+    /// [from_try_stream](#method.from_try_stream) for streams which can fail.
+    pub fn from_stream<S>(stream: S) -> StreamBody
+    where
+        S: Stream<Item = Bytes> + Send + 'static,
+    {
+        let mut body = StreamBody::from_source(StreamSource::new(stream));
+        body.source = "stream";
+        body
+    }
+
+    /// Creates a body from a stream of `Result<Bytes, E>` chunks, propagating the error type of the stream as is.
+    ///
+    /// Unlike `StreamBody`, whose error type is `io::Error`, the returned body reports mid-stream failures with their
+    /// original error, e.g. to let hyper or a middleware inspect it. Empty chunks are skipped and the body is fused
+    /// after the first error.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let rows = db.query_stream(sql).map(|row| row.map(|row| Bytes::from(row.to_csv())));
+    /// let body = StreamBody::from_try_stream(rows);
+    /// ```
+    pub fn from_try_stream<S, E>(stream: S) -> TryStreamBody<S>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    {
+        TryStreamBody {
+            stream: Box::pin(stream),
+            terminated: false,
+        }
+    }
+}
+
+/// A body over a stream of `Result<Bytes, E>` chunks with `E` as its error type, created by
+/// [StreamBody::from_try_stream](./struct.StreamBody.html#method.from_try_stream).
+pub struct TryStreamBody<S> {
+    stream: Pin<Box<S>>,
+    terminated: bool,
+}
+
+impl<S, E> TryStreamBody<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<Box<dyn Error + Send + Sync>>,
+{
+    /// Converts the body into a `StreamBody`, wrapping the errors of the stream into an `io::Error` whose
+    /// [get_ref](https://doc.rust-lang.org/std/io/struct.Error.html#method.get_ref) gives the original error back.
+    ///
+    /// It allows to use the adapters of `StreamBody` with a fallible stream.
+    pub fn into_stream_body(self) -> StreamBody {
+        let mut body = StreamBody::wrap(IoErrors { inner: self });
+        body.source = "stream";
+        body
+    }
+}
+
+impl<S, E> Body for TryStreamBody<S>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+{
+    type Data = StreamData;
+    type Error = E;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        if self.terminated {
+            return Poll::Ready(None);
+        }
+
+        loop {
+            return match self.stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) if chunk.is_empty() => continue,
+                Poll::Ready(Some(Ok(chunk))) => Poll::Ready(Some(Ok(StreamData::from_bytes(chunk)))),
+                Poll::Ready(Some(Err(err))) => {
+                    self.terminated = true;
+                    Poll::Ready(Some(Err(err)))
+                }
+                Poll::Ready(None) => {
+                    self.terminated = true;
+                    Poll::Ready(None)
+                }
+                Poll::Pending => Poll::Pending,
+            };
+        }
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Poll::Ready(Ok(None))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.terminated
+    }
+}
+
+/// A [Source](./trait.Source.html) over a stream of [Bytes](https://docs.rs/bytes/0.5.4/bytes/struct.Bytes.html)
+/// chunks, which are handed over without copying them.
+///
+/// It requires the `futures` feature.
+pub struct StreamSource<S> {
+    stream: Pin<Box<S>>,
+}
+
+impl<S: Stream<Item = Bytes>> StreamSource<S> {
+    /// Creates a source over the stream.
+    pub fn new(stream: S) -> StreamSource<S> {
+        StreamSource {
+            stream: Box::pin(stream),
+        }
+    }
+}
+
+impl<S: Stream<Item = Bytes>> Source for StreamSource<S> {
+    fn poll_fill(mut self: Pin<&mut Self>, cx: &mut Context, _buf: &mut [u8]) -> Poll<io::Result<Fill>> {
+        match self.stream.as_mut().poll_next(cx) {
+            Poll::Ready(Some(chunk)) => Poll::Ready(Ok(Fill::Chunk(chunk))),
+            Poll::Ready(None) => Poll::Ready(Ok(Fill::Eof)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+struct IoErrors<S> {
+    inner: TryStreamBody<S>,
+}
+
+impl<S, E> Body for IoErrors<S>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    E: Into<Box<dyn Error + Send + Sync>>,
+{
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        match Pin::new(&mut self.inner).poll_data(cx) {
+            Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(io::Error::new(io::ErrorKind::Other, err)))),
+            Poll::Ready(Some(Ok(data))) => Poll::Ready(Some(Ok(data))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Poll::Ready(Ok(None))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
@@ -0,0 +1,218 @@
+use crate::body::StreamBody;
+use crate::throughput::ThroughputEstimator;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// How long a chunk should take the client to consume, the buffer capacity follows from its estimated speed.
+const TARGET_CHUNK_TIME: Duration = Duration::from_millis(50);
+
+// The weight of a new delay sample, as for the throughput.
+const SAMPLE_WEIGHT: f64 = 0.25;
+
+/// The auto-tuner of a channel body attached via
+/// [StreamBody::with_auto_tuning](./struct.StreamBody.html#method.with_auto_tuning), which sizes its buffers after
+/// the bandwidth-delay product of the client, and reports the chosen values.
+///
+/// The bandwidth is the rate at which the client consumes the chunks. The delay is the longer of the configured
+/// round-trip time and how long the producer has to wait for a free buffer, i.e. the stalls. The buffers are sized
+/// so a chunk takes the client about 50 ms to consume, within the configured range, and as many buffers are kept in
+/// flight as needed to cover the bandwidth-delay product, within the configured maximum.
+///
+/// The handle is cheap to clone, the clones share the same values. Attach one handle per body, the values reflect the
+/// body it was last attached to.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use stream_body::{AutoTuning, StreamBody};
+///
+/// let tuning = AutoTuning::new()
+///     .capacity_range(4 * 1024, 256 * 1024)
+///     .max_chunks(8)
+///     .round_trip_time(Duration::from_millis(80));
+///
+/// let (writer, body) = StreamBody::channel();
+/// let body = body.with_auto_tuning(&tuning);
+///
+/// // Later, e.g. from a metrics endpoint.
+/// println!("{} buffers of {} bytes", tuning.in_flight_chunks(), tuning.buffer_capacity());
+/// ```
+#[derive(Debug, Clone)]
+pub struct AutoTuning {
+    shared: Arc<Mutex<TuningState>>,
+}
+
+#[derive(Debug)]
+struct TuningState {
+    min_capacity: usize,
+    max_capacity: usize,
+    max_chunks: usize,
+    round_trip_time: Duration,
+    buffer_capacity: usize,
+    in_flight_chunks: usize,
+    bytes_per_sec: Option<f64>,
+    delay: Duration,
+    stalls: u64,
+}
+
+impl Default for AutoTuning {
+    fn default() -> AutoTuning {
+        AutoTuning {
+            shared: Arc::new(Mutex::new(TuningState {
+                min_capacity: 4 * 1024,
+                max_capacity: 256 * 1024,
+                max_chunks: 16,
+                round_trip_time: Duration::from_millis(50),
+                buffer_capacity: 0,
+                in_flight_chunks: 0,
+                bytes_per_sec: None,
+                delay: Duration::from_millis(50),
+                stalls: 0,
+            })),
+        }
+    }
+}
+
+impl AutoTuning {
+    /// Creates an auto-tuner with buffers of 4 KiB to 256 KiB, at most 16 buffers in flight and a round-trip time of
+    /// 50 ms.
+    pub fn new() -> AutoTuning {
+        AutoTuning::default()
+    }
+
+    /// Sets the range of the buffer capacity.
+    pub fn capacity_range(self, min: usize, max: usize) -> AutoTuning {
+        {
+            let mut state = self.lock();
+            state.min_capacity = min.max(1);
+            state.max_capacity = max.max(state.min_capacity);
+        }
+        self
+    }
+
+    /// Sets the maximum number of buffers in flight.
+    pub fn max_chunks(self, max_chunks: usize) -> AutoTuning {
+        self.lock().max_chunks = max_chunks.max(1);
+        self
+    }
+
+    /// Sets the minimum delay the data in flight has to cover, typically the round-trip time to the clients.
+    pub fn round_trip_time(self, round_trip_time: Duration) -> AutoTuning {
+        {
+            let mut state = self.lock();
+            state.round_trip_time = round_trip_time;
+            state.delay = round_trip_time;
+        }
+        self
+    }
+
+    /// Returns the chosen capacity of the buffers, zero until attached to a channel body.
+    pub fn buffer_capacity(&self) -> usize {
+        self.lock().buffer_capacity
+    }
+
+    /// Returns the chosen number of buffers in flight, zero until attached to a channel body.
+    pub fn in_flight_chunks(&self) -> usize {
+        self.lock().in_flight_chunks
+    }
+
+    /// Returns the estimated rate at which the client consumes the data, `None` until the first chunk is consumed.
+    pub fn bytes_per_sec(&self) -> Option<f64> {
+        self.lock().bytes_per_sec
+    }
+
+    /// Returns the delay the data in flight covers.
+    pub fn delay(&self) -> Duration {
+        self.lock().delay
+    }
+
+    /// Returns the estimated bandwidth-delay product, in bytes.
+    pub fn bandwidth_delay_product(&self) -> u64 {
+        let state = self.lock();
+        state.bandwidth_delay_product() as u64
+    }
+
+    /// Returns the number of times the producer had to wait for a free buffer.
+    pub fn stalls(&self) -> u64 {
+        self.lock().stalls
+    }
+
+    fn lock(&self) -> MutexGuard<'_, TuningState> {
+        self.shared.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl TuningState {
+    fn bandwidth_delay_product(&self) -> f64 {
+        self.bytes_per_sec.unwrap_or(0.0) * self.delay.as_secs_f64()
+    }
+}
+
+impl StreamBody {
+    /// Auto-tunes the buffers of a channel body, i.e. their capacity and how many of them are in flight, after the
+    /// bandwidth-delay product of the client, see [AutoTuning](./struct.AutoTuning.html). It's a no-op for the
+    /// other bodies.
+    ///
+    /// The new capacity applies to the next allocation of each buffer, and unneeded buffers are released once their
+    /// chunk is consumed. A buffer of a [BodyPool](./struct.BodyPool.html) whose capacity changed isn't recycled.
+    pub fn with_auto_tuning(mut self, tuning: &AutoTuning) -> StreamBody {
+        let tuner = Tuner {
+            tuning: tuning.clone(),
+            estimator: ThroughputEstimator::new(),
+            stalled_since: None,
+        };
+        self.set_tuner(tuner);
+        self
+    }
+}
+
+/// The per-body side of an [AutoTuning](./struct.AutoTuning.html) handle, fed by a channel body.
+pub(crate) struct Tuner {
+    tuning: AutoTuning,
+    estimator: ThroughputEstimator,
+    // When the producer started waiting for a free buffer.
+    pub(crate) stalled_since: Option<Instant>,
+}
+
+impl Tuner {
+    /// Publishes the current buffers of the body.
+    pub(crate) fn attach(&self, capacity: usize, chunks: usize) {
+        let mut state = self.tuning.lock();
+        state.buffer_capacity = capacity;
+        state.in_flight_chunks = chunks;
+    }
+
+    /// Records that a chunk of `len` bytes was consumed `elapsed` after its emission.
+    pub(crate) fn record_drain(&mut self, len: usize, elapsed: Duration) {
+        self.estimator.record(len, elapsed);
+        self.tuning.lock().bytes_per_sec = self.estimator.bytes_per_sec();
+    }
+
+    /// Records that the producer waited `elapsed` for a free buffer.
+    pub(crate) fn record_stall(&mut self, elapsed: Duration) {
+        let mut state = self.tuning.lock();
+        state.stalls += 1;
+
+        let delay = state.delay.as_secs_f64();
+        let sample = elapsed.as_secs_f64().max(state.round_trip_time.as_secs_f64());
+        state.delay = Duration::from_secs_f64(delay + SAMPLE_WEIGHT * (sample - delay));
+    }
+
+    /// Returns the capacity and the number of the buffers to use, once the bandwidth is known.
+    pub(crate) fn targets(&self) -> Option<(usize, usize)> {
+        let mut state = self.tuning.lock();
+        let rate = state.bytes_per_sec?;
+
+        let capacity = ((rate * TARGET_CHUNK_TIME.as_secs_f64()) as usize)
+            .max(state.min_capacity)
+            .min(state.max_capacity);
+        let chunks = ((state.bandwidth_delay_product() / capacity as f64).ceil() as usize)
+            .max(1)
+            .min(state.max_chunks);
+
+        state.buffer_capacity = capacity;
+        state.in_flight_chunks = chunks;
+        Some((capacity, chunks))
+    }
+}
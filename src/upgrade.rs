@@ -0,0 +1,179 @@
+use crate::body::StreamBody;
+use bytes::Buf;
+use http_body::Body;
+use std::future::{poll_fn, Future};
+use std::marker::Unpin;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{self, AsyncRead, AsyncWrite, AsyncWriteExt};
+
+const COPY_BUF_SIZE: usize = 8 * 1024;
+
+impl StreamBody {
+    /// Writes the remaining data of the body to `w` and flushes it, returning the number of written bytes.
+    ///
+    /// Useful when a connection is taken over, e.g. after a `101 Switching Protocols` response, and the data still
+    /// buffered in the body has to reach the raw connection first.
+    pub async fn write_remaining_to<W: AsyncWrite + Unpin>(mut self, w: &mut W) -> io::Result<u64> {
+        let mut written = 0;
+
+        while let Some(data) = poll_fn(|cx| Pin::new(&mut self).poll_data(cx)).await {
+            let data = data?;
+            w.write_all(data.bytes()).await?;
+            written += data.remaining() as u64;
+        }
+
+        w.flush().await?;
+        Ok(written)
+    }
+
+    /// Hands a connection over from this body to a raw upgraded connection: the remaining data of the body is written
+    /// to `upgraded` first, then data is copied in both directions between `upgraded` and `peer`, e.g. a backend
+    /// connection, until both sides are closed.
+    ///
+    /// It has the semantics of tokio's `copy_bidirectional`: when one side reaches its end, the other side's writer
+    /// is shut down while the opposite direction keeps going. Returns the number of bytes copied from `upgraded` to
+    /// `peer` and from `peer` to `upgraded`, the latter excluding the data of the body.
+    pub async fn hand_off<A, B>(self, upgraded: &mut A, peer: &mut B) -> io::Result<(u64, u64)>
+    where
+        A: AsyncRead + AsyncWrite + Unpin,
+        B: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.write_remaining_to(upgraded).await?;
+
+        CopyBidirectional {
+            a: upgraded,
+            b: peer,
+            a_to_b: TransferState::Running(CopyBuffer::new()),
+            b_to_a: TransferState::Running(CopyBuffer::new()),
+        }
+        .await
+    }
+}
+
+struct CopyBuffer {
+    buf: Box<[u8]>,
+    pos: usize,
+    cap: usize,
+    read_done: bool,
+    need_flush: bool,
+    amount: u64,
+}
+
+impl CopyBuffer {
+    fn new() -> CopyBuffer {
+        CopyBuffer {
+            buf: vec![0_u8; COPY_BUF_SIZE].into_boxed_slice(),
+            pos: 0,
+            cap: 0,
+            read_done: false,
+            need_flush: false,
+            amount: 0,
+        }
+    }
+
+    fn poll_copy<R, W>(&mut self, cx: &mut Context, r: &mut R, w: &mut W) -> Poll<io::Result<u64>>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        loop {
+            if self.pos == self.cap && !self.read_done {
+                match Pin::new(&mut *r).poll_read(cx, &mut self.buf) {
+                    Poll::Ready(Ok(0)) => self.read_done = true,
+                    Poll::Ready(Ok(n)) => {
+                        self.pos = 0;
+                        self.cap = n;
+                    }
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                    Poll::Pending => {
+                        // Pushes out what was written so far while waiting for more data.
+                        if self.need_flush {
+                            ready!(Pin::new(&mut *w).poll_flush(cx))?;
+                            self.need_flush = false;
+                        }
+                        return Poll::Pending;
+                    }
+                }
+            }
+
+            while self.pos < self.cap {
+                let n = ready!(Pin::new(&mut *w).poll_write(cx, &self.buf[self.pos..self.cap]))?;
+                if n == 0 {
+                    return Poll::Ready(Err(write_zero()));
+                }
+                self.pos += n;
+                self.amount += n as u64;
+                self.need_flush = true;
+            }
+
+            if self.pos == self.cap && self.read_done {
+                ready!(Pin::new(&mut *w).poll_flush(cx))?;
+                return Poll::Ready(Ok(self.amount));
+            }
+        }
+    }
+}
+
+enum TransferState {
+    Running(CopyBuffer),
+    ShuttingDown(u64),
+    Done(u64),
+}
+
+struct CopyBidirectional<'a, A, B> {
+    a: &'a mut A,
+    b: &'a mut B,
+    a_to_b: TransferState,
+    b_to_a: TransferState,
+}
+
+fn poll_transfer<R, W>(cx: &mut Context, state: &mut TransferState, r: &mut R, w: &mut W) -> Poll<io::Result<u64>>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    loop {
+        match state {
+            TransferState::Running(buf) => {
+                let amount = ready!(buf.poll_copy(cx, r, w))?;
+                *state = TransferState::ShuttingDown(amount);
+            }
+            TransferState::ShuttingDown(amount) => {
+                ready!(Pin::new(&mut *w).poll_shutdown(cx))?;
+                *state = TransferState::Done(*amount);
+            }
+            TransferState::Done(amount) => return Poll::Ready(Ok(*amount)),
+        }
+    }
+}
+
+impl<'a, A, B> Future for CopyBidirectional<'a, A, B>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    type Output = io::Result<(u64, u64)>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let me = &mut *self;
+
+        let a_to_b = poll_transfer(cx, &mut me.a_to_b, &mut *me.a, &mut *me.b)?;
+        let b_to_a = poll_transfer(cx, &mut me.b_to_a, &mut *me.b, &mut *me.a)?;
+
+        match (a_to_b, b_to_a) {
+            (Poll::Ready(a_to_b), Poll::Ready(b_to_a)) => Poll::Ready(Ok((a_to_b, b_to_a))),
+            _ => Poll::Pending,
+        }
+    }
+}
+
+fn write_zero() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::WriteZero,
+        format!(
+            "{}: hand_off: Failed to write the data to the connection",
+            env!("CARGO_PKG_NAME")
+        ),
+    )
+}
@@ -0,0 +1,333 @@
+use crate::digest::{DigestMismatch, ExpectedDigest};
+use bytes::Buf;
+use http_body::Body;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::marker::Unpin;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::fs::{self, File};
+use tokio::io::{self, AsyncWriteExt};
+
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// When [save_body_to_file](./fn.save_body_to_file.html) syncs the saved file to the disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// The file is never synced, the data reaches the disk whenever the OS flushes it.
+    Never,
+    /// The file is synced once completely written, before the upload is reported as saved. It's the default.
+    OnComplete,
+    /// The data is synced every time the given number of bytes was written, and the file once completely written,
+    /// which bounds the dirty pages a large upload accumulates.
+    Every(u64),
+}
+
+/// The limits and policies of [save_body_to_file](./fn.save_body_to_file.html).
+///
+/// By default the size is unlimited, the file is synced once complete and no digest is verified.
+#[derive(Debug, Clone)]
+pub struct UploadLimits {
+    max_size: Option<u64>,
+    fsync: FsyncPolicy,
+    expected: Vec<ExpectedDigest>,
+}
+
+impl Default for UploadLimits {
+    fn default() -> UploadLimits {
+        UploadLimits {
+            max_size: None,
+            fsync: FsyncPolicy::OnComplete,
+            expected: Vec::new(),
+        }
+    }
+}
+
+impl UploadLimits {
+    /// Creates the default limits.
+    pub fn new() -> UploadLimits {
+        UploadLimits::default()
+    }
+
+    /// Fails the upload with an `InvalidData` error as soon as the body exceeds `max_size` bytes.
+    pub fn max_size(mut self, max_size: u64) -> UploadLimits {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Sets when the file is synced to the disk.
+    pub fn fsync(mut self, policy: FsyncPolicy) -> UploadLimits {
+        self.fsync = policy;
+        self
+    }
+
+    /// Verifies the saved data against the digests declared by the client, e.g. via
+    /// [ExpectedDigest::from_headers](./enum.ExpectedDigest.html#method.from_headers). A mismatch fails the upload
+    /// with a [DigestMismatch](./struct.DigestMismatch.html) error.
+    pub fn expect_digests(mut self, expected: Vec<ExpectedDigest>) -> UploadLimits {
+        self.expected = expected;
+        self
+    }
+}
+
+/// The outcome of a successful [save_body_to_file](./fn.save_body_to_file.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedFile {
+    len: u64,
+    sha256: [u8; 32],
+}
+
+impl SavedFile {
+    /// Returns the number of bytes saved.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns whether the saved file is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the SHA-256 checksum of the saved data, e.g. to store it along with the file.
+    pub fn sha256(&self) -> &[u8; 32] {
+        &self.sha256
+    }
+}
+
+/// Streams a body, typically an incoming request body, to a file at `path`, the upload counterpart of
+/// [StreamBody::from_file](./struct.StreamBody.html#method.from_file). It requires the `upload` feature.
+///
+/// The data is written as it arrives, with the size cap, sync policy and digests of `limits`, while its SHA-256
+/// checksum is computed. It's written to a temporary file next to `path`, which replaces `path` only once the upload
+/// completed. The temporary file is removed if the upload fails for any reason, i.e. a body error, an exceeded size,
+/// a digest mismatch or a write error, and also if the returned future is dropped before completion, so a partial
+/// file is never left behind and a file previously saved at `path` stays untouched.
+///
+/// # Examples
+///
+/// ```no_run
+/// use hyper::{Body, Request, Response};
+/// use stream_body::{save_body_to_file, ExpectedDigest, UploadLimits};
+///
+/// # async fn run(req: Request<Body>) -> std::io::Result<Response<Body>> {
+/// let limits = UploadLimits::new()
+///     .max_size(100 * 1024 * 1024)
+///     .expect_digests(ExpectedDigest::from_headers(req.headers()));
+///
+/// let saved = save_body_to_file(req.into_body(), "upload.bin", &limits).await?;
+/// Ok(Response::new(Body::from(format!("saved {} bytes", saved.len()))))
+/// # }
+/// ```
+pub async fn save_body_to_file<B, P>(mut body: B, path: P, limits: &UploadLimits) -> io::Result<SavedFile>
+where
+    B: Body + Unpin,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+    P: AsRef<Path>,
+{
+    let temp_path = temp_path(path.as_ref());
+    // Declared before the file, so the file is closed before it's removed.
+    let mut guard = PartialFile { path: None };
+    let mut file = File::create(&temp_path).await?;
+    guard.path = Some(temp_path.clone());
+
+    let mut md5 = limits.expected.iter().find_map(|expected| match expected {
+        ExpectedDigest::Md5(digest) => Some((*digest, Md5::new())),
+        _ => None,
+    });
+    let mut sha256 = Sha256::new();
+    let mut len = 0_u64;
+    let mut unsynced = 0_u64;
+
+    while let Some(data) = body.data().await {
+        let mut data = data.map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+        len += data.remaining() as u64;
+        if let Some(max_size) = limits.max_size {
+            if len > max_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{}: save_body_to_file: The body exceeds the limit of {} bytes",
+                        env!("CARGO_PKG_NAME"),
+                        max_size
+                    ),
+                ));
+            }
+        }
+
+        while data.has_remaining() {
+            let bytes = data.bytes();
+            file.write_all(bytes).await?;
+            sha256.update(bytes);
+            if let Some((_, ref mut hasher)) = md5 {
+                hasher.update(bytes);
+            }
+
+            let count = bytes.len();
+            unsynced += count as u64;
+            data.advance(count);
+        }
+
+        if let FsyncPolicy::Every(interval) = limits.fsync {
+            if unsynced >= interval {
+                file.sync_data().await?;
+                unsynced = 0;
+            }
+        }
+    }
+
+    let sha256: [u8; 32] = sha256.finalize().into();
+    let md5_mismatch = md5.map(|(expected, hasher)| hasher.finalize()[..] != expected[..]);
+    let mismatch = if md5_mismatch == Some(true) {
+        Some("md5")
+    } else if limits
+        .expected
+        .iter()
+        .any(|expected| matches!(expected, ExpectedDigest::Sha256(digest) if digest[..] != sha256[..]))
+    {
+        Some("sha-256")
+    } else {
+        None
+    };
+    if let Some(algorithm) = mismatch {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            DigestMismatch {
+                algorithm,
+                forwarded: false,
+            },
+        ));
+    }
+
+    file.flush().await?;
+    if limits.fsync != FsyncPolicy::Never {
+        file.sync_all().await?;
+    }
+    drop(file);
+    fs::rename(&temp_path, path.as_ref()).await?;
+
+    guard.path = None;
+    Ok(SavedFile { len, sha256 })
+}
+
+/// Returns a unique temporary path in the same directory as `path`, so the final rename stays on one file system.
+fn temp_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    let counter = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    path.with_file_name(format!(".{}.{}.{}.tmp", file_name, std::process::id(), counter))
+}
+
+/// Removes the temporary file being saved unless the upload completed.
+struct PartialFile {
+    path: Option<PathBuf>,
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.path {
+            if let Err(err) = std::fs::remove_file(path) {
+                log::warn!(
+                    "{}: save_body_to_file: Couldn't remove the partial file {}: {}",
+                    env!("CARGO_PKG_NAME"),
+                    path.display(),
+                    err
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::StreamBody;
+    use std::time::Duration;
+
+    // A directory holding a previously saved `upload.bin`.
+    fn upload_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("stream-body-upload-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("upload.bin"), b"previous").unwrap();
+        dir
+    }
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut files = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    #[tokio::test]
+    async fn a_completed_upload_replaces_the_file() {
+        let dir = upload_dir("completed");
+        let path = dir.join("upload.bin");
+
+        let body = StreamBody::concat(vec![StreamBody::from("hello "), StreamBody::from("world")]);
+        let saved = save_body_to_file(body, &path, &UploadLimits::new()).await.unwrap();
+        assert_eq!(saved.len(), 11);
+        assert_eq!(saved.sha256()[..], Sha256::digest(b"hello world")[..]);
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello world");
+        assert_eq!(files(&dir), vec!["upload.bin"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_failed_upload_keeps_the_previous_file() {
+        let dir = upload_dir("failed");
+        let path = dir.join("upload.bin");
+
+        let chunks = vec![
+            Ok("partial"),
+            Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset")),
+        ];
+        let body = hyper::Body::wrap_stream(futures_util::stream::iter(chunks));
+        assert!(save_body_to_file(body, &path, &UploadLimits::new()).await.is_err());
+
+        let limits = UploadLimits::new().max_size(4);
+        let err = save_body_to_file(StreamBody::from("too large"), &path, &limits)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let limits = UploadLimits::new().expect_digests(vec![ExpectedDigest::Sha256([0; 32])]);
+        let err = save_body_to_file(StreamBody::from("tampered"), &path, &limits)
+            .await
+            .unwrap_err();
+        assert!(err.get_ref().unwrap().is::<DigestMismatch>());
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"previous");
+        assert_eq!(files(&dir), vec!["upload.bin"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_upload_keeps_the_previous_file() {
+        let dir = upload_dir("cancelled");
+        let path = dir.join("upload.bin");
+
+        // The writer stays open, so the upload only ends when it's dropped.
+        let (mut writer, body) = StreamBody::channel();
+        let limits = UploadLimits::new();
+        let upload = Box::pin(save_body_to_file(body, &path, &limits));
+        let write = Box::pin(async {
+            writer.write_all(b"partial").await.unwrap();
+            tokio::time::delay_for(Duration::from_millis(50)).await;
+        });
+        match futures_util::future::select(upload, write).await {
+            futures_util::future::Either::Right(((), upload)) => drop(upload),
+            futures_util::future::Either::Left(_) => panic!("the upload completed"),
+        }
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"previous");
+        assert_eq!(files(&dir), vec!["upload.bin"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
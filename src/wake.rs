@@ -0,0 +1,31 @@
+use crate::body::StreamBody;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+/// Describes when a dropped chunk wakes the task polling its body up, see
+/// [StreamBody::with_wake_strategy](./struct.StreamBody.html#method.with_wake_strategy).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WakeStrategy {
+    /// Every dropped chunk wakes the body up if it ever waited for it, which is the default.
+    #[default]
+    Eager,
+    /// Only the first chunk dropped since the body last waited for one wakes it up, and none does once the body was
+    /// polled again anyway. It cuts the redundant wakeups of bodies with several buffers in flight, e.g. the ones
+    /// created via [channel_with_buffers](./struct.StreamBody.html#method.channel_with_buffers).
+    Coalesced,
+}
+
+impl StreamBody {
+    /// Sets when the dropped chunks wake the task polling the body up.
+    ///
+    /// It applies to bodies created from bytes, via a channel or from a source, and has no effect on adapter bodies, so
+    /// it has to be set before wrapping the body.
+    pub fn with_wake_strategy(mut self, strategy: WakeStrategy) -> StreamBody {
+        let needs_wake = match strategy {
+            WakeStrategy::Eager => None,
+            WakeStrategy::Coalesced => Some(Arc::new(AtomicBool::new(false))),
+        };
+        self.set_needs_wake(needs_wake);
+        self
+    }
+}
@@ -0,0 +1,56 @@
+use crate::body::StreamBody;
+use crate::state::State;
+use tokio::io;
+
+/// Describes what a body does when one of its chunks was dropped before being fully consumed, see
+/// [StreamBody::with_abandon_watchdog](./struct.StreamBody.html#method.with_abandon_watchdog).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbandonAction {
+    /// Logs a warning via [log::warn!](https://docs.rs/log/0.4.10/log/macro.warn.html) and streams on.
+    Log,
+    /// Yields an error, so the stream is reset instead of ending as if all the data was sent.
+    Error,
+}
+
+impl StreamBody {
+    /// Watches for chunks dropped with bytes still remaining, which points to a consumer bug or an aborted send that
+    /// would otherwise go unnoticed as the body still ends normally.
+    ///
+    /// It applies to bodies created from bytes, via a channel, e.g. `from_reader`, or from a source, and has no effect
+    /// on adapter bodies whose chunks aren't tied to a source buffer. Adapters which deliberately skip data, like byte
+    /// ranges, drop partial chunks too, so the watchdog is meant for bodies handed to the server as is.
+    pub fn with_abandon_watchdog(mut self, action: AbandonAction) -> StreamBody {
+        self.set_abandon_action(action);
+        self
+    }
+}
+
+/// Checks the state of a body for abandoned bytes, returning the error to yield if the action asks for it.
+pub(crate) fn check_abandoned(state: &mut State, action: Option<AbandonAction>, kind: &str) -> Option<io::Error> {
+    let action = action?;
+    if state.abandoned_bytes == 0 {
+        return None;
+    }
+
+    let abandoned_bytes = std::mem::take(&mut state.abandoned_bytes);
+    match action {
+        AbandonAction::Log => {
+            log::warn!(
+                "{}: StreamBody [{}]: A chunk was dropped with {} bytes remaining",
+                env!("CARGO_PKG_NAME"),
+                kind,
+                abandoned_bytes
+            );
+            None
+        }
+        AbandonAction::Error => Some(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "{}: StreamBody [{}]: A chunk was dropped with {} bytes remaining",
+                env!("CARGO_PKG_NAME"),
+                kind,
+                abandoned_bytes
+            ),
+        )),
+    }
+}
@@ -0,0 +1,239 @@
+use crate::body::StreamBody;
+use crate::data::StreamData;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io;
+use zstd::bulk::Compressor;
+
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A5E;
+const SEEKABLE_MAGIC: u32 = 0x8F92_EAB1;
+// The frames are capped so their sizes fit the 32 bits entries of the seek table.
+const MAX_FRAME_SIZE: usize = 1 << 30;
+
+impl StreamBody {
+    /// Compresses the body in the [zstd seekable format](https://github.com/facebook/zstd/blob/dev/contrib/seekable_format/zstd_seekable_compression_format.md),
+    /// one zstd frame per chunk with the seek table appended at the end, so a stored copy, e.g. one written by a
+    /// cache-fill [tee](#method.tee), can later be read from any frame without decoding the previous ones. It requires
+    /// the `zstd-seekable` feature.
+    ///
+    /// The level goes from 1 (fastest) to 22 (best compression), 0 meaning the default level of zstd. Each chunk is
+    /// compressed on its own, so the chunk sizes trade the compression ratio for the granularity of the seeks, e.g.
+    /// via [StreamBodyBuilder::min_chunk](./struct.StreamBodyBuilder.html#method.min_chunk). The seek table has no
+    /// checksums.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use stream_body::StreamBody;
+    ///
+    /// # async fn run() -> std::io::Result<()> {
+    /// let file = tokio::fs::File::open("large-file").await?;
+    /// let copy = tokio::fs::File::create("large-file.zst").await?;
+    ///
+    /// let body = StreamBody::from_reader_inline(file).zstd_seekable(3).tee(copy);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn zstd_seekable(self, level: i32) -> StreamBody {
+        self.wrap_with(|inner| ZstdSeekable {
+            inner,
+            level,
+            compressor: None,
+            queue: VecDeque::new(),
+            entries: Vec::new(),
+            reached_eof: false,
+            finished: false,
+        })
+    }
+}
+
+struct ZstdSeekable {
+    inner: StreamBody,
+    level: i32,
+    // The compression context reused for every frame, created on the first one.
+    compressor: Option<Compressor<'static>>,
+    // The frames left to emit.
+    queue: VecDeque<StreamData>,
+    // The compressed and decompressed sizes of the emitted frames.
+    entries: Vec<(u32, u32)>,
+    reached_eof: bool,
+    // Whether the seek table was emitted.
+    finished: bool,
+}
+
+impl ZstdSeekable {
+    /// Compresses `data` into a frame and queues it.
+    fn push_frame(&mut self, mut data: StreamData) -> io::Result<()> {
+        let compressor = match self.compressor {
+            Some(ref mut compressor) => compressor,
+            None => self.compressor.get_or_insert(Compressor::new(self.level)?),
+        };
+
+        let len = data.remaining();
+        let frame = compressor.compress(data.bytes())?;
+        data.advance(len);
+
+        self.entries.push((frame.len() as u32, len as u32));
+        self.queue.push_back(StreamData::from_bytes(Bytes::from(frame)));
+        Ok(())
+    }
+
+    fn seek_table(&self) -> Bytes {
+        let table_len = self.entries.len() * 8 + 9;
+        let mut table = BytesMut::with_capacity(8 + table_len);
+        table.put_u32_le(SKIPPABLE_MAGIC);
+        table.put_u32_le(table_len as u32);
+        for &(compressed, decompressed) in self.entries.iter() {
+            table.put_u32_le(compressed);
+            table.put_u32_le(decompressed);
+        }
+        table.put_u32_le(self.entries.len() as u32);
+        // The descriptor, without checksums.
+        table.put_u8(0);
+        table.put_u32_le(SEEKABLE_MAGIC);
+        table.freeze()
+    }
+}
+
+impl Body for ZstdSeekable {
+    type Data = StreamData;
+    type Error = io::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let me = &mut *self;
+
+        loop {
+            if let Some(data) = me.queue.pop_front() {
+                return Poll::Ready(Some(Ok(data)));
+            }
+
+            if me.finished {
+                return Poll::Ready(None);
+            }
+            if me.reached_eof {
+                me.finished = true;
+                return Poll::Ready(Some(Ok(StreamData::from_bytes(me.seek_table()))));
+            }
+
+            match Pin::new(&mut me.inner).poll_data(cx) {
+                Poll::Ready(Some(Ok(mut data))) => {
+                    let mut result = Ok(());
+                    while result.is_ok() && data.remaining() > MAX_FRAME_SIZE {
+                        let part = data.split_to(MAX_FRAME_SIZE);
+                        result = me.push_frame(part);
+                    }
+                    // Empty chunks don't need a frame.
+                    if result.is_ok() && data.has_remaining() {
+                        result = me.push_frame(data);
+                    }
+
+                    if let Err(err) = result {
+                        return Poll::Ready(Some(Err(io::Error::new(
+                            err.kind(),
+                            format!(
+                                "{}: StreamBody [Zstd Seekable]: Failed to compress a frame: {}",
+                                env!("CARGO_PKG_NAME"),
+                                err
+                            ),
+                        ))));
+                    }
+                }
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                Poll::Ready(None) => me.reached_eof = true,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.finished && self.queue.is_empty()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        if self.is_end_stream() {
+            return SizeHint::with_exact(0);
+        }
+        SizeHint::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_le(buf: &[u8]) -> u32 {
+        u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]])
+    }
+
+    async fn compressed(chunks: &[&[u8]]) -> Vec<u8> {
+        let body = StreamBody::concat(chunks.iter().map(|chunk| StreamBody::from(chunk.to_vec())).collect());
+        let mut body = body.zstd_seekable(3);
+        let mut out = Vec::new();
+        while let Some(data) = body.data().await {
+            out.extend_from_slice(data.unwrap().bytes());
+        }
+        assert!(body.is_end_stream());
+        out
+    }
+
+    // Returns the compressed and decompressed sizes of the seek table entries.
+    fn seek_table(out: &[u8]) -> Vec<(u32, u32)> {
+        let footer = &out[out.len() - 9..];
+        assert_eq!(u32_le(&footer[5..]), SEEKABLE_MAGIC);
+        assert_eq!(footer[4], 0);
+        let frames = u32_le(footer) as usize;
+
+        let table = &out[out.len() - 8 - frames * 8 - 9..];
+        assert_eq!(u32_le(table), SKIPPABLE_MAGIC);
+        assert_eq!(u32_le(&table[4..]) as usize, table.len() - 8);
+
+        table[8..8 + frames * 8]
+            .chunks(8)
+            .map(|entry| (u32_le(entry), u32_le(&entry[4..])))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn every_chunk_is_a_frame_listed_in_the_seek_table() {
+        let first = b"hello hello hello hello".to_vec();
+        let second = (0..10_000).map(|i| (i % 7) as u8).collect::<Vec<_>>();
+        let third = b"!".to_vec();
+        let out = compressed(&[&first, b"", &second, &third]).await;
+
+        // A regular decoder skips the seek table.
+        let expected = [&first[..], &second[..], &third[..]].concat();
+        assert_eq!(zstd::stream::decode_all(&out[..]).unwrap(), expected);
+
+        let entries = seek_table(&out);
+        assert_eq!(entries.len(), 3);
+        let mut offset = 0;
+        for (&(compressed_len, decompressed_len), chunk) in entries.iter().zip([first, second, third].iter()) {
+            assert_eq!(decompressed_len as usize, chunk.len());
+
+            // Each frame decodes on its own, from the offset given by the table.
+            let frame = &out[offset..offset + compressed_len as usize];
+            assert_eq!(&zstd::bulk::decompress(frame, chunk.len()).unwrap(), chunk);
+            offset += compressed_len as usize;
+        }
+        assert_eq!(offset + 8 + entries.len() * 8 + 9, out.len());
+    }
+
+    #[tokio::test]
+    async fn empty_bodies_only_have_a_seek_table() {
+        let out = compressed(&[]).await;
+        assert!(seek_table(&out).is_empty());
+        assert_eq!(out.len(), 17);
+        assert!(zstd::stream::decode_all(&out[..]).unwrap().is_empty());
+    }
+}
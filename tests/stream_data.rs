@@ -0,0 +1,75 @@
+use bytes::Buf;
+use http_body::Body;
+use stream_body::{StreamBody, StreamData};
+use tokio::io::AsyncWriteExt;
+
+async fn first_chunk(mut body: StreamBody) -> StreamData {
+    body.data().await.unwrap().unwrap()
+}
+
+#[tokio::test]
+async fn advance_within_bounds() {
+    let mut data = first_chunk(StreamBody::from("hello world")).await;
+
+    data.advance(0);
+    assert_eq!(data.bytes(), b"hello world");
+
+    data.advance(6);
+    assert_eq!(data.remaining(), 5);
+    assert_eq!(data.bytes(), b"world");
+
+    data.advance(5);
+    assert_eq!(data.remaining(), 0);
+    assert_eq!(data.bytes(), b"");
+}
+
+#[tokio::test]
+#[should_panic(expected = "cannot advance past `remaining`")]
+async fn advance_past_remaining_panics() {
+    let mut data = first_chunk(StreamBody::from("hello")).await;
+    data.advance(6);
+}
+
+#[tokio::test]
+#[should_panic(expected = "cannot advance past `remaining`")]
+async fn advance_past_remaining_after_partial_advance_panics() {
+    let mut data = first_chunk(StreamBody::from("hello")).await;
+    data.advance(3);
+    data.advance(3);
+}
+
+#[tokio::test]
+async fn try_advance_past_remaining_leaves_chunk_unchanged() {
+    let mut data = first_chunk(StreamBody::from("hello")).await;
+
+    assert!(data.try_advance(2).is_ok());
+    assert_eq!(data.bytes(), b"llo");
+
+    let err = data.try_advance(4).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(data.bytes(), b"llo");
+
+    assert!(data.try_advance(3).is_ok());
+    assert!(!data.has_remaining());
+    assert!(data.try_advance(0).is_ok());
+    assert!(data.try_advance(1).is_err());
+}
+
+#[tokio::test]
+async fn advance_on_channel_chunk() {
+    let (mut writer, body) = StreamBody::channel();
+
+    tokio::spawn(async move {
+        writer.write_all(b"0123456789").await.unwrap();
+    });
+
+    let mut data = first_chunk(body).await;
+    let len = data.remaining();
+    assert!(len > 0);
+
+    data.advance(len - 1);
+    assert_eq!(data.remaining(), 1);
+    assert!(data.try_advance(2).is_err());
+    data.advance(1);
+    assert_eq!(data.bytes(), b"");
+}
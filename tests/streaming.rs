@@ -0,0 +1,1094 @@
+use bytes::{Buf, Bytes};
+use http_body::Body;
+use std::io::ErrorKind;
+use std::time::Duration;
+use stream_body::test::{self, FailingReader, SlowConsumer};
+use stream_body::StreamBody;
+use tokio::io::AsyncWriteExt;
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+#[tokio::test]
+async fn collect_static_body() {
+    let body = StreamBody::from("hello world");
+    assert_eq!(test::collect(body).await.unwrap(), b"hello world");
+}
+
+#[tokio::test]
+async fn channel_delivers_everything_to_a_slow_consumer() {
+    let data = payload(64 * 1024);
+    let (mut writer, body) = StreamBody::channel_with_capacity(1024);
+
+    let expected = data.clone();
+    let producer = tokio::spawn(async move {
+        for part in data.chunks(700) {
+            writer.write_all(part).await.unwrap();
+        }
+    });
+
+    let consumer = SlowConsumer::new(Duration::from_millis(1)).poll_delay(Duration::from_millis(1));
+    assert_eq!(consumer.consume(body).await.unwrap(), expected);
+    producer.await.unwrap();
+}
+
+#[tokio::test]
+async fn channel_with_buffers_keeps_the_order() {
+    let data = payload(32 * 1024);
+    let (mut writer, body) = StreamBody::channel_with_buffers(4, 512);
+
+    let expected = data.clone();
+    tokio::spawn(async move {
+        writer.write_all(&data).await.unwrap();
+    });
+
+    let consumer = SlowConsumer::new(Duration::from_millis(1));
+    assert_eq!(consumer.consume(body).await.unwrap(), expected);
+}
+
+#[tokio::test]
+async fn channel_with_buffers_reads_ahead_while_chunks_are_in_flight() {
+    use futures_util::FutureExt;
+
+    let (mut writer, mut body) = StreamBody::channel_with_buffers(2, 4);
+    tokio::spawn(async move {
+        writer.write_all(b"abcdefghijkl").await.unwrap();
+    });
+
+    let first = body.data().await.unwrap().unwrap();
+    let second = body.data().await.unwrap().unwrap();
+    assert_eq!(first.bytes(), b"abcd");
+    assert_eq!(second.bytes(), b"efgh");
+
+    // The writer has more data, but both buffers are in flight.
+    tokio::time::delay_for(Duration::from_millis(10)).await;
+    assert!(body.data().now_or_never().is_none());
+
+    drop(first);
+    let third = body.data().now_or_never().unwrap().unwrap().unwrap();
+    assert_eq!(third.bytes(), b"ijkl");
+    assert_eq!(second.bytes(), b"efgh");
+    drop(second);
+    drop(third);
+    assert!(body.data().await.is_none());
+}
+
+#[tokio::test]
+async fn coalesced_wakeups_wake_the_body_once_for_many_dropped_chunks() {
+    use futures_util::task::{self, ArcWake};
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use stream_body::WakeStrategy;
+
+    struct WakeCounter(AtomicUsize);
+
+    impl ArcWake for WakeCounter {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    async fn wakes_for_dropped_chunks(strategy: WakeStrategy) -> usize {
+        let (mut writer, body) = StreamBody::channel_with_buffers(4, 4);
+        let mut body = body.with_wake_strategy(strategy);
+        tokio::spawn(async move {
+            writer.write_all(&payload(20)).await.unwrap();
+        });
+
+        let mut chunks = Vec::new();
+        for _ in 0..4 {
+            chunks.push(body.data().await.unwrap().unwrap());
+        }
+        // Lets the writer hand over the last chunk.
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+
+        let wakes = Arc::new(WakeCounter(AtomicUsize::new(0)));
+        let waker = task::waker(Arc::clone(&wakes));
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut body).poll_data(&mut cx).is_pending());
+
+        chunks.clear();
+        let count = wakes.0.load(Ordering::SeqCst);
+        match Pin::new(&mut body).poll_data(&mut cx) {
+            Poll::Ready(Some(Ok(data))) => assert_eq!(data.bytes(), &payload(20)[16..]),
+            _ => panic!("the body didn't read the last chunk"),
+        }
+        count
+    }
+
+    assert_eq!(wakes_for_dropped_chunks(WakeStrategy::Coalesced).await, 1);
+    assert_eq!(wakes_for_dropped_chunks(WakeStrategy::Eager).await, 4);
+}
+
+#[tokio::test]
+async fn from_reader_forwards_the_data_before_the_error() {
+    let reader = FailingReader::new("partial", ErrorKind::ConnectionReset);
+    let mut chunks = Vec::new();
+    let mut body = StreamBody::from_reader_inline(reader);
+
+    let err = loop {
+        match body.data().await {
+            Some(Ok(data)) => chunks.extend_from_slice(data.bytes()),
+            Some(Err(err)) => break err,
+            None => panic!("the body ended without the reader error"),
+        }
+    };
+    assert_eq!(chunks, b"partial");
+    assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+}
+
+#[tokio::test]
+async fn from_reader_inline_fails_with_a_slow_consumer() {
+    let reader = FailingReader::new(payload(4096), ErrorKind::BrokenPipe).read_size(100);
+    let body = StreamBody::from_reader_inline(reader);
+
+    let err = SlowConsumer::new(Duration::from_millis(1))
+        .consume(body)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+}
+
+#[tokio::test]
+async fn chunk_channel_keeps_the_chunk_boundaries() {
+    let (mut writer, body) = StreamBody::chunk_channel();
+
+    tokio::spawn(async move {
+        writer.write_all(b"event: a\n").await.unwrap();
+        writer.write_all(b"data: 1\n\n").await.unwrap();
+        writer.flush_chunk().await.unwrap();
+        writer.send_chunk(Bytes::from_static(b"data: 2\n\n")).await.unwrap();
+    });
+
+    let chunks = test::collect_chunks(body).await.unwrap();
+    assert_eq!(
+        chunks,
+        vec![
+            Bytes::from_static(b"event: a\ndata: 1\n\n"),
+            Bytes::from_static(b"data: 2\n\n"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn chunk_channel_queue_is_bounded_by_bytes() {
+    use futures_util::FutureExt;
+
+    let (mut writer, body) = StreamBody::chunk_channel_with_queue(64);
+
+    // Many small chunks are queued without the body being polled.
+    for _ in 0..8 {
+        let sent = writer.send_chunk(Bytes::from(payload(8))).now_or_never();
+        assert!(matches!(sent, Some(Ok(()))));
+    }
+    // A chunk exceeding the bound waits for the queue to drain, it stays pending in the writer.
+    assert!(writer.send_chunk(Bytes::from(payload(100))).now_or_never().is_none());
+
+    let producer = tokio::spawn(async move {
+        writer.flush_chunk().await.unwrap();
+    });
+    let chunks = test::collect_chunks(body).await.unwrap();
+    assert_eq!(chunks.len(), 9);
+    assert_eq!(chunks[8].len(), 100);
+    producer.await.unwrap();
+}
+
+#[tokio::test]
+async fn send_borrowed_waits_for_the_consumer() {
+    let (mut writer, body) = StreamBody::chunk_channel();
+
+    let producer = tokio::spawn(async move {
+        let mut frame = vec![0u8; 16];
+        for i in 0..4u8 {
+            frame.iter_mut().for_each(|byte| *byte = i);
+            writer.send_borrowed(&frame).await.unwrap();
+        }
+    });
+
+    let data = SlowConsumer::new(Duration::from_millis(5)).consume(body).await.unwrap();
+    let expected = (0..4u8).flat_map(|i| vec![i; 16]).collect::<Vec<_>>();
+    assert_eq!(data, expected);
+    producer.await.unwrap();
+}
+
+#[tokio::test]
+async fn send_borrowed_fails_once_the_body_is_dropped() {
+    let (mut writer, body) = StreamBody::chunk_channel();
+    drop(body);
+
+    let err = writer.send_borrowed(b"lost").await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+}
+
+#[tokio::test]
+async fn builder_shapes_the_chunks() {
+    let (mut writer, body) = StreamBody::builder().min_chunk(8).max_chunk(16).channel();
+
+    tokio::spawn(async move {
+        writer.write_all(&payload(40)).await.unwrap();
+    });
+
+    let chunks = test::collect_chunks(body).await.unwrap();
+    assert!(chunks.iter().all(|chunk| chunk.len() <= 16));
+    assert_eq!(chunks.concat(), payload(40));
+}
+
+#[tokio::test]
+async fn chain_and_limits() {
+    let body = StreamBody::from("hello ").chain(StreamBody::from("world"));
+    assert_eq!(test::collect(body).await.unwrap(), b"hello world");
+
+    let body = StreamBody::from("hello world").limited(5);
+    assert_eq!(test::collect(body).await.unwrap_err().kind(), ErrorKind::InvalidData);
+
+    let body = StreamBody::from("hello").exact(6);
+    assert_eq!(test::collect(body).await.unwrap_err().kind(), ErrorKind::UnexpectedEof);
+}
+
+#[tokio::test]
+async fn auto_tuning_reports_the_chosen_buffers() {
+    let tuning = stream_body::AutoTuning::new().capacity_range(1024, 4096).max_chunks(4);
+    let (mut writer, body) = StreamBody::channel();
+    let body = body.with_auto_tuning(&tuning);
+    assert_eq!((tuning.in_flight_chunks(), tuning.buffer_capacity()), (1, 8 * 1024));
+
+    let data = payload(256 * 1024);
+    let expected = data.clone();
+    tokio::spawn(async move {
+        writer.write_all(&data).await.unwrap();
+    });
+
+    let consumer = SlowConsumer::new(Duration::from_millis(1));
+    assert_eq!(consumer.consume(body).await.unwrap(), expected);
+    assert!(tuning.bytes_per_sec().is_some());
+    assert!((1024..=4096).contains(&tuning.buffer_capacity()));
+    assert!((1..=4).contains(&tuning.in_flight_chunks()));
+}
+
+#[tokio::test]
+async fn bodies_are_fused_after_an_error_or_their_end() {
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use stream_body::{Fill, Source};
+
+    // Fails on its second poll, and would produce data again afterwards.
+    struct Flaky(Arc<AtomicUsize>);
+
+    impl Source for Flaky {
+        fn poll_fill(self: Pin<&mut Self>, _cx: &mut Context, _buf: &mut [u8]) -> Poll<std::io::Result<Fill>> {
+            match self.0.fetch_add(1, Ordering::SeqCst) {
+                1 => Poll::Ready(Err(std::io::Error::new(ErrorKind::ConnectionReset, "reset"))),
+                _ => Poll::Ready(Ok(Fill::Chunk(Bytes::from("data")))),
+            }
+        }
+    }
+
+    let polls = Arc::new(AtomicUsize::new(0));
+    let mut body = StreamBody::from_source(Flaky(Arc::clone(&polls)));
+    assert_eq!(body.data().await.unwrap().unwrap().bytes(), b"data");
+    assert!(!body.is_terminated());
+    match body.data().await {
+        Some(Err(err)) => assert_eq!(err.kind(), ErrorKind::ConnectionReset),
+        _ => panic!("the body didn't fail"),
+    }
+
+    assert!(body.is_terminated());
+    assert!(body.is_end_stream());
+    assert_eq!(body.size_hint().exact(), Some(0));
+    assert!(body.data().await.is_none());
+    assert!(body.data().await.is_none());
+    assert_eq!(polls.load(Ordering::SeqCst), 2);
+
+    let mut body = StreamBody::from("done");
+    assert_eq!(body.data().await.unwrap().unwrap().bytes(), b"done");
+    assert!(body.data().await.is_none());
+    assert!(body.is_terminated());
+    assert!(body.data().await.is_none());
+}
+
+#[tokio::test]
+async fn source_bodies_wait_for_their_chunks_like_channel_bodies() {
+    use futures_util::FutureExt;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use stream_body::{AbandonAction, Fill, Source};
+
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Source for Counted {
+        fn poll_fill(self: Pin<&mut Self>, _cx: &mut Context, _buf: &mut [u8]) -> Poll<std::io::Result<Fill>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Poll::Ready(Ok(Fill::Chunk(Bytes::from("data"))))
+        }
+    }
+
+    let polls = Arc::new(AtomicUsize::new(0));
+    let mut body =
+        StreamBody::from_source_with_len(Counted(Arc::clone(&polls)), 8).with_abandon_watchdog(AbandonAction::Error);
+
+    let mut first = body.data().await.unwrap().unwrap();
+    assert!(body.data().now_or_never().is_none());
+    assert_eq!(polls.load(Ordering::SeqCst), 1);
+
+    first.advance(4);
+    drop(first);
+    let mut second = body.data().now_or_never().unwrap().unwrap().unwrap();
+    assert_eq!(polls.load(Ordering::SeqCst), 2);
+    assert_eq!(body.size_hint().exact(), Some(0));
+    // With a watchdog, the body waits to see how its last chunk is dropped.
+    assert!(!body.is_end_stream());
+
+    // The last chunk is abandoned halfway, which the watchdog reports instead of ending the body.
+    second.advance(2);
+    drop(second);
+    assert!(!body.is_end_stream());
+    assert!(body.data().await.unwrap().is_err());
+}
+
+#[tokio::test]
+async fn body_pool_recycles_only_released_buffers() {
+    use futures_util::future;
+    use stream_body::BodyPool;
+
+    let pool = BodyPool::new(1024, 2);
+    for round in 0..3 {
+        let (mut writer, body) = pool.channel();
+        let data = payload(3000 + round);
+        let expected = data.clone();
+
+        let write = async move { writer.write_all(&data).await.unwrap() };
+        let (_, collected) = future::join(write, test::collect(body)).await;
+        assert_eq!(collected.unwrap(), expected);
+        // The buffer of the previous round was reused, so only one is ever idle.
+        assert_eq!(pool.idle_count(), 1);
+    }
+
+    let bodies = (0..3).map(|_| pool.channel()).collect::<Vec<_>>();
+    assert_eq!(pool.idle_count(), 0);
+    drop(bodies);
+    assert_eq!(pool.idle_count(), 2);
+
+    // A chunk outliving its body keeps the buffer out of the pool.
+    let pool = BodyPool::new(1024, 2);
+    let (mut writer, mut body) = pool.channel();
+    let (written, data) = future::join(writer.write_all(b"held"), body.data()).await;
+    written.unwrap();
+    let data = data.unwrap().unwrap();
+    drop(writer);
+    drop(body);
+    assert_eq!(pool.idle_count(), 0);
+    assert_eq!(data.bytes(), b"held");
+    drop(data);
+    assert_eq!(pool.idle_count(), 0);
+}
+
+#[tokio::test]
+async fn quota_splits_a_chunk_without_copying() {
+    use futures_util::FutureExt;
+    use stream_body::Quota;
+
+    let text: &'static [u8] = b"hello world";
+    let quota = Quota::new(5);
+    let mut body = StreamBody::from(Bytes::from_static(text)).with_quota(&quota);
+
+    let head = body.data().await.unwrap().unwrap();
+    assert_eq!(head.bytes(), b"hello");
+    assert_eq!(head.bytes().as_ptr(), text.as_ptr());
+    drop(head);
+    assert!(body.data().now_or_never().is_none());
+
+    quota.refill(100);
+    let rest = body.data().await.unwrap().unwrap();
+    assert_eq!(rest.bytes(), b" world");
+    assert_eq!(rest.bytes().as_ptr(), text[5..].as_ptr());
+    drop(rest);
+    assert!(body.data().await.is_none());
+    assert_eq!(quota.remaining(), 94);
+}
+
+#[tokio::test]
+async fn fallback_only_replaces_a_source_failing_before_its_first_byte() {
+    let reader = FailingReader::new("", ErrorKind::NotFound);
+    let body = StreamBody::from_reader_inline(reader).with_fallback(|err| {
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        StreamBody::from("fallback")
+    });
+    assert_eq!(test::collect(body).await.unwrap(), b"fallback");
+
+    let reader = FailingReader::new("partial", ErrorKind::ConnectionReset);
+    let body = StreamBody::from_reader_inline(reader).with_fallback(|_| StreamBody::from("fallback"));
+    assert_eq!(
+        test::collect(body).await.unwrap_err().kind(),
+        ErrorKind::ConnectionReset
+    );
+}
+
+#[tokio::test]
+async fn checkpointed_body_resumes_from_the_last_checkpoint() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use stream_body::{Checkpoint, CheckpointStore, CheckpointWriter, FileCheckpointStore};
+
+    fn line(row: usize) -> String {
+        format!("row {}\n", row)
+    }
+
+    // Generates 100 rows, checkpointing every 10 rows, and fails at row `fail_at` if any.
+    async fn generate(
+        mut writer: CheckpointWriter,
+        checkpoint: Option<Checkpoint>,
+        fail_at: Option<usize>,
+        generated: Arc<AtomicUsize>,
+    ) -> std::io::Result<()> {
+        let start = checkpoint.map_or(0, |checkpoint| {
+            String::from_utf8(checkpoint.into_state()).unwrap().parse().unwrap()
+        });
+        for row in start..100 {
+            if Some(row) == fail_at {
+                return Err(std::io::Error::new(ErrorKind::ConnectionAborted, "crashed"));
+            }
+            if row % 10 == 0 {
+                writer.checkpoint(row.to_string());
+            }
+            writer.write_all(line(row).as_bytes()).await?;
+            generated.fetch_add(1, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    let dir = std::env::temp_dir().join(format!("stream-body-checkpoints-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let store = Arc::new(FileCheckpointStore::new(&dir));
+    let expected = (0..100).map(line).collect::<String>().into_bytes();
+
+    let generated = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&generated);
+    let mut body = StreamBody::checkpointed(Arc::clone(&store), "export", move |writer, checkpoint| {
+        generate(writer, checkpoint, Some(35), counter)
+    });
+    let mut received = Vec::new();
+    let err = loop {
+        match body.data().await.unwrap() {
+            Ok(data) => received.extend_from_slice(data.bytes()),
+            Err(err) => break err,
+        }
+    };
+    assert_eq!(err.kind(), ErrorKind::ConnectionAborted);
+    assert_eq!(received, &expected[..received.len()]);
+    // The checkpoints are saved on the blocking thread pool.
+    for _ in 0..100 {
+        if matches!(store.load("export").unwrap().last(), Some(checkpoint) if checkpoint.state() == b"30") {
+            break;
+        }
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+    }
+    let states = store
+        .load("export")
+        .unwrap()
+        .into_iter()
+        .map(Checkpoint::into_state)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        states,
+        vec![b"0".to_vec(), b"10".to_vec(), b"20".to_vec(), b"30".to_vec()]
+    );
+
+    // The resumed generation starts over from row 30 and skips what the client already received.
+    let generated = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&generated);
+    let body = StreamBody::resume_checkpointed(
+        Arc::clone(&store),
+        "export",
+        received.len() as u64,
+        move |writer, checkpoint| generate(writer, checkpoint, None, counter),
+    );
+    received.extend(test::collect(body).await.unwrap());
+    assert_eq!(received, expected);
+    assert_eq!(generated.load(Ordering::SeqCst), 70);
+    assert!(store.load("export").unwrap().is_empty());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn checkpoints_are_saved_off_the_body_keeping_only_the_latest() {
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+    use stream_body::{Checkpoint, CheckpointStore};
+
+    // Records the saved offsets, the first save blocking until the test releases it.
+    struct SlowStore {
+        gate: Mutex<Option<mpsc::Receiver<()>>>,
+        saved: Mutex<Vec<u64>>,
+        removed: Mutex<bool>,
+    }
+
+    impl CheckpointStore for SlowStore {
+        fn save(&self, _job: &str, checkpoints: &[Checkpoint]) -> std::io::Result<()> {
+            let gate = self.gate.lock().unwrap().take();
+            if let Some(gate) = gate {
+                gate.recv().unwrap();
+            }
+            self.saved.lock().unwrap().push(checkpoints.last().unwrap().offset());
+            Ok(())
+        }
+
+        fn load(&self, _job: &str) -> std::io::Result<Vec<Checkpoint>> {
+            Ok(Vec::new())
+        }
+
+        fn remove(&self, _job: &str) -> std::io::Result<()> {
+            *self.removed.lock().unwrap() = true;
+            Ok(())
+        }
+    }
+
+    let (release, gate) = mpsc::channel();
+    let store = Arc::new(SlowStore {
+        gate: Mutex::new(Some(gate)),
+        saved: Mutex::new(Vec::new()),
+        removed: Mutex::new(false),
+    });
+    let mut body = StreamBody::checkpointed(Arc::clone(&store), "export", |mut writer, _| async move {
+        for row in 0..5 {
+            writer.checkpoint(vec![row]);
+            writer.write_all(b"0123456789").await?;
+        }
+        Ok(())
+    });
+
+    let mut received = body.data().await.unwrap().unwrap().remaining();
+    for _ in 0..100 {
+        if store.gate.lock().unwrap().is_none() {
+            break;
+        }
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+    }
+
+    // The body streams on while the first save is blocked.
+    while received < 50 {
+        received += body.data().await.unwrap().unwrap().remaining();
+    }
+    assert!(store.saved.lock().unwrap().is_empty());
+
+    // The checkpoints emitted in the meantime are saved at once.
+    release.send(()).unwrap();
+    for _ in 0..100 {
+        if store.saved.lock().unwrap().len() == 2 {
+            break;
+        }
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+    }
+    assert_eq!(*store.saved.lock().unwrap(), vec![0, 40]);
+
+    // The body ends once the checkpoint of the completed job is removed.
+    assert!(body.data().await.is_none());
+    assert!(*store.removed.lock().unwrap());
+}
+
+#[tokio::test]
+async fn duplex_transforms_the_request_into_the_response() {
+    let upper_case = |mut reader: stream_body::BodyReader<StreamBody>, mut writer: async_pipe::PipeWriter| async move {
+        let mut buf = [0; 4];
+        loop {
+            let n = tokio::io::AsyncReadExt::read(&mut reader, &mut buf).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            writer.write_all(&buf[..n].to_ascii_uppercase()).await?;
+        }
+    };
+
+    let request = StreamBody::from("hello ").chain(StreamBody::from("world"));
+    let body = StreamBody::duplex(request, upper_case);
+    assert_eq!(test::collect(body).await.unwrap(), b"HELLO WORLD");
+
+    // The request exceeding the limit fails the response instead of truncating it.
+    let body = StreamBody::duplex_with_limit(StreamBody::from("hello world"), 5, upper_case);
+    assert_eq!(test::collect(body).await.unwrap_err().kind(), ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn idle_eviction_closes_the_idlest_body() {
+    use stream_body::IdleEviction;
+
+    test::pause();
+    let evictions = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let evicted = std::sync::Arc::clone(&evictions);
+    let eviction = IdleEviction::new(2)
+        .min_idle(Duration::from_millis(10))
+        .ignore_sse_comments()
+        .farewell("retry: 1000\n\n")
+        .on_evict(move |idle| evicted.lock().unwrap().push(idle));
+
+    let (mut heartbeats, first) = StreamBody::channel();
+    let mut first = first.with_idle_eviction(&eviction);
+    let (mut events, second) = StreamBody::channel();
+    let mut second = second.with_idle_eviction(&eviction);
+
+    test::advance(Duration::from_millis(20)).await;
+    let (written, data) = futures_util::future::join(heartbeats.write_all(b":\n"), first.data()).await;
+    written.unwrap();
+    data.unwrap().unwrap();
+    let (written, data) = futures_util::future::join(events.write_all(b"data: x\n\n"), second.data()).await;
+    written.unwrap();
+    data.unwrap().unwrap();
+
+    // Only heartbeats went through the first body, it's the idlest one.
+    let third = StreamBody::from("data: y\n\n").with_idle_eviction(&eviction);
+    assert_eq!(eviction.len(), 2);
+    assert_eq!(eviction.evicted(), 1);
+    assert_eq!(*evictions.lock().unwrap(), vec![Duration::from_millis(20)]);
+    assert_eq!(test::collect(first).await.unwrap(), b"retry: 1000\n\n");
+
+    drop(third);
+    assert_eq!(eviction.len(), 1);
+}
+
+#[cfg(feature = "futures")]
+#[tokio::test]
+async fn body_lines_split_across_chunks() {
+    use futures_util::StreamExt;
+    use stream_body::BodyReader;
+
+    let body = StreamBody::from("first\r\nsec").chain(StreamBody::from("ond\n\nlast"));
+    let lines = BodyReader::new(body).lines(16);
+    let lines = lines.map(|line| line.unwrap()).collect::<Vec<_>>().await;
+    assert_eq!(lines, vec!["first", "second", "", "last"]);
+
+    let body = StreamBody::from("short\nway too long for the limit\nnext");
+    let mut lines = BodyReader::new(body).lines(8);
+    assert_eq!(lines.next().await.unwrap().unwrap(), "short");
+    assert_eq!(lines.next().await.unwrap().unwrap_err().kind(), ErrorKind::InvalidData);
+    assert!(lines.next().await.is_none());
+}
+
+#[cfg(feature = "json")]
+#[tokio::test]
+async fn query_results_follow_the_error_policy() {
+    use futures_util::stream;
+    use serde_json::json;
+    use stream_body::{stream_query_results, QueryLimits, RowErrorPolicy, RowFormat};
+
+    let rows = || {
+        stream::iter(vec![
+            Ok(json!({ "id": 1, "name": "a, b" })),
+            Err(std::io::Error::new(ErrorKind::ConnectionReset, "connection lost")),
+            Ok(json!({ "id": 2, "name": "c" })),
+        ])
+    };
+
+    let res = stream_query_results(
+        rows(),
+        RowFormat::Csv,
+        &QueryLimits::new().on_error(RowErrorPolicy::Skip),
+    );
+    assert_eq!(res.headers()["content-type"], "text/csv; charset=utf-8");
+    let data = test::collect(res.into_body()).await.unwrap();
+    assert_eq!(data, b"id,name\r\n1,\"a, b\"\r\n2,c\r\n");
+
+    let limits = QueryLimits::new().on_error(RowErrorPolicy::Report);
+    let data = test::collect(stream_query_results(rows(), RowFormat::Array, &limits).into_body()).await;
+    assert_eq!(
+        data.unwrap(),
+        br#"[{"id":1,"name":"a, b"},{"error":"connection lost"}]"#.to_vec()
+    );
+
+    let mut body = stream_query_results(rows(), RowFormat::Lines, &QueryLimits::new()).into_body();
+    assert_eq!(
+        body.data().await.unwrap().unwrap().bytes(),
+        &b"{\"id\":1,\"name\":\"a, b\"}\n"[..]
+    );
+    match body.data().await {
+        Some(Err(err)) => assert_eq!(err.to_string(), "connection lost"),
+        _ => panic!("the body should fail"),
+    }
+
+    let limits = QueryLimits::new().max_rows(1).on_error(RowErrorPolicy::Skip);
+    let data = test::collect(stream_query_results(rows(), RowFormat::Array, &limits).into_body()).await;
+    assert_eq!(data.unwrap(), br#"[{"id":1,"name":"a, b"}]"#.to_vec());
+}
+
+#[cfg(feature = "shared-reads")]
+#[tokio::test]
+async fn shared_reads_read_a_popular_file_once() {
+    use stream_body::SharedReads;
+
+    let path = std::env::temp_dir().join(format!("stream-body-shared-reads-{}", std::process::id()));
+    let content = (0..600 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    std::fs::write(&path, &content).unwrap();
+
+    let reads = SharedReads::with_window(128 * 1024);
+    let first = reads.open(&path).await.unwrap();
+    let second = reads.open(&path).await.unwrap();
+    let mut stalled = reads.open(&path).await.unwrap();
+    assert_eq!(stalled.size_hint().exact(), Some(content.len() as u64));
+
+    let stalled_chunk = stalled.data().await.unwrap().unwrap();
+    let (first, second) = futures_util::future::join(test::collect(first), test::collect(second)).await;
+    assert_eq!(first.unwrap(), content);
+    assert_eq!(second.unwrap(), content);
+    assert_eq!(reads.bytes_read(), content.len() as u64);
+
+    // The stalled body fell behind the window, it reads the rest on its own.
+    let mut data = stalled_chunk.bytes().to_vec();
+    data.extend(test::collect(stalled).await.unwrap());
+    assert_eq!(data, content);
+    assert_eq!(reads.bytes_read(), content.len() as u64);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "gzip")]
+#[tokio::test]
+async fn compression_bounds_the_memory_of_a_stalled_client() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use stream_body::Encoding;
+
+    const TOTAL: usize = 8 * 1024 * 1024;
+    const MAX_OUTSTANDING: usize = 16 * 1024;
+
+    for &encoding in [Encoding::Gzip, Encoding::Deflate].iter() {
+        let (mut writer, body) = StreamBody::channel_with_capacity(4096);
+        let mut body = body.compressed_with_max_outstanding(encoding, MAX_OUTSTANDING);
+
+        // Incompressible data, so the compressed chunks are as large as the source.
+        let written = Arc::new(AtomicUsize::new(0));
+        let producer_written = Arc::clone(&written);
+        let producer = tokio::spawn(async move {
+            let mut seed = 0x2545_f491_u32;
+            let mut block = vec![0u8; 1024];
+            while producer_written.load(Ordering::SeqCst) < TOTAL {
+                for byte in block.iter_mut() {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 17;
+                    seed ^= seed << 5;
+                    *byte = seed as u8;
+                }
+                writer.write_all(&block).await.unwrap();
+                producer_written.fetch_add(block.len(), Ordering::SeqCst);
+            }
+        });
+
+        // The stalled client holds on to every chunk.
+        let mut held = Vec::new();
+        while let Ok(Some(data)) = tokio::time::timeout(Duration::from_millis(100), body.data()).await {
+            held.push(data.unwrap());
+        }
+        let held_bytes = held.iter().map(|data| data.remaining()).sum::<usize>();
+        assert!(held_bytes > 0);
+        assert!(held_bytes <= MAX_OUTSTANDING + 8 * 1024, "{} bytes held", held_bytes);
+        // The encoder only buffers a bounded amount of input on top of its output.
+        assert!(written.load(Ordering::SeqCst) < 256 * 1024);
+
+        // Once the client drains, the whole body goes through.
+        drop(held);
+        let rest = test::collect(body).await.unwrap();
+        assert!(held_bytes + rest.len() >= TOTAL);
+        producer.await.unwrap();
+    }
+}
+
+#[cfg(feature = "sse")]
+#[tokio::test]
+async fn heartbeats_are_suppressed_while_events_flow() {
+    use futures_util::FutureExt;
+    use std::future::Future;
+    use stream_body::{Event, EventWriter};
+
+    // Polls the future while advancing the simulated clock by small steps, as the timers don't fire on their own.
+    async fn drive<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        loop {
+            if let Some(output) = (&mut future).now_or_never() {
+                return output;
+            }
+            test::advance(Duration::from_millis(10)).await;
+        }
+    }
+
+    test::pause();
+    let mut events = EventWriter::new(Vec::new()).with_heartbeat(Duration::from_millis(200));
+
+    for _ in 0..8 {
+        let delay = tokio::time::delay_for(Duration::from_millis(50));
+        drive(events.wait_for(delay)).await.unwrap();
+        events.send(&Event::new("tick")).await.unwrap();
+    }
+    assert_eq!(events.heartbeats_sent(), 0);
+
+    let delay = tokio::time::delay_for(Duration::from_millis(500));
+    drive(events.wait_for(delay)).await.unwrap();
+    assert_eq!(events.heartbeats_sent(), 2);
+    assert!(events.get_ref().ends_with(b"data: tick\n\n:\n:\n"));
+}
+
+#[cfg(feature = "timeout")]
+#[tokio::test]
+async fn idle_timeout_follows_the_simulated_clock() {
+    use futures_util::FutureExt;
+
+    test::pause();
+    let (_writer, body) = StreamBody::channel();
+    let mut body = body.with_idle_timeout(Duration::from_secs(30));
+
+    assert!(body.data().now_or_never().is_none());
+    test::advance(Duration::from_secs(29)).await;
+    assert!(body.data().now_or_never().is_none());
+
+    test::advance(Duration::from_secs(2)).await;
+    match body.data().await {
+        Some(Err(err)) => assert_eq!(err.kind(), ErrorKind::TimedOut),
+        _ => panic!("the body didn't time out"),
+    }
+}
+
+#[cfg(feature = "timeout")]
+#[tokio::test]
+async fn coalesce_timeout_follows_the_simulated_clock() {
+    use futures_util::FutureExt;
+
+    test::pause();
+    let (mut writer, mut body) = StreamBody::builder()
+        .min_chunk(1024)
+        .coalesce_timeout(Duration::from_millis(100))
+        .channel();
+
+    tokio::spawn(async move {
+        writer.write_all(b"tiny").await.unwrap();
+        // Keeps the writer open, so only the timeout emits the coalesced chunk.
+        std::future::pending::<()>().await;
+    });
+    assert!(body.data().now_or_never().is_none());
+
+    // Advancing the clock lets the writer run, the body then waits for more data to coalesce.
+    test::advance(Duration::from_millis(50)).await;
+    assert!(body.data().now_or_never().is_none());
+    test::advance(Duration::from_millis(99)).await;
+    assert!(body.data().now_or_never().is_none());
+
+    test::advance(Duration::from_millis(1)).await;
+    let data = body.data().await.unwrap().unwrap();
+    assert_eq!(data.bytes(), b"tiny");
+}
+
+#[cfg(feature = "scheduler")]
+#[tokio::test]
+async fn scheduler_splits_the_rate_by_weight() {
+    use futures_util::FutureExt;
+    use stream_body::{Priority, Scheduler};
+
+    test::pause();
+    let scheduler = Scheduler::new(400);
+    let two_chunks = || StreamBody::concat(vec![StreamBody::from(vec![0_u8; 30]), StreamBody::from(vec![0_u8; 30])]);
+
+    let mut light = two_chunks().scheduled(&scheduler, 1);
+    let mut heavy = two_chunks().scheduled(&scheduler, 3);
+    let mut interactive = two_chunks()
+        .with_priority(Priority::Interactive)
+        .scheduled(&scheduler, 1);
+    assert_eq!(scheduler.active_bodies(), 2);
+
+    // Interactive bodies aren't paced, nor do they take a share of the rate.
+    assert!(interactive.data().now_or_never().is_some());
+    assert!(interactive.data().now_or_never().is_some());
+
+    // The first chunks are emitted right away, the next ones once their debt is paid at 100 and 300 bytes per second.
+    assert_eq!(light.data().await.unwrap().unwrap().remaining(), 30);
+    assert_eq!(heavy.data().await.unwrap().unwrap().remaining(), 30);
+    test::advance(Duration::from_millis(90)).await;
+    assert!(heavy.data().now_or_never().is_none());
+    test::advance(Duration::from_millis(20)).await;
+    assert_eq!(heavy.data().now_or_never().unwrap().unwrap().unwrap().remaining(), 30);
+    assert!(light.data().now_or_never().is_none());
+
+    test::advance(Duration::from_millis(200)).await;
+    assert_eq!(light.data().now_or_never().unwrap().unwrap().unwrap().remaining(), 30);
+
+    // The finished bodies give their share back.
+    assert!(heavy.data().now_or_never().unwrap().is_none());
+    assert_eq!(scheduler.active_bodies(), 1);
+    drop(light);
+    assert_eq!(scheduler.active_bodies(), 0);
+}
+
+#[cfg(feature = "driver")]
+#[tokio::test]
+async fn driver_copies_many_readers_on_shared_tasks() {
+    use futures_util::future;
+    use stream_body::Driver;
+
+    let driver = Driver::with_tasks(2);
+    let lens = (0..16).map(|i| 1000 + i * 4096).collect::<Vec<_>>();
+    let bodies = lens
+        .iter()
+        .map(|&len| StreamBody::from_reader_with_driver(std::io::Cursor::new(payload(len)), &driver))
+        .collect::<Vec<_>>();
+    let failing =
+        StreamBody::from_reader_with_driver(FailingReader::new("partial", ErrorKind::ConnectionReset), &driver);
+
+    // The queued copies still complete once every handle of the driver is dropped.
+    drop(driver);
+    let collected = future::join_all(bodies.into_iter().map(test::collect)).await;
+    for (data, &len) in collected.into_iter().zip(lens.iter()) {
+        assert_eq!(data.unwrap(), payload(len));
+    }
+
+    // Like with a dedicated task, a reader error is only logged and ends the body.
+    assert_eq!(test::collect(failing).await.unwrap(), b"partial");
+}
+
+#[cfg(feature = "cache")]
+#[tokio::test]
+async fn tee_to_file_only_keeps_complete_bodies() {
+    let dir = std::env::temp_dir().join(format!("stream-body-cache-fill-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let data = payload(64 * 1024);
+    let body = StreamBody::from_reader_inline(std::io::Cursor::new(data.clone())).tee_to_file(dir.join("complete"));
+    assert_eq!(test::collect(body).await.unwrap(), data);
+    assert_eq!(std::fs::read(dir.join("complete")).unwrap(), data);
+
+    let reader = FailingReader::new(payload(4096), ErrorKind::ConnectionReset);
+    let body = StreamBody::from_reader_inline(reader).tee_to_file(dir.join("failed"));
+    assert_eq!(
+        test::collect(body).await.unwrap_err().kind(),
+        ErrorKind::ConnectionReset
+    );
+
+    // The partial file is removed in the background.
+    let mut entries = 2;
+    for _ in 0..100 {
+        entries = std::fs::read_dir(&dir).unwrap().count();
+        if entries == 1 {
+            break;
+        }
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+    }
+    assert_eq!(entries, 1);
+    assert!(!dir.join("failed").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "pacing")]
+#[tokio::test]
+async fn paced_bodies_wait_for_the_rate_unless_interactive() {
+    use futures_util::FutureExt;
+    use stream_body::Priority;
+
+    test::pause();
+    let chunks = || StreamBody::concat((0..3).map(|_| StreamBody::from(vec![0_u8; 100])).collect());
+
+    // The first 100 bytes are the burst, the next ones are released at 1000 bytes per second.
+    let mut body = chunks().paced(1000, 100);
+    assert_eq!(body.data().await.unwrap().unwrap().remaining(), 100);
+    assert_eq!(body.data().await.unwrap().unwrap().remaining(), 100);
+    assert!(body.data().now_or_never().is_none());
+    test::advance(Duration::from_millis(90)).await;
+    assert!(body.data().now_or_never().is_none());
+    test::advance(Duration::from_millis(20)).await;
+    assert_eq!(body.data().now_or_never().unwrap().unwrap().unwrap().remaining(), 100);
+
+    let mut body = chunks().with_priority(Priority::Interactive).paced(1, 0);
+    for _ in 0..3 {
+        assert!(body.data().now_or_never().unwrap().is_some());
+    }
+    assert!(body.data().now_or_never().unwrap().is_none());
+}
+
+#[cfg(feature = "pacing")]
+#[tokio::test]
+async fn throttled_bodies_split_chunks_to_the_rate_unless_interactive() {
+    use futures_util::FutureExt;
+    use stream_body::Priority;
+
+    test::pause();
+
+    // The bucket holds 100 bytes at 1000 bytes per second, so the chunk is released in three parts.
+    let mut body = StreamBody::from(vec![0_u8; 300]).throttled(1000);
+    assert_eq!(body.data().await.unwrap().unwrap().remaining(), 100);
+    assert!(body.data().now_or_never().is_none());
+    test::advance(Duration::from_millis(90)).await;
+    assert!(body.data().now_or_never().is_none());
+    test::advance(Duration::from_millis(20)).await;
+    assert_eq!(body.data().now_or_never().unwrap().unwrap().unwrap().remaining(), 100);
+
+    let mut body = StreamBody::from(vec![0_u8; 300])
+        .with_priority(Priority::Interactive)
+        .throttled(1);
+    assert_eq!(body.data().now_or_never().unwrap().unwrap().unwrap().remaining(), 300);
+    assert!(body.data().now_or_never().unwrap().is_none());
+}
+
+#[cfg(feature = "axum")]
+#[tokio::test]
+async fn axum_responses_keep_the_headers_and_the_chunks() {
+    use axum_core::response::IntoResponse;
+    use futures_util::StreamExt;
+    use http::header::{HeaderValue, ACCEPT_RANGES};
+
+    let body = StreamBody::builder()
+        .header(ACCEPT_RANGES, HeaderValue::from_static("bytes"))
+        .shape(StreamBody::from("hello world"));
+    // The inherent `into_response` creates a hyper 0.13 response, so the trait method is called explicitly.
+    let res = IntoResponse::into_response(body);
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.headers()["accept-ranges"], "bytes");
+
+    let mut chunks = res.into_body().into_data_stream();
+    assert_eq!(chunks.next().await.unwrap().unwrap(), "hello world");
+    assert!(chunks.next().await.is_none());
+
+    // A chunk held by axum keeps the channel from reading into its buffer.
+    let (mut writer, body) = StreamBody::channel_with_capacity(4);
+    tokio::spawn(async move {
+        writer.write_all(b"abcdefgh").await.unwrap();
+    });
+    let mut chunks = axum_core::body::Body::from(body).into_data_stream();
+    let first = chunks.next().await.unwrap().unwrap();
+    tokio::time::delay_for(Duration::from_millis(10)).await;
+    assert!(futures_util::FutureExt::now_or_never(chunks.next()).is_none());
+    assert_eq!(first, "abcd");
+    drop(first);
+    assert_eq!(chunks.next().await.unwrap().unwrap(), "efgh");
+}
+
+#[cfg(feature = "zstd-seekable")]
+#[tokio::test]
+async fn zstd_seekable_compresses_each_chunk_into_a_frame() {
+    let chunks = [payload(4096), vec![b'a'; 10_000], b"tail".to_vec()];
+    let body = StreamBody::concat(chunks.iter().cloned().map(StreamBody::from).collect());
+    let out = test::collect(body.zstd_seekable(3)).await.unwrap();
+
+    let u32_at = |pos: usize| u32::from_le_bytes([out[pos], out[pos + 1], out[pos + 2], out[pos + 3]]);
+    let end = out.len();
+    assert_eq!(u32_at(end - 4), 0x8F92_EAB1);
+    assert_eq!(u32_at(end - 9) as usize, chunks.len());
+
+    let table_start = end - 9 - chunks.len() * 8;
+    let mut offset = 0;
+    for (i, chunk) in chunks.iter().enumerate() {
+        let compressed = u32_at(table_start + i * 8) as usize;
+        assert_eq!(u32_at(table_start + i * 8 + 4) as usize, chunk.len());
+
+        // Every frame decodes on its own.
+        let frame = &out[offset..offset + compressed];
+        assert_eq!(&zstd::decode_all(frame).unwrap(), chunk);
+        offset += compressed;
+    }
+    assert!(offset < 4096 + 10_000);
+
+    // The frames are followed by the seek table in a skippable frame.
+    assert_eq!(u32_at(offset), 0x184D_2A5E);
+    assert_eq!(offset + 8 + u32_at(offset + 4) as usize, end);
+    assert_eq!(zstd::decode_all(&out[..]).unwrap(), chunks.concat());
+}